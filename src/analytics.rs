@@ -0,0 +1,269 @@
+//! Per-client traffic over the last minute: who's asking the most, who's getting mostly
+//! NXDOMAIN back (as malware cycling through generated domains does) and who's asking for
+//! a great many different names under one domain (as DNS tunnels do). Clients past the
+//! configured thresholds are flagged until the next minute's figures are in.
+
+use crate::ResultCode;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long each window of figures covers.
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// Clients beyond this many in one window aren't tracked until the next, so a flood of
+/// spoofed sources can't use up memory.
+const MAX_CLIENTS: usize = 10_000;
+
+/// The NXDOMAIN ratio means little for a client that has only asked a handful of times.
+const MIN_QUERIES_FOR_RATIO: u64 = 20;
+
+/// Only this many clients get a queries series in the metrics, to keep the number of
+/// series bounded.
+const METRICS_TOP_CLIENTS: usize = 20;
+
+/// When a client gets flagged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Thresholds {
+    /// Queries per second, averaged over the window.
+    pub qps: u32,
+    /// The percentage of queries answered with NXDOMAIN.
+    pub nxdomain_percent: u32,
+    /// Different names asked for under any one domain.
+    pub unique_subdomains: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            qps: 50,
+            nxdomain_percent: 50,
+            unique_subdomains: 100,
+        }
+    }
+}
+
+/// Why a client was flagged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Qps,
+    NxDomain,
+    Subdomains,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Flag::Qps => "qps",
+            Flag::NxDomain => "nxdomain",
+            Flag::Subdomains => "subdomains",
+        })
+    }
+}
+
+/// One client's figures for a finished window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientReport {
+    pub client: IpAddr,
+    pub queries: u64,
+    pub qps: f64,
+    pub nxdomain_ratio: f64,
+    /// The domain the client asked the most different names under, and how many.
+    pub busiest_domain: Option<(String, usize)>,
+    pub flags: Vec<Flag>,
+}
+
+impl fmt::Display for ClientReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} queries={} qps={:.1} nxdomain={:.2}",
+            self.client, self.queries, self.qps, self.nxdomain_ratio
+        )?;
+        if let Some((ref domain, count)) = self.busiest_domain {
+            write!(f, " subdomains={} ({})", count, domain)?;
+        }
+        if !self.flags.is_empty() {
+            let flags: Vec<String> = self.flags.iter().map(Flag::to_string).collect();
+            write!(f, " flagged={}", flags.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queries: u64,
+    nxdomain: u64,
+    /// First labels seen under each parent domain, up to one more than the threshold.
+    subdomains: HashMap<String, HashSet<String>>,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    clients: HashMap<IpAddr, Counters>,
+}
+
+#[derive(Debug)]
+pub struct Analytics {
+    thresholds: Thresholds,
+    current: Mutex<Window>,
+    /// Reports for the last finished window, busiest client first.
+    last: Mutex<Vec<ClientReport>>,
+}
+
+impl Analytics {
+    pub fn new(thresholds: Thresholds) -> Analytics {
+        Analytics {
+            thresholds,
+            current: Mutex::new(Window {
+                started: Instant::now(),
+                clients: HashMap::new(),
+            }),
+            last: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Counts one query from `client` for `name`, answered with `rescode`.
+    pub fn record(&self, client: IpAddr, name: &str, rescode: ResultCode) {
+        let mut window = self.current.lock().unwrap();
+        if window.clients.len() >= MAX_CLIENTS && !window.clients.contains_key(&client) {
+            return;
+        }
+
+        let counters = window.clients.entry(client).or_default();
+        counters.queries += 1;
+        if rescode == ResultCode::NonexistantDomain {
+            counters.nxdomain += 1;
+        }
+
+        // Different names straight under a top-level domain are just different sites.
+        let name = name.trim_end_matches('.').to_lowercase();
+        if let Some((label, parent)) = name.split_once('.').filter(|(_, p)| p.contains('.')) {
+            let labels = counters.subdomains.entry(parent.to_string()).or_default();
+            if labels.len() <= self.thresholds.unique_subdomains {
+                labels.insert(label.to_string());
+            }
+        }
+    }
+
+    /// Closes the current window and starts a new one, returning the clients that were
+    /// flagged in the window just closed.
+    pub fn rotate(&self) -> Vec<ClientReport> {
+        let window = {
+            let mut current = self.current.lock().unwrap();
+            std::mem::replace(
+                &mut *current,
+                Window {
+                    started: Instant::now(),
+                    clients: HashMap::new(),
+                },
+            )
+        };
+
+        let secs = window.started.elapsed().as_secs_f64().max(1.0);
+        let mut reports: Vec<ClientReport> = window
+            .clients
+            .into_iter()
+            .map(|(client, counters)| self.report(client, counters, secs))
+            .collect();
+        reports.sort_by(|a, b| b.queries.cmp(&a.queries).then(a.client.cmp(&b.client)));
+
+        let flagged = reports
+            .iter()
+            .filter(|report| !report.flags.is_empty())
+            .cloned()
+            .collect();
+        *self.last.lock().unwrap() = reports;
+        flagged
+    }
+
+    fn report(&self, client: IpAddr, counters: Counters, secs: f64) -> ClientReport {
+        let qps = counters.queries as f64 / secs;
+        let nxdomain_ratio = counters.nxdomain as f64 / counters.queries.max(1) as f64;
+        let busiest_domain = counters
+            .subdomains
+            .into_iter()
+            .map(|(domain, labels)| (domain, labels.len()))
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+
+        let mut flags = Vec::new();
+        if qps > self.thresholds.qps as f64 {
+            flags.push(Flag::Qps);
+        }
+        if counters.queries >= MIN_QUERIES_FOR_RATIO
+            && nxdomain_ratio * 100.0 > self.thresholds.nxdomain_percent as f64
+        {
+            flags.push(Flag::NxDomain);
+        }
+        if busiest_domain
+            .as_ref()
+            .is_some_and(|(_, count)| *count > self.thresholds.unique_subdomains)
+        {
+            flags.push(Flag::Subdomains);
+        }
+
+        ClientReport {
+            client,
+            queries: counters.queries,
+            qps,
+            nxdomain_ratio,
+            busiest_domain,
+            flags,
+        }
+    }
+
+    /// The `count` busiest clients of the last finished window.
+    pub fn top(&self, count: usize) -> Vec<ClientReport> {
+        self.last
+            .lock()
+            .unwrap()
+            .iter()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// The clients flagged in the last finished window.
+    pub fn flagged(&self) -> Vec<ClientReport> {
+        self.last
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|report| !report.flags.is_empty())
+            .cloned()
+            .collect()
+    }
+
+    /// The last window's figures in the Prometheus text format: queries for the busiest
+    /// clients, and a series for each reason each client was flagged.
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP diydns_client_queries Queries from the client in the last minute.\n");
+        out.push_str("# TYPE diydns_client_queries gauge\n");
+        for report in self.top(METRICS_TOP_CLIENTS) {
+            out.push_str(&format!(
+                "diydns_client_queries{{client=\"{}\"}} {}\n",
+                report.client, report.queries
+            ));
+        }
+
+        out.push_str(
+            "# HELP diydns_client_flagged Clients flagged in the last minute, by reason.\n",
+        );
+        out.push_str("# TYPE diydns_client_flagged gauge\n");
+        for report in self.flagged() {
+            for flag in &report.flags {
+                out.push_str(&format!(
+                    "diydns_client_flagged{{client=\"{}\",reason=\"{}\"}} 1\n",
+                    report.client, flag
+                ));
+            }
+        }
+
+        out
+    }
+}
@@ -0,0 +1,198 @@
+use crate::resolver::{QueryOptions, Resolver};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::{DnsPacket, DnsRecord, QueryType, Result, MAX_EDNS_PACKET_SIZE};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+const AUDIT_NAME: &str = "audit.diydns.test";
+const GENUINE_ADDR: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const FORGED_ADDR: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 66);
+
+/// Where the question ends in a query or response for `AUDIT_NAME`: the header, the name's
+/// labels and the type and class.
+const QUESTION_END: usize = 12 + AUDIT_NAME.len() + 2 + 4;
+
+/// How long to wait for the port unreachable that shows a datagram from the wrong address
+/// was dropped by the kernel.
+const REFUSAL_WAIT: Duration = Duration::from_secs(1);
+
+/// A way of forging a response that the resolver must not accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attack {
+    WrongId,
+    WrongSource,
+    WrongQuestion,
+    WrongCase,
+    Malformed,
+}
+
+impl Attack {
+    pub const ALL: [Attack; 5] = [
+        Attack::WrongId,
+        Attack::WrongSource,
+        Attack::WrongQuestion,
+        Attack::WrongCase,
+        Attack::Malformed,
+    ];
+
+    /// Whether the resolver's counters show that it spotted and dropped this attack. A
+    /// response from the wrong address can instead be dropped by the kernel, if the
+    /// resolver's socket is connected to the server, which `refused` says happened.
+    fn was_counted(self, before: StatsSnapshot, after: StatsSnapshot, refused: bool) -> bool {
+        match self {
+            Attack::WrongId => after.wrong_id > before.wrong_id,
+            Attack::WrongSource => refused || after.unexpected_source > before.unexpected_source,
+            Attack::WrongQuestion | Attack::WrongCase => {
+                after.wrong_question > before.wrong_question
+            }
+            Attack::Malformed => after.malformed > before.malformed,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditResult {
+    pub attack: Attack,
+    /// The resolver returned the genuine answer rather than the forged one.
+    pub rejected: bool,
+    /// The attempt showed up in the resolver's counters.
+    pub counted: bool,
+    pub error: Option<String>,
+}
+
+impl AuditResult {
+    pub fn passed(&self) -> bool {
+        self.rejected && self.counted && self.error.is_none()
+    }
+}
+
+/// Runs every attack against a resolver talking to a fake upstream on the loopback
+/// interface. For each, the upstream sends a forged response ahead of the genuine one, and
+/// the resolver passes if it ignores the forgery and returns the genuine answer.
+pub fn run_audit() -> Vec<AuditResult> {
+    Attack::ALL
+        .iter()
+        .map(|attack| run_attack(*attack))
+        .collect()
+}
+
+fn run_attack(attack: Attack) -> AuditResult {
+    let resolver = Resolver::new(Stats::new(false), Default::default());
+    let before = resolver.stats().snapshot();
+
+    let result = UdpSocket::bind(("127.0.0.1", 0)).and_then(|upstream| {
+        let addr = upstream.local_addr()?;
+        let server = thread::spawn(move || fake_upstream(upstream, attack));
+        let options = QueryOptions {
+            randomize_case: true,
+            ..QueryOptions::default()
+        };
+        let response = resolver.lookup_with(AUDIT_NAME, QueryType::A, &options, addr);
+        let refused = server.join().unwrap()?;
+        Ok((response?, refused))
+    });
+
+    match result {
+        Ok((response, refused)) => AuditResult {
+            attack,
+            rejected: answer_addr(&response) == Some(GENUINE_ADDR),
+            counted: attack.was_counted(before, resolver.stats().snapshot(), refused),
+            error: None,
+        },
+        Err(e) => AuditResult {
+            attack,
+            rejected: false,
+            counted: false,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn answer_addr(response: &DnsPacket) -> Option<Ipv4Addr> {
+    response.answers.iter().find_map(|rec| match rec {
+        DnsRecord::A { addr, .. } => Some(*addr),
+        _ => None,
+    })
+}
+
+/// Answers a single query, sending the forged response for `attack` before the real one.
+/// Returns whether the kernel refused the forgery, as it does one from the wrong address
+/// to a socket connected to the server.
+fn fake_upstream(socket: UdpSocket, attack: Attack) -> Result<bool> {
+    let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+    let (len, client) = socket.recv_from(&mut buf)?;
+    let request = DnsPacket::from_bytes(&buf[..len])?;
+    // Responses carry the question as it was sent, since the parser lowercases the name
+    // and the resolver checks its case.
+    let question = buf[12..QUESTION_END].to_vec();
+
+    let mut forged = response_to(&request, FORGED_ADDR);
+    match attack {
+        Attack::WrongId => forged.header.id = forged.header.id.wrapping_add(1),
+        Attack::WrongQuestion => forged.questions[0].name = format!("evil.{}", AUDIT_NAME),
+        Attack::WrongSource | Attack::WrongCase | Attack::Malformed => {}
+    }
+
+    let mut forged_bytes = forged.to_bytes()?;
+    if attack != Attack::WrongQuestion {
+        forged_bytes[12..QUESTION_END].copy_from_slice(&question);
+    }
+    let mut refused = false;
+
+    match attack {
+        Attack::WrongSource => {
+            // Sent from a socket of its own connected to the resolver, so that the port
+            // unreachable the kernel answers with, if no socket of the resolver's will take
+            // it, comes back here.
+            let other = UdpSocket::bind(("127.0.0.1", 0))?;
+            other.connect(client)?;
+            other.send(&forged_bytes)?;
+            other.set_read_timeout(Some(REFUSAL_WAIT))?;
+            refused = matches!(
+                other.recv(&mut buf),
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused
+            );
+        }
+        Attack::WrongCase => {
+            // The right name, with the case of every letter the other way round.
+            for byte in &mut forged_bytes[12..QUESTION_END] {
+                if byte.is_ascii_alphabetic() {
+                    *byte ^= 0x20;
+                }
+            }
+            socket.send_to(&forged_bytes, client)?;
+        }
+        Attack::Malformed => {
+            // Replace the answer with a compression pointer past the end of the buffer.
+            forged_bytes.truncate(QUESTION_END);
+            forged_bytes.extend_from_slice(&[0xFF, 0xFF]);
+            socket.send_to(&forged_bytes, client)?;
+        }
+        Attack::WrongId | Attack::WrongQuestion => {
+            socket.send_to(&forged_bytes, client)?;
+        }
+    }
+
+    let mut genuine = response_to(&request, GENUINE_ADDR).to_bytes()?;
+    genuine[12..QUESTION_END].copy_from_slice(&question);
+    socket.send_to(&genuine, client)?;
+
+    Ok(refused)
+}
+
+fn response_to(request: &DnsPacket, addr: Ipv4Addr) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.id = request.header.id;
+    packet.header.response = true;
+    packet.header.questions = 1;
+    packet.header.answers = 1;
+    packet.questions = request.questions.clone();
+    packet.answers.push(DnsRecord::A {
+        domain: request.questions[0].name.clone(),
+        addr,
+        ttl: 60,
+    });
+    packet
+}
@@ -0,0 +1,196 @@
+//! Blocking names from lists, such as the ad and malware lists published for Pi-hole. A
+//! blocked name gets NXDOMAIN, and every block is put down to the list and the line of it
+//! that matched, so a list's worth can be judged from its hits and a name blocked by
+//! mistake traced back to the rule responsible.
+
+use crate::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A list to block names from: the label it goes by in logs and metrics, and its file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListFile {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for ListFile {
+    type Err = Error;
+
+    /// Parses `<label> <path>`.
+    fn from_str(s: &str) -> Result<ListFile> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [label, path] => Ok(ListFile {
+                label: label.to_string(),
+                path: PathBuf::from(path),
+            }),
+            _ => Err(invalid(format!(
+                "expected a label followed by a path, found {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+/// The rule a blocked name matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The label of the list it's on.
+    pub list: String,
+    /// The domain on the list, which blocks itself and every name under it.
+    pub rule: String,
+    /// Where in the list's file, counting from 1.
+    pub line: usize,
+}
+
+/// `ads rule doubleclick.net (line 12)`.
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} rule {} (line {})", self.list, self.rule, self.line)
+    }
+}
+
+#[derive(Debug)]
+struct List {
+    label: String,
+    /// Each domain on the list and the line it's on.
+    rules: HashMap<String, usize>,
+    hits: AtomicU64,
+}
+
+/// The lists queries are checked against, in the order they were given.
+#[derive(Debug, Default)]
+pub struct Blocklists {
+    lists: Vec<List>,
+}
+
+impl Blocklists {
+    /// Reads the files of `lists`.
+    pub fn load(lists: &[ListFile]) -> Result<Blocklists> {
+        let lists = lists
+            .iter()
+            .map(|list| {
+                let contents = fs::read_to_string(&list.path).map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!("blocklist {}: {}: {}", list.label, list.path.display(), e),
+                    )
+                })?;
+                Ok(List {
+                    label: list.label.clone(),
+                    rules: parse_rules(&contents),
+                    hits: AtomicU64::new(0),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Blocklists { lists })
+    }
+
+    /// The number of rules across every list.
+    pub fn len(&self) -> usize {
+        self.lists.iter().map(|list| list.rules.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The rule that blocks `name`, if any, counting a hit for its list. The first list
+    /// with a rule for the name wins, and within a list the rule closest to the name.
+    pub fn check(&self, name: &str) -> Option<Match> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let suffixes: Vec<&str> = name
+            .match_indices('.')
+            .map(|(idx, _)| &name[idx + 1..])
+            .collect();
+
+        for list in &self.lists {
+            let found = std::iter::once(name.as_str())
+                .chain(suffixes.iter().copied())
+                .find_map(|domain| Some((domain, *list.rules.get(domain)?)));
+
+            if let Some((rule, line)) = found {
+                list.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(Match {
+                    list: list.label.clone(),
+                    rule: rule.to_string(),
+                    line,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Each list's label and the queries it has blocked since startup.
+    pub fn hits(&self) -> Vec<(String, u64)> {
+        self.lists
+            .iter()
+            .map(|list| (list.label.clone(), list.hits.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// The hit counts in the Prometheus text format, one series per list.
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP diydns_blocklist_hits_total Queries blocked by each list.\n");
+        out.push_str("# TYPE diydns_blocklist_hits_total counter\n");
+        for (label, hits) in self.hits() {
+            out.push_str(&format!(
+                "diydns_blocklist_hits_total{{list=\"{}\",action=\"nxdomain\"}} {}\n",
+                label, hits
+            ));
+        }
+
+        out.push_str("# HELP diydns_blocklist_rules Rules loaded from each list.\n");
+        out.push_str("# TYPE diydns_blocklist_rules gauge\n");
+        for list in &self.lists {
+            out.push_str(&format!(
+                "diydns_blocklist_rules{{list=\"{}\"}} {}\n",
+                list.label,
+                list.rules.len()
+            ));
+        }
+
+        out
+    }
+}
+
+/// The domains on a list and their lines. A line holds a domain, or a hosts file entry
+/// pointing one or more domains at a null address; `#` starts a comment, and lines that
+/// are neither are skipped. A domain listed twice keeps its first line.
+fn parse_rules(contents: &str) -> HashMap<String, usize> {
+    let mut rules = HashMap::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let domains = match fields.as_slice() {
+            [] => continue,
+            [_] => &fields[..],
+            [addr, domains @ ..] if addr.parse::<std::net::IpAddr>().is_ok() => domains,
+            _ => continue,
+        };
+
+        for domain in domains {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            // Hosts files name the machine itself too.
+            if domain.is_empty() || domain == "localhost" || domain.contains('/') {
+                continue;
+            }
+            rules.entry(domain).or_insert(idx + 1);
+        }
+    }
+
+    rules
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,193 @@
+use crate::clock::{Clock, SystemClock};
+use crate::{DnsPacket, DnsRecord, QueryType, ResultCode};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Records are never kept longer than this, whatever their TTL says.
+const MAX_TTL: u32 = 86400;
+
+/// RFC 2308 says a server failure mustn't be cached for longer than five minutes.
+pub(crate) const MAX_SERVFAIL_TTL: u32 = 300;
+
+/// The cache is split into this many shards by a hash of the question, each with its own
+/// lock, so server threads looking up different names rarely wait on each other.
+const SHARDS: usize = 16;
+
+/// Once a shard holds this many responses, expired ones are swept out before inserting,
+/// and if that doesn't free anything the insert is dropped.
+const MAX_SHARD_ENTRIES: usize = 10_000 / SHARDS;
+
+type Key = (String, QueryType);
+
+#[derive(Debug)]
+struct Entry {
+    packet: DnsPacket,
+    stored: Instant,
+    ttl: u32,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.stored) >= Duration::from_secs(self.ttl.into())
+    }
+
+    /// The response as it would be served at `now`, or nothing if it has expired.
+    fn current(&self, now: Instant, min_ttl: u32) -> Option<DnsPacket> {
+        if self.is_expired(now) {
+            return None;
+        }
+
+        let elapsed = now.saturating_duration_since(self.stored);
+
+        let elapsed = elapsed.as_secs() as u32;
+        let mut packet = self.packet.clone();
+        for rec in packet
+            .answers
+            .iter_mut()
+            .chain(packet.authorities.iter_mut())
+            .chain(packet.resources.iter_mut())
+        {
+            let ttl = rec.ttl().max(min_ttl).min(MAX_TTL);
+            rec.set_ttl(ttl.saturating_sub(elapsed));
+        }
+
+        Some(packet)
+    }
+}
+
+/// Responses by question, kept for the lowest TTL among their records.
+#[derive(Debug)]
+pub struct Cache {
+    shards: Vec<Mutex<HashMap<Key, Entry>>>,
+    /// Picks the shard for a question. Like the maps themselves, it's SipHash with keys
+    /// chosen at random per process, so nobody can craft names that all land in one shard
+    /// or one bucket.
+    hasher: RandomState,
+    /// TTLs below this are raised to it. Keeping answers longer than their owners asked
+    /// saves round trips at the cost of noticing changes later.
+    min_ttl: u32,
+    /// How long a SERVFAIL is kept, so a broken domain isn't resolved afresh for every
+    /// retry. Zero leaves failures out.
+    servfail_ttl: u32,
+    clock: Arc<dyn Clock>,
+}
+
+impl Cache {
+    pub fn new(min_ttl: u32, servfail_ttl: u32) -> Cache {
+        Cache::with_clock(min_ttl, servfail_ttl, Arc::new(SystemClock))
+    }
+
+    /// Like [`Cache::new`], telling the time by `clock`, so tests can step past a TTL
+    /// rather than wait it out.
+    pub fn with_clock(min_ttl: u32, servfail_ttl: u32, clock: Arc<dyn Clock>) -> Cache {
+        Cache {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+            min_ttl: min_ttl.min(MAX_TTL),
+            servfail_ttl: servfail_ttl.min(MAX_SERVFAIL_TTL),
+            clock,
+        }
+    }
+
+    /// The cached response for a question, with TTLs counted down by the time it has spent
+    /// in the cache.
+    pub fn get(&self, name: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let key = (name.to_lowercase(), qtype);
+        let mut entries = self.shard(&key).lock().unwrap();
+
+        let packet = entries.get(&key)?.current(self.clock.now(), self.min_ttl);
+        if packet.is_none() {
+            entries.remove(&key);
+        }
+
+        packet
+    }
+
+    /// Every unexpired response for a name at or under `suffix`, as `get` would return
+    /// them, sorted by name and type. An empty suffix matches everything.
+    pub fn dump(&self, suffix: &str) -> Vec<(String, QueryType, DnsPacket)> {
+        let suffix = suffix.trim_matches('.').to_lowercase();
+        let now = self.clock.now();
+        let mut dump = Vec::new();
+        for shard in &self.shards {
+            let entries = shard.lock().unwrap();
+            for ((name, qtype), entry) in entries.iter() {
+                let under =
+                    suffix.is_empty() || *name == suffix || name.ends_with(&format!(".{}", suffix));
+                if !under {
+                    continue;
+                }
+                if let Some(packet) = entry.current(now, self.min_ttl) {
+                    dump.push((name.clone(), *qtype, packet));
+                }
+            }
+        }
+
+        dump.sort_by_key(|(name, qtype, _)| (name.clone(), qtype.to_num()));
+        dump
+    }
+
+    /// Stores a response, unless it has nothing saying how long to keep it. A SERVFAIL is
+    /// kept for `servfail_ttl` seconds, and other errors not at all. An SOA in the
+    /// authority section, as comes with a negative answer, counts for no more than its
+    /// minimum field (RFC 2308).
+    pub fn insert(&self, name: &str, qtype: QueryType, packet: &DnsPacket) {
+        let authority_ttl = |rec: &DnsRecord| match *rec {
+            DnsRecord::SOA { ttl, minimum, .. } => ttl.min(minimum),
+            _ => rec.ttl(),
+        };
+        let ttl = match packet.header.rescode {
+            ResultCode::Success | ResultCode::NonexistantDomain => packet
+                .answers
+                .iter()
+                .map(DnsRecord::ttl)
+                .chain(packet.authorities.iter().map(authority_ttl))
+                .min()
+                .unwrap_or(0)
+                .max(self.min_ttl)
+                .min(MAX_TTL),
+            ResultCode::ServerFail => self.servfail_ttl,
+            _ => return,
+        };
+        if ttl == 0 {
+            return;
+        }
+
+        let key = (name.to_lowercase(), qtype);
+        let now = self.clock.now();
+        let mut entries = self.shard(&key).lock().unwrap();
+        if entries.len() >= MAX_SHARD_ENTRIES {
+            entries.retain(|_, entry| !entry.is_expired(now));
+            if entries.len() >= MAX_SHARD_ENTRIES {
+                return;
+            }
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                packet: packet.clone(),
+                stored: now,
+                ttl,
+            },
+        );
+    }
+
+    fn shard(&self, key: &Key) -> &Mutex<HashMap<Key, Entry>> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARDS]
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
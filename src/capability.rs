@@ -0,0 +1,117 @@
+use crate::{EDNS_PAYLOAD_SIZE, MAX_PACKET_SIZE};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a server is held to a smaller payload size before the full one is tried again,
+/// in case the path to it has changed or the timeout had nothing to do with the size.
+const PROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// What the resolver has learned from the responses of each server it queries, so that
+/// later queries go out in a form the server can take. It lasts as long as the resolver
+/// and its clones do.
+#[derive(Debug, Default)]
+pub struct Capabilities {
+    servers: Mutex<HashMap<SocketAddr, Capability>>,
+}
+
+/// What one server has shown it can and can't do. A server nothing is known about yet is
+/// taken to handle everything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capability {
+    /// It answered BADVERS to EDNS version 0, the only version we send, so it gets no OPT
+    /// record at all.
+    pub no_edns: bool,
+    /// When it last timed out while offered the full payload size. For a while after that
+    /// it's offered 512 bytes, since a response in fragments that never arrive looks just
+    /// like a server that never answered.
+    pub lowered: Option<Instant>,
+    /// The largest response it has sent, in bytes. Once that's over 512, big responses
+    /// have shown they get through, and a timeout is taken for just a timeout.
+    pub largest_response: usize,
+    /// Whether it copied the DO bit back when asked with it, as servers that know DNSSEC
+    /// do. One that didn't isn't asked with DO again.
+    pub dnssec: Option<bool>,
+}
+
+impl Capability {
+    /// The EDNS payload size to offer the server, or `None` for no EDNS at all.
+    pub fn payload_size(&self) -> Option<u16> {
+        match self.lowered {
+            _ if self.no_edns => None,
+            Some(since) if since.elapsed() < PROBE_INTERVAL => Some(MAX_PACKET_SIZE as u16),
+            _ => Some(EDNS_PAYLOAD_SIZE),
+        }
+    }
+}
+
+/// As in `edns 1232, largest response 700 bytes, dnssec yes`.
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.payload_size() {
+            Some(size) => write!(f, "edns {}", size)?,
+            None => write!(f, "no edns")?,
+        }
+        write!(f, ", largest response {} bytes", self.largest_response)?;
+        match self.dnssec {
+            Some(true) => write!(f, ", dnssec yes"),
+            Some(false) => write!(f, ", dnssec no"),
+            None => write!(f, ", dnssec unknown"),
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn new() -> Capabilities {
+        Capabilities::default()
+    }
+
+    pub fn get(&self, server: SocketAddr) -> Capability {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(&server)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every server heard from, in order of address.
+    pub fn snapshot(&self) -> Vec<(SocketAddr, Capability)> {
+        let servers = self.servers.lock().unwrap();
+        let mut snapshot: Vec<_> = servers.iter().map(|(addr, cap)| (*addr, *cap)).collect();
+        snapshot.sort_by_key(|(addr, _)| *addr);
+        snapshot
+    }
+
+    /// Notes a response of `len` bytes from `server`, and whether it copied the DO bit
+    /// back, if it was asked with it.
+    pub fn answered(&self, server: SocketAddr, len: usize, dnssec: Option<bool>) {
+        let mut servers = self.servers.lock().unwrap();
+        let capability = servers.entry(server).or_default();
+        capability.largest_response = capability.largest_response.max(len);
+        if dnssec.is_some() {
+            capability.dnssec = dnssec;
+        }
+    }
+
+    /// Notes that a query to `server` went unanswered.
+    pub fn timed_out(&self, server: SocketAddr) {
+        let mut servers = self.servers.lock().unwrap();
+        let capability = servers.entry(server).or_default();
+        if capability.largest_response <= MAX_PACKET_SIZE {
+            capability.lowered = Some(Instant::now());
+        }
+    }
+
+    /// Notes that `server` answered BADVERS.
+    pub fn bad_version(&self, server: SocketAddr) {
+        self.servers
+            .lock()
+            .unwrap()
+            .entry(server)
+            .or_default()
+            .no_edns = true;
+    }
+}
@@ -0,0 +1,219 @@
+//! Reading upstream settings the way a cluster hands them to a pod: the kube-dns ConfigMap
+//! (`stubDomains` and `upstreamNameservers`, mounted as files) and the pod's resolv.conf.
+
+use crate::config;
+use crate::Result;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// Servers to forward a domain's queries to, instead of the default upstreams.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StubDomain {
+    pub domain: String,
+    pub servers: Vec<SocketAddr>,
+}
+
+/// What a mounted kube-dns ConfigMap asks for. Either key may be missing.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KubeDnsConfig {
+    pub stub_domains: Vec<StubDomain>,
+    pub upstream_nameservers: Vec<SocketAddr>,
+}
+
+/// Reads the `stubDomains` and `upstreamNameservers` files from a directory where the
+/// kube-dns ConfigMap is mounted.
+pub fn load_kube_dns_config(dir: &Path) -> Result<KubeDnsConfig> {
+    let mut config = KubeDnsConfig::default();
+
+    if let Some(json) = read_optional(&dir.join("stubDomains"))? {
+        config.stub_domains = parse_stub_domains(&json)?;
+    }
+    if let Some(json) = read_optional(&dir.join("upstreamNameservers"))? {
+        config.upstream_nameservers = parse_upstream_nameservers(&json)?;
+    }
+
+    Ok(config)
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parses `stubDomains`, a JSON object from domain to a list of server addresses:
+/// `{"acme.local": ["1.2.3.4"]}`.
+pub fn parse_stub_domains(json: &str) -> Result<Vec<StubDomain>> {
+    let mut tokens = Tokens::new(json);
+    let mut stubs = Vec::new();
+
+    tokens.expect('{')?;
+    if !tokens.eat('}') {
+        loop {
+            let domain = tokens.string()?;
+            tokens.expect(':')?;
+            let servers = string_array(&mut tokens)?
+                .iter()
+                .map(|addr| parse_server(addr))
+                .collect::<Result<_>>()?;
+            stubs.push(StubDomain {
+                domain: normalize_domain(&domain),
+                servers,
+            });
+
+            if !tokens.eat(',') {
+                tokens.expect('}')?;
+                break;
+            }
+        }
+    }
+    tokens.end()?;
+
+    Ok(stubs)
+}
+
+/// Parses `upstreamNameservers`, a JSON list of server addresses: `["8.8.8.8"]`.
+pub fn parse_upstream_nameservers(json: &str) -> Result<Vec<SocketAddr>> {
+    let mut tokens = Tokens::new(json);
+    let servers = string_array(&mut tokens)?;
+    tokens.end()?;
+
+    servers.iter().map(|addr| parse_server(addr)).collect()
+}
+
+/// The `nameserver` addresses in a resolv.conf, in order. Link-local IPv6 servers may carry
+/// a zone index (`fe80::1%eth0`).
+pub fn parse_resolv_conf(contents: &str) -> Vec<SocketAddr> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(addr)) => parse_server(addr).ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+pub fn load_resolv_conf(path: &Path) -> Result<Vec<SocketAddr>> {
+    Ok(parse_resolv_conf(&fs::read_to_string(path)?))
+}
+
+/// The stub domain with the longest suffix match for `name`, if any.
+pub fn find_stub<'a>(stubs: &'a [StubDomain], name: &str) -> Option<&'a StubDomain> {
+    let name = normalize_domain(name);
+    stubs
+        .iter()
+        .filter(|stub| {
+            name == stub.domain
+                || stub.domain.is_empty()
+                || name.ends_with(&format!(".{}", stub.domain))
+        })
+        .max_by_key(|stub| stub.domain.len())
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_lowercase()
+}
+
+/// kube-dns accepts `ip`, `ip:port` and `[ipv6]:port`. Host names are refused, since we'd
+/// need a resolver to find them.
+fn parse_server(addr: &str) -> Result<SocketAddr> {
+    if addr.parse::<SocketAddr>().is_err() && addr.parse::<IpAddr>().is_err() && !addr.contains('%')
+    {
+        return Err(invalid(format!("invalid server address {:?}", addr)));
+    }
+    config::parse_addr(addr, 53)
+}
+
+fn string_array(tokens: &mut Tokens) -> Result<Vec<String>> {
+    let mut values = Vec::new();
+
+    tokens.expect('[')?;
+    if tokens.eat(']') {
+        return Ok(values);
+    }
+    loop {
+        values.push(tokens.string()?);
+        if !tokens.eat(',') {
+            tokens.expect(']')?;
+            return Ok(values);
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Just enough of a JSON reader for the ConfigMap values above, which only ever hold
+/// objects, arrays and strings.
+struct Tokens<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(json: &'a str) -> Tokens<'a> {
+        Tokens {
+            chars: json.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "expected {:?}, found {:?}",
+                c,
+                self.chars.peek()
+            )))
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.chars.next() {
+                    Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => value.push(c),
+                    other => return Err(invalid(format!("unsupported escape {:?}", other))),
+                },
+                Some(c) => value.push(c),
+                None => return Err(invalid("unterminated string".to_string())),
+            }
+        }
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => Ok(()),
+            Some(c) => Err(invalid(format!("unexpected {:?} after value", c))),
+        }
+    }
+}
@@ -0,0 +1,961 @@
+use crate::analytics::Thresholds;
+use crate::blocklist::ListFile;
+use crate::cache::MAX_SERVFAIL_TTL;
+use crate::cluster::{self, StubDomain};
+use crate::history;
+use crate::leases::LeaseFormat;
+pub use crate::log::LogLevel;
+use crate::safesearch::Network;
+use crate::shaping::Shape;
+use crate::synth;
+use crate::synth::SyntheticDomain;
+use crate::tunnel::{Limits, TunnelAction};
+use crate::zone::{self, Zone};
+use crate::{ttl, Result, EDNS_PAYLOAD_SIZE, MAX_EDNS_PACKET_SIZE, MAX_PACKET_SIZE};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::iter;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variables override the config file, and are named after the keys:
+/// `DIYDNS_` followed by the key in upper case, e.g. `DIYDNS_UPSTREAM`.
+const ENV_PREFIX: &str = "DIYDNS_";
+
+/// A problem found by [`Config::check_file`] or [`Config::check`]. Those in the file have
+/// the line and column they're at, counting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogFormat> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(invalid(format!(
+                "unknown log format {:?}, expected text or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// The label queries on `bind` go by.
+pub const DEFAULT_LISTENER: &str = "default";
+
+/// Another address to answer queries on, with the label its queries go by in logs, stats
+/// and policy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listener {
+    pub label: String,
+    pub addr: SocketAddr,
+}
+
+/// How queries are shared out among several upstreams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamStrategy {
+    /// Try them one at a time in order, moving on when one fails.
+    Ordered,
+    /// Ask them all at once and take the first answer.
+    Race,
+    /// Share queries out by `upstream_weights`, leaving out servers that have failed
+    /// until they answer again.
+    Weighted,
+}
+
+impl FromStr for UpstreamStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UpstreamStrategy> {
+        match s {
+            "ordered" => Ok(UpstreamStrategy::Ordered),
+            "race" => Ok(UpstreamStrategy::Race),
+            "weighted" => Ok(UpstreamStrategy::Weighted),
+            _ => Err(invalid(format!(
+                "unknown upstream strategy {:?}, expected ordered, race or weighted",
+                s
+            ))),
+        }
+    }
+}
+
+/// Settings for `serve`. Each is resolved in layers, later ones winning: the defaults
+/// below, then the config file, then `DIYDNS_*` environment variables, then command-line
+/// flags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Address the server listens on.
+    pub bind: SocketAddr,
+    /// More addresses to answer on besides `bind`, whose queries are labelled
+    /// `DEFAULT_LISTENER`.
+    pub listeners: Vec<Listener>,
+    /// How many threads answer queries. Each takes the next datagram off the socket.
+    pub threads: usize,
+    /// Forward queries to these servers, tried in order, instead of resolving them from
+    /// the root.
+    pub upstream: Vec<SocketAddr>,
+    pub upstream_strategy: UpstreamStrategy,
+    /// Each upstream's share of the queries under the weighted strategy, in the same order
+    /// as `upstream`. Servers without one get 1.
+    pub upstream_weights: Vec<u32>,
+    /// Relay queries to `upstream` and responses back byte for byte, changing only the id,
+    /// rather than answering them ourselves.
+    pub transparent: bool,
+    /// Domains whose queries go to their own servers rather than `upstream`.
+    pub stub_domains: Vec<StubDomain>,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    /// Where to answer HTTP health checks on `/healthz`, if anywhere.
+    pub health: Option<SocketAddr>,
+    /// A Unix socket to take `diydns control` commands on, if any.
+    pub control: Option<PathBuf>,
+    /// A file to log every query to, for `diydns history`.
+    pub history: Option<PathBuf>,
+    /// How many seconds of queries `history` keeps.
+    pub history_retention: u64,
+    /// Track each client's traffic and flag those past `analytics_thresholds`.
+    pub analytics: bool,
+    pub analytics_thresholds: Thresholds,
+    /// What to do with queries that look like DNS tunnel traffic, if we look for it.
+    pub tunnel_action: Option<TunnelAction>,
+    pub tunnel_limits: Limits,
+    /// Send search engines and YouTube to their safe-search hosts.
+    pub safe_search: bool,
+    /// Only for clients in these networks, or for everyone if there are none.
+    pub safe_search_clients: Vec<Network>,
+    /// And only for queries arriving on listeners with these labels, or on any if there
+    /// are none.
+    pub safe_search_listeners: Vec<String>,
+    /// Changes to responses for some listeners or clients, made just before they're sent.
+    pub shapes: Vec<Shape>,
+    /// Lists of names to answer NXDOMAIN for, checked in order.
+    pub blocklists: Vec<ListFile>,
+    /// Recurse on behalf of clients that ask for it. Without this we only hand out referrals.
+    pub recursion: bool,
+    /// Resolve the targets of MX and SRV answers into the additional section.
+    pub resolve_targets: bool,
+    /// Log each forged-looking or malformed datagram as it arrives.
+    pub log_suspicious: bool,
+    /// Log each query sent upstream and its outcome, numbered after the client query it's for.
+    pub log_upstream: bool,
+    /// Send upstream queries in a random mix of case and drop responses that don't echo it.
+    pub randomize_case: bool,
+    /// Ask upstreams for DNSSEC records, and pass them on to clients that set DO.
+    pub dnssec_passthrough: bool,
+    /// The EDNS payload size advertised to clients, and the most sent to one that asks with
+    /// EDNS. It has nothing to do with the size offered to upstreams.
+    pub edns_payload_size: u16,
+    /// Keep responses for their TTL and answer repeat questions from memory.
+    pub cache: bool,
+    /// Keep cached responses at least this many seconds, whatever their TTL.
+    pub cache_min_ttl: u32,
+    /// Keep SERVFAIL responses this many seconds, up to five minutes. Zero doesn't keep them.
+    pub cache_servfail_ttl: u32,
+    /// Point the system resolv.conf at `bind` while running, restoring it on exit.
+    pub rewrite_resolv_conf: bool,
+    /// A DHCP server's lease file, whose hosts are published under `local_domain`.
+    pub dhcp_leases: Option<PathBuf>,
+    pub dhcp_lease_format: LeaseFormat,
+    pub local_domain: String,
+    /// Ask the local link over LLMNR about single-label names DNS can't resolve.
+    pub llmnr: bool,
+    /// Then broadcast a NetBIOS name query for them to `netbios_broadcast`.
+    pub netbios: bool,
+    pub netbios_broadcast: SocketAddr,
+    /// Domains whose names we make up answers for rather than resolve.
+    pub synth: Vec<SyntheticDomain>,
+    /// Zones we answer for with authority, from their zone files.
+    pub zones: Vec<Zone>,
+    /// Refuse to serve a zone with anything to warn about, rather than logging it.
+    pub strict_zones: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: SocketAddr::from(([0, 0, 0, 0], 2053)),
+            listeners: Vec::new(),
+            threads: 1,
+            upstream: Vec::new(),
+            upstream_strategy: UpstreamStrategy::Ordered,
+            upstream_weights: Vec::new(),
+            transparent: false,
+            stub_domains: Vec::new(),
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+            health: None,
+            control: None,
+            history: None,
+            history_retention: 7 * 86400,
+            analytics: false,
+            analytics_thresholds: Thresholds::default(),
+            tunnel_action: None,
+            tunnel_limits: Limits::default(),
+            safe_search: false,
+            safe_search_clients: Vec::new(),
+            safe_search_listeners: Vec::new(),
+            shapes: Vec::new(),
+            blocklists: Vec::new(),
+            recursion: true,
+            resolve_targets: false,
+            log_suspicious: false,
+            log_upstream: false,
+            randomize_case: false,
+            dnssec_passthrough: false,
+            edns_payload_size: EDNS_PAYLOAD_SIZE,
+            cache: false,
+            cache_min_ttl: 0,
+            cache_servfail_ttl: 5,
+            rewrite_resolv_conf: false,
+            dhcp_leases: None,
+            dhcp_lease_format: LeaseFormat::Dnsmasq,
+            local_domain: "lan".to_string(),
+            llmnr: false,
+            netbios: false,
+            netbios_broadcast: SocketAddr::from(([255, 255, 255, 255], 137)),
+            synth: Vec::new(),
+            zones: Vec::new(),
+            strict_zones: false,
+        }
+    }
+}
+
+impl Config {
+    pub const KEYS: [&'static str; 49] = [
+        "bind",
+        "listen",
+        "threads",
+        "upstream",
+        "upstream_strategy",
+        "upstream_weights",
+        "transparent",
+        "stub_domain",
+        "kube_dns_config",
+        "resolv_conf",
+        "log_level",
+        "log_format",
+        "health",
+        "control",
+        "history",
+        "history_retention",
+        "analytics",
+        "analytics_qps",
+        "analytics_nxdomain_percent",
+        "analytics_subdomains",
+        "tunnel_action",
+        "tunnel_label_length",
+        "tunnel_txt_per_minute",
+        "tunnel_rate_limit",
+        "safe_search",
+        "safe_search_clients",
+        "safe_search_listeners",
+        "shape",
+        "blocklist",
+        "recursion",
+        "resolve_targets",
+        "log_suspicious",
+        "log_upstream",
+        "randomize_case",
+        "dnssec_passthrough",
+        "edns_payload_size",
+        "cache",
+        "cache_min_ttl",
+        "cache_servfail_ttl",
+        "rewrite_resolv_conf",
+        "dhcp_leases",
+        "dhcp_lease_format",
+        "local_domain",
+        "llmnr",
+        "netbios",
+        "netbios_broadcast",
+        "synth",
+        "zone",
+        "strict_zones",
+    ];
+
+    /// Sets one option from its textual form, as found in a file, variable or flag.
+    /// `listen`, `stub_domain`, `kube_dns_config`, `blocklist`, `synth` and `zone` add to
+    /// what's there rather than replacing it, and `kube_dns_config`, `resolv_conf` and
+    /// `zone` read their files straight away.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "bind" => self.bind = parse_addr(value, 2053)?,
+            "listen" if value.is_empty() => self.listeners.clear(),
+            "listen" => {
+                let listener = parse_listener(value)?;
+                if listener.label == DEFAULT_LISTENER
+                    || self.listeners.iter().any(|l| l.label == listener.label)
+                {
+                    return Err(invalid(format!(
+                        "listener label {:?} is taken",
+                        listener.label
+                    )));
+                }
+                self.listeners.push(listener);
+            }
+            "threads" => {
+                self.threads = match value.parse() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => {
+                        return Err(invalid(format!(
+                            "expected a thread count, found {:?}",
+                            value
+                        )))
+                    }
+                }
+            }
+            "upstream" => {
+                self.upstream = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|addr| !addr.is_empty())
+                    .map(|addr| parse_addr(addr, 53))
+                    .collect::<Result<_>>()?
+            }
+            "upstream_strategy" => self.upstream_strategy = value.parse()?,
+            "upstream_weights" => {
+                self.upstream_weights = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|weight| !weight.is_empty())
+                    .map(|weight| match parse_count(weight)? {
+                        0 => Err(invalid("upstream weights start at 1".to_string())),
+                        weight => Ok(weight),
+                    })
+                    .collect::<Result<_>>()?
+            }
+            "stub_domain" if value.is_empty() => self.stub_domains.clear(),
+            "stub_domain" => self.stub_domains.push(parse_stub_domain(value)?),
+            "kube_dns_config" => {
+                let kube = cluster::load_kube_dns_config(Path::new(value))?;
+                self.stub_domains.extend(kube.stub_domains);
+                if !kube.upstream_nameservers.is_empty() {
+                    self.upstream = kube.upstream_nameservers;
+                }
+            }
+            "resolv_conf" => {
+                let servers = cluster::load_resolv_conf(Path::new(value))?;
+                if servers.is_empty() {
+                    return Err(invalid(format!("no nameserver lines in {}", value)));
+                }
+                self.upstream = servers;
+            }
+            "log_level" => self.log_level = value.parse()?,
+            "log_format" => self.log_format = value.parse()?,
+            "health" if value.is_empty() => self.health = None,
+            "health" => self.health = Some(parse_addr(value, 8080)?),
+            "control" if value.is_empty() => self.control = None,
+            "control" => self.control = Some(PathBuf::from(value)),
+            "history" if value.is_empty() => self.history = None,
+            "history" => self.history = Some(PathBuf::from(value)),
+            "history_retention" => self.history_retention = history::parse_duration(value)?,
+            "analytics" => self.analytics = parse_bool(value)?,
+            "analytics_qps" => self.analytics_thresholds.qps = parse_count(value)?,
+            "analytics_nxdomain_percent" => {
+                self.analytics_thresholds.nxdomain_percent = match parse_count(value)? {
+                    percent if percent <= 100 => percent,
+                    _ => return Err(invalid(format!("expected a percentage, found {:?}", value))),
+                }
+            }
+            "analytics_subdomains" => {
+                self.analytics_thresholds.unique_subdomains = parse_count(value)?
+            }
+            "tunnel_action" if value == "off" => self.tunnel_action = None,
+            "tunnel_action" => self.tunnel_action = Some(value.parse()?),
+            "tunnel_label_length" => self.tunnel_limits.label_length = parse_count(value)?,
+            "tunnel_txt_per_minute" => self.tunnel_limits.txt_per_minute = parse_count(value)?,
+            "tunnel_rate_limit" => self.tunnel_limits.rate_limit = parse_count(value)?,
+            "safe_search" => self.safe_search = parse_bool(value)?,
+            "safe_search_clients" => {
+                self.safe_search_clients = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|net| !net.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_>>()?
+            }
+            "safe_search_listeners" => {
+                self.safe_search_listeners = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|label| !label.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "shape" if value.is_empty() => self.shapes.clear(),
+            "shape" => self.shapes.push(value.parse()?),
+            "blocklist" if value.is_empty() => self.blocklists.clear(),
+            "blocklist" => {
+                let list: ListFile = value.parse()?;
+                if self.blocklists.iter().any(|l| l.label == list.label) {
+                    return Err(invalid(format!(
+                        "blocklist label {:?} is taken",
+                        list.label
+                    )));
+                }
+                self.blocklists.push(list);
+            }
+            "transparent" => self.transparent = parse_bool(value)?,
+            "recursion" => self.recursion = parse_bool(value)?,
+            "resolve_targets" => self.resolve_targets = parse_bool(value)?,
+            "log_suspicious" => self.log_suspicious = parse_bool(value)?,
+            "log_upstream" => self.log_upstream = parse_bool(value)?,
+            "randomize_case" => self.randomize_case = parse_bool(value)?,
+            "dnssec_passthrough" => self.dnssec_passthrough = parse_bool(value)?,
+            "edns_payload_size" => {
+                self.edns_payload_size = match value.parse() {
+                    Ok(size)
+                        if (MAX_PACKET_SIZE..=MAX_EDNS_PACKET_SIZE)
+                            .contains(&usize::from(size)) =>
+                    {
+                        size
+                    }
+                    _ => {
+                        return Err(invalid(format!(
+                            "expected a payload size of {} to {} bytes, found {:?}",
+                            MAX_PACKET_SIZE, MAX_EDNS_PACKET_SIZE, value
+                        )))
+                    }
+                }
+            }
+            "cache" => self.cache = parse_bool(value)?,
+            "cache_min_ttl" => self.cache_min_ttl = ttl::parse(value)?,
+            "cache_servfail_ttl" => {
+                self.cache_servfail_ttl = match ttl::parse(value)? {
+                    ttl if ttl <= MAX_SERVFAIL_TTL => ttl,
+                    _ => {
+                        return Err(invalid(format!(
+                            "SERVFAIL can be cached for at most {}, found {:?}",
+                            ttl::humanize(MAX_SERVFAIL_TTL),
+                            value
+                        )))
+                    }
+                }
+            }
+            "rewrite_resolv_conf" => self.rewrite_resolv_conf = parse_bool(value)?,
+            "dhcp_leases" if value.is_empty() => self.dhcp_leases = None,
+            "dhcp_leases" => self.dhcp_leases = Some(PathBuf::from(value)),
+            "dhcp_lease_format" => self.dhcp_lease_format = value.parse()?,
+            "local_domain" => self.local_domain = value.trim_end_matches('.').to_lowercase(),
+            "llmnr" => self.llmnr = parse_bool(value)?,
+            "netbios" => self.netbios = parse_bool(value)?,
+            "netbios_broadcast" => self.netbios_broadcast = parse_addr(value, 137)?,
+            "synth" if value.is_empty() => self.synth.clear(),
+            "synth" => self.synth.push(value.parse()?),
+            "zone" if value.is_empty() => self.zones.clear(),
+            "zone" => self.add_zone(value, &HashMap::new())?,
+            "strict_zones" => self.strict_zones = parse_bool(value)?,
+            _ => return Err(unknown_option(key)),
+        }
+        Ok(())
+    }
+
+    /// Loads the zone in `value`, `<origin> <file>`, expanding `${NAME}` in the file from
+    /// `vars` or the environment.
+    fn add_zone(&mut self, value: &str, vars: &HashMap<String, String>) -> Result<()> {
+        let zone = match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [origin, path] => Zone::load_with(origin, Path::new(path), vars)?,
+            _ => {
+                return Err(invalid(format!(
+                    "expected a zone followed by its file, found {:?}",
+                    value
+                )))
+            }
+        };
+        if self.zones.iter().any(|z| z.origin == zone.origin) {
+            return Err(invalid(format!("zone {:?} is given twice", zone.origin)));
+        }
+        self.zones.push(zone);
+        Ok(())
+    }
+
+    /// Applies a config file of `key = value` lines. Blank lines and lines starting with
+    /// `#` are ignored. `$NAME = value` lines define variables, and `$NAME` or `${NAME}` in
+    /// a value is replaced by the variable defined earlier in the file or, failing that, the
+    /// environment variable; `$$` is a plain `$`. `$NAME@host = value` defines a variable
+    /// only on the machine called `host`. Zone files see the variables as `${NAME}`.
+    pub fn load_file(&mut self, path: &str) -> Result<()> {
+        match self.check_file(path)?.into_iter().next() {
+            Some(Diagnostic {
+                location: Some((line, _)),
+                message,
+            }) => Err(invalid(format!("{}:{}: {}", path, line, message))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a config file like [`Config::load_file`], but carries on past the lines
+    /// that are wrong, returning a diagnostic for each with its line and column.
+    pub fn check_file(&mut self, path: &str) -> Result<Vec<Diagnostic>> {
+        let contents = fs::read_to_string(path)?;
+        let mut vars = HashMap::new();
+        let mut diagnostics = Vec::new();
+
+        for (idx, raw) in contents.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Columns count characters from 1.
+            let column = |byte: usize| raw[..byte].chars().count() + 1;
+            let indent = raw.len() - raw.trim_start().len();
+            let (at, result) = match line.split_once('=') {
+                Some((key, value)) => {
+                    let (key, value) = (key.trim(), value.trim());
+                    let key_at = column(indent);
+                    let value_at = column(raw.trim_end().len() - value.len());
+                    match key.strip_prefix('$') {
+                        Some(var) if split_var(var).is_none() => (key_at, Err(bad_var(var))),
+                        Some(var) => (value_at, define(&mut vars, var, unquote(value))),
+                        None if !Config::KEYS.contains(&key) => (key_at, Err(unknown_option(key))),
+                        None => (
+                            value_at,
+                            expand(unquote(value), &vars).and_then(|value| match key {
+                                "zone" if !value.is_empty() => self.add_zone(&value, &vars),
+                                _ => self.set(key, &value),
+                            }),
+                        ),
+                    }
+                }
+                None => (
+                    column(indent),
+                    Err(invalid("expected key = value".to_string())),
+                ),
+            };
+
+            if let Err(e) = result {
+                diagnostics.push(Diagnostic {
+                    location: Some((idx + 1, at)),
+                    message: e.to_string(),
+                });
+            }
+        }
+
+        Ok(diagnostics)
+    }
+
+    /// Problems with the configuration as a whole, which no one setting shows: listeners
+    /// on the same address, forwarding that can never happen because recursion is off or
+    /// another answer comes first, zone warnings with `strict_zones` set, and files the
+    /// server won't be able to reach.
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut problems = Vec::new();
+
+        let listeners: Vec<(&str, SocketAddr)> = iter::once((DEFAULT_LISTENER, self.bind))
+            .chain(self.listeners.iter().map(|l| (l.label.as_str(), l.addr)))
+            .collect();
+        for (idx, (label, addr)) in listeners.iter().enumerate() {
+            for (other_label, other_addr) in &listeners[idx + 1..] {
+                if overlaps(*addr, *other_addr) {
+                    problems.push(format!(
+                        "listeners {} ({}) and {} ({}) overlap",
+                        label, addr, other_label, other_addr
+                    ));
+                }
+            }
+        }
+
+        if self.transparent && self.upstream.is_empty() {
+            problems.push("transparent mode needs an upstream to relay to".to_string());
+        }
+        if !self.recursion && !self.upstream.is_empty() {
+            problems.push("upstream is never forwarded to with recursion off".to_string());
+        }
+        for (idx, stub) in self.stub_domains.iter().enumerate() {
+            let domain = &stub.domain;
+            if self.stub_domains[..idx].iter().any(|s| s.domain == *domain) {
+                problems.push(format!("stub_domain {} is given twice", domain));
+            }
+            if !self.recursion {
+                problems.push(format!(
+                    "stub_domain {} is never forwarded to with recursion off",
+                    domain
+                ));
+            }
+            if let Some(zone) = zone::find(&self.zones, domain) {
+                problems.push(format!(
+                    "stub_domain {} is never forwarded to, zone {} answers for it",
+                    domain, zone.origin
+                ));
+            }
+            if let Some(synth) = synth::find(&self.synth, domain) {
+                problems.push(format!(
+                    "stub_domain {} is never forwarded to, synth {} answers for it",
+                    domain, synth.suffix
+                ));
+            }
+        }
+
+        if self.strict_zones {
+            for zone in &self.zones {
+                for warning in zone.warnings() {
+                    problems.push(format!("zone {}: {}", zone.origin, warning));
+                }
+            }
+        }
+
+        let mut read: Vec<(&str, &Path)> = Vec::new();
+        if let Some(ref path) = self.dhcp_leases {
+            read.push(("dhcp_leases", path));
+        }
+        for list in &self.blocklists {
+            read.push(("blocklist", &list.path));
+        }
+        for (key, path) in read {
+            if let Err(e) = fs::metadata(path) {
+                problems.push(format!("{} {}: {}", key, path.display(), e));
+            }
+        }
+
+        // The server creates these, in a directory that has to be there already.
+        let mut created: Vec<(&str, &Path)> = Vec::new();
+        if let Some(ref path) = self.history {
+            created.push(("history", path));
+        }
+        if let Some(ref path) = self.control {
+            created.push(("control", path));
+        }
+        for (key, path) in created {
+            match path.parent() {
+                Some(dir) if dir != Path::new("") && !dir.is_dir() => problems.push(format!(
+                    "{} {}: no directory {}",
+                    key,
+                    path.display(),
+                    dir.display()
+                )),
+                _ => {}
+            }
+        }
+
+        problems
+            .into_iter()
+            .map(|message| Diagnostic {
+                location: None,
+                message,
+            })
+            .collect()
+    }
+
+    /// Applies any `DIYDNS_*` environment variables.
+    pub fn load_env(&mut self) -> Result<()> {
+        for key in Config::KEYS.iter() {
+            let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Ok(value) = env::var(&var) {
+                self.set(key, &value)
+                    .map_err(|e| invalid(format!("{}: {}", var, e)))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// The error for a key we don't have, suggesting the one that was probably meant.
+fn unknown_option(key: &str) -> Error {
+    let closest = Config::KEYS
+        .iter()
+        .map(|known| (edit_distance(key, known), known))
+        .min();
+    match closest {
+        Some((distance, known)) if distance <= 2 => invalid(format!(
+            "unknown option {:?}, did you mean {:?}?",
+            key, known
+        )),
+        _ => invalid(format!("unknown option {:?}", key)),
+    }
+}
+
+/// How many characters have to be inserted, deleted or replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Whether sockets bound to `a` and `b` would take the same datagrams: the same port on
+/// the same address, or on the same family's wildcard address.
+fn overlaps(a: SocketAddr, b: SocketAddr) -> bool {
+    a.port() == b.port()
+        && a.is_ipv4() == b.is_ipv4()
+        && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The name and host in the `NAME` or `NAME@host` of a variable definition, or `None` if
+/// either is missing or the name isn't one.
+fn split_var(var: &str) -> Option<(&str, Option<&str>)> {
+    let (name, host) = match var.split_once('@') {
+        Some((name, host)) => (name, Some(host)),
+        None => (var, None),
+    };
+    (is_var_name(name) && host != Some("")).then_some((name, host))
+}
+
+fn bad_var(var: &str) -> Error {
+    invalid(format!("bad variable name {:?}", format!("${}", var)))
+}
+
+/// Defines the variable in a `$NAME = value` line, given `NAME`, or in a `$NAME@host =
+/// value` line if this machine is `host`.
+fn define(vars: &mut HashMap<String, String>, var: &str, value: &str) -> Result<()> {
+    let (name, host) = split_var(var).ok_or_else(|| bad_var(var))?;
+    if host.is_some_and(|host| !is_this_host(host)) {
+        return Ok(());
+    }
+
+    let value = expand(value, vars)?;
+    vars.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// Whether `host` names this machine, in full or by the first label of its host name.
+fn is_this_host(host: &str) -> bool {
+    hostname().is_some_and(|name| {
+        name.eq_ignore_ascii_case(host)
+            || name
+                .split('.')
+                .next()
+                .is_some_and(|short| short.eq_ignore_ascii_case(host))
+    })
+}
+
+fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8(buf[..len].to_vec()).ok();
+        }
+    }
+
+    env::var("COMPUTERNAME").ok()
+}
+
+/// Replaces the `$NAME` and `${NAME}` references in `value` with the variables in `vars`,
+/// or the environment variables of those names.
+fn expand(value: &str, vars: &HashMap<String, String>) -> Result<String> {
+    substitute(value, vars, true)
+}
+
+/// Like `expand`, for zone files, where only `${NAME}` is a reference: `$ORIGIN` and
+/// `$TTL` are directives there, and a `$` anywhere else is just a `$`.
+pub(crate) fn expand_braced(value: &str, vars: &HashMap<String, String>) -> Result<String> {
+    substitute(value, vars, false)
+}
+
+fn substitute(value: &str, vars: &HashMap<String, String>, bare: bool) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(dollar) = rest.find('$') {
+        out.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if !bare && !rest.starts_with('{') {
+            out.push('$');
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(invalid(format!("unclosed ${{ in {:?}", value))),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if !is_var_name(name) {
+            return Err(invalid(format!("bad variable reference in {:?}", value)));
+        }
+
+        match vars.get(name).cloned().or_else(|| env::var(name).ok()) {
+            Some(var) => out.push_str(&var),
+            None => return Err(invalid(format!("undefined variable ${}", name))),
+        }
+        rest = after;
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parses `label addr`.
+fn parse_listener(value: &str) -> Result<Listener> {
+    match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [label, addr] => Ok(Listener {
+            label: label.to_string(),
+            addr: parse_addr(addr, 2053)?,
+        }),
+        _ => Err(invalid(format!(
+            "expected a label followed by an address, found {:?}",
+            value
+        ))),
+    }
+}
+
+/// Parses `domain addr [addr...]`.
+fn parse_stub_domain(value: &str) -> Result<StubDomain> {
+    let mut fields = value.split_whitespace();
+    let domain = fields.next().unwrap_or("");
+    let servers = fields
+        .map(|addr| parse_addr(addr, 53))
+        .collect::<Result<Vec<_>>>()?;
+
+    if servers.is_empty() {
+        return Err(invalid(format!(
+            "expected a domain followed by servers, found {:?}",
+            value
+        )));
+    }
+
+    Ok(StubDomain {
+        domain: domain.trim_end_matches('.').to_lowercase(),
+        servers,
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_count<T: FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("expected a number, found {:?}", value)))
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(invalid(format!(
+            "expected true or false, found {:?}",
+            value
+        ))),
+    }
+}
+
+/// Parses `ip` or `ip:port`, using `default_port` when none is given. IPv6 link-local
+/// addresses need a zone index naming the interface, as in `fe80::1%eth0` or
+/// `[fe80::1%eth0]:53`.
+pub(crate) fn parse_addr(value: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    if let Some(addr) = parse_scoped_addr(value, default_port)? {
+        return Ok(addr);
+    }
+
+    let addr = match value.parse::<std::net::IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, default_port),
+        Err(_) => value
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| invalid(format!("invalid address {:?}", value)))?,
+    };
+
+    Ok(addr)
+}
+
+/// Parses an IPv6 address with a zone index, if `value` has one.
+fn parse_scoped_addr(value: &str, default_port: u16) -> Result<Option<SocketAddr>> {
+    let (host, port) = match value.strip_prefix('[') {
+        Some(rest) => match rest.split_once("]:") {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid port in {:?}", value)))?;
+                (host, port)
+            }
+            None => (rest.strip_suffix(']').unwrap_or(rest), default_port),
+        },
+        None => (value, default_port),
+    };
+
+    let (ip, zone) = match host.split_once('%') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let ip: Ipv6Addr = ip
+        .parse()
+        .map_err(|_| invalid(format!("invalid IPv6 address in {:?}", value)))?;
+
+    Ok(Some(SocketAddr::V6(SocketAddrV6::new(
+        ip,
+        port,
+        0,
+        scope_id(zone)?,
+    ))))
+}
+
+/// The zone index for an interface, given by name or number.
+fn scope_id(zone: &str) -> Result<u32> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Ok(name) = std::ffi::CString::new(zone) {
+            let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            if index != 0 {
+                return Ok(index);
+            }
+        }
+    }
+
+    Err(invalid(format!("unknown interface {:?}", zone)))
+}
@@ -0,0 +1,209 @@
+//! A Unix socket for asking a running server what it knows. Each connection sends one
+//! command line and gets the reply back as text:
+//!
+//! - `cache dump [<suffix>]` lists the cached responses for names at or under the suffix,
+//!   or all of them.
+//! - `cache lookup <name> [<type>]` shows what the cache would answer for the question
+//!   right now.
+//! - `clients top [<count>]` lists the busiest clients of the last minute, ten by default.
+//! - `clients flagged` lists the clients the analytics flagged in the last minute.
+//! - `stats [<count>]` gives the totals since startup, with the busiest names and clients,
+//!   ten of each by default. It's what `diydns top` polls.
+//! - `servers` lists what the resolver has learned about each server it has queried.
+
+use crate::analytics::{Analytics, ClientReport};
+use crate::cache::Cache;
+use crate::capability::Capabilities;
+use crate::traffic::Traffic;
+use crate::{DnsPacket, QueryType};
+use std::fmt::Write as _;
+
+#[cfg(unix)]
+use crate::{init, Result};
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use std::{fs, thread};
+
+/// How long the accept loop sleeps between checks for a connection or a shutdown.
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A client that hasn't sent its command by now is hung up on.
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the commands can look at. Each is there only if it's turned on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Context<'a> {
+    pub cache: Option<&'a Cache>,
+    pub analytics: Option<&'a Analytics>,
+    pub traffic: Option<&'a Traffic>,
+    pub capabilities: Option<&'a Capabilities>,
+}
+
+/// Listens at `path`, replacing a socket left behind by a previous run.
+#[cfg(unix)]
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        use std::os::unix::fs::FileTypeExt;
+        if meta.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Answers commands on `listener` until shutdown, then removes the socket at `path`.
+#[cfg(unix)]
+pub fn serve(listener: UnixListener, path: &Path, context: Context) {
+    while !init::shutdown_requested() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = answer(stream, context);
+            }
+            // Nobody waiting, or a client that gave up before we got to it.
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+
+    let _ = fs::remove_file(path);
+}
+
+#[cfg(unix)]
+fn answer(mut stream: UnixStream, context: Context) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut command = Vec::new();
+    let mut byte = [0];
+    while stream.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        command.push(byte[0]);
+    }
+
+    let reply = execute(context, &String::from_utf8_lossy(&command));
+    stream.write_all(reply.as_bytes())
+}
+
+/// Sends one command to the server listening at `path` and returns its reply.
+#[cfg(unix)]
+pub fn request(path: &Path, command: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// Runs one command and returns the reply, which ends with a newline.
+pub fn execute(context: Context, command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["cache", rest @ ..] => match context.cache {
+            Some(cache) => cache_command(cache, rest),
+            None => "error: the cache is off\n".to_string(),
+        },
+        ["clients", rest @ ..] => match context.analytics {
+            Some(analytics) => clients_command(analytics, rest),
+            None => "error: analytics are off\n".to_string(),
+        },
+        ["stats", rest @ ..] => match context.traffic {
+            Some(traffic) => stats_command(traffic, rest),
+            None => "error: stats are off\n".to_string(),
+        },
+        ["servers"] => match context.capabilities {
+            Some(capabilities) => capabilities
+                .snapshot()
+                .iter()
+                .map(|(server, capability)| format!("{} {}\n", server, capability))
+                .collect(),
+            None => "error: no resolver to ask\n".to_string(),
+        },
+        _ => format!("error: unknown command {:?}\n", command.trim()),
+    }
+}
+
+fn cache_command(cache: &Cache, args: &[&str]) -> String {
+    match args {
+        ["dump"] | ["dump", _] => {
+            let suffix = args.get(1).copied().unwrap_or("");
+            let mut reply = String::new();
+            for (name, qtype, packet) in cache.dump(suffix) {
+                write_entry(&mut reply, &name, qtype, &packet);
+            }
+            reply
+        }
+        ["lookup", name] | ["lookup", name, _] => {
+            let qtype = match args.get(2).map(|qtype| qtype.parse::<QueryType>()) {
+                Some(Ok(qtype)) => qtype,
+                Some(Err(e)) => return format!("error: {}\n", e),
+                None => QueryType::A,
+            };
+            let name = name.trim_end_matches('.').to_lowercase();
+
+            match cache.get(&name, qtype) {
+                Some(packet) => {
+                    let mut reply = String::new();
+                    write_entry(&mut reply, &name, qtype, &packet);
+                    reply
+                }
+                None => format!("; {} {} not cached\n", name, qtype),
+            }
+        }
+        _ => "error: expected cache dump [<suffix>] or cache lookup <name> [<type>]\n".to_string(),
+    }
+}
+
+fn clients_command(analytics: &Analytics, args: &[&str]) -> String {
+    let reports = match args {
+        ["top"] => analytics.top(10),
+        ["top", count] => match count.parse() {
+            Ok(count) => analytics.top(count),
+            Err(_) => return format!("error: expected a count, found {:?}\n", count),
+        },
+        ["flagged"] => analytics.flagged(),
+        _ => return "error: expected clients top [<count>] or clients flagged\n".to_string(),
+    };
+
+    reports
+        .iter()
+        .map(|report: &ClientReport| format!("{}\n", report))
+        .collect()
+}
+
+fn stats_command(traffic: &Traffic, args: &[&str]) -> String {
+    let count = match args {
+        [] => 10,
+        [count] => match count.parse() {
+            Ok(count) => count,
+            Err(_) => return format!("error: expected a count, found {:?}\n", count),
+        },
+        _ => return "error: expected stats [<count>]\n".to_string(),
+    };
+
+    traffic.snapshot(count).to_string()
+}
+
+/// A comment line naming the question and response code, then the response's records.
+fn write_entry(out: &mut String, name: &str, qtype: QueryType, packet: &DnsPacket) {
+    let _ = writeln!(out, "; {} {} {:?}", name, qtype, packet.header.rescode);
+    for rec in packet
+        .answers
+        .iter()
+        .chain(packet.authorities.iter())
+        .chain(packet.resources.iter())
+    {
+        let _ = writeln!(out, "{}", rec);
+    }
+}
@@ -0,0 +1,116 @@
+//! Deliberately broken queries, for seeing how other servers cope with them: labels and
+//! names too long to be legal, compression pointers that lead nowhere or in circles,
+//! header counts that don't match what follows, and messages cut off part way through.
+
+use crate::{DnsPacket, DnsQuestion, QueryType, Result, WritePolicy, CLASS_IN};
+
+/// Where the question starts, straight after the header.
+const QUESTION_OFFSET: usize = 12;
+
+/// The furthest a compression pointer can point.
+const MAX_POINTER: usize = 0x3FFF;
+
+/// One thing to get wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Defect {
+    /// Put a 64-character label in front of the name, one more than allowed.
+    LongLabel,
+    /// Put 63-character labels in front of the name until it's over 255 bytes.
+    LongName,
+    /// Replace the name with a compression pointer past the end of the message.
+    BadPointer,
+    /// Replace the name with a compression pointer to itself.
+    PointerLoop,
+    /// Claim this many questions in the header.
+    QuestionCount(u16),
+    AnswerCount(u16),
+    AuthorityCount(u16),
+    AdditionalCount(u16),
+    /// Cut the message off after this many bytes.
+    TruncateAt(usize),
+}
+
+/// A query for `qname` with `defects`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Craft {
+    pub id: u16,
+    pub qname: String,
+    pub qtype: QueryType,
+    pub defects: Vec<Defect>,
+}
+
+impl Craft {
+    pub fn new(qname: &str, qtype: QueryType) -> Craft {
+        Craft {
+            id: 0,
+            qname: qname.to_string(),
+            qtype,
+            defects: Vec::new(),
+        }
+    }
+
+    /// The query's bytes, defects and all.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let mut qname = self.qname.trim_end_matches('.').to_string();
+        if self.has(Defect::LongLabel) {
+            qname = format!("{}.{}", "a".repeat(64), qname);
+        }
+        if self.has(Defect::LongName) {
+            while wire_len(&qname) <= 255 {
+                qname = format!("{}.{}", "a".repeat(63), qname);
+            }
+        }
+
+        let mut packet = DnsPacket::default();
+        packet.header.id = self.id;
+        packet.header.recursion_desired = true;
+        packet.questions.push(DnsQuestion {
+            name: qname.clone(),
+            qtype: self.qtype,
+            qclass: CLASS_IN,
+        });
+        packet.header.questions = 1;
+        for defect in &self.defects {
+            match *defect {
+                Defect::QuestionCount(count) => packet.header.questions = count,
+                Defect::AnswerCount(count) => packet.header.answers = count,
+                Defect::AuthorityCount(count) => packet.header.authoritative_entries = count,
+                Defect::AdditionalCount(count) => packet.header.resource_entries = count,
+                _ => {}
+            }
+        }
+        let mut bytes = packet.to_bytes_with(WritePolicy::Verbatim)?;
+
+        let target = self.defects.iter().find_map(|defect| match defect {
+            Defect::BadPointer => Some(MAX_POINTER),
+            Defect::PointerLoop => Some(QUESTION_OFFSET),
+            _ => None,
+        });
+        if let Some(target) = target {
+            let pointer = [0xC0 | (target >> 8) as u8, target as u8];
+            let name = QUESTION_OFFSET..QUESTION_OFFSET + wire_len(&qname);
+            bytes.splice(name, pointer);
+        }
+
+        for defect in &self.defects {
+            if let Defect::TruncateAt(len) = *defect {
+                bytes.truncate(len);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn has(&self, defect: Defect) -> bool {
+        self.defects.contains(&defect)
+    }
+}
+
+/// How many bytes `name` takes up uncompressed.
+fn wire_len(name: &str) -> usize {
+    name.split('.')
+        .filter(|label| !label.is_empty())
+        .map(|label| label.len() + 1)
+        .sum::<usize>()
+        + 1
+}
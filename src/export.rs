@@ -0,0 +1,102 @@
+//! Writing out the answers of lookups for other tools: as a zone file fragment to paste
+//! into a zone, CSV for a spreadsheet, or JSON for scripts. Useful for taking an inventory
+//! of a set of names, or moving them from one provider to another.
+
+use crate::log::json_string;
+use crate::{DnsRecord, Result};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One record a line in zone-file format.
+    Zone,
+    /// A `name,type,ttl,data` header, then a row for each record.
+    Csv,
+    /// An array of objects with `name`, `type`, `ttl` and `data` fields.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "zone" => Ok(Format::Zone),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown output format {:?}, expected zone, csv or json", s),
+            )),
+        }
+    }
+}
+
+/// `records` written out in `format`, ending in a newline.
+pub fn export(format: Format, records: &[DnsRecord]) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Zone => {
+            for rec in records {
+                out.push_str(&format!("{}\n", rec));
+            }
+        }
+        Format::Csv => {
+            out.push_str("name,type,ttl,data\n");
+            for rec in records {
+                out.push_str(&format!(
+                    "{},{},{},{}\n",
+                    csv_field(name(rec)),
+                    rec.query_type(),
+                    rec.ttl(),
+                    csv_field(&data(rec))
+                ));
+            }
+        }
+        Format::Json => {
+            let objects: Vec<String> = records
+                .iter()
+                .map(|rec| {
+                    format!(
+                        "  {{\"name\":{},\"type\":{},\"ttl\":{},\"data\":{}}}",
+                        json_string(name(rec)),
+                        json_string(&rec.query_type().to_string()),
+                        rec.ttl(),
+                        json_string(&data(rec))
+                    )
+                })
+                .collect();
+            if objects.is_empty() {
+                out.push_str("[]\n");
+            } else {
+                out.push_str(&format!("[\n{}\n]\n", objects.join(",\n")));
+            }
+        }
+    }
+    out
+}
+
+fn name(rec: &DnsRecord) -> &str {
+    rec.domain().trim_end_matches('.')
+}
+
+/// The record's data as it's written in a zone file, such as `10 mail.example.com.` for an
+/// MX record.
+fn data(rec: &DnsRecord) -> String {
+    // Everything after the owner, TTL, class and type, none of which has a space in it.
+    rec.to_string()
+        .splitn(5, ' ')
+        .nth(4)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A field quoted as RFC 4180 has it, if it needs to be.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -0,0 +1,160 @@
+//! Asking the local link about single-label names DNS knows nothing of: LLMNR (RFC 4795),
+//! and optionally a NetBIOS name query broadcast, which is how Windows machines on a home
+//! network find each other.
+
+use crate::{DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, CLASS_IN, MAX_EDNS_PACKET_SIZE};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+const LLMNR_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 252), 5355);
+
+/// RFC 4795 suggests waiting a second for responders.
+const LLMNR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// NetBIOS nodes on the segment answer broadcasts quickly or not at all.
+const NETBIOS_TIMEOUT: Duration = Duration::from_millis(750);
+
+const NETBIOS_NB: u16 = 0x20;
+
+/// The answer a fallback found, with a TTL short enough that DNS gets another chance soon.
+const FALLBACK_TTL: u32 = 30;
+
+/// Whether `name` is a single label, the only kind of name the fallbacks are asked about.
+pub fn is_single_label(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    !name.is_empty() && !name.contains('.')
+}
+
+/// Multicasts an LLMNR query for `name`, returning the A or AAAA records from the first
+/// host to claim it.
+pub fn llmnr_lookup(name: &str, qtype: QueryType) -> Result<Vec<DnsRecord>> {
+    let mut packet = DnsPacket::default();
+    packet.header.id = rand::random();
+    packet.header.questions = 1;
+    packet.questions.push(DnsQuestion {
+        name: name.to_string(),
+        qtype,
+        qclass: CLASS_IN,
+    });
+    let id = packet.header.id;
+
+    let responses = exchange(packet, SocketAddr::V4(LLMNR_GROUP), false, LLMNR_TIMEOUT)?;
+    Ok(responses
+        .into_iter()
+        .filter(|response| response.header.id == id && response.header.response)
+        .map(|response| {
+            response
+                .answers
+                .into_iter()
+                .filter(|rec| rec.query_type() == qtype && rec.domain().eq_ignore_ascii_case(name))
+                .collect::<Vec<_>>()
+        })
+        .find(|answers| !answers.is_empty())
+        .unwrap_or_default())
+}
+
+/// Broadcasts a NetBIOS name query for `name` to `broadcast`, returning an A record for each
+/// address in the first positive response.
+pub fn netbios_lookup(name: &str, broadcast: SocketAddr) -> Result<Vec<DnsRecord>> {
+    let mut packet = DnsPacket::default();
+    packet.header.id = rand::random();
+    packet.header.recursion_desired = true;
+    // NetBIOS keeps its broadcast flag where DNS has CD.
+    packet.header.checking_disabled = true;
+    packet.header.questions = 1;
+    packet.questions.push(DnsQuestion {
+        name: netbios_encode(name),
+        qtype: QueryType::Unknown(NETBIOS_NB),
+        qclass: CLASS_IN,
+    });
+    let id = packet.header.id;
+
+    let responses = exchange(packet, broadcast, true, NETBIOS_TIMEOUT)?;
+    for response in responses {
+        if response.header.id != id || !response.header.response {
+            continue;
+        }
+
+        let mut records = Vec::new();
+        for rec in response.answers {
+            if let DnsRecord::Unknown {
+                qtype: NETBIOS_NB,
+                data,
+                ..
+            } = rec
+            {
+                // Each entry is two bytes of flags followed by an IPv4 address.
+                for entry in data.chunks_exact(6) {
+                    records.push(DnsRecord::A {
+                        domain: name.to_string(),
+                        addr: Ipv4Addr::new(entry[2], entry[3], entry[4], entry[5]),
+                        ttl: FALLBACK_TTL,
+                    });
+                }
+            }
+        }
+        if !records.is_empty() {
+            return Ok(records);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// The first-level encoding of RFC 1001: the name upper-cased and padded with spaces to 15
+/// bytes, plus a suffix byte of 0 for a workstation, each byte then split into two nibbles
+/// written as `'A' + nibble`.
+fn netbios_encode(name: &str) -> String {
+    let mut raw = [b' '; 16];
+    for (slot, b) in raw
+        .iter_mut()
+        .zip(name.to_ascii_uppercase().bytes().take(15))
+    {
+        *slot = b;
+    }
+    raw[15] = 0;
+
+    raw.iter()
+        .flat_map(|b| [b'A' + (b >> 4), b'A' + (b & 0x0F)])
+        .map(char::from)
+        .collect()
+}
+
+/// Sends `packet` to `dest` and gathers whatever parses as a response until `timeout`.
+fn exchange(
+    packet: DnsPacket,
+    dest: SocketAddr,
+    broadcast: bool,
+    timeout: Duration,
+) -> Result<Vec<DnsPacket>> {
+    let request = packet.to_bytes()?;
+
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_broadcast(broadcast)?;
+    socket.send_to(&request, dest)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut responses = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Ok(responses);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                if let Ok(response) = DnsPacket::from_bytes(&buf[..len]) {
+                    // One answer is all we need; a second responder just races the first.
+                    let answered = !response.answers.is_empty();
+                    responses.push(response);
+                    if answered {
+                        return Ok(responses);
+                    }
+                }
+            }
+            Err(_) => return Ok(responses),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! A log of the queries the server has answered, kept in a file so `diydns history` can
+//! search it afterwards: who asked what, when, what they got and how long it took.
+//!
+//! Each query is one tab-separated line of unix time, client address, name, type, response
+//! code and latency in microseconds, then for a blocked query the list, rule and line that
+//! blocked it. Appending a line per query is cheap, and lines older
+//! than the retention period are dropped when the file is pruned.
+
+use crate::blocklist::Match;
+use crate::{civil_from_days, ttl, QueryType, Result, ResultCode};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// One answered query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    /// Unix time the query arrived.
+    pub time: u64,
+    pub client: IpAddr,
+    pub name: String,
+    pub qtype: QueryType,
+    pub rescode: ResultCode,
+    pub latency: Duration,
+    /// The blocklist rule that answered it, if one did.
+    pub blocked: Option<Match>,
+}
+
+impl Query {
+    fn to_line(&self) -> String {
+        let blocked = match self.blocked {
+            Some(ref blocked) => format!("\t{}\t{}\t{}", blocked.list, blocked.rule, blocked.line),
+            None => String::new(),
+        };
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}{}\n",
+            self.time,
+            self.client,
+            // A tab or newline in the name would break the line up.
+            self.name.replace(|c: char| c.is_control(), "?"),
+            self.qtype.to_num(),
+            self.rescode.to_num(),
+            self.latency.as_micros(),
+            blocked
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Query> {
+        let mut fields = line.split('\t');
+        let mut query = Query {
+            time: fields.next()?.parse().ok()?,
+            client: fields.next()?.parse().ok()?,
+            name: fields.next()?.to_string(),
+            qtype: QueryType::from_num(fields.next()?.parse().ok()?),
+            rescode: match fields.next()?.parse().ok()? {
+                num @ (0..=5 | 16) => ResultCode::from_extended(num),
+                _ => return None,
+            },
+            latency: Duration::from_micros(fields.next()?.parse().ok()?),
+            blocked: None,
+        };
+
+        if let Some(list) = fields.next() {
+            query.blocked = Some(Match {
+                list: list.to_string(),
+                rule: fields.next()?.to_string(),
+                line: fields.next()?.parse().ok()?,
+            });
+        }
+
+        match fields.next() {
+            None => Some(query),
+            Some(_) => None,
+        }
+    }
+}
+
+/// `2024-01-31 12:00:00 10.0.0.5 example.com A NOERROR 1.2ms`, with the time in UTC, and
+/// for a blocked query `blocked by ads rule example.com (line 12)` after it.
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {:.1}ms",
+            format_time(self.time),
+            self.client,
+            self.name,
+            self.qtype,
+            self.rescode,
+            self.latency.as_secs_f64() * 1000.0
+        )?;
+        if let Some(ref blocked) = self.blocked {
+            write!(f, " blocked by {}", blocked)?;
+        }
+        Ok(())
+    }
+}
+
+/// A unix time as `2024-01-31 12:00:00`, in UTC.
+pub fn format_time(time: u64) -> String {
+    let secs = time % 86400;
+    let (year, month, day) = civil_from_days((time / 86400) as i64);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The history file the server appends to.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    /// Queries older than this many seconds are dropped on pruning.
+    retention: u64,
+    file: Mutex<File>,
+}
+
+impl History {
+    /// Opens the file at `path` for appending, creating it if needed, after dropping
+    /// whatever has passed `retention` seconds.
+    pub fn open(path: &Path, retention: u64) -> Result<History> {
+        let history = History {
+            path: path.to_path_buf(),
+            retention,
+            file: Mutex::new(append(path)?),
+        };
+        history.prune()?;
+
+        Ok(history)
+    }
+
+    pub fn record(&self, query: &Query) -> Result<()> {
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(query.to_line().as_bytes())
+    }
+
+    /// Rewrites the file without the queries that have passed the retention period.
+    pub fn prune(&self) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+
+        let cutoff = unix_now().saturating_sub(self.retention);
+        let kept = read(&self.path)?
+            .into_iter()
+            .filter(|query| query.time >= cutoff)
+            .map(|query| query.to_line())
+            .collect::<String>();
+
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, kept)?;
+        fs::rename(&tmp, &self.path)?;
+        *file = append(&self.path)?;
+
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Which queries `search` returns. Every criterion that's set has to match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub client: Option<IpAddr>,
+    /// Only queries at or after this unix time.
+    pub since: Option<u64>,
+    /// Only queries for this name or names under it.
+    pub name: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, query: &Query) -> bool {
+        if self.client.is_some_and(|client| client != query.client) {
+            return false;
+        }
+        if self.since.is_some_and(|since| query.time < since) {
+            return false;
+        }
+        if let Some(ref suffix) = self.name {
+            let suffix = suffix.trim_matches('.').to_lowercase();
+            let name = query.name.to_lowercase();
+            if name != suffix && !name.ends_with(&format!(".{}", suffix)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// The queries in the history file at `path` that match `filter`, oldest first.
+pub fn search(path: &Path, filter: &Filter) -> Result<Vec<Query>> {
+    Ok(read(path)?
+        .into_iter()
+        .filter(|query| filter.matches(query))
+        .collect())
+}
+
+/// Every query in the file. Lines that can't be read, such as one cut short by a crash,
+/// are skipped.
+fn read(path: &Path) -> Result<Vec<Query>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut queries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Some(query) = Query::from_line(&line?) {
+            queries.push(query);
+        }
+    }
+
+    Ok(queries)
+}
+
+/// Parses a period such as `90`, `30m`, `12h` or `7d` into seconds.
+pub fn parse_duration(value: &str) -> Result<u64> {
+    ttl::parse(value).map(u64::from)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
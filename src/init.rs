@@ -0,0 +1,47 @@
+//! The duties that fall on PID 1, for running as the only process in a container.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Installs handlers for SIGTERM and SIGINT that ask the server to stop. PID 1 gets no
+/// default handling for these, so without this `docker stop` waits out its timeout and
+/// then kills us.
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    extern "C" fn request_shutdown(_: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    let handler = request_shutdown as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Collects any exited children. Orphaned processes in the container are re-parented to
+/// PID 1, and stay around as zombies until it waits on them.
+#[cfg(unix)]
+pub fn reap_children() {
+    if std::process::id() != 1 {
+        return;
+    }
+
+    loop {
+        let pid = unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) };
+        if pid <= 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn reap_children() {}
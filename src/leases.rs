@@ -0,0 +1,278 @@
+//! Publishing names for DHCP leases, read from the lease file of dnsmasq, ISC dhcpd or Kea.
+
+use crate::config::LogLevel;
+use crate::local::LocalRecords;
+use crate::ptr::reverse_name;
+use crate::{days_from_civil, log, DnsRecord, Result};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the lease file is re-read. Leases also lapse between changes to the file, so
+/// we re-read on a timer rather than waiting for it to be written.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lease records change whenever a device comes or goes, so they're handed out with a
+/// short TTL.
+const LEASE_TTL: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseFormat {
+    /// `dnsmasq.leases`: `<expiry> <mac> <ip> <hostname> <client id>` per line.
+    Dnsmasq,
+    /// ISC dhcpd's `dhcpd.leases`: `lease <ip> { ... }` blocks, the last for an address
+    /// winning.
+    Isc,
+    /// Kea's memfile CSV, with a header row naming the columns.
+    Kea,
+}
+
+impl FromStr for LeaseFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LeaseFormat> {
+        match s {
+            "dnsmasq" => Ok(LeaseFormat::Dnsmasq),
+            "isc" => Ok(LeaseFormat::Isc),
+            "kea" => Ok(LeaseFormat::Kea),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown lease file format {:?}, expected dnsmasq, isc or kea",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub addr: IpAddr,
+    pub hostname: String,
+    /// Seconds since the epoch, or `None` for a lease that never expires.
+    pub expires: Option<u64>,
+}
+
+impl Lease {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+/// The leases in a lease file that name their host. Lines or blocks that can't be parsed
+/// are skipped.
+pub fn parse_leases(format: LeaseFormat, contents: &str) -> Vec<Lease> {
+    let leases = match format {
+        LeaseFormat::Dnsmasq => parse_dnsmasq(contents),
+        LeaseFormat::Isc => parse_isc(contents),
+        LeaseFormat::Kea => parse_kea(contents),
+    };
+
+    leases
+        .into_iter()
+        .filter(|lease| is_valid_label(&lease.hostname))
+        .collect()
+}
+
+fn parse_dnsmasq(contents: &str) -> Vec<Lease> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 || fields[0] == "duid" {
+                return None;
+            }
+
+            let expires = fields[0].parse::<u64>().ok()?;
+            Some(Lease {
+                addr: fields[2].parse().ok()?,
+                hostname: fields[3].to_string(),
+                // dnsmasq writes 0 for infinite leases.
+                expires: if expires == 0 { None } else { Some(expires) },
+            })
+        })
+        .collect()
+}
+
+fn parse_isc(contents: &str) -> Vec<Lease> {
+    let mut leases: Vec<Lease> = Vec::new();
+    let mut rest = contents;
+
+    while let Some(start) = rest.find("lease ") {
+        rest = &rest[start + "lease ".len()..];
+        let (header, body) = match rest.split_once('{') {
+            Some(parts) => parts,
+            None => break,
+        };
+        let (body, after) = body.split_once('}').unwrap_or((body, ""));
+        rest = after;
+
+        let addr = match header.trim().parse() {
+            Ok(addr) => addr,
+            Err(_) => continue,
+        };
+
+        let mut hostname = None;
+        let mut expires = None;
+        let mut active = true;
+        for statement in body.split(';') {
+            let words: Vec<&str> = statement.split_whitespace().collect();
+            match words.as_slice() {
+                ["client-hostname", name] => hostname = Some(name.trim_matches('"').to_string()),
+                ["ends", "never"] => expires = None,
+                ["ends", "epoch", secs, ..] => expires = secs.parse().ok(),
+                ["ends", _weekday, date, time] => expires = parse_isc_time(date, time),
+                ["binding", "state", state] => active = *state == "active",
+                _ => {}
+            }
+        }
+
+        // A later block for the same address supersedes an earlier one.
+        leases.retain(|lease| lease.addr != addr);
+        if let (Some(hostname), true) = (hostname, active) {
+            leases.push(Lease {
+                addr,
+                hostname,
+                expires,
+            });
+        }
+    }
+
+    leases
+}
+
+/// Parses dhcpd's `YYYY/MM/DD HH:MM:SS`, which is in UTC.
+fn parse_isc_time(date: &str, time: &str) -> Option<u64> {
+    let date: Vec<i64> = date
+        .split('/')
+        .map(str::parse)
+        .collect::<std::result::Result<_, _>>()
+        .ok()?;
+    let time: Vec<u64> = time
+        .split(':')
+        .map(str::parse)
+        .collect::<std::result::Result<_, _>>()
+        .ok()?;
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+
+    let days = days_from_civil(date[0], date[1], date[2]);
+    Some(days as u64 * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+fn parse_kea(contents: &str) -> Vec<Lease> {
+    let mut lines = contents.lines();
+    let header: Vec<&str> = match lines.next() {
+        Some(header) => header.split(',').collect(),
+        None => return Vec::new(),
+    };
+    let column = |name: &str| header.iter().position(|col| *col == name);
+    let (address, expire, hostname, state) = match (
+        column("address"),
+        column("expire"),
+        column("hostname"),
+        column("state"),
+    ) {
+        (Some(a), Some(e), Some(h), s) => (a, e, h, s),
+        _ => return Vec::new(),
+    };
+
+    lines
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            // State 0 is an assigned lease; 1 and 2 are declined and expired-reclaimed.
+            if state.is_some_and(|s| fields.get(s) != Some(&"0")) {
+                return None;
+            }
+
+            Some(Lease {
+                addr: fields.get(address)?.parse().ok()?,
+                // Kea may record a fully qualified name; we publish its first label.
+                hostname: fields.get(hostname)?.split('.').next()?.to_string(),
+                expires: fields.get(expire)?.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// Clients choose their own host names, so only a plain single label is published. This
+/// keeps out `*` (dnsmasq's "no name") and names that would land outside our domain.
+fn is_valid_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !name.starts_with('-')
+}
+
+/// A/AAAA records for each active lease under `domain`, and PTR records pointing back.
+pub fn lease_records(leases: &[Lease], domain: &str, now: u64) -> Vec<DnsRecord> {
+    let mut records = Vec::new();
+
+    for lease in leases.iter().filter(|lease| lease.is_active(now)) {
+        let name = format!("{}.{}", lease.hostname.to_lowercase(), domain);
+        records.push(match lease.addr {
+            IpAddr::V4(addr) => DnsRecord::A {
+                domain: name.clone(),
+                addr,
+                ttl: LEASE_TTL,
+            },
+            IpAddr::V6(addr) => DnsRecord::AAAA {
+                domain: name.clone(),
+                addr,
+                ttl: LEASE_TTL,
+            },
+        });
+        records.push(DnsRecord::PTR {
+            domain: reverse_name(&lease.addr),
+            host: name,
+            ttl: LEASE_TTL,
+        });
+    }
+
+    records
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Keeps `local` in step with the lease file at `path` from a background thread.
+pub fn watch(path: PathBuf, format: LeaseFormat, domain: String, local: Arc<LocalRecords>) {
+    thread::spawn(move || {
+        let mut last_count = None;
+
+        loop {
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let records =
+                        lease_records(&parse_leases(format, &contents), &domain, unix_now());
+                    let count = records.len() / 2;
+                    if last_count != Some(count) {
+                        log::write(
+                            LogLevel::Info,
+                            &format!("Publishing {} DHCP leases under {}", count, domain),
+                        );
+                        last_count = Some(count);
+                    }
+                    local.replace(records);
+                }
+                Err(e) => log::write(
+                    LogLevel::Error,
+                    &format!("Failed to read leases from {}: {}", path.display(), e),
+                ),
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
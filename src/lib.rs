@@ -1,37 +1,242 @@
+//! The packet codec is always built. The `resolver` feature adds sending queries and the
+//! checks built on them, and `server` everything `diydns serve` needs; both are on by
+//! default.
+
+#[cfg(all(unix, feature = "server"))]
+extern crate libc;
+#[cfg(feature = "resolver")]
 extern crate rand;
+
+#[cfg(feature = "resolver")]
+pub mod audit;
+#[cfg(feature = "resolver")]
+pub mod capability;
+#[cfg(feature = "resolver")]
+pub mod export;
+#[cfg(feature = "resolver")]
+pub mod lint;
+#[cfg(feature = "resolver")]
+pub mod log;
+#[cfg(feature = "resolver")]
+pub mod mail;
+#[cfg(feature = "resolver")]
+pub mod propagation;
+#[cfg(feature = "resolver")]
+pub mod ptr;
+#[cfg(feature = "resolver")]
+pub mod resolver;
+#[cfg(feature = "resolver")]
+pub mod scheduler;
+#[cfg(feature = "resolver")]
+pub mod stats;
+
+#[cfg(feature = "server")]
+pub mod analytics;
+#[cfg(feature = "server")]
+pub mod blocklist;
+#[cfg(feature = "server")]
+pub mod cache;
+pub mod clock;
+#[cfg(feature = "server")]
+pub mod cluster;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod control;
+pub mod craft;
+#[cfg(feature = "server")]
+pub mod fallback;
+#[cfg(feature = "server")]
+pub mod history;
+#[cfg(feature = "server")]
+pub mod init;
+#[cfg(feature = "server")]
+pub mod leases;
+#[cfg(feature = "server")]
+pub mod local;
+#[cfg(feature = "server")]
+pub mod loopcheck;
+#[cfg(feature = "server")]
+pub mod passive;
+pub mod rdata;
+pub mod replay;
+#[cfg(feature = "server")]
+pub mod safesearch;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "server")]
+pub mod shaping;
+#[cfg(feature = "server")]
+pub mod stub;
+#[cfg(feature = "server")]
+pub mod synth;
+#[cfg(feature = "server")]
+pub mod traffic;
+pub mod ttl;
+#[cfg(feature = "server")]
+pub mod tunnel;
+#[cfg(feature = "server")]
+pub mod upstream;
+#[cfg(feature = "resolver")]
+pub mod walk;
+#[cfg(feature = "server")]
+pub mod zone;
+
+use std::collections::HashMap;
 use std::default::Default;
+use std::fmt;
 use std::fs::File;
-use std::io::{Error, ErrorKind, Read};
-use std::iter;
+use std::io::{Error, ErrorKind, Read, Write};
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The most a packet can hold without EDNS.
+pub const MAX_PACKET_SIZE: usize = 512;
+
+/// The largest packet we read or write, and so the size of our receive buffers. Servers
+/// may send more than the payload size we advertise, and this is where most cap it.
+pub const MAX_EDNS_PACKET_SIZE: usize = 4096;
+
+/// The UDP payload size we advertise with EDNS: what fits in a packet on the smallest MTU
+/// IPv6 allows, so that responses aren't fragmented on the way.
+pub const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// The Internet class, which is all anyone uses outside of a few CHAOS-class queries.
+pub const CLASS_IN: u16 = 1;
+
+/// The longest a label can be, and a name in wire format, labels and lengths together.
+const MAX_LABEL: usize = 63;
+const MAX_NAME: usize = 255;
+
+/// A compression pointer holds an offset of 14 bits, so names written past this can't be
+/// pointed back to.
+const MAX_POINTER: usize = 0x3FFF;
+
+/// The highest TTL allowed. RFC 2181 reserves the top bit.
+const MAX_TTL: u32 = i32::MAX as u32;
+
+/// The DO bit of an OPT record's flags, asking for DNSSEC records.
+pub const EDNS_DO: u16 = 0x8000;
+
+/// What encoding does with values the protocol doesn't allow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Refuse to encode them.
+    Strict,
+    /// Fix what can be fixed: TTLs are cut down to the highest allowed, the header's counts
+    /// are taken from the sections, and unknown records' data lengths from their data.
+    /// Names too long to encode are still an error.
+    #[default]
+    Lenient,
+    /// Write everything as given, for tests that need broken packets: counts, TTLs, data
+    /// lengths, and labels and names of any length a byte can state. Names are written out
+    /// in full rather than compressed.
+    Verbatim,
+}
+
+/// How much of a packet decoding reads. Proxying, rate limiting and logging often need no
+/// more than the question, and needn't pay for every record of a large response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseLevel {
+    /// The header alone.
+    Header,
+    /// The header and the questions.
+    Questions,
+    /// Everything.
+    #[default]
+    Full,
+}
 
-const MAX_BUFFER_SIZE: usize = 512;
+fn out_of_spec(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
 
+/// The cursor the codec reads and writes packets through. Most callers want
+/// [`DnsPacket::from_bytes`] and [`DnsPacket::to_bytes`] instead.
 pub struct BytePacketBuffer {
-    pub buf: [u8; MAX_BUFFER_SIZE],
+    pub buf: [u8; MAX_EDNS_PACKET_SIZE],
     pub pos: usize,
+    /// How much of `buf` holds the packet, or may when writing. Reading past it is an
+    /// error rather than a run of zeros.
+    len: usize,
+    policy: WritePolicy,
+    /// Where each name written so far, and each name ending one, starts, for compression.
+    /// Keyed in lower case, since names that differ only in case are the same name: a name
+    /// written in one case points back to the same name written in another, and the case
+    /// first written is the one kept.
+    names: HashMap<String, usize>,
+    /// What reading has noticed that's odd but not wrong enough to fail on.
+    warnings: Vec<Warning>,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        BytePacketBuffer::new()
+    }
+}
+
 impl BytePacketBuffer {
     pub fn new() -> BytePacketBuffer {
         BytePacketBuffer {
             pos: 0,
-            buf: [0; MAX_BUFFER_SIZE],
+            buf: [0; MAX_EDNS_PACKET_SIZE],
+            len: MAX_EDNS_PACKET_SIZE,
+            policy: WritePolicy::default(),
+            names: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// An empty buffer that writes out-of-spec values as `policy` says.
+    pub fn with_policy(policy: WritePolicy) -> BytePacketBuffer {
+        BytePacketBuffer {
+            policy,
+            ..BytePacketBuffer::new()
+        }
+    }
+
+    /// An empty buffer for a packet of at most `size` bytes, up to `MAX_EDNS_PACKET_SIZE`.
+    pub fn with_size(size: usize) -> BytePacketBuffer {
+        BytePacketBuffer {
+            len: size.min(MAX_EDNS_PACKET_SIZE),
+            ..BytePacketBuffer::new()
+        }
+    }
+
+    /// A buffer holding a copy of `data`, which must fit in `MAX_EDNS_PACKET_SIZE`.
+    pub fn from_bytes(data: &[u8]) -> Result<BytePacketBuffer> {
+        if data.len() > MAX_EDNS_PACKET_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Packet of {} bytes exceeds {}",
+                    data.len(),
+                    MAX_EDNS_PACKET_SIZE
+                ),
+            ));
         }
+
+        let mut buffer = BytePacketBuffer::new();
+        buffer.buf[..data.len()].copy_from_slice(data);
+        buffer.len = data.len();
+
+        Ok(buffer)
     }
 
     pub fn from_file(filename: &str) -> Result<BytePacketBuffer> {
-        let mut file = File::open(filename).unwrap();
-        let mut buf = [0; MAX_BUFFER_SIZE];
-        file.read(&mut buf).unwrap();
+        let mut data = Vec::with_capacity(MAX_EDNS_PACKET_SIZE);
+        File::open(filename)?
+            .take(MAX_EDNS_PACKET_SIZE as u64)
+            .read_to_end(&mut data)?;
 
-        Ok(BytePacketBuffer { buf, pos: 0 })
+        BytePacketBuffer::from_bytes(&data)
     }
 
     fn is_in_range(&self, pos: usize) -> Result<()> {
-        if pos < MAX_BUFFER_SIZE {
+        if pos < self.len {
             Ok(())
         } else {
             Err(Error::new(
@@ -42,19 +247,24 @@ impl BytePacketBuffer {
     }
 
     fn get(&self, pos: usize) -> Result<u8> {
-        self.is_in_range(pos)?;
-        Ok(self.buf[pos])
+        Ok(self.get_range(pos, 1)?[0])
     }
 
-    pub fn get_range<'a>(&'a self, start: usize, len: usize) -> Result<&'a [u8]> {
-        self.is_in_range(start + len)?;
+    pub fn get_range(&self, start: usize, len: usize) -> Result<&[u8]> {
+        if start + len > self.len {
+            let kind = DnsErrorKind::Truncated {
+                needed: len,
+                found: self.len.saturating_sub(start),
+            };
+            return Err(DnsError::new(kind, start).into());
+        }
         Ok(&self.buf[start..start + len])
     }
 
     fn read(&mut self) -> Result<u8> {
-        self.is_in_range(self.pos)?;
+        let val = self.get(self.pos)?;
         self.pos += 1;
-        Ok(self.buf[self.pos - 1])
+        Ok(val)
     }
 
     fn read_u16(&mut self) -> Result<u16> {
@@ -65,11 +275,73 @@ impl BytePacketBuffer {
         Ok(((self.read_u16()? as u32) << 16) | (self.read_u16()? as u32))
     }
 
+    /// A string with its length in front, as in TXT and NAPTR records.
+    fn read_character_string(&mut self) -> Result<String> {
+        let len = self.read()? as usize;
+        let string = String::from_utf8_lossy(self.get_range(self.pos, len)?).into_owned();
+        self.pos += len;
+        Ok(string)
+    }
+
+    /// The bytes from here to `end`, the end of a record's data, for fields that take up
+    /// whatever's left of it.
+    fn read_to(&mut self, end: usize) -> Result<Vec<u8>> {
+        let len = end.saturating_sub(self.pos);
+        let data = self.get_range(self.pos, len)?.to_vec();
+        self.pos += len;
+        Ok(data)
+    }
+
+    /// A field of binary data led by its length in a byte, such as an NSEC3 record's salt.
+    fn read_counted(&mut self, qtype: QueryType, span: &RecordSpan) -> Result<Vec<u8>> {
+        let len = self.read()? as usize;
+        if self.pos + len > span.rdata.end {
+            let kind = DnsErrorKind::BadData {
+                qtype: qtype.to_num(),
+            };
+            return Err(DnsError::new(kind, span.rdata.start).into());
+        }
+        self.read_to(self.pos + len)
+    }
+
+    /// The record types in an NSEC or NSEC3 record's type bitmap, which runs from here to
+    /// `span`'s end: for each block of 256 types with any present, the block's number, the
+    /// length of its bitmap and the bitmap, a bit per type.
+    fn read_types(&mut self, qtype: QueryType, span: &RecordSpan) -> Result<Vec<u16>> {
+        let mut types = Vec::new();
+        let mut last_window = None;
+        while self.pos < span.rdata.end {
+            let window = self.read()?;
+            let len = self.read()? as usize;
+            // Blocks come in order, and each has between 1 and 32 bytes.
+            if last_window.is_some_and(|last| window <= last)
+                || !(1..=32).contains(&len)
+                || self.pos + len > span.rdata.end
+            {
+                let kind = DnsErrorKind::BadData {
+                    qtype: qtype.to_num(),
+                };
+                return Err(DnsError::new(kind, span.rdata.start).into());
+            }
+            last_window = Some(window);
+
+            for (idx, byte) in self.read_to(self.pos + len)?.into_iter().enumerate() {
+                for bit in (0..8).filter(|bit| byte & (0x80 >> bit) != 0) {
+                    types.push(u16::from(window) << 8 | (idx * 8 + bit) as u16);
+                }
+            }
+        }
+        Ok(types)
+    }
+
     fn read_qname(&mut self) -> Result<String> {
         let mut qname_pos = self.pos;
         let mut jumped = false;
         let mut first = true;
         let mut out = String::new();
+        // Pointers have to go back before the part of the name we're reading, so every
+        // jump goes further back than the last and the name can't loop.
+        let mut limit = self.pos;
 
         loop {
             let len = self.get(qname_pos)? as usize;
@@ -80,8 +352,17 @@ impl BytePacketBuffer {
                 }
 
                 let second_byte = self.get(qname_pos + 1)? as usize;
-                qname_pos = ((len ^ 0xC0) << 8) | second_byte;
+                let target = ((len ^ 0xC0) << 8) | second_byte;
+                if target >= limit {
+                    return Err(
+                        DnsError::new(DnsErrorKind::BadPointer { target }, qname_pos).into(),
+                    );
+                }
+                qname_pos = target;
+                limit = target;
                 jumped = true;
+            } else if len & 0xC0 != 0 {
+                return Err(DnsError::new(DnsErrorKind::BadLabelType(len as u8), qname_pos).into());
             } else {
                 qname_pos += 1;
 
@@ -92,11 +373,11 @@ impl BytePacketBuffer {
                 if first {
                     first = false;
                 } else {
-                    out.push_str(".");
+                    out.push('.');
                 }
 
                 let str_buffer = self.get_range(qname_pos, len)?;
-                out.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+                out.push_str(&String::from_utf8_lossy(str_buffer));
                 qname_pos += len;
             }
         }
@@ -105,7 +386,22 @@ impl BytePacketBuffer {
             self.pos = qname_pos;
         }
 
-        Ok(out)
+        let lower = out.to_lowercase();
+        if lower != out {
+            self.warn(Warning::NotCanonical { name: out });
+        }
+        Ok(lower)
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// The warnings from reading so far, leaving none behind.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
     }
 
     fn write(&mut self, val: u8) -> Result<()> {
@@ -129,17 +425,54 @@ impl BytePacketBuffer {
         Ok(())
     }
 
+    /// Writes a name, pointing back to where its longest ending was written before, if
+    /// anywhere.
     fn write_qname(&mut self, qname: &str) -> Result<()> {
-        for label in qname.split('.') {
+        self.write_name(qname, true)
+    }
+
+    /// Writes `qname` without pointing back to earlier names, for fields whose RFCs forbid
+    /// compressing them, such as an SRV record's target.
+    fn write_qname_uncompressed(&mut self, qname: &str) -> Result<()> {
+        self.write_name(qname, false)
+    }
+
+    fn write_name(&mut self, qname: &str, compress: bool) -> Result<()> {
+        let verbatim = self.policy == WritePolicy::Verbatim;
+        // Counting the root label at the end.
+        let mut name_len = 1;
+
+        // The root is the empty name, and a trailing dot doesn't add a label.
+        let labels: Vec<&str> = qname.split('.').filter(|label| !label.is_empty()).collect();
+        for label in &labels {
             let len = label.len();
-            if len > 0x34 {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Label exceeds 63 characters of length",
-                ));
+            if len > MAX_LABEL && !verbatim || len > u8::MAX as usize {
+                return Err(out_of_spec(format!(
+                    "Label {:?} exceeds {} characters of length",
+                    label, MAX_LABEL
+                )));
             }
+            name_len += len + 1;
+            if name_len > MAX_NAME && !verbatim {
+                return Err(out_of_spec(format!(
+                    "Name {:?} exceeds {} bytes",
+                    qname, MAX_NAME
+                )));
+            }
+        }
 
-            self.write(len as u8)?;
+        for (idx, label) in labels.iter().enumerate() {
+            if compress && !verbatim {
+                let rest = labels[idx..].join(".").to_ascii_lowercase();
+                if let Some(&target) = self.names.get(&rest) {
+                    return self.write_u16(0xC000 | target as u16);
+                }
+                if self.pos <= MAX_POINTER {
+                    self.names.insert(rest, self.pos);
+                }
+            }
+
+            self.write(label.len() as u8)?;
             for b in label.as_bytes() {
                 self.write(*b)?;
             }
@@ -148,6 +481,79 @@ impl BytePacketBuffer {
         self.write(0)
     }
 
+    /// A string of up to 255 bytes with its length in front, as in TXT and NAPTR records.
+    /// Writes `data` led by its length in a byte.
+    fn write_counted(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > u8::MAX as usize {
+            return Err(out_of_spec(format!(
+                "Field of {} bytes exceeds {}",
+                data.len(),
+                u8::MAX
+            )));
+        }
+        self.write(data.len() as u8)?;
+        for b in data {
+            self.write(*b)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `types` as an NSEC or NSEC3 record's type bitmap.
+    fn write_types(&mut self, types: &[u16]) -> Result<()> {
+        let mut types = types.to_vec();
+        types.sort_unstable();
+        types.dedup();
+
+        for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+            let mut bitmap = [0u8; 32];
+            for &qtype in window {
+                let low = (qtype & 0xFF) as usize;
+                bitmap[low / 8] |= 0x80 >> (low % 8);
+            }
+            let len = (window[window.len() - 1] & 0xFF) as usize / 8 + 1;
+
+            self.write((window[0] >> 8) as u8)?;
+            self.write(len as u8)?;
+            for b in &bitmap[..len] {
+                self.write(*b)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_character_string(&mut self, string: &str) -> Result<()> {
+        if string.len() > u8::MAX as usize {
+            return Err(out_of_spec(format!(
+                "String {:?} exceeds {} bytes",
+                string,
+                u8::MAX
+            )));
+        }
+        self.write(string.len() as u8)?;
+        for b in string.as_bytes() {
+            self.write(*b)?;
+        }
+        Ok(())
+    }
+
+    /// Moves back to `pos`, forgetting the names written after it so nothing points into
+    /// what gets written over them.
+    fn rewind(&mut self, pos: usize) {
+        self.pos = pos;
+        self.names.retain(|_, &mut start| start < pos);
+    }
+
+    fn write_ttl(&mut self, ttl: u32) -> Result<()> {
+        let ttl = match self.policy {
+            WritePolicy::Strict if ttl > MAX_TTL => {
+                return Err(out_of_spec(format!("TTL {} exceeds {}", ttl, MAX_TTL)))
+            }
+            WritePolicy::Lenient => ttl.min(MAX_TTL),
+            _ => ttl,
+        };
+        self.write_u32(ttl)
+    }
+
     fn set(&mut self, pos: usize, val: u8) -> Result<()> {
         self.is_in_range(pos)?;
         self.buf[pos] = val;
@@ -163,23 +569,22 @@ impl BytePacketBuffer {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum ResultCode {
+    #[default]
     Success,
     FormError,
     ServerFail,
     NonexistantDomain,
     NotImplemented,
     Refused,
-}
-
-impl Default for ResultCode {
-    fn default() -> Self {
-        ResultCode::Success
-    }
+    /// The OPT record's EDNS version isn't one the server speaks. An extended code, with
+    /// its upper bits in the OPT record.
+    BadVersion,
 }
 
 impl ResultCode {
+    /// The code for the four bits in the header, which is all there is without EDNS.
     pub fn from_num(num: u8) -> ResultCode {
         match num {
             1 => ResultCode::FormError,
@@ -188,11 +593,49 @@ impl ResultCode {
             4 => ResultCode::NotImplemented,
             5 => ResultCode::Refused,
             0 => ResultCode::Success,
-            _ => unreachable!(),
+            // The update and DSO codes, or unassigned ones. Whatever they mean, the server
+            // didn't answer the question.
+            _ => ResultCode::ServerFail,
+        }
+    }
+
+    /// The code for the full twelve bits, the OPT record's eight above the header's four.
+    pub fn from_extended(num: u16) -> ResultCode {
+        match num {
+            16 => ResultCode::BadVersion,
+            0..=0x0F => ResultCode::from_num(num as u8),
+            _ => ResultCode::ServerFail,
+        }
+    }
+
+    pub fn to_num(self) -> u16 {
+        match self {
+            ResultCode::Success => 0,
+            ResultCode::FormError => 1,
+            ResultCode::ServerFail => 2,
+            ResultCode::NonexistantDomain => 3,
+            ResultCode::NotImplemented => 4,
+            ResultCode::Refused => 5,
+            ResultCode::BadVersion => 16,
         }
     }
 }
 
+/// The response code's mnemonic, as dig shows it.
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            ResultCode::Success => "NOERROR",
+            ResultCode::FormError => "FORMERR",
+            ResultCode::ServerFail => "SERVFAIL",
+            ResultCode::NonexistantDomain => "NXDOMAIN",
+            ResultCode::NotImplemented => "NOTIMP",
+            ResultCode::Refused => "REFUSED",
+            ResultCode::BadVersion => "BADVERS",
+        })
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct DnsHeader {
     pub id: u16, // 16 bits
@@ -266,11 +709,11 @@ impl BytePacketBuffer {
                 | ((header.truncated_message as u8) << 1)
                 | ((header.authoritative_answer as u8) << 2)
                 | (header.opcode << 3)
-                | ((header.response as u8) << 7) as u8,
+                | ((header.response as u8) << 7),
         )?;
 
         self.write(
-            (header.rescode as u8)
+            (header.rescode.to_num() & 0x0F) as u8
                 | ((header.checking_disabled as u8) << 4)
                 | ((header.authed_data as u8) << 5)
                 | ((header.z as u8) << 6)
@@ -290,8 +733,23 @@ pub enum QueryType {
     A,
     NS,
     CNAME,
+    SOA,
+    PTR,
     MX,
+    TXT,
     AAAA,
+    SRV,
+    NAPTR,
+    OPT,
+    DS,
+    SSHFP,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    NSEC3PARAM,
+    TLSA,
+    CAA,
 }
 
 impl QueryType {
@@ -301,8 +759,23 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::SSHFP => 44,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::TLSA => 52,
+            QueryType::CAA => 257,
         }
     }
 
@@ -311,33 +784,131 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            44 => QueryType::SSHFP,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            52 => QueryType::TLSA,
+            257 => QueryType::CAA,
             _ => QueryType::Unknown(num),
         }
     }
 }
 
+/// The type's mnemonic, or `TYPE<number>` (RFC 3597) for types we don't know by name and
+/// have no codec registered for.
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryType::A => f.write_str("A"),
+            QueryType::NS => f.write_str("NS"),
+            QueryType::CNAME => f.write_str("CNAME"),
+            QueryType::SOA => f.write_str("SOA"),
+            QueryType::PTR => f.write_str("PTR"),
+            QueryType::MX => f.write_str("MX"),
+            QueryType::TXT => f.write_str("TXT"),
+            QueryType::AAAA => f.write_str("AAAA"),
+            QueryType::SRV => f.write_str("SRV"),
+            QueryType::NAPTR => f.write_str("NAPTR"),
+            QueryType::OPT => f.write_str("OPT"),
+            QueryType::DS => f.write_str("DS"),
+            QueryType::SSHFP => f.write_str("SSHFP"),
+            QueryType::RRSIG => f.write_str("RRSIG"),
+            QueryType::NSEC => f.write_str("NSEC"),
+            QueryType::DNSKEY => f.write_str("DNSKEY"),
+            QueryType::NSEC3 => f.write_str("NSEC3"),
+            QueryType::NSEC3PARAM => f.write_str("NSEC3PARAM"),
+            QueryType::TLSA => f.write_str("TLSA"),
+            QueryType::CAA => f.write_str("CAA"),
+            QueryType::Unknown(num) => match rdata::codec(num) {
+                Some(codec) => f.write_str(codec.mnemonic()),
+                None => write!(f, "TYPE{}", num),
+            },
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = Error;
+
+    /// Parses a mnemonic in any case, including those of registered codecs, or the
+    /// `TYPE<number>` form.
+    fn from_str(s: &str) -> Result<QueryType> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(num) = upper.strip_prefix("TYPE") {
+            if let Ok(num) = num.parse() {
+                return Ok(QueryType::from_num(num));
+            }
+        }
+
+        match upper.as_str() {
+            "A" => Ok(QueryType::A),
+            "NS" => Ok(QueryType::NS),
+            "CNAME" => Ok(QueryType::CNAME),
+            "SOA" => Ok(QueryType::SOA),
+            "PTR" => Ok(QueryType::PTR),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "AAAA" => Ok(QueryType::AAAA),
+            "SRV" => Ok(QueryType::SRV),
+            "NAPTR" => Ok(QueryType::NAPTR),
+            "OPT" => Ok(QueryType::OPT),
+            "DS" => Ok(QueryType::DS),
+            "SSHFP" => Ok(QueryType::SSHFP),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "NSEC" => Ok(QueryType::NSEC),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "NSEC3" => Ok(QueryType::NSEC3),
+            "NSEC3PARAM" => Ok(QueryType::NSEC3PARAM),
+            "TLSA" => Ok(QueryType::TLSA),
+            "CAA" => Ok(QueryType::CAA),
+            _ => match rdata::by_mnemonic(&upper) {
+                Some((num, _)) => Ok(QueryType::Unknown(num)),
+                None => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown record type {:?}", s),
+                )),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsQuestion {
     pub name: String,
     pub qtype: QueryType,
+    pub qclass: u16,
 }
 
 impl BytePacketBuffer {
     pub fn read_question(&mut self) -> Result<DnsQuestion> {
         let name = self.read_qname()?;
         let qtype = QueryType::from_num(self.read_u16()?);
-        self.read_u16()?; // class, which we ignore
+        let qclass = self.read_u16()?;
 
-        Ok(DnsQuestion { name, qtype })
+        Ok(DnsQuestion {
+            name,
+            qtype,
+            qclass,
+        })
     }
 
     pub fn write_question(&mut self, question: DnsQuestion) -> Result<()> {
         self.write_qname(&question.name)?;
 
         self.write_u16(question.qtype.to_num())?;
-        self.write_u16(1)
+        self.write_u16(question.qclass)
     }
 }
 
@@ -347,6 +918,7 @@ pub enum DnsRecord {
         domain: String,
         qtype: u16,
         data_len: u16,
+        data: Vec<u8>,
         ttl: u32,
     },
     A {
@@ -364,119 +936,1501 @@ pub enum DnsRecord {
         host: String,
         ttl: u32,
     },
+    SOA {
+        domain: String,
+        /// The zone's primary name server.
+        mname: String,
+        /// The mailbox of whoever looks after the zone, its `@` written as a dot.
+        rname: String,
+        serial: u32,
+        /// How often secondaries check the serial, how long they wait to try again after
+        /// failing to, and how long they keep serving the zone without reaching the primary.
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        /// How long a negative answer from the zone may be cached (RFC 2308).
+        minimum: u32,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
     MX {
         domain: String,
         priority: u16,
         host: String,
         ttl: u32,
     },
+    TXT {
+        domain: String,
+        data: Vec<String>,
+        ttl: u32,
+    },
     AAAA {
         domain: String,
         addr: Ipv6Addr,
         ttl: u32,
     },
+    /// Where to find a service, for names like `_sip._tcp.example.com` (RFC 2782). A
+    /// target of `.` says the service isn't offered.
+    SRV {
+        domain: String,
+        priority: u16,
+        /// Shares out the load between targets of the same priority.
+        weight: u16,
+        port: u16,
+        host: String,
+        ttl: u32,
+    },
+    /// A rewrite rule for ENUM and SIP (RFC 3403), as in
+    /// `100 10 "U" "E2U+sip" "!^.*$!sip:info@example.com!" .`.
+    NAPTR {
+        domain: String,
+        /// Rules are tried by order, then by preference among those of the same order.
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        /// The next name to look up, when there's no `regexp`.
+        replacement: String,
+        ttl: u32,
+    },
+    /// EDNS's pseudo-record (RFC 6891), which goes in the additional section and stretches
+    /// the header. It has no name of its own, and its class and TTL fields hold the fields
+    /// below instead.
+    OPT {
+        /// The largest UDP payload the sender can take.
+        udp_payload_size: u16,
+        /// The upper eight bits of the response code, above the four in the header.
+        extended_rcode: u8,
+        version: u8,
+        /// The DO bit, asking for DNSSEC records, and the bits after it, which are unused.
+        flags: u16,
+        /// Each option's code and data.
+        options: Vec<(u16, Vec<u8>)>,
+    },
+    /// A digest of a child zone's key, held by the parent (RFC 4034), as in
+    /// `60485 5 1 <hex>`. It vouches for the key, carrying trust down from the parent.
+    DS {
+        domain: String,
+        /// The key tag of the DNSKEY record it's a digest of.
+        key_tag: u16,
+        /// The key's algorithm.
+        algorithm: u8,
+        /// The hash the digest was taken with: 1 for SHA-1, 2 for SHA-256.
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    },
+    /// The fingerprint of an SSH host key of the name (RFC 4255), as in `4 2 <hex>`.
+    SSHFP {
+        domain: String,
+        /// The kind of key: RSA, DSA, ECDSA or Ed25519.
+        algorithm: u8,
+        /// The hash the fingerprint was taken with: SHA-1 or SHA-256.
+        fingerprint_type: u8,
+        fingerprint: Vec<u8>,
+        ttl: u32,
+    },
+    /// A signature over the records of one type at the name (RFC 4034), as in
+    /// `A 13 2 300 20240301000000 20240201000000 12345 example.com. <base64>`.
+    RRSIG {
+        domain: String,
+        /// The type of the records signed.
+        type_covered: u16,
+        algorithm: u8,
+        /// How many labels the name signed has, leaving out the root and a leading `*`.
+        /// Fewer than the owner has means the records were made from a wildcard.
+        labels: u8,
+        /// The records' TTL as signed, before any cache counted it down.
+        original_ttl: u32,
+        /// When the signature stops and starts being valid, in seconds since 1970.
+        expiration: u32,
+        inception: u32,
+        /// The key tag of the DNSKEY record to check the signature with.
+        key_tag: u16,
+        /// The zone holding that key.
+        signer: String,
+        signature: Vec<u8>,
+        ttl: u32,
+    },
+    /// Proof that nothing exists between the name and the next in the zone, and of which
+    /// types the name has (RFC 4034), as in `host.example.com. A MX RRSIG NSEC`.
+    NSEC {
+        domain: String,
+        /// The next name in the zone, in canonical order. The last name's is the apex.
+        next_domain: String,
+        /// The types of the records at the name, by number.
+        types: Vec<u16>,
+        ttl: u32,
+    },
+    /// A public key a zone is signed with (RFC 4034), as in `257 3 13 <base64>`.
+    DNSKEY {
+        domain: String,
+        /// Bit 7 marks a zone key, which all keys signing records are, and bit 15 a key
+        /// signing entry point, a key that signs the zone's other keys.
+        flags: u16,
+        /// Always 3.
+        protocol: u8,
+        /// The signing algorithm, such as 8 for RSA/SHA-256 or 13 for ECDSA P-256.
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    },
+    /// NSEC with the names hashed (RFC 5155), so the chain can't be walked to list the
+    /// zone, as in `1 0 10 AABB <base32hex> A RRSIG`. The owner's first label is a hash too.
+    NSEC3 {
+        domain: String,
+        /// The hash, which is always 1, for SHA-1.
+        hash_algorithm: u8,
+        /// Bit 0 is the opt-out flag: the span may hide unsigned delegations.
+        flags: u8,
+        /// How many extra times names are hashed.
+        iterations: u16,
+        /// Added to names before hashing them.
+        salt: Vec<u8>,
+        /// The hash of the next name in the zone, in hash order.
+        next_hashed_owner: Vec<u8>,
+        /// The types of the records at the name whose hash the owner is, by number.
+        types: Vec<u16>,
+        ttl: u32,
+    },
+    /// How the zone's NSEC3 records hash names (RFC 5155), held at the apex for its
+    /// servers, as in `1 0 10 AABB`.
+    NSEC3PARAM {
+        domain: String,
+        hash_algorithm: u8,
+        /// Always 0 here, the opt-out flag being for NSEC3 records.
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        ttl: u32,
+    },
+    /// The certificate a TLS server at the name should present, for DANE (RFC 6698), as in
+    /// `3 1 1 <hex>`.
+    TLSA {
+        domain: String,
+        /// Whether `data` is a CA or the server's own certificate, and whether it has to
+        /// chain to a trusted root.
+        usage: u8,
+        /// Whether `data` covers the whole certificate or only its public key.
+        selector: u8,
+        /// Whether `data` is the thing itself or its SHA-256 or SHA-512 hash.
+        matching_type: u8,
+        data: Vec<u8>,
+        ttl: u32,
+    },
+    /// Which certificate authorities may issue for the name (RFC 8659), as in
+    /// `0 issue "letsencrypt.org"`.
+    CAA {
+        domain: String,
+        /// Bit 7 is the issuer critical flag: a CA that doesn't understand the tag mustn't
+        /// issue.
+        flags: u8,
+        tag: String,
+        value: String,
+        ttl: u32,
+    },
 }
 
-impl BytePacketBuffer {
-    pub fn read_record(&mut self) -> Result<DnsRecord> {
-        let domain = self.read_qname()?;
+impl DnsRecord {
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::Unknown { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::NSEC3PARAM { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::CAA { domain, .. } => domain,
+            DnsRecord::OPT { .. } => "",
+        }
+    }
 
-        let qtype = QueryType::from_num(self.read_u16()?);
-        self.read_u16()?; // class, which we ignore
-        let ttl = self.read_u32()?;
-        let data_len = self.read_u16()?;
+    pub fn query_type(&self) -> QueryType {
+        match *self {
+            DnsRecord::Unknown { qtype, .. } => QueryType::from_num(qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::NAPTR { .. } => QueryType::NAPTR,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::SSHFP { .. } => QueryType::SSHFP,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+        }
+    }
 
-        Ok(match qtype {
-            QueryType::A => DnsRecord::A {
-                domain,
-                ttl,
-                addr: Ipv4Addr::from(self.read_u32()?),
-            },
-            QueryType::AAAA => DnsRecord::AAAA {
-                domain,
-                ttl,
-                addr: Ipv6Addr::new(
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                    self.read_u16()?,
-                ),
-            },
-            QueryType::NS => DnsRecord::NS {
-                domain,
-                ttl,
-                host: self.read_qname()?,
-            },
-            QueryType::CNAME => DnsRecord::CNAME {
-                domain,
-                ttl,
-                host: self.read_qname()?,
-            },
-            QueryType::MX => DnsRecord::MX {
-                domain: domain,
-                priority: self.read_u16()?,
-                host: self.read_qname()?,
-                ttl: ttl,
-            },
-            QueryType::Unknown(qtype) => {
-                self.pos += data_len as usize;
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            DnsRecord::Unknown { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => ttl,
+            // Its TTL field holds flags, not a time to live.
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
 
-                DnsRecord::Unknown {
-                    domain,
-                    qtype,
-                    data_len,
-                    ttl,
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::Unknown { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    /// The record's data in its shortest useful form: the address for A/AAAA records, the
+    /// target host for NS/CNAME/PTR/MX/SRV, the quoted strings of a TXT record, an OPT
+    /// record's EDNS fields the way `dig` shows them, and the fields of the other types we
+    /// know in zone-file order. Records of a type with a registered codec are shown as it
+    /// presents them, and other records we don't understand in the generic
+    /// `\# <length> <hex>` form of RFC 3597.
+    pub fn rdata(&self) -> Option<String> {
+        match self {
+            DnsRecord::A { addr, .. } => Some(addr.to_string()),
+            DnsRecord::AAAA { addr, .. } => Some(addr.to_string()),
+            DnsRecord::NS { host, .. }
+            | DnsRecord::CNAME { host, .. }
+            | DnsRecord::PTR { host, .. }
+            | DnsRecord::MX { host, .. }
+            | DnsRecord::SRV { host, .. } => Some(host.clone()),
+            DnsRecord::SOA { .. } => Some(self.soa_data("")),
+            DnsRecord::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => Some(format!(
+                "{} {} {} {} {} {}.",
+                order,
+                preference,
+                quote_txt(flags),
+                quote_txt(services),
+                quote_txt(regexp),
+                replacement.trim_end_matches('.')
+            )),
+            DnsRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+                ..
+            } => Some(format!(
+                "{} {} {} {} {} {} {} {}. {}",
+                QueryType::from_num(*type_covered),
+                algorithm,
+                labels,
+                original_ttl,
+                signature_time(*expiration),
+                signature_time(*inception),
+                key_tag,
+                signer.trim_end_matches('.'),
+                base64(signature)
+            )),
+            DnsRecord::NSEC {
+                next_domain, types, ..
+            } => Some(format!(
+                "{}.{}",
+                next_domain.trim_end_matches('.'),
+                type_list(types)
+            )),
+            DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ..
+            } => Some(format!(
+                "{} {} {} {}",
+                flags,
+                protocol,
+                algorithm,
+                base64(public_key)
+            )),
+            DnsRecord::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+                ..
+            } => Some(format!(
+                "{} {} {} {} {}{}",
+                hash_algorithm,
+                flags,
+                iterations,
+                nsec3_salt(salt),
+                base32hex(next_hashed_owner),
+                type_list(types)
+            )),
+            DnsRecord::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                ..
+            } => Some(format!(
+                "{} {} {} {}",
+                hash_algorithm,
+                flags,
+                iterations,
+                nsec3_salt(salt)
+            )),
+            DnsRecord::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data,
+                ..
+            } => Some(format!(
+                "{} {} {} {}",
+                usage,
+                selector,
+                matching_type,
+                hex(data)
+            )),
+            // The extended rcode is shown with the rest of the response code.
+            DnsRecord::OPT {
+                udp_payload_size,
+                version,
+                flags,
+                options,
+                ..
+            } => {
+                let mut data = format!(
+                    "version: {}, flags:{}; udp: {}",
+                    version,
+                    if flags & EDNS_DO != 0 { " do" } else { "" },
+                    udp_payload_size
+                );
+                for (code, option) in options {
+                    data.push_str(&format!("; option {}: {}", code, hex(option)));
                 }
+                Some(data)
             }
-        })
+            DnsRecord::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ..
+            } => Some(format!(
+                "{} {} {} {}",
+                key_tag,
+                algorithm,
+                digest_type,
+                hex(digest)
+            )),
+            DnsRecord::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+                ..
+            } => Some(format!(
+                "{} {} {}",
+                algorithm,
+                fingerprint_type,
+                hex(fingerprint)
+            )),
+            DnsRecord::CAA {
+                flags, tag, value, ..
+            } => Some(format!("{} {} {}", flags, tag, quote_txt(value))),
+            DnsRecord::TXT { data, .. } => Some(
+                data.iter()
+                    .map(|s| quote_txt(s))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            DnsRecord::Unknown { qtype, data, .. } => {
+                let presented = rdata::codec(*qtype).and_then(|codec| codec.present(data).ok());
+                Some(presented.unwrap_or_else(|| match data.len() {
+                    0 => "\\# 0".to_string(),
+                    len => format!(
+                        "\\# {} {}",
+                        len,
+                        data.iter()
+                            .map(|b| format!("{:02x}", b))
+                            .collect::<String>()
+                    ),
+                }))
+            }
+        }
     }
+}
 
-    pub fn write_record(&mut self, record: DnsRecord) -> Result<usize> {
-        let start_pos = self.pos;
+impl DnsRecord {
+    /// A DNSKEY record's key tag (RFC 4034, appendix B), the short number that RRSIG and DS
+    /// records identify the key by. Other records have none.
+    pub fn key_tag(&self) -> Option<u16> {
+        let (flags, protocol, algorithm, public_key) = match self {
+            DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ..
+            } => (*flags, *protocol, *algorithm, public_key),
+            _ => return None,
+        };
+
+        let mut rdata = flags.to_be_bytes().to_vec();
+        rdata.extend_from_slice(&[protocol, algorithm]);
+        rdata.extend_from_slice(public_key);
+        let mut sum: u32 = rdata
+            .iter()
+            .enumerate()
+            .map(|(idx, &b)| {
+                if idx % 2 == 0 {
+                    (b as u32) << 8
+                } else {
+                    b as u32
+                }
+            })
+            .sum();
+        sum += (sum >> 16) & 0xFFFF;
+        Some(sum as u16)
+    }
 
-        match record {
-            DnsRecord::A { domain, addr, ttl } => {
-                self.write_qname(&domain)?;
-                self.write_u16(QueryType::A.to_num())?;
-                self.write_u16(1)?;
-                self.write_u32(ttl)?;
-                self.write_u16(4)?;
+    /// An SOA record's fields in zone-file order, with `suffix` after each name.
+    fn soa_data(&self, suffix: &str) -> String {
+        match self {
+            DnsRecord::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => format!(
+                "{}{} {}{} {} {} {} {} {}",
+                mname.trim_end_matches('.'),
+                suffix,
+                rname.trim_end_matches('.'),
+                suffix,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            _ => String::new(),
+        }
+    }
+}
 
-                for octet in addr.octets().iter() {
-                    self.write(*octet)?;
-                }
+/// The record in zone-file presentation format: `example.com. 300 IN MX 10 mail.example.com.`
+/// An OPT record, which has no such format, is shown as a comment the way `dig` shows it.
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = match self {
+            DnsRecord::OPT { .. } => {
+                return write!(f, "; EDNS: {}", self.rdata().unwrap_or_default());
             }
-            DnsRecord::NS {
+            DnsRecord::NS { host, .. }
+            | DnsRecord::CNAME { host, .. }
+            | DnsRecord::PTR { host, .. } => format!("{}.", host.trim_end_matches('.')),
+            DnsRecord::MX { priority, host, .. } => {
+                format!("{} {}.", priority, host.trim_end_matches('.'))
+            }
+            DnsRecord::SOA { .. } => self.soa_data("."),
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                host,
+                ..
+            } => format!(
+                "{} {} {} {}.",
+                priority,
+                weight,
+                port,
+                host.trim_end_matches('.')
+            ),
+            _ => self.rdata().unwrap_or_default(),
+        };
+
+        write!(
+            f,
+            "{}. {} IN {} {}",
+            self.domain().trim_end_matches('.'),
+            self.ttl(),
+            self.query_type(),
+            data
+        )
+    }
+}
+
+/// A TXT string in presentation format: quoted, with quotes and backslashes escaped by a
+/// backslash and anything but printable ASCII written as `\DDD`, byte by byte.
+fn quote_txt(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for &byte in s.as_bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Binary record data in presentation format: upper-case hex, as `dig` shows it.
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// An NSEC or NSEC3 record's types in presentation format, each after a space.
+fn type_list(types: &[u16]) -> String {
+    types
+        .iter()
+        .map(|&qtype| format!(" {}", QueryType::from_num(qtype)))
+        .collect()
+}
+
+/// An NSEC3 or NSEC3PARAM record's salt in presentation format: hex, or `-` for none.
+fn nsec3_salt(salt: &[u8]) -> String {
+    match salt {
+        [] => "-".to_string(),
+        salt => hex(salt),
+    }
+}
+
+/// A hashed name in presentation format: base32 with the extended hex alphabet (RFC 4648),
+/// unpadded, so that hashes sort the same as the names they'd make.
+fn base32hex(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut bits, mut count) = (0u32, 0);
+    for &b in data {
+        bits = (bits << 8 | b as u32) & 0xFFFF;
+        count += 8;
+        while count >= 5 {
+            count -= 5;
+            out.push(ALPHABET[(bits >> count & 0x1F) as usize] as char);
+        }
+    }
+    if count > 0 {
+        out.push(ALPHABET[(bits << (5 - count) & 0x1F) as usize] as char);
+    }
+    out
+}
+
+/// An RRSIG record's expiration or inception in presentation format, `YYYYMMDDHHmmSS` in UTC.
+fn signature_time(time: u32) -> String {
+    let secs = time % 86400;
+    let (year, month, day) = civil_from_days((time / 86400) as i64);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+#[cfg(feature = "server")]
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`: the date `days` after 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Keys and signatures in presentation format: base64 (RFC 4648), padded.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (idx, &b)| bits | (b as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * idx) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The packet laid out the way `dig` does it: the header as comments, then each section
+/// with its records in presentation format, each TTL repeated in a comment in a form
+/// that's easier to read. MX records are listed by priority, most preferred first.
+impl fmt::Display for DnsPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        writeln!(
+            f,
+            ";; opcode: {}, status: {}, id: {}",
+            header.opcode, header.rescode, header.id
+        )?;
+
+        let flags: Vec<&str> = [
+            (header.response, "qr"),
+            (header.authoritative_answer, "aa"),
+            (header.truncated_message, "tc"),
+            (header.recursion_desired, "rd"),
+            (header.recursion_available, "ra"),
+            (header.authed_data, "ad"),
+            (header.checking_disabled, "cd"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|&(_, flag)| flag)
+        .collect();
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            header.questions,
+            header.answers,
+            header.authoritative_entries,
+            header.resource_entries
+        )?;
+
+        let (opts, additional): (Vec<&DnsRecord>, Vec<&DnsRecord>) = self
+            .resources
+            .iter()
+            .partition(|rec| matches!(rec, DnsRecord::OPT { .. }));
+        if !opts.is_empty() {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            for opt in opts {
+                writeln!(f, "{}", opt)?;
+            }
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                let class = match question.qclass {
+                    CLASS_IN => "IN".to_string(),
+                    class => format!("CLASS{}", class),
+                };
+                writeln!(
+                    f,
+                    ";{}. {} {}",
+                    question.name.trim_end_matches('.'),
+                    class,
+                    question.qtype
+                )?;
+            }
+        }
+
+        for (title, records) in [
+            ("ANSWER", self.answers.iter().collect()),
+            ("AUTHORITY", self.authorities.iter().collect()),
+            ("ADDITIONAL", additional),
+        ] {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", title)?;
+            for rec in ranked(&records) {
+                writeln!(f, "{} ; {}", rec, ttl::humanize(rec.ttl()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `records` with the MX records among them sorted by priority, each taking the place of
+/// one of the originals so the other records stay where they were.
+fn ranked<'a>(records: &[&'a DnsRecord]) -> Vec<&'a DnsRecord> {
+    let mut mx: Vec<&DnsRecord> = records
+        .iter()
+        .copied()
+        .filter(|rec| matches!(rec, DnsRecord::MX { .. }))
+        .collect();
+    mx.sort_by_key(|rec| match rec {
+        DnsRecord::MX { priority, .. } => *priority,
+        _ => 0,
+    });
+
+    let mut mx = mx.into_iter();
+    records
+        .iter()
+        .map(|&rec| match rec {
+            DnsRecord::MX { .. } => mx.next().unwrap_or(rec),
+            _ => rec,
+        })
+        .collect()
+}
+
+impl BytePacketBuffer {
+    pub fn read_record(&mut self) -> Result<DnsRecord> {
+        self.read_record_with_span().map(|(record, _)| record)
+    }
+
+    /// Reads a record, noting where it and its data sit in the buffer.
+    pub fn read_record_with_span(&mut self) -> Result<(DnsRecord, RecordSpan)> {
+        let start = self.pos;
+        let domain = self.read_qname()?;
+
+        let qtype = QueryType::from_num(self.read_u16()?);
+        // The class, which only OPT records make any use of.
+        let class = self.read_u16()?;
+        let ttl = self.read_u32()?;
+        let data_len = self.read_u16()?;
+
+        let rdata = self.pos..self.pos + data_len as usize;
+        let span = RecordSpan {
+            record: start..rdata.end,
+            rdata,
+        };
+
+        let record = match qtype {
+            QueryType::A => DnsRecord::A {
+                domain,
+                ttl,
+                addr: Ipv4Addr::from(self.read_u32()?),
+            },
+            QueryType::AAAA => DnsRecord::AAAA {
+                domain,
+                ttl,
+                addr: Ipv6Addr::new(
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                    self.read_u16()?,
+                ),
+            },
+            QueryType::NS => DnsRecord::NS {
+                domain,
+                ttl,
+                host: self.read_qname()?,
+            },
+            QueryType::CNAME => DnsRecord::CNAME {
+                domain,
+                ttl,
+                host: self.read_qname()?,
+            },
+            QueryType::SRV => DnsRecord::SRV {
+                domain,
+                ttl,
+                priority: self.read_u16()?,
+                weight: self.read_u16()?,
+                port: self.read_u16()?,
+                host: self.read_qname()?,
+            },
+            QueryType::SOA => DnsRecord::SOA {
+                domain,
+                ttl,
+                mname: self.read_qname()?,
+                rname: self.read_qname()?,
+                serial: self.read_u32()?,
+                refresh: self.read_u32()?,
+                retry: self.read_u32()?,
+                expire: self.read_u32()?,
+                minimum: self.read_u32()?,
+            },
+            QueryType::PTR => DnsRecord::PTR {
+                domain,
+                ttl,
+                host: self.read_qname()?,
+            },
+            QueryType::MX => DnsRecord::MX {
+                domain,
+                priority: self.read_u16()?,
+                host: self.read_qname()?,
+                ttl,
+            },
+            QueryType::NAPTR => DnsRecord::NAPTR {
+                domain,
+                ttl,
+                order: self.read_u16()?,
+                preference: self.read_u16()?,
+                flags: self.read_character_string()?,
+                services: self.read_character_string()?,
+                regexp: self.read_character_string()?,
+                replacement: self.read_qname()?,
+            },
+            QueryType::TLSA => {
+                let usage = self.read()?;
+                let selector = self.read()?;
+                let matching_type = self.read()?;
+
+                DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    data: self.read_to(span.rdata.end)?,
+                    ttl,
+                }
+            }
+            QueryType::OPT => {
+                let mut options = Vec::new();
+                while self.pos < span.rdata.end {
+                    let code = self.read_u16()?;
+                    let len = self.read_u16()? as usize;
+                    if self.pos + len > span.rdata.end {
+                        let kind = DnsErrorKind::BadData {
+                            qtype: QueryType::OPT.to_num(),
+                        };
+                        return Err(DnsError::new(kind, span.rdata.start).into());
+                    }
+                    options.push((code, self.read_to(self.pos + len)?));
+                }
+
+                DnsRecord::OPT {
+                    udp_payload_size: class,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    flags: ttl as u16,
+                    options,
+                }
+            }
+            QueryType::RRSIG => DnsRecord::RRSIG {
+                domain,
+                type_covered: self.read_u16()?,
+                algorithm: self.read()?,
+                labels: self.read()?,
+                original_ttl: self.read_u32()?,
+                expiration: self.read_u32()?,
+                inception: self.read_u32()?,
+                key_tag: self.read_u16()?,
+                signer: self.read_qname()?,
+                signature: self.read_to(span.rdata.end)?,
+                ttl,
+            },
+            QueryType::NSEC => DnsRecord::NSEC {
+                domain,
+                next_domain: self.read_qname()?,
+                types: self.read_types(QueryType::NSEC, &span)?,
+                ttl,
+            },
+            QueryType::NSEC3 => {
+                let hash_algorithm = self.read()?;
+                let flags = self.read()?;
+                let iterations = self.read_u16()?;
+                let salt = self.read_counted(QueryType::NSEC3, &span)?;
+                let next_hashed_owner = self.read_counted(QueryType::NSEC3, &span)?;
+
+                DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed_owner,
+                    types: self.read_types(QueryType::NSEC3, &span)?,
+                    ttl,
+                }
+            }
+            QueryType::NSEC3PARAM => DnsRecord::NSEC3PARAM {
+                domain,
+                hash_algorithm: self.read()?,
+                flags: self.read()?,
+                iterations: self.read_u16()?,
+                salt: self.read_counted(QueryType::NSEC3PARAM, &span)?,
+                ttl,
+            },
+            QueryType::DNSKEY => DnsRecord::DNSKEY {
+                domain,
+                flags: self.read_u16()?,
+                protocol: self.read()?,
+                algorithm: self.read()?,
+                public_key: self.read_to(span.rdata.end)?,
+                ttl,
+            },
+            QueryType::DS => DnsRecord::DS {
+                domain,
+                key_tag: self.read_u16()?,
+                algorithm: self.read()?,
+                digest_type: self.read()?,
+                digest: self.read_to(span.rdata.end)?,
+                ttl,
+            },
+            QueryType::SSHFP => {
+                let algorithm = self.read()?;
+                let fingerprint_type = self.read()?;
+
+                DnsRecord::SSHFP {
+                    domain,
+                    algorithm,
+                    fingerprint_type,
+                    fingerprint: self.read_to(span.rdata.end)?,
+                    ttl,
+                }
+            }
+            QueryType::CAA => {
+                let flags = self.read()?;
+                let tag_len = self.read()? as usize;
+                if tag_len == 0 || self.pos + tag_len > span.rdata.end {
+                    let kind = DnsErrorKind::BadData {
+                        qtype: QueryType::CAA.to_num(),
+                    };
+                    return Err(DnsError::new(kind, span.rdata.start).into());
+                }
+                let tag = String::from_utf8_lossy(self.get_range(self.pos, tag_len)?).into_owned();
+                self.pos += tag_len;
+                // The value runs to the end of the data.
+                let value_len = span.rdata.end - self.pos;
+                let value =
+                    String::from_utf8_lossy(self.get_range(self.pos, value_len)?).into_owned();
+                self.pos += value_len;
+
+                DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    ttl,
+                }
+            }
+            QueryType::TXT => {
+                let end = span.rdata.end;
+                let mut data = Vec::new();
+                while self.pos < end {
+                    let len = self.read()? as usize;
+                    let str_buffer = self.get_range(self.pos, len)?;
+                    data.push(String::from_utf8_lossy(str_buffer).into_owned());
+                    self.pos += len;
+                }
+
+                DnsRecord::TXT { domain, data, ttl }
+            }
+            QueryType::Unknown(qtype) => {
+                let data = self.get_range(self.pos, data_len as usize)?.to_vec();
+                if let Some(codec) = rdata::codec(qtype) {
+                    if codec.present(&data).is_err() {
+                        let kind = DnsErrorKind::BadData { qtype };
+                        return Err(DnsError::new(kind, span.rdata.start).into());
+                    }
+                }
+                self.pos += data_len as usize;
+
+                DnsRecord::Unknown {
+                    domain,
+                    qtype,
+                    data_len,
+                    data,
+                    ttl,
+                }
+            }
+        };
+
+        let found = self.pos - span.rdata.start;
+        if found != data_len as usize {
+            let kind = DnsErrorKind::DataLength {
+                expected: data_len as usize,
+                found,
+            };
+            return Err(DnsError::new(kind, span.rdata.start).into());
+        }
+
+        Ok((record, span))
+    }
+
+    pub fn write_record(&mut self, record: DnsRecord) -> Result<usize> {
+        let start_pos = self.pos;
+
+        match record {
+            DnsRecord::A { domain, addr, ttl } => {
+                self.write_qname(&domain)?;
+                self.write_u16(QueryType::A.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+                self.write_u16(4)?;
+
+                for octet in addr.octets().iter() {
+                    self.write(*octet)?;
+                }
+            }
+            DnsRecord::NS {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::NS.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_qname(host)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::CNAME {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::CNAME.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_qname(host)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NAPTR {
+                ref domain,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::NAPTR.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_u16(order)?;
+                self.write_u16(preference)?;
+                for string in [flags, services, regexp] {
+                    self.write_character_string(string)?;
+                }
+                // RFC 3403 forbids compressing the replacement.
+                self.write_qname_uncompressed(replacement)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::RRSIG {
+                ref domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::RRSIG.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_u16(type_covered)?;
+                self.write(algorithm)?;
+                self.write(labels)?;
+                self.write_u32(original_ttl)?;
+                self.write_u32(expiration)?;
+                self.write_u32(inception)?;
+                self.write_u16(key_tag)?;
+                // Signatures cover the signer's name in full, so it's never compressed.
+                self.write_qname_uncompressed(signer)?;
+                for b in signature {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC {
+                ref domain,
+                ref next_domain,
+                ref types,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::NSEC.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_qname_uncompressed(next_domain)?;
+                self.write_types(types)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DNSKEY {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::DNSKEY.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_u16(flags)?;
+                for b in [protocol, algorithm].iter().chain(public_key) {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC3 {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ref next_hashed_owner,
+                ref types,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::NSEC3.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write(hash_algorithm)?;
+                self.write(flags)?;
+                self.write_u16(iterations)?;
+                self.write_counted(salt)?;
+                self.write_counted(next_hashed_owner)?;
+                self.write_types(types)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC3PARAM {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::NSEC3PARAM.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write(hash_algorithm)?;
+                self.write(flags)?;
+                self.write_u16(iterations)?;
+                self.write_counted(salt)?;
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::TLSA {
+                ref domain,
+                usage,
+                selector,
+                matching_type,
+                ref data,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::TLSA.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                for b in [usage, selector, matching_type].iter().chain(data) {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::OPT {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                flags,
+                ref options,
+            } => {
+                self.write_qname("")?;
+                self.write_u16(QueryType::OPT.to_num())?;
+                self.write_u16(udp_payload_size)?;
+                self.write(extended_rcode)?;
+                self.write(version)?;
+                self.write_u16(flags)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+                for (code, data) in options {
+                    self.write_u16(*code)?;
+                    self.write_u16(data.len() as u16)?;
+                    for b in data {
+                        self.write(*b)?;
+                    }
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DS {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::DS.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_u16(key_tag)?;
+                for b in [algorithm, digest_type].iter().chain(digest) {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::SSHFP {
+                ref domain,
+                algorithm,
+                fingerprint_type,
+                ref fingerprint,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::SSHFP.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                for b in [algorithm, fingerprint_type].iter().chain(fingerprint) {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::CAA {
+                ref domain,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                if tag.is_empty() || tag.len() > u8::MAX as usize {
+                    return Err(out_of_spec(format!("Bad CAA tag {:?}", tag)));
+                }
+
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::CAA.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write(flags)?;
+                self.write(tag.len() as u8)?;
+                for b in tag.as_bytes().iter().chain(value.as_bytes()) {
+                    self.write(*b)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::SRV {
                 ref domain,
+                priority,
+                weight,
+                port,
                 ref host,
                 ttl,
             } => {
                 self.write_qname(domain)?;
-                self.write_u16(QueryType::NS.to_num())?;
+                self.write_u16(QueryType::SRV.to_num())?;
                 self.write_u16(1)?;
-                self.write_u32(ttl)?;
+                self.write_ttl(ttl)?;
 
                 let pos = self.pos;
                 self.write_u16(0)?;
 
-                self.write_qname(host)?;
+                self.write_u16(priority)?;
+                self.write_u16(weight)?;
+                self.write_u16(port)?;
+                // RFC 2782 forbids compressing the target.
+                self.write_qname_uncompressed(host)?;
 
                 let size = self.pos - (pos + 2);
                 self.set_u16(pos, size as u16)?;
             }
-            DnsRecord::CNAME {
+            DnsRecord::SOA {
+                ref domain,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::SOA.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                self.write_qname(mname)?;
+                self.write_qname(rname)?;
+                for value in [serial, refresh, retry, expire, minimum] {
+                    self.write_u32(value)?;
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::PTR {
                 ref domain,
                 ref host,
                 ttl,
             } => {
                 self.write_qname(domain)?;
-                self.write_u16(QueryType::CNAME.to_num())?;
+                self.write_u16(QueryType::PTR.to_num())?;
                 self.write_u16(1)?;
-                self.write_u32(ttl)?;
+                self.write_ttl(ttl)?;
 
                 let pos = self.pos;
                 self.write_u16(0)?;
@@ -495,7 +2449,7 @@ impl BytePacketBuffer {
                 self.write_qname(domain)?;
                 self.write_u16(QueryType::MX.to_num())?;
                 self.write_u16(1)?;
-                self.write_u32(ttl)?;
+                self.write_ttl(ttl)?;
 
                 let pos = self.pos;
                 self.write_u16(0)?;
@@ -514,15 +2468,72 @@ impl BytePacketBuffer {
                 self.write_qname(domain)?;
                 self.write_u16(QueryType::AAAA.to_num())?;
                 self.write_u16(1)?;
-                self.write_u32(ttl)?;
+                self.write_ttl(ttl)?;
                 self.write_u16(16)?;
 
                 for octet in &addr.segments() {
                     self.write_u16(*octet)?;
                 }
             }
-            _ => {
-                println!("Skipping record: {:#?}", record);
+            DnsRecord::TXT {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(QueryType::TXT.to_num())?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+
+                let pos = self.pos;
+                self.write_u16(0)?;
+
+                for string in data {
+                    // Strings longer than a character-string allows are split across
+                    // several, which readers concatenate back together.
+                    let bytes = string.as_bytes();
+                    let mut chunks = bytes.chunks(255).peekable();
+                    if chunks.peek().is_none() {
+                        self.write(0)?;
+                    }
+                    for chunk in chunks {
+                        self.write(chunk.len() as u8)?;
+                        for b in chunk {
+                            self.write(*b)?;
+                        }
+                    }
+                }
+
+                let size = self.pos - (pos + 2);
+                self.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::Unknown {
+                ref domain,
+                qtype,
+                data_len,
+                ref data,
+                ttl,
+            } => {
+                self.write_qname(domain)?;
+                self.write_u16(qtype)?;
+                self.write_u16(1)?;
+                self.write_ttl(ttl)?;
+                self.write_u16(match self.policy {
+                    WritePolicy::Strict if data_len as usize != data.len() => {
+                        return Err(out_of_spec(format!(
+                            "Data length {} of a type {} record doesn't match its {} bytes",
+                            data_len,
+                            qtype,
+                            data.len()
+                        )))
+                    }
+                    WritePolicy::Verbatim => data_len,
+                    _ => data.len() as u16,
+                })?;
+
+                for b in data {
+                    self.write(*b)?;
+                }
             }
         }
 
@@ -540,6 +2551,31 @@ pub struct DnsPacket {
 }
 
 impl DnsPacket {
+    /// Hosts named by MX and SRV answers that have no address record in the additional
+    /// section. An SRV target of `.`, which means there's no such service, isn't a host.
+    pub fn unresolved_targets(&self) -> Vec<String> {
+        let mut targets: Vec<String> = Vec::new();
+        for rec in &self.answers {
+            let host = match rec {
+                DnsRecord::MX { host, .. } => host,
+                DnsRecord::SRV { host, .. } if !host.trim_end_matches('.').is_empty() => host,
+                _ => continue,
+            };
+
+            let resolved = self.resources.iter().any(|rsrc| match rsrc {
+                DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => domain == host,
+                _ => false,
+            });
+
+            if !resolved && !targets.contains(host) {
+                targets.push(host.clone());
+            }
+        }
+
+        targets
+    }
+
+    #[cfg(feature = "resolver")]
     pub fn get_random_a(&self) -> Option<String> {
         if !self.answers.is_empty() {
             let idx = rand::random::<usize>() % self.answers.len();
@@ -552,6 +2588,7 @@ impl DnsPacket {
         None
     }
 
+    #[cfg(feature = "resolver")]
     pub fn get_resolved_ns(&self, qname: &str) -> Option<String> {
         let mut new_authorities = Vec::new();
         for auth in &self.authorities {
@@ -587,6 +2624,19 @@ impl DnsPacket {
         None
     }
 
+    pub fn get_all_unresolved_ns(&self, qname: &str) -> Vec<String> {
+        self.authorities
+            .iter()
+            .filter_map(|auth| match auth {
+                DnsRecord::NS { domain, host, .. } if qname.ends_with(domain.as_str()) => {
+                    Some(host.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "resolver")]
     pub fn get_unresolved_ns(&self, qname: &str) -> Option<String> {
         let mut new_authorities = Vec::new();
         for auth in &self.authorities {
@@ -613,33 +2663,691 @@ impl DnsPacket {
     }
 }
 
+/// What a response amounts to, whatever shape it comes in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseKind {
+    /// Records for the question, or a CNAME towards them.
+    Answer,
+    /// The name exists, but has no records of the type asked for.
+    NoData,
+    /// The name doesn't exist.
+    NxDomain,
+    /// No answer, but name servers closer to one to ask instead.
+    Referral,
+    /// The server couldn't or wouldn't answer, with FORMERR, SERVFAIL, REFUSED and so on.
+    Error,
+}
+
+impl DnsPacket {
+    /// Classifies the packet as a response. It needn't echo the question, and it can have
+    /// nothing but authority and additional records, or nothing at all: a NOERROR response
+    /// with no answers and no name servers to refer us to is NODATA.
+    pub fn kind(&self) -> ResponseKind {
+        match self.header.rescode {
+            ResultCode::Success => {}
+            ResultCode::NonexistantDomain => return ResponseKind::NxDomain,
+            _ => return ResponseKind::Error,
+        }
+        if !self.answers.is_empty() {
+            return ResponseKind::Answer;
+        }
+
+        // An SOA means the server is authoritative for the name and is telling us there's
+        // nothing there, even if it throws in its NS records too.
+        let has = |qtype: QueryType| self.authorities.iter().any(|rec| rec.query_type() == qtype);
+        if has(QueryType::NS) && !has(QueryType::SOA) {
+            ResponseKind::Referral
+        } else {
+            ResponseKind::NoData
+        }
+    }
+
+    /// Parses a packet from the bytes of a datagram.
+    pub fn from_bytes(data: &[u8]) -> Result<DnsPacket> {
+        BytePacketBuffer::from_bytes(data)?.read_packet()
+    }
+
+    /// Like [`DnsPacket::from_bytes`], reading only as far as `level`. See
+    /// [`BytePacketBuffer::read_packet_at`].
+    pub fn from_bytes_at(data: &[u8], level: ParseLevel) -> Result<DnsPacket> {
+        BytePacketBuffer::from_bytes(data)?.read_packet_at(level)
+    }
+
+    /// Like [`DnsPacket::from_bytes`], along with anything about the packet that's legal
+    /// enough to parse but says something about the care taken over its data.
+    pub fn from_bytes_with_warnings(data: &[u8]) -> Result<(DnsPacket, Vec<Warning>)> {
+        let mut buffer = BytePacketBuffer::from_bytes(data)?;
+        let packet = buffer.read_packet()?;
+        let mut warnings = buffer.take_warnings();
+        warnings.extend(packet.warnings());
+        Ok((packet, warnings))
+    }
+
+    /// What's odd about the packet's records: RRsets whose records have different TTLs.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for (section, records) in [
+            (Section::Answer, &self.answers),
+            (Section::Authority, &self.authorities),
+            (Section::Additional, &self.resources),
+        ] {
+            let mut ttls: Vec<(&str, QueryType, u32)> = Vec::new();
+            for rec in records {
+                let (name, qtype) = (rec.domain(), rec.query_type());
+                let first = ttls.iter().find(|(n, t, _)| *n == name && *t == qtype);
+                match first {
+                    Some(&(_, _, ttl)) if ttl != rec.ttl() => {
+                        let warning = Warning::TtlMismatch {
+                            section,
+                            name: name.to_string(),
+                            qtype,
+                        };
+                        if !warnings.contains(&warning) {
+                            warnings.push(warning);
+                        }
+                    }
+                    Some(_) => {}
+                    None => ttls.push((name, qtype, rec.ttl())),
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Encodes the packet in wire format, fixing what it can of values out of spec.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with(WritePolicy::Lenient)
+    }
+
+    /// Encodes the packet in wire format, with values out of spec handled as `policy` says.
+    pub fn to_bytes_with(&self, policy: WritePolicy) -> Result<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::with_policy(policy);
+        buffer.write_packet(self.clone())?;
+        Ok(buffer.buf[..buffer.pos].to_vec())
+    }
+
+    /// Encodes the packet with each section in canonical order, so two packets holding
+    /// the same records encode to the same bytes however the records were gathered. Which
+    /// names are compressed follows from the order they're written in, so that's the only
+    /// choice there is to pin down.
+    pub fn to_canonical_bytes(&self) -> Result<Vec<u8>> {
+        let mut packet = self.clone();
+        packet.canonicalize();
+        packet.to_bytes()
+    }
+
+    /// Sorts each section into the canonical order of RFC 4034: by owner name compared
+    /// label by label from the right and ignoring case, then by type, then by the record's
+    /// data in its canonical form (section 6.2), uncompressed and with the names in it
+    /// lowercased. Records of an RRset therefore sort by their data whatever their TTLs.
+    pub fn canonicalize(&mut self) {
+        for section in [
+            &mut self.answers,
+            &mut self.authorities,
+            &mut self.resources,
+        ] {
+            section.sort_by_cached_key(canonical_key);
+        }
+    }
+}
+
+fn canonical_key(rec: &DnsRecord) -> (Vec<String>, u16, Vec<u8>) {
+    let owner = rec
+        .domain()
+        .trim_end_matches('.')
+        .rsplit('.')
+        .map(str::to_lowercase)
+        .collect();
+
+    // The types whose data RFC 4034 lowercases the names in, less NSEC (RFC 6840).
+    let mut rec = rec.clone();
+    match rec {
+        DnsRecord::NS { ref mut host, .. }
+        | DnsRecord::CNAME { ref mut host, .. }
+        | DnsRecord::PTR { ref mut host, .. }
+        | DnsRecord::MX { ref mut host, .. }
+        | DnsRecord::SRV { ref mut host, .. }
+        | DnsRecord::NAPTR {
+            replacement: ref mut host,
+            ..
+        }
+        | DnsRecord::RRSIG {
+            signer: ref mut host,
+            ..
+        } => *host = host.to_lowercase(),
+        DnsRecord::SOA {
+            ref mut mname,
+            ref mut rname,
+            ..
+        } => {
+            *mname = mname.to_lowercase();
+            *rname = rname.to_lowercase();
+        }
+        _ => {}
+    }
+    let qtype = rec.query_type().to_num();
+
+    // The canonical form has no compression, which only Verbatim leaves out. The data
+    // follows the owner name and ten bytes of type, class, TTL and length.
+    let mut owner_name = BytePacketBuffer::with_policy(WritePolicy::Verbatim);
+    let mut buffer = BytePacketBuffer::with_policy(WritePolicy::Verbatim);
+    let rdata = match (
+        owner_name.write_qname(rec.domain()),
+        buffer.write_record(rec),
+    ) {
+        (Ok(_), Ok(_)) => buffer.buf[owner_name.pos + 10..buffer.pos].to_vec(),
+        _ => Vec::new(),
+    };
+
+    (owner, qtype, rdata)
+}
+
+/// Where a parsed record sat in the message it came from, as offsets into its bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordSpan {
+    /// The whole record, from its owner name to the end of its data.
+    pub record: Range<usize>,
+    /// The record's data exactly as it was sent, compression pointers and all.
+    pub rdata: Range<usize>,
+}
+
+/// The sections of a message that hold records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Section::Answer => "answer",
+            Section::Authority => "authority",
+            Section::Additional => "additional",
+        })
+    }
+}
+
+/// Something about a message that's allowed, or at least can be read, but points to
+/// careless data or a careless server. Tooling can show these without failing over them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// Records of one RRset with different TTLs, which RFC 2181 forbids. Caches are left to
+    /// pick one.
+    TtlMismatch {
+        section: Section,
+        name: String,
+        qtype: QueryType,
+    },
+    /// A name, as written, with upper case letters. Names compare the same in any case,
+    /// but DNSSEC signs them in lower case.
+    NotCanonical { name: String },
+    /// A response with the TC flag set, whose records may be incomplete. We don't retry
+    /// over TCP.
+    Truncated,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::TtlMismatch {
+                section,
+                name,
+                qtype,
+            } => write!(
+                f,
+                "TTLs differ within the {} {} RRset in the {} section",
+                name, qtype, section
+            ),
+            Warning::NotCanonical { name } => {
+                write!(f, "name {} isn't in canonical (lower) case", name)
+            }
+            Warning::Truncated => f.write_str("response truncated, records may be missing"),
+        }
+    }
+}
+
+/// Whereabouts in a message something is, with questions and records counted from 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    Header,
+    Question(usize),
+    Record(Section, usize),
+}
+
+/// Counts from 1, as people do: `answer record 3` is the third answer.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Location::Header => write!(f, "the header"),
+            Location::Question(index) => write!(f, "question {}", index + 1),
+            Location::Record(section, index) => write!(f, "{} record {}", section, index + 1),
+        }
+    }
+}
+
+/// What was wrong with a message we couldn't parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsErrorKind {
+    /// The message ran out `found` bytes into something `needed` bytes long.
+    Truncated { needed: usize, found: usize },
+    /// A compression pointer to `target` that doesn't point back before the name it's in.
+    BadPointer { target: usize },
+    /// A label length byte starting with the reserved bits 01 or 10.
+    BadLabelType(u8),
+    /// A record whose data took `found` bytes to read where its length said `expected`.
+    DataLength { expected: usize, found: usize },
+    /// Data that doesn't follow the format of type `qtype`, or that the codec registered
+    /// for the type rejected.
+    BadData { qtype: u16 },
+}
+
+/// Why a message couldn't be parsed, and where. Parse errors are `io::Error`s of kind
+/// `InvalidData` wrapping one of these, which [`DnsError::find`] gets back out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsError {
+    kind: DnsErrorKind,
+    offset: usize,
+    location: Option<Location>,
+}
+
+impl DnsError {
+    fn new(kind: DnsErrorKind, offset: usize) -> DnsError {
+        DnsError {
+            kind,
+            offset,
+            location: None,
+        }
+    }
+
+    /// The `DnsError` behind `error`, if it came from parsing.
+    pub fn find(error: &Error) -> Option<&DnsError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    pub fn kind(&self) -> DnsErrorKind {
+        self.kind
+    }
+
+    /// Where in the message's bytes the problem is.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The part of the message being read, known when the whole message was being parsed
+    /// rather than a piece of it through [`BytePacketBuffer`].
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    /// The section of the record being read, if it was a record.
+    pub fn section(&self) -> Option<Section> {
+        match self.location? {
+            Location::Record(section, _) => Some(section),
+            _ => None,
+        }
+    }
+
+    /// Which record of its section was being read, counting from 0, if it was a record.
+    pub fn record_index(&self) -> Option<usize> {
+        match self.location? {
+            Location::Record(_, index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// How many bytes there should have been, for a short message or record.
+    pub fn expected(&self) -> Option<usize> {
+        match self.kind {
+            DnsErrorKind::Truncated { needed, .. } => Some(needed),
+            DnsErrorKind::DataLength { expected, .. } => Some(expected),
+            _ => None,
+        }
+    }
+
+    /// How many there were.
+    pub fn found(&self) -> Option<usize> {
+        match self.kind {
+            DnsErrorKind::Truncated { found, .. } | DnsErrorKind::DataLength { found, .. } => {
+                Some(found)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DnsErrorKind::Truncated { .. } => write!(f, "unexpected end of message")?,
+            DnsErrorKind::BadPointer { .. } => write!(f, "bad compression pointer")?,
+            DnsErrorKind::BadLabelType(byte) => write!(f, "bad label type 0x{:02X}", byte)?,
+            DnsErrorKind::DataLength { .. } => write!(f, "wrong record data length")?,
+            DnsErrorKind::BadData { qtype } => {
+                write!(f, "bad {} data", QueryType::from_num(qtype))?
+            }
+        }
+        write!(f, " at offset 0x{:X}", self.offset)?;
+        if let Some(location) = self.location {
+            write!(f, " in {}", location)?;
+        }
+
+        match self.kind {
+            DnsErrorKind::Truncated { needed, found } => {
+                write!(f, ": needed {} bytes, found {}", needed, found)
+            }
+            DnsErrorKind::BadPointer { target } => write!(f, ": points to 0x{:X}", target),
+            DnsErrorKind::BadLabelType(_) | DnsErrorKind::BadData { .. } => Ok(()),
+            DnsErrorKind::DataLength { expected, found } => {
+                write!(f, ": expected {} bytes, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Error {
+        Error::new(ErrorKind::InvalidData, error)
+    }
+}
+
+/// Notes that a parse error happened while reading `location`, unless it already says
+/// where it was.
+fn locate(error: Error, location: Location) -> Error {
+    match DnsError::find(&error) {
+        Some(dns_error) if dns_error.location.is_none() => DnsError {
+            location: Some(location),
+            ..dns_error.clone()
+        }
+        .into(),
+        _ => error,
+    }
+}
+
+/// A parsed packet that keeps the bytes it came from and where each record was in them,
+/// for work that has to see a record exactly as it was sent rather than as we'd encode it,
+/// such as checking a signature over it or passing it on unchanged.
+#[derive(Clone, Debug)]
+pub struct WirePacket {
+    pub packet: DnsPacket,
+    bytes: Vec<u8>,
+    spans: [Vec<RecordSpan>; 3],
+}
+
+impl WirePacket {
+    pub fn from_bytes(data: &[u8]) -> Result<WirePacket> {
+        let (packet, spans) = BytePacketBuffer::from_bytes(data)?.read_packet_with_spans()?;
+
+        Ok(WirePacket {
+            packet,
+            bytes: data.to_vec(),
+            spans,
+        })
+    }
+
+    /// The message as it was received.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Where the `index`th record of `section` was.
+    pub fn span(&self, section: Section, index: usize) -> Option<&RecordSpan> {
+        let spans = match section {
+            Section::Answer => &self.spans[0],
+            Section::Authority => &self.spans[1],
+            Section::Additional => &self.spans[2],
+        };
+        spans.get(index)
+    }
+
+    /// The original bytes of the `index`th record of `section`.
+    pub fn record_bytes(&self, section: Section, index: usize) -> Option<&[u8]> {
+        self.bytes.get(self.span(section, index)?.record.clone())
+    }
+
+    /// The original data of the `index`th record of `section`. Names in it may be
+    /// compression pointers into the rest of the message.
+    pub fn rdata(&self, section: Section, index: usize) -> Option<&[u8]> {
+        self.bytes.get(self.span(section, index)?.rdata.clone())
+    }
+}
+
+/// Encodes a response too big for one message, such as a zone transfer, as a run of
+/// messages that each hold as many answers as fit. Records are pulled from the iterator as
+/// they're written, so the whole answer never has to be in memory at once.
+///
+/// Every message carries `header` with its own answer count; the questions go in the first
+/// one only.
+pub struct MessageWriter<I> {
+    header: DnsHeader,
+    questions: Vec<DnsQuestion>,
+    records: I,
+    /// A record that didn't fit in the last message, so starts the next one.
+    pending: Option<DnsRecord>,
+    started: bool,
+}
+
+impl<I: Iterator<Item = DnsRecord>> MessageWriter<I> {
+    pub fn new(header: DnsHeader, questions: Vec<DnsQuestion>, records: I) -> MessageWriter<I> {
+        MessageWriter {
+            header,
+            questions,
+            records,
+            pending: None,
+            started: false,
+        }
+    }
+
+    /// Writes every message to `out` with the two-byte length prefix used over TCP, and
+    /// returns how many messages were sent.
+    pub fn write_framed<W: Write>(self, out: &mut W) -> Result<usize> {
+        let mut count = 0;
+        for message in self {
+            let message = message?;
+            out.write_all(&(message.len() as u16).to_be_bytes())?;
+            out.write_all(&message)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn next_message(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::with_size(MAX_PACKET_SIZE);
+
+        let mut header = self.header.clone();
+        header.questions = 0;
+        header.answers = 0;
+        header.authoritative_entries = 0;
+        header.resource_entries = 0;
+        if !self.started {
+            header.questions = self.questions.len() as u16;
+        }
+        buffer.write_header(header)?;
+
+        if !self.started {
+            for question in self.questions.drain(..) {
+                buffer.write_question(question)?;
+            }
+            self.started = true;
+        }
+
+        let mut answers = 0u16;
+        while let Some(rec) = self.pending.take().or_else(|| self.records.next()) {
+            let start = buffer.pos;
+            if let Err(err) = buffer.write_record(rec.clone()) {
+                // A record that won't fit even in a message of its own can't be sent at all.
+                if answers == 0 {
+                    return Err(err);
+                }
+                buffer.rewind(start);
+                self.pending = Some(rec);
+                break;
+            }
+            answers += 1;
+        }
+        buffer.set_u16(6, answers)?;
+
+        Ok(buffer.buf[..buffer.pos].to_vec())
+    }
+}
+
+impl<I: Iterator<Item = DnsRecord>> Iterator for MessageWriter<I> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.started && self.pending.is_none() {
+            // Only peek at the iterator once the previous message has been written.
+            let rec = self.records.next()?;
+            self.pending = Some(rec);
+        }
+
+        let message = self.next_message();
+        if message.is_err() {
+            // Don't keep trying to send the record that failed.
+            self.pending = None;
+        }
+        Some(message)
+    }
+}
+
 impl BytePacketBuffer {
     pub fn read_packet(&mut self) -> Result<DnsPacket> {
-        let header = self.read_header()?;
-
-        let questions = iter::repeat_with(|| self.read_question().unwrap())
-            .take(header.questions as usize)
-            .collect();
-        let answers = iter::repeat_with(|| self.read_record().unwrap())
-            .take(header.answers as usize)
-            .collect();
-        let authorities = iter::repeat_with(|| self.read_record().unwrap())
-            .take(header.authoritative_entries as usize)
-            .collect();
-        let resources = iter::repeat_with(|| self.read_record().unwrap())
-            .take(header.resource_entries as usize)
-            .collect();
+        self.read_packet_with_spans().map(|(packet, _)| packet)
+    }
+
+    /// Reads a packet only as far as `level`, leaving the sections after that empty while
+    /// the header keeps the counts it was sent with. Nothing past `level` is looked at, so
+    /// a packet that reads fine at one level may be malformed further on.
+    pub fn read_packet_at(&mut self, level: ParseLevel) -> Result<DnsPacket> {
+        if level == ParseLevel::Full {
+            return self.read_packet();
+        }
+
+        let header = self
+            .read_header()
+            .map_err(|e| locate(e, Location::Header))?;
+        let questions = match level {
+            ParseLevel::Header => Vec::new(),
+            _ => self.read_questions(header.questions)?,
+        };
 
         Ok(DnsPacket {
+            header,
+            questions,
+            ..DnsPacket::default()
+        })
+    }
+
+    fn read_questions(&mut self, count: u16) -> Result<Vec<DnsQuestion>> {
+        (0..count as usize)
+            .map(|i| {
+                self.read_question()
+                    .map_err(|e| locate(e, Location::Question(i)))
+            })
+            .collect()
+    }
+
+    /// Reads a packet along with the span of each answer, authority and additional
+    /// record, in that order.
+    fn read_packet_with_spans(&mut self) -> Result<(DnsPacket, [Vec<RecordSpan>; 3])> {
+        let header = self
+            .read_header()
+            .map_err(|e| locate(e, Location::Header))?;
+
+        let questions = self.read_questions(header.questions)?;
+
+        let mut read_section =
+            |section: Section, count: u16| -> Result<(Vec<DnsRecord>, Vec<RecordSpan>)> {
+                (0..count as usize)
+                    .map(|i| {
+                        self.read_record_with_span()
+                            .map_err(|e| locate(e, Location::Record(section, i)))
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .map(|records| records.into_iter().unzip())
+            };
+        let (answers, answer_spans) = read_section(Section::Answer, header.answers)?;
+        let (authorities, authority_spans) =
+            read_section(Section::Authority, header.authoritative_entries)?;
+        let (resources, resource_spans) =
+            read_section(Section::Additional, header.resource_entries)?;
+
+        // With EDNS the response code goes on in the OPT record.
+        let mut header = header;
+        if let Some(&DnsRecord::OPT { extended_rcode, .. }) = resources
+            .iter()
+            .find(|rec| rec.query_type() == QueryType::OPT)
+        {
+            let low = u16::from(self.buf[3] & 0x0F);
+            header.rescode = ResultCode::from_extended(u16::from(extended_rcode) << 4 | low);
+        }
+
+        let packet = DnsPacket {
             header,
             questions,
             answers,
             authorities,
             resources,
-        })
+        };
+        Ok((packet, [answer_spans, authority_spans, resource_spans]))
     }
 
-    pub fn write_packet(&mut self, packet: DnsPacket) -> Result<()> {
+    /// Writes `packet`, with the header's counts checked against or taken from its
+    /// sections as the buffer's policy says.
+    pub fn write_packet(&mut self, mut packet: DnsPacket) -> Result<()> {
+        let counts = [
+            packet.questions.len(),
+            packet.answers.len(),
+            packet.authorities.len(),
+            packet.resources.len(),
+        ];
+        let header = &mut packet.header;
+        let stated = [
+            header.questions,
+            header.answers,
+            header.authoritative_entries,
+            header.resource_entries,
+        ];
+        match self.policy {
+            WritePolicy::Strict if counts.iter().zip(stated).any(|(&n, s)| n != s as usize) => {
+                return Err(out_of_spec(format!(
+                    "Header counts {:?} don't match sections of {:?}",
+                    stated, counts
+                )));
+            }
+            WritePolicy::Verbatim => {}
+            _ => {
+                header.questions = counts[0] as u16;
+                header.answers = counts[1] as u16;
+                header.authoritative_entries = counts[2] as u16;
+                header.resource_entries = counts[3] as u16;
+            }
+        }
+
+        // The header only has room for the low four bits of the response code, and the
+        // rest go in the OPT record.
+        let extended = (packet.header.rescode.to_num() >> 4) as u8;
+        if self.policy != WritePolicy::Verbatim {
+            let opt = packet.resources.iter_mut().find_map(|rec| match rec {
+                DnsRecord::OPT { extended_rcode, .. } => Some(extended_rcode),
+                _ => None,
+            });
+            match opt {
+                Some(extended_rcode) => *extended_rcode = extended,
+                None if extended != 0 => {
+                    return Err(out_of_spec(format!(
+                        "Response code {} needs an OPT record",
+                        packet.header.rescode
+                    )));
+                }
+                None => {}
+            }
+        }
+
         self.write_header(packet.header)?;
 
         for question in packet.questions {
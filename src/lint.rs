@@ -0,0 +1,373 @@
+use crate::propagation::{find_zone_servers, has_wildcard};
+use crate::ptr::reverse_name;
+use crate::resolver::Resolver;
+use crate::{
+    ttl, BytePacketBuffer, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode,
+    CLASS_IN,
+};
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+const AXFR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The ranges zone checkers generally recommend for the SOA timers, after RFC 1912 and,
+/// for the negative caching TTL, RFC 2308: refresh from 20 minutes to 12 hours, retry from
+/// 2 minutes to 2 hours, expire from 2 to 4 weeks and the minimum from 5 minutes to a day.
+const SOA_REFRESH: RangeInclusive<u32> = 1200..=43_200;
+const SOA_RETRY: RangeInclusive<u32> = 120..=7200;
+const SOA_EXPIRE: RangeInclusive<u32> = 1_209_600..=2_419_200;
+const SOA_MINIMUM: RangeInclusive<u32> = 300..=86_400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn penalty(self) -> u32 {
+        match self {
+            Severity::Info => 0,
+            Severity::Warning => 5,
+            Severity::Error => 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct LintReport {
+    pub domain: String,
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    pub(crate) fn new(domain: &str) -> LintReport {
+        LintReport {
+            domain: domain.to_string(),
+            findings: Vec::new(),
+        }
+    }
+
+    /// 100 for a clean delegation, less 20 per error and 5 per warning.
+    pub fn score(&self) -> u32 {
+        let penalty: u32 = self.findings.iter().map(|f| f.severity.penalty()).sum();
+        100u32.saturating_sub(penalty)
+    }
+
+    pub(crate) fn note(&mut self, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Info,
+            message,
+        });
+    }
+
+    pub(crate) fn warn(&mut self, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    pub(crate) fn error(&mut self, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            message,
+        });
+    }
+}
+
+/// Checks the delegation of `domain`: that the parent and child agree on its name servers,
+/// that glue matches the child's own address records, that every name server answers
+/// authoritatively, that the zone's SOA timers are within the recommended ranges, that
+/// none of the name servers allow zone transfers to anyone, and that their addresses have
+/// reverse records. `via` is a recursive resolver used for discovery.
+pub fn lint(resolver: &Resolver, via: IpAddr, domain: &str) -> Result<LintReport> {
+    let parent = match domain.find('.') {
+        Some(idx) => &domain[idx + 1..],
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Linting top level domains isn't supported",
+            ))
+        }
+    };
+
+    let mut report = LintReport::new(domain);
+
+    let (_, parent_servers) = find_zone_servers(resolver, via, parent)?;
+    let (parent_host, parent_addr) = match parent_servers.first() {
+        Some(server) => server.clone(),
+        None => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No name servers found for {}", parent),
+            ))
+        }
+    };
+
+    let referral = resolver.lookup(domain, QueryType::NS, (parent_addr, 53))?;
+    let parent_ns = ns_hosts(domain, &referral.authorities, &referral.answers);
+    if parent_ns.is_empty() {
+        report.error(format!(
+            "Parent server {} returned no delegation for {}",
+            parent_host, domain
+        ));
+        return Ok(report);
+    }
+
+    let mut child_ns: Option<BTreeSet<String>> = None;
+    let mut soa = None;
+    for host in &parent_ns {
+        let glue = address_records(host, &referral.resources);
+        let addrs = if glue.is_empty() {
+            if in_bailiwick(host, domain) {
+                report.error(format!(
+                    "Missing glue for in-bailiwick name server {}",
+                    host
+                ));
+            }
+            resolve_addrs(resolver, via, host)
+        } else {
+            glue.clone()
+        };
+
+        if addrs.is_empty() {
+            report.error(format!("Name server {} has no address", host));
+            continue;
+        }
+
+        for addr in addrs {
+            let response = match resolver.lookup(domain, QueryType::NS, (addr, 53)) {
+                Ok(response) => response,
+                Err(e) => {
+                    report.error(format!("Name server {} ({}) failed: {}", host, addr, e));
+                    continue;
+                }
+            };
+
+            if !response.header.authoritative_answer
+                || response.header.rescode != ResultCode::Success
+            {
+                report.error(format!(
+                    "Lame delegation: {} ({}) is not authoritative for {}",
+                    host, addr, domain
+                ));
+                continue;
+            }
+
+            let hosts = ns_hosts(domain, &response.answers, &[]);
+            match child_ns {
+                Some(ref seen) if *seen != hosts => report.warn(format!(
+                    "Name server {} ({}) lists different NS records than its peers",
+                    host, addr
+                )),
+                Some(_) => {}
+                None => child_ns = Some(hosts),
+            }
+
+            if soa.is_none() {
+                soa = resolver
+                    .lookup(domain, QueryType::SOA, (addr, 53))
+                    .ok()
+                    .and_then(|response| {
+                        response
+                            .answers
+                            .into_iter()
+                            .find(|rec| matches!(rec, DnsRecord::SOA { .. }))
+                    });
+            }
+
+            if !glue.is_empty() {
+                if let Ok(own) = resolver.lookup(host, QueryType::A, (addr, 53)) {
+                    let own = address_records(host, &own.answers);
+                    if !own.is_empty()
+                        && own.iter().collect::<BTreeSet<_>>() != glue.iter().collect()
+                    {
+                        report.error(format!(
+                            "Glue for {} doesn't match its address records in the zone",
+                            host
+                        ));
+                    }
+                }
+            }
+
+            if axfr_allowed(domain, addr) {
+                report.warn(format!(
+                    "Name server {} ({}) allows zone transfers to anyone",
+                    host, addr
+                ));
+            }
+
+            let ptr = resolver.lookup(&reverse_name(&addr), QueryType::PTR, (via, 53));
+            let has_ptr = match ptr {
+                Ok(ref packet) => packet
+                    .answers
+                    .iter()
+                    .any(|rec| matches!(rec, DnsRecord::PTR { .. })),
+                Err(_) => false,
+            };
+            if !has_ptr {
+                report.warn(format!(
+                    "Name server address {} has no reverse record",
+                    addr
+                ));
+            }
+        }
+    }
+
+    if has_wildcard(resolver, (via, 53), domain) {
+        report.note(format!(
+            "{} answers for random names (wildcard), so missing names won't show as NXDOMAIN",
+            domain
+        ));
+    }
+
+    if let Some(ref soa) = soa {
+        report.findings.extend(soa_findings(soa));
+    }
+
+    if let Some(child_ns) = child_ns {
+        if child_ns != parent_ns {
+            report.error(format!(
+                "Parent lists NS [{}] but the zone lists [{}]",
+                join(&parent_ns),
+                join(&child_ns)
+            ));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Warnings for the timers of `soa` that are outside the recommended ranges, or that
+/// contradict each other. There are none for a record that isn't an SOA.
+pub fn soa_findings(soa: &DnsRecord) -> Vec<Finding> {
+    let (refresh, retry, expire, minimum) = match *soa {
+        DnsRecord::SOA {
+            refresh,
+            retry,
+            expire,
+            minimum,
+            ..
+        } => (refresh, retry, expire, minimum),
+        _ => return Vec::new(),
+    };
+
+    let mut report = LintReport::new(soa.domain());
+    for (name, value, range) in &[
+        ("refresh", refresh, SOA_REFRESH),
+        ("retry", retry, SOA_RETRY),
+        ("expire", expire, SOA_EXPIRE),
+        ("minimum", minimum, SOA_MINIMUM),
+    ] {
+        if !range.contains(value) {
+            report.warn(format!(
+                "SOA {} of {} is outside the recommended {} to {}",
+                name,
+                ttl::humanize(*value),
+                ttl::humanize(*range.start()),
+                ttl::humanize(*range.end())
+            ));
+        }
+    }
+
+    if retry >= refresh {
+        report.warn(format!(
+            "SOA retry of {} isn't shorter than the refresh of {}",
+            ttl::humanize(retry),
+            ttl::humanize(refresh)
+        ));
+    }
+    if expire <= refresh.saturating_add(retry) {
+        report.warn(format!(
+            "SOA expire of {} leaves secondaries no time to retry a failed refresh",
+            ttl::humanize(expire)
+        ));
+    }
+
+    report.findings
+}
+
+fn join(hosts: &BTreeSet<String>) -> String {
+    hosts.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+fn in_bailiwick(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn ns_hosts(domain: &str, primary: &[DnsRecord], secondary: &[DnsRecord]) -> BTreeSet<String> {
+    primary
+        .iter()
+        .chain(secondary.iter())
+        .filter_map(|rec| match rec {
+            DnsRecord::NS {
+                domain: owner,
+                host,
+                ..
+            } if owner == domain => Some(host.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn address_records(host: &str, records: &[DnsRecord]) -> Vec<IpAddr> {
+    records
+        .iter()
+        .filter_map(|rec| match rec {
+            DnsRecord::A { domain, addr, .. } if domain == host => Some(IpAddr::V4(*addr)),
+            DnsRecord::AAAA { domain, addr, .. } if domain == host => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn resolve_addrs(resolver: &Resolver, via: IpAddr, host: &str) -> Vec<IpAddr> {
+    match resolver.lookup(host, QueryType::A, (via, 53)) {
+        Ok(response) => address_records(host, &response.answers),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Attempts a zone transfer over TCP and reports whether the server started sending the
+/// zone. Only the first message's header is examined.
+fn axfr_allowed(domain: &str, addr: IpAddr) -> bool {
+    try_axfr(domain, addr).unwrap_or(false)
+}
+
+fn try_axfr(domain: &str, addr: IpAddr) -> Result<bool> {
+    let mut packet = DnsPacket::default();
+    packet.header.id = rand::random();
+    packet.header.questions = 1;
+    packet.questions.push(DnsQuestion {
+        name: domain.to_string(),
+        qtype: QueryType::Unknown(252),
+        qclass: CLASS_IN,
+    });
+
+    let request = packet.to_bytes()?;
+
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::new(addr, 53), AXFR_TIMEOUT)?;
+    stream.set_read_timeout(Some(AXFR_TIMEOUT))?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(&request)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+
+    let mut res_buffer = BytePacketBuffer::new();
+    stream.read_exact(&mut res_buffer.buf[..12])?;
+    let header = res_buffer.read_header()?;
+
+    Ok(header.rescode == ResultCode::Success && header.answers > 0)
+}
@@ -0,0 +1,65 @@
+use crate::{DnsRecord, QueryType, ResultCode};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Records we answer for ourselves rather than asking anyone, such as names for DHCP
+/// leases. The whole set is swapped at once when its source changes.
+#[derive(Debug, Default)]
+pub struct LocalRecords {
+    /// Domains we're the authority for: a name under one of them that isn't in `records`
+    /// doesn't exist.
+    domains: Vec<String>,
+    records: RwLock<HashMap<String, Vec<DnsRecord>>>,
+}
+
+impl LocalRecords {
+    pub fn new(domains: Vec<String>) -> LocalRecords {
+        LocalRecords {
+            domains: domains.iter().map(|d| normalize(d)).collect(),
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces every record with `records`.
+    pub fn replace(&self, records: Vec<DnsRecord>) {
+        let mut by_name: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+        for rec in records {
+            by_name
+                .entry(normalize(rec.domain()))
+                .or_default()
+                .push(rec);
+        }
+
+        *self.records.write().unwrap() = by_name;
+    }
+
+    /// The response code and answers for a question, or `None` if it isn't ours to answer.
+    pub fn answer(&self, name: &str, qtype: QueryType) -> Option<(ResultCode, Vec<DnsRecord>)> {
+        let name = normalize(name);
+        let records = self.records.read().unwrap();
+
+        match records.get(&name) {
+            Some(recs) => Some((
+                ResultCode::Success,
+                recs.iter()
+                    .filter(|rec| rec.query_type() == qtype)
+                    .cloned()
+                    .collect(),
+            )),
+            None if self.is_authoritative(&name) => {
+                Some((ResultCode::NonexistantDomain, Vec::new()))
+            }
+            None => None,
+        }
+    }
+
+    fn is_authoritative(&self, name: &str) -> bool {
+        self.domains
+            .iter()
+            .any(|domain| name == domain || name.ends_with(&format!(".{}", domain)))
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
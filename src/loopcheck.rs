@@ -0,0 +1,85 @@
+//! Catching forwarding loops: an upstream that hands our queries back to us, directly or
+//! by way of other forwarders. Each query would go round until something timed out, every
+//! lap adding to the load. An upstream at one of our own addresses is caught at startup.
+//! For the rest, a query for a made-up name is sent upstream now and then, and if it
+//! arrives back at our own listeners the upstreams loop.
+
+use crate::{DnsPacket, Result};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+pub struct LoopCheck {
+    /// The name the probe in flight asks for.
+    probe: Mutex<Option<String>>,
+    /// Whether it has come back to us.
+    returned: AtomicBool,
+    /// Whether the last probe came back.
+    looping: AtomicBool,
+}
+
+impl LoopCheck {
+    pub fn new() -> LoopCheck {
+        LoopCheck::default()
+    }
+
+    /// Whether the upstreams were found to loop back to us, in which case queries for them
+    /// get SERVFAIL rather than going round.
+    pub fn looping(&self) -> bool {
+        self.looping.load(Ordering::Relaxed)
+    }
+
+    /// Whether a client's query for `name` is our probe come back. It's not to be passed
+    /// on, which would send it round again.
+    pub fn is_probe(&self, name: &str) -> bool {
+        let probe = self.probe.lock().unwrap();
+        let returned = probe
+            .as_ref()
+            .is_some_and(|probe| probe.eq_ignore_ascii_case(name.trim_end_matches('.')));
+        if returned {
+            self.returned.store(true, Ordering::Relaxed);
+        }
+        returned
+    }
+
+    /// Sends a probe for a fresh name with `forward`, and notes whether it came back
+    /// before `forward` returned. Returns whether it did.
+    pub fn probe<F>(&self, forward: F) -> bool
+    where
+        F: FnOnce(&str) -> Result<DnsPacket>,
+    {
+        let name = probe_name();
+        *self.probe.lock().unwrap() = Some(name.clone());
+        self.returned.store(false, Ordering::Relaxed);
+
+        // The answer doesn't matter, only whether the question found its way back.
+        let _ = forward(&name);
+
+        let returned = self.returned.load(Ordering::Relaxed);
+        self.looping.store(returned, Ordering::Relaxed);
+        *self.probe.lock().unwrap() = None;
+        returned
+    }
+}
+
+/// Two random labels, so no upstream can answer it without passing it on.
+fn probe_name() -> String {
+    format!(
+        "{:08x}.{:08x}",
+        rand::random::<u32>(),
+        rand::random::<u32>()
+    )
+}
+
+/// Whether `server` is one of `ours`, the addresses we answer on. A wildcard address stands
+/// for the loopback address too.
+pub fn is_own_address(server: SocketAddr, ours: &[SocketAddr]) -> bool {
+    ours.iter().any(|addr| {
+        addr.port() == server.port()
+            && (addr.ip() == server.ip()
+                || (addr.ip().is_unspecified()
+                    && addr.is_ipv4() == server.is_ipv4()
+                    && (server.ip().is_loopback() || server.ip().is_unspecified())))
+    })
+}
@@ -0,0 +1,282 @@
+use crate::lint::LintReport;
+use crate::resolver::Resolver;
+use crate::{DnsRecord, QueryType, Result, ResultCode};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// SPF evaluation may trigger at most this many DNS lookups (RFC 7208 section 4.6.4).
+const SPF_LOOKUP_LIMIT: usize = 10;
+
+/// Audits the mail-related records of `domain`: MX, SPF, DKIM for each of
+/// `dkim_selectors`, DMARC and MTA-STS. `via` is the recursive resolver queried.
+pub fn mail_check(
+    resolver: &Resolver,
+    via: IpAddr,
+    domain: &str,
+    dkim_selectors: &[&str],
+) -> Result<LintReport> {
+    let mut report = LintReport::new(domain);
+
+    check_mx(resolver, via, domain, &mut report)?;
+
+    let spf: Vec<String> = txt_strings(resolver, via, domain)?
+        .into_iter()
+        .filter(|txt| is_versioned(txt, "v=spf1"))
+        .collect();
+    match spf.len() {
+        0 => report.warn(format!("No SPF record at {}", domain)),
+        1 => check_spf(&spf[0], &mut report),
+        _ => report.error(format!(
+            "{} has {} SPF records, only one is allowed",
+            domain,
+            spf.len()
+        )),
+    }
+
+    for selector in dkim_selectors {
+        let name = format!("{}._domainkey.{}", selector, domain);
+        let dkim = txt_strings(resolver, via, &name)?;
+        match dkim.first() {
+            Some(txt) => check_dkim(&name, txt, &mut report),
+            None => report.error(format!("No DKIM record at {}", name)),
+        }
+    }
+
+    let name = format!("_dmarc.{}", domain);
+    let dmarc: Vec<String> = txt_strings(resolver, via, &name)?
+        .into_iter()
+        .filter(|txt| is_versioned(txt, "v=DMARC1"))
+        .collect();
+    match dmarc.len() {
+        0 => report.warn(format!("No DMARC record at {}", name)),
+        1 => check_dmarc(&dmarc[0], &mut report),
+        _ => report.error(format!(
+            "{} has {} DMARC records, only one is allowed",
+            name,
+            dmarc.len()
+        )),
+    }
+
+    let name = format!("_mta-sts.{}", domain);
+    let sts: Vec<String> = txt_strings(resolver, via, &name)?
+        .into_iter()
+        .filter(|txt| is_versioned(txt, "v=STSv1"))
+        .collect();
+    match sts.first() {
+        Some(txt) => check_mta_sts(txt, &mut report),
+        None => report.warn(format!("No MTA-STS record at {}", name)),
+    }
+
+    Ok(report)
+}
+
+/// The TXT records at `name`, each with its character-strings concatenated.
+fn txt_strings(resolver: &Resolver, via: IpAddr, name: &str) -> Result<Vec<String>> {
+    let response = resolver.lookup(name, QueryType::TXT, (via, 53))?;
+    Ok(response
+        .answers
+        .into_iter()
+        .filter_map(|rec| match rec {
+            DnsRecord::TXT { data, .. } => Some(data.concat()),
+            _ => None,
+        })
+        .collect())
+}
+
+fn is_versioned(txt: &str, version: &str) -> bool {
+    txt == version
+        || txt.starts_with(&format!("{} ", version))
+        || txt.starts_with(&format!("{};", version))
+}
+
+/// Splits a `tag=value; tag=value` record as used by DKIM, DMARC and MTA-STS.
+fn tags(txt: &str) -> std::result::Result<Vec<(String, String)>, String> {
+    txt.split(';')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.find('=') {
+            Some(idx) => Ok((
+                tag[..idx].trim().to_string(),
+                tag[idx + 1..].trim().to_string(),
+            )),
+            None => Err(format!("malformed tag {:?}", tag)),
+        })
+        .collect()
+}
+
+fn tag<'a>(tags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(tag, _)| tag == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn check_mx(resolver: &Resolver, via: IpAddr, domain: &str, report: &mut LintReport) -> Result<()> {
+    let response = resolver.lookup(domain, QueryType::MX, (via, 53))?;
+    let hosts: Vec<(u16, String)> = response
+        .answers
+        .iter()
+        .filter_map(|rec| match rec {
+            DnsRecord::MX { priority, host, .. } => Some((*priority, host.clone())),
+            _ => None,
+        })
+        .collect();
+
+    if hosts.is_empty() {
+        report.warn(format!("No MX records at {}", domain));
+        return Ok(());
+    }
+
+    // A lone MX of priority 0 pointing at the root is a "null MX" (RFC 7505): the domain
+    // explicitly accepts no mail.
+    if hosts.len() == 1 && hosts[0].0 == 0 && hosts[0].1.is_empty() {
+        return Ok(());
+    }
+
+    for (_, host) in hosts {
+        let a = resolver.lookup(&host, QueryType::A, (via, 53))?;
+        let aaaa = resolver.lookup(&host, QueryType::AAAA, (via, 53))?;
+        let has_addr = a
+            .answers
+            .iter()
+            .chain(aaaa.answers.iter())
+            .any(|rec| matches!(rec, DnsRecord::A { .. } | DnsRecord::AAAA { .. }));
+
+        if !has_addr {
+            if a.header.rescode == ResultCode::NonexistantDomain {
+                report.error(format!("MX host {} doesn't exist", host));
+            } else {
+                report.error(format!("MX host {} has no address records", host));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_spf(spf: &str, report: &mut LintReport) {
+    let mut lookups = 0;
+    let mut has_all = false;
+    let mut has_redirect = false;
+
+    for term in spf.split_whitespace().skip(1) {
+        if let Some(idx) = term.find('=') {
+            match &term[..idx] {
+                "redirect" => {
+                    has_redirect = true;
+                    lookups += 1;
+                }
+                "exp" => {}
+                modifier => report.warn(format!("SPF has unknown modifier {:?}", modifier)),
+            }
+            continue;
+        }
+
+        let (qualifier, mechanism) = match term.chars().next() {
+            Some(c) if "+-~?".contains(c) => (c, &term[1..]),
+            _ => ('+', term),
+        };
+        let name = mechanism.split([':', '/']).next().unwrap_or("");
+
+        match name {
+            "all" => {
+                has_all = true;
+                if qualifier == '+' {
+                    report.error("SPF ends in +all, allowing anyone to send".to_string());
+                }
+            }
+            "include" | "exists" => {
+                lookups += 1;
+                if !mechanism.contains(':') {
+                    report.error(format!("SPF {} mechanism needs a domain", name));
+                }
+            }
+            "a" | "mx" => lookups += 1,
+            "ptr" => {
+                lookups += 1;
+                report.warn("SPF uses the deprecated ptr mechanism".to_string());
+            }
+            "ip4" | "ip6" => {
+                let value = &mechanism[name.len()..];
+                let addr = value
+                    .trim_start_matches(':')
+                    .split('/')
+                    .next()
+                    .unwrap_or("");
+                let valid = match name {
+                    "ip4" => addr.parse::<Ipv4Addr>().is_ok(),
+                    _ => addr.parse::<Ipv6Addr>().is_ok(),
+                };
+                if !value.starts_with(':') || !valid {
+                    report.error(format!("SPF has invalid {} mechanism {:?}", name, term));
+                }
+            }
+            _ => report.error(format!("SPF has unknown mechanism {:?}", term)),
+        }
+    }
+
+    if lookups > SPF_LOOKUP_LIMIT {
+        report.error(format!(
+            "SPF needs {} DNS lookups, more than the limit of {}",
+            lookups, SPF_LOOKUP_LIMIT
+        ));
+    }
+
+    if !has_all && !has_redirect {
+        report.warn("SPF has neither an all mechanism nor a redirect".to_string());
+    }
+}
+
+fn check_dkim(name: &str, txt: &str, report: &mut LintReport) {
+    let tags = match tags(txt) {
+        Ok(tags) => tags,
+        Err(e) => return report.error(format!("DKIM record at {} has a {}", name, e)),
+    };
+
+    if let Some(idx) = tags.iter().position(|(tag, _)| tag == "v") {
+        if idx != 0 || tags[idx].1 != "DKIM1" {
+            report.error(format!("DKIM record at {} has a bad version tag", name));
+        }
+    }
+
+    match tag(&tags, "p") {
+        None => report.error(format!("DKIM record at {} has no public key", name)),
+        Some("") => report.warn(format!("DKIM key at {} has been revoked", name)),
+        Some(_) => {}
+    }
+}
+
+fn check_dmarc(txt: &str, report: &mut LintReport) {
+    let tags = match tags(txt) {
+        Ok(tags) => tags,
+        Err(e) => return report.error(format!("DMARC record has a {}", e)),
+    };
+
+    match tag(&tags, "p") {
+        Some("none") => report.warn("DMARC policy is p=none, so nothing is enforced".to_string()),
+        Some("quarantine") | Some("reject") => {}
+        Some(policy) => report.error(format!("DMARC has invalid policy {:?}", policy)),
+        None => report.error("DMARC record has no policy".to_string()),
+    }
+
+    if let Some(pct) = tag(&tags, "pct") {
+        match pct.parse::<u8>() {
+            Ok(pct) if pct <= 100 => {}
+            _ => report.error(format!("DMARC has invalid pct {:?}", pct)),
+        }
+    }
+}
+
+fn check_mta_sts(txt: &str, report: &mut LintReport) {
+    let tags = match tags(txt) {
+        Ok(tags) => tags,
+        Err(e) => return report.error(format!("MTA-STS record has a {}", e)),
+    };
+
+    match tag(&tags, "id") {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= 32
+                && id.chars().all(|c| c.is_ascii_alphanumeric()) => {}
+        Some(id) => report.error(format!("MTA-STS has invalid id {:?}", id)),
+        None => report.error("MTA-STS record has no id".to_string()),
+    }
+}
@@ -1,176 +1,470 @@
-use diydns::{BytePacketBuffer, DnsPacket, DnsQuestion, QueryType, Result, ResultCode};
+use diydns::audit::{self, AuditResult};
+use diydns::config::Config;
+#[cfg(unix)]
+use diydns::control;
+use diydns::craft::{Craft, Defect};
+use diydns::export;
+use diydns::history::{self, Filter};
+use diydns::lint::{self, LintReport, Severity};
+use diydns::mail;
+use diydns::passive::{self, PassiveDb};
+use diydns::propagation::{self, Propagation};
+use diydns::ptr::{self, PtrCheck};
+use diydns::replay::{self, Summary};
+use diydns::resolver::{QueryOptions, Resolver, Step, Warnings};
+use diydns::scheduler::Scheduler;
+use diydns::server;
+use diydns::stats::Stats;
+#[cfg(unix)]
+use diydns::traffic::{self, Snapshot};
+use diydns::walk::{self, Exposure, Listing};
+use diydns::{DnsPacket, DnsRecord, QueryType, ResponseKind, Result};
 use std::default::Default;
 use std::env;
-use std::net::UdpSocket;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
 
 fn decode(packet: DnsPacket) {
-    println!("{:#?}", packet.header);
+    print!("{}", packet);
+}
 
-    for q in packet.questions {
-        println!("{:#?}", q);
+// Exit statuses for `resolve`, so scripts can branch on the outcome without parsing output.
+const EXIT_ANSWER: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_NODATA: i32 = 2;
+const EXIT_NXDOMAIN: i32 = 3;
+const EXIT_SERVFAIL: i32 = 4;
+const EXIT_TIMEOUT: i32 = 5;
+
+fn exit_status(result: &Result<DnsPacket>) -> i32 {
+    match result {
+        Ok(packet) => match packet.kind() {
+            ResponseKind::Answer => EXIT_ANSWER,
+            ResponseKind::NoData | ResponseKind::Referral => EXIT_NODATA,
+            ResponseKind::NxDomain => EXIT_NXDOMAIN,
+            ResponseKind::Error => EXIT_SERVFAIL,
+        },
+        Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => {
+            EXIT_TIMEOUT
+        }
+        Err(_) => EXIT_ERROR,
     }
-    for rec in packet.answers {
-        println!("{:#?}", rec);
+}
+
+fn report_ptr_check(check: &PtrCheck) {
+    if check.forward.is_empty() {
+        println!("{}: no PTR records at {}", check.addr, check.reverse_name);
+        process::exit(EXIT_NODATA);
     }
-    for rec in packet.authorities {
-        println!("{:#?}", rec);
+
+    for lookup in &check.forward {
+        let addrs: Vec<String> = lookup.addrs.iter().map(IpAddr::to_string).collect();
+        println!(
+            "{} -> {} -> [{}] {}",
+            check.addr,
+            lookup.host,
+            addrs.join(", "),
+            if lookup.matches(&check.addr) {
+                "ok"
+            } else {
+                "MISMATCH"
+            }
+        );
     }
-    for rec in packet.resources {
-        println!("{:#?}", rec);
+
+    if !check.is_consistent() {
+        process::exit(EXIT_ERROR);
     }
 }
 
-fn lookup(name: &str, qtype: QueryType, server: (&str, u16)) -> Result<DnsPacket> {
-    let mut packet: DnsPacket = Default::default();
-    packet.header.id = 6666;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet.questions.push(DnsQuestion {
-        name: name.to_owned(),
-        qtype,
-    });
+/// Arguments that aren't flags, skipping the values of flags listed in `value_flags`.
+fn positional<'a>(args: &'a [String], value_flags: &[&str]) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if value_flags.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            out.push(arg.as_str());
+        }
+    }
+    out
+}
 
-    let mut req_buffer = BytePacketBuffer::new();
-    req_buffer.write_packet(packet).unwrap();
+/// Every value given for a repeatable flag such as `--resolver 1.1.1.1`.
+fn flag_values<'a>(args: &'a [String], flag: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+fn report_propagation(propagation: &Propagation) {
+    println!("Zone: {}", propagation.zone);
+    if propagation.wildcard {
+        println!(
+            "Note: {} is a wildcard zone, answers don't prove a name exists",
+            propagation.zone
+        );
+    }
 
-    let socket = UdpSocket::bind(("0.0.0.0", 43210)).unwrap();
-    socket
-        .send_to(&req_buffer.buf[0..req_buffer.pos], server)
-        .unwrap();
+    for view in &propagation.views {
+        let server = match view.host {
+            Some(ref host) => format!("{} ({})", host, view.addr),
+            None => format!("{} (public)", view.addr),
+        };
+        let serial = match view.serial {
+            Some(serial) => format!(", serial {}", serial),
+            None => String::new(),
+        };
 
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf).unwrap();
+        match view.response {
+            Ok(ref packet) => {
+                let rdata: Vec<String> =
+                    packet.answers.iter().filter_map(DnsRecord::rdata).collect();
+                println!(
+                    "{}: {:?} [{}]{}",
+                    server,
+                    packet.header.rescode,
+                    rdata.join(", "),
+                    serial
+                );
+            }
+            Err(ref e) => println!("{}: error: {}{}", server, e, serial),
+        }
+    }
 
-    Ok(res_buffer.read_packet().unwrap())
+    let serials = propagation.serial_mismatch();
+    if !serials.is_empty() {
+        let serials: Vec<String> = serials.iter().map(u32::to_string).collect();
+        println!(
+            "Name servers disagree on the serial of {}: {}",
+            propagation.zone,
+            serials.join(", ")
+        );
+    }
+    let consistent = propagation.is_consistent();
+    if !consistent {
+        println!("Servers disagree");
+    }
+    if !consistent || !serials.is_empty() {
+        process::exit(EXIT_ERROR);
+    }
 }
 
-fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
-    // For now we're always starting with *a.root-servers.net*.
-    let mut ns = "198.41.0.4".to_string();
+fn report_lint(report: &LintReport) {
+    for finding in &report.findings {
+        println!("{:?}: {}", finding.severity, finding.message);
+    }
+    println!("{}: score {}/100", report.domain, report.score());
+
+    if report
+        .findings
+        .iter()
+        .any(|f| f.severity == Severity::Error)
+    {
+        process::exit(EXIT_ERROR);
+    }
+}
 
-    loop {
-        println!("Attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+fn report_exposure(exposure: &Exposure) {
+    println!("Zone: {}", exposure.zone);
+    for (host, addr) in &exposure.transfers {
+        println!("{} ({}) allows zone transfers to anyone", host, addr);
+    }
 
-        let ns_copy = ns.clone();
+    let (how, host, addr) = match exposure.listing {
+        Some(Listing::Transfer { ref host, addr }) => ("zone transfer", host, addr),
+        Some(Listing::NsecWalk { ref host, addr }) => ("walking NSEC records", host, addr),
+        None => {
+            println!("No zone transfers allowed, and no NSEC chain to walk");
+            return;
+        }
+    };
+    println!(
+        "Listed {}{} names by {} from {} ({}):",
+        if exposure.complete { "" } else { "at least " },
+        exposure.names.len(),
+        how,
+        host,
+        addr
+    );
+    for name in &exposure.names {
+        println!("  {}", name);
+    }
+    process::exit(EXIT_ERROR);
+}
 
-        let server = (ns_copy.as_str(), 53);
-        let response = lookup(qname, qtype.clone(), server)?;
+/// Layers the server configuration: defaults, then `--config <file>`, then `DIYDNS_*`
+/// environment variables, then the remaining flags.
+fn serve_config(args: &[String]) -> Result<Config> {
+    let mut config = base_config(args)?;
+    if let Some(path) = flag_values(args, "--config").first() {
+        config.load_file(path)?;
+    }
+    config.load_env()?;
+    apply_flags(&mut config, args)?;
+    Ok(config)
+}
 
-        // If there are entries in the answer section, and no errors, we are done!
-        if !response.answers.is_empty() && response.header.rescode == ResultCode::Success {
-            return Ok(response.clone());
+/// Builds the server configuration as `serve_config` does, but goes through the whole of
+/// it, returning every problem found instead of stopping at the first.
+fn check_config(args: &[String]) -> Vec<String> {
+    let mut config = match base_config(args) {
+        Ok(config) => config,
+        Err(e) => return vec![e.to_string()],
+    };
+
+    let mut problems = Vec::new();
+    if let Some(path) = flag_values(args, "--config").first() {
+        match config.check_file(path) {
+            Ok(diagnostics) => problems.extend(
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| format!("{}:{}", path, diagnostic)),
+            ),
+            Err(e) => problems.push(format!("{}: {}", path, e)),
         }
-
-        // We might also get a `NXDOMAIN` reply, which is the authoritative name servers
-        // way of telling us that the name doesn't exist.
-        if response.header.rescode == ResultCode::NonexistantDomain {
-            return Ok(response.clone());
+    }
+    for result in [config.load_env(), apply_flags(&mut config, args)] {
+        if let Err(e) = result {
+            problems.push(e.to_string());
         }
+    }
+    problems.extend(config.check().iter().map(ToString::to_string));
+    problems
+}
 
-        // Otherwise, we'll try to find a new nameserver based on NS and a corresponding A
-        // record in the additional section. If this succeeds, we can switch name server
-        // and retry the loop.
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns.clone();
+/// The defaults `serve` starts from, which `--stub` changes.
+fn base_config(args: &[String]) -> Result<Config> {
+    match args.iter().any(|arg| arg == "--stub") {
+        true => Config::stub(),
+        false => Ok(Config::default()),
+    }
+}
 
-            continue;
+/// Applies the flags given to `serve`, over whatever `config` has already.
+fn apply_flags(config: &mut Config, args: &[String]) -> Result<()> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => {
+                iter.next();
+            }
+            "--stub" | "--check-config" => {}
+            "--no-recursion" => config.recursion = false,
+            "--resolve-targets" => config.resolve_targets = true,
+            "--log-suspicious" => config.log_suspicious = true,
+            "--log-upstream" => config.log_upstream = true,
+            "--randomize-case" => config.randomize_case = true,
+            "--dnssec-passthrough" => config.dnssec_passthrough = true,
+            "--strict" => config.strict_zones = true,
+            "--cache" => config.cache = true,
+            "--transparent" => config.transparent = true,
+            "--rewrite-resolv-conf" => config.rewrite_resolv_conf = true,
+            flag if flag.starts_with("--") => {
+                let key = flag[2..].replace('-', "_");
+                let value = iter.next().map(String::as_str).unwrap_or("");
+                config.set(&key, value)?;
+            }
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unexpected argument {:?}", other),
+                ))
+            }
         }
+    }
 
-        // If not, we'll have to resolve the ip of a NS record. If no NS records exist,
-        // we'll go with what the last server told us.
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
-            None => return Ok(response.clone()),
-        };
-
-        // Here we go down the rabbit hole by starting _another_ lookup sequence in the
-        // midst of our current one. Hopefully, this will give us the IP of an appropriate
-        // name server.
-        let recursive_response = recursive_lookup(&new_ns_name, QueryType::A)?;
+    Ok(())
+}
 
-        // Finally, we pick a random ip from the result, and restart the loop. If no such
-        // record is available, we again return the last result we got.
-        if let Some(new_ns) = recursive_response.get_random_a() {
-            ns = new_ns.clone();
-        } else {
-            return Ok(response.clone());
-        }
+/// Searches the history file named by `--file`, or by the `history` option of the server
+/// configuration from `--config` and `DIYDNS_*` variables.
+fn search_history(args: &[String]) -> Result<Vec<history::Query>> {
+    let mut config = Config::default();
+    if let Some(path) = flag_values(args, "--config").first() {
+        config.load_file(path)?;
+    }
+    config.load_env()?;
+
+    let path = match flag_values(args, "--file").first() {
+        Some(path) => PathBuf::from(path),
+        None => config.history.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "no history file; give --file or set history in the configuration",
+            )
+        })?,
+    };
+
+    let mut filter = Filter::default();
+    if let Some(client) = flag_values(args, "--client").first() {
+        filter.client = Some(client.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("expected an address, found {:?}", client),
+            )
+        })?);
+    }
+    if let Some(last) = flag_values(args, "--last").first() {
+        filter.since = Some(history::unix_now().saturating_sub(history::parse_duration(last)?));
+    }
+    if let Some(name) = flag_values(args, "--name").first() {
+        filter.name = Some(name.to_string());
     }
-}
 
-fn serve() {
-    let socket = UdpSocket::bind(("0.0.0.0", 2053)).unwrap();
+    history::search(&path, &filter)
+}
 
-    println!("DNS running on port 2053...");
+/// The query `craft` is asked for: `--qname` (example.com by default), `--qtype` and `--id`,
+/// with whichever defects are flagged.
+fn craft_options(args: &[String]) -> Result<Craft> {
+    let number = |flag: &str| -> Result<Option<usize>> {
+        match flag_values(args, flag).first() {
+            Some(value) => value.parse().map(Some).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("expected a number for {}, found {:?}", flag, value),
+                )
+            }),
+            None => Ok(None),
+        }
+    };
+
+    let qname = flag_values(args, "--qname")
+        .first()
+        .copied()
+        .unwrap_or("example.com");
+    let qtype = match flag_values(args, "--qtype").first() {
+        Some(qtype) => qtype.parse()?,
+        None => QueryType::A,
+    };
+    let mut craft = Craft::new(qname, qtype);
+    craft.id = number("--id")?.unwrap_or(0) as u16;
+
+    for (flag, defect) in [
+        ("--long-label", Defect::LongLabel),
+        ("--long-name", Defect::LongName),
+        ("--bad-pointer", Defect::BadPointer),
+        ("--pointer-loop", Defect::PointerLoop),
+    ] {
+        if args.iter().any(|arg| arg == flag) {
+            craft.defects.push(defect);
+        }
+    }
+    if let Some(count) = number("--qdcount")? {
+        craft.defects.push(Defect::QuestionCount(count as u16));
+    }
+    if let Some(count) = number("--ancount")? {
+        craft.defects.push(Defect::AnswerCount(count as u16));
+    }
+    if let Some(count) = number("--nscount")? {
+        craft.defects.push(Defect::AuthorityCount(count as u16));
+    }
+    if let Some(count) = number("--arcount")? {
+        craft.defects.push(Defect::AdditionalCount(count as u16));
+    }
+    if let Some(len) = number("--truncate-at")? {
+        craft.defects.push(Defect::TruncateAt(len));
+    }
 
-    loop {
-        let mut req_buffer = BytePacketBuffer::new();
-        let (_, src) = match socket.recv_from(&mut req_buffer.buf) {
-            Ok(x) => x,
-            Err(e) => {
-                println!("Failed to read from UDP socket: {:?}", e);
-                continue;
-            }
-        };
+    Ok(craft)
+}
 
-        let request = match req_buffer.read_packet() {
-            Ok(packet) => packet,
-            Err(error) => {
-                println!("Failed to parse UDP query packet: {:?}", error);
-                continue;
-            }
-        };
+/// Sends a crafted query to `server` and prints whatever comes back.
+fn send_crafted(data: &[u8], server: &str) -> Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_read_timeout(Some(std::time::Duration::from_secs(2)))?;
+    socket.connect(server)?;
+    socket.send(data)?;
+
+    let mut buf = [0; 512];
+    let len = match socket.recv(&mut buf) {
+        Ok(len) => len,
+        // Silence is an answer too, and a common one to broken queries.
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+            println!("No response from {}", server);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    match DnsPacket::from_bytes(&buf[..len]) {
+        Ok(packet) => decode(packet),
+        Err(e) => {
+            println!("Unparseable response: {}", e);
+            print_hex(&buf[..len]);
+        }
+    }
+    Ok(())
+}
 
-        let mut packet: DnsPacket = Default::default();
-        packet.header.id = request.header.id;
-        packet.header.recursion_desired = true;
-        packet.header.recursion_available = true;
-        packet.header.response = true;
-
-        if request.questions.is_empty() {
-            packet.header.rescode = ResultCode::FormError;
-        } else {
-            let question = &request.questions[0];
-            println!("Received query: {:?}", question);
-
-            if let Ok(result) = recursive_lookup(&question.name, question.qtype) {
-                packet.questions.push(question.clone());
-                packet.header.questions = 1;
-                packet.header.rescode = result.header.rescode;
-
-                packet.answers = result.answers;
-                packet.authorities = result.authorities;
-                packet.resources = result.resources;
-
-                packet.header.answers = result.header.answers;
-                packet.header.authoritative_entries = result.header.authoritative_entries;
-                packet.header.resource_entries = result.header.resource_entries;
-            } else {
-                packet.header.rescode = ResultCode::ServerFail;
-            }
+fn print_hex(data: &[u8]) {
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{:04x}  {}", line * 16, bytes.join(" "));
+    }
+}
 
-            println!("{:#?}", packet);
+/// Redraws the server's totals from the control socket at `path` every second, until
+/// interrupted or the server goes away.
+#[cfg(unix)]
+fn top(path: &Path) -> Result<()> {
+    let mut last: Option<(Instant, u64)> = None;
 
-            let mut res_buffer = BytePacketBuffer::new();
-            if let Err(e) = res_buffer.write_packet(packet) {
-                println!("Failed to encode UDP response packet: {:?}", e);
-                continue;
-            };
+    loop {
+        let snapshot: Snapshot = control::request(path, "stats 10")?.parse()?;
+        let now = Instant::now();
+        let rate = last.map(|(then, queries)| {
+            snapshot.queries.saturating_sub(queries) as f64 / now.duration_since(then).as_secs_f64()
+        });
+        last = Some((now, snapshot.queries));
+
+        // Home the cursor and clear the screen before drawing.
+        print!("\x1b[H\x1b[2J{}", traffic::render(&snapshot, rate));
+        thread::sleep(Duration::from_secs(1));
+    }
+}
 
-            let len = res_buffer.pos;
-            let data = match res_buffer.get_range(0, len) {
-                Ok(x) => x,
-                Err(e) => {
-                    println!("Failed to retrieve response buffer: {:?}", e);
-                    continue;
-                }
-            };
+fn report_replay(summary: &Summary) {
+    let rejected: usize = summary.rejected.values().sum();
+    let panicked: usize = summary.panics.values().map(|site| site.inputs.len()).sum();
+    println!(
+        "{} inputs: {} parsed, {} rejected, {} panicked at {} sites",
+        summary.parsed + rejected + panicked,
+        summary.parsed,
+        rejected,
+        panicked,
+        summary.panics.len()
+    );
+
+    if !summary.rejected.is_empty() {
+        println!();
+        for (error, count) in &summary.rejected {
+            println!("  {:>6}  {}", count, error);
+        }
+    }
 
-            if let Err(e) = socket.send_to(data, src) {
-                println!("Failed to send response buffer: {:?}", e);
-                continue;
-            };
+    for (site, failure) in &summary.panics {
+        println!();
+        println!(
+            "Panicked at {} ({} inputs): {}",
+            site,
+            failure.inputs.len(),
+            failure.first.message
+        );
+        for input in &failure.inputs {
+            println!("  {}", input.display());
         }
+        println!("Backtrace for {}:", failure.inputs[0].display());
+        print!("{}", failure.first.backtrace);
     }
 }
 
@@ -179,21 +473,296 @@ fn main() {
 
     match args[1].as_str() {
         "decode" => {
-            let mut buffer = BytePacketBuffer::from_file(args.get(2).unwrap()).unwrap();
-            let packet = buffer.read_packet().unwrap();
-            decode(packet);
+            let path = args.get(2).unwrap();
+            let data = fs::read(path).unwrap();
+            match DnsPacket::from_bytes_with_warnings(&data) {
+                Ok((packet, warnings)) => {
+                    decode(packet);
+                    for warning in warnings {
+                        println!(";; WARNING: {}", warning);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to decode {}: {}", path, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
         }
         "resolve" => {
+            let short = args[2..].iter().any(|arg| arg == "--short");
+            let output = flag_values(&args[2..], "--output")
+                .first()
+                .map(|format| format.parse::<export::Format>().unwrap());
+            let names = positional(&args[2..], &["--raw-type", "--class", "--output"]);
+            let qtype = match flag_values(&args[2..], "--raw-type").first() {
+                Some(num) => QueryType::from_num(num.parse().unwrap()),
+                None => QueryType::A,
+            };
+            let mut options = QueryOptions::default();
+            if let Some(num) = flag_values(&args[2..], "--class").first() {
+                options.qclass = num.parse().unwrap();
+            }
+            let server = ("8.8.8.8", 53);
+            let verbose = !short && output.is_none();
+            let warnings = Warnings::new();
+            let resolver =
+                Resolver::new(Stats::new(verbose), Scheduler::default()).with_warnings(&warnings);
+
+            let mut status = EXIT_ANSWER;
+            let mut records = Vec::new();
+            for name in names {
+                let result = resolver.lookup_with(name, qtype, &options, server);
+                if status == EXIT_ANSWER {
+                    status = exit_status(&result);
+                }
+                match result {
+                    Ok(packet) if output.is_some() => records.extend(packet.answers),
+                    Ok(packet) if short => {
+                        for rdata in packet.answers.iter().filter_map(DnsRecord::rdata) {
+                            println!("{}", rdata);
+                        }
+                    }
+                    Ok(packet) => decode(packet),
+                    // Kept out of the exported records, which may be going to a file.
+                    Err(e) if output.is_some() => eprintln!("Failed to resolve {}: {}", name, e),
+                    Err(e) => println!("Failed to resolve {}: {}", name, e),
+                }
+                for (server, warning) in warnings.take() {
+                    if verbose {
+                        println!(";; WARNING from {}: {}", server, warning);
+                    } else {
+                        eprintln!("Warning from {} for {}: {}", server, name, warning);
+                    }
+                }
+            }
+            if let Some(format) = output {
+                print!("{}", export::export(format, &records));
+            }
+            process::exit(status);
+        }
+        "trace" => {
             let name = args.get(2).unwrap();
-            let qtype = QueryType::A;
+            let resolver = Resolver::new(Stats::new(true), Scheduler::default());
+            for step in resolver.iterate(name, QueryType::A) {
+                match step {
+                    Ok(Step::Referral { server, next, .. }) => {
+                        println!("{}: referral to {}", server, next)
+                    }
+                    Ok(Step::Final { server, response }) => {
+                        println!("{}: {}", server, response.header.rescode);
+                        decode(response);
+                    }
+                    Err(e) => {
+                        println!("Failed to trace {}: {}", name, e);
+                        process::exit(EXIT_ERROR);
+                    }
+                }
+            }
+        }
+        "check-ptr" => {
+            let addr: IpAddr = args.get(2).unwrap().parse().unwrap();
             let server = ("8.8.8.8", 53);
-            let packet = lookup(&name, qtype, server).unwrap();
-            decode(packet);
+            let resolver = Resolver::new(Stats::new(true), Scheduler::default());
+            match ptr::check_ptr(&resolver, server, addr) {
+                Ok(check) => report_ptr_check(&check),
+                Err(e) => {
+                    println!("Failed to check {}: {}", addr, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "propagation" => {
+            let name = positional(&args[2..], &["--resolver"])[0];
+            let mut public: Vec<IpAddr> = flag_values(&args[2..], "--resolver")
+                .iter()
+                .map(|addr| addr.parse().unwrap())
+                .collect();
+            if public.is_empty() {
+                public = propagation::DEFAULT_PUBLIC_RESOLVERS
+                    .iter()
+                    .map(|addr| addr.parse().unwrap())
+                    .collect();
+            }
+
+            let resolver = Resolver::new(Stats::new(true), Scheduler::default());
+            match propagation::check_propagation(&resolver, name, QueryType::A, &public) {
+                Ok(propagation) => report_propagation(&propagation),
+                Err(e) => {
+                    println!("Failed to check propagation of {}: {}", name, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "lint" => {
+            let domain = args.get(2).unwrap();
+            let via: IpAddr = "8.8.8.8".parse().unwrap();
+            let resolver = Resolver::new(Stats::new(true), Scheduler::default());
+            match lint::lint(&resolver, via, domain) {
+                Ok(report) => report_lint(&report),
+                Err(e) => {
+                    println!("Failed to lint {}: {}", domain, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "zone-walk" => {
+            let domain = args.get(2).unwrap();
+            let via: IpAddr = "8.8.8.8".parse().unwrap();
+            let resolver = Resolver::new(Stats::new(false), Scheduler::default());
+            match walk::exposure(&resolver, via, domain) {
+                Ok(exposure) => report_exposure(&exposure),
+                Err(e) => {
+                    println!("Failed to check {}: {}", domain, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "mail-check" => {
+            let domain = positional(&args[2..], &["--dkim-selector"])[0];
+            let selectors = flag_values(&args[2..], "--dkim-selector");
+            let via: IpAddr = "8.8.8.8".parse().unwrap();
+            let resolver = Resolver::new(Stats::new(true), Scheduler::default());
+            match mail::mail_check(&resolver, via, domain, &selectors) {
+                Ok(report) => report_lint(&report),
+                Err(e) => {
+                    println!("Failed to check mail records of {}: {}", domain, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "audit" => {
+            let results = audit::run_audit();
+            for result in &results {
+                let verdict = match result.error {
+                    Some(ref e) => format!("FAILED ({})", e),
+                    None if !result.rejected => "ACCEPTED".to_string(),
+                    None if !result.counted => "rejected, but not counted".to_string(),
+                    None => "rejected".to_string(),
+                };
+                println!("{:?}: {}", result.attack, verdict);
+            }
+            if !results.iter().all(AuditResult::passed) {
+                process::exit(EXIT_ERROR);
+            }
+        }
+        "serve" if args[2..].iter().any(|arg| arg == "--check-config") => {
+            let problems = check_config(&args[2..]);
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                process::exit(EXIT_ERROR);
+            }
+            println!("Configuration OK");
+        }
+        "serve" => {
+            let config = match serve_config(&args[2..]) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("Invalid configuration: {}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            };
+
+            let resolver = Resolver::new(Stats::new(config.log_suspicious), Scheduler::default());
+            if let Err(e) = server::serve(&resolver, &config) {
+                println!("Server failed: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        "history" => match search_history(&args[2..]) {
+            Ok(queries) => {
+                for query in queries {
+                    println!("{}", query);
+                }
+            }
+            Err(e) => {
+                println!("Failed to search the query history: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        },
+        #[cfg(unix)]
+        "control" => {
+            let socket = Path::new(&args[2]);
+            match control::request(socket, &args[3..].join(" ")) {
+                Ok(reply) => print!("{}", reply),
+                Err(e) => {
+                    println!("Failed to reach {}: {}", socket.display(), e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "passive" => {
+            let (interface, path) = (&args[2], Path::new(&args[3]));
+            let result = PassiveDb::open(path).and_then(|db| passive::capture(interface, &db));
+            if let Err(e) = result {
+                println!("Failed to capture on {}: {}", interface, e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        "passive-search" => {
+            let (path, query) = (Path::new(&args[2]), &args[3]);
+            match passive::search(path, query) {
+                Ok(observations) => {
+                    for observation in observations {
+                        println!("{}", observation);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to search {}: {}", path.display(), e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "fuzz-replay" => {
+            let dir = Path::new(&args[2]);
+            match replay::replay_dir(dir) {
+                Ok(summary) => {
+                    report_replay(&summary);
+                    if !summary.panics.is_empty() {
+                        process::exit(EXIT_ERROR);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to read {}: {}", dir.display(), e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        "craft" => {
+            let data = match craft_options(&args[2..]).and_then(|craft| craft.build()) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("Failed to craft a packet: {}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            };
+            let result = match (
+                flag_values(&args[2..], "--out").first(),
+                flag_values(&args[2..], "--send").first(),
+            ) {
+                (Some(path), _) => fs::write(path, &data),
+                (None, Some(server)) => send_crafted(&data, server),
+                (None, None) => {
+                    print_hex(&data);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                println!("Failed: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        #[cfg(unix)]
+        "top" => {
+            let socket = Path::new(&args[2]);
+            if let Err(e) = top(socket) {
+                println!("Failed to read stats from {}: {}", socket.display(), e);
+                process::exit(EXIT_ERROR);
+            }
         }
-        "serve" => serve(),
         _ => {
-            println!("Unknown subcommand! Acceptable inputs: decode, resolve, serve");
-            return;
+            println!("Unknown subcommand! Acceptable inputs: decode, resolve, trace, check-ptr, propagation, lint, zone-walk, mail-check, audit, serve, history, control, top, passive, passive-search, fuzz-replay, craft");
         }
     }
 }
@@ -0,0 +1,384 @@
+//! Passive DNS: watching the DNS responses that cross a network, say on a switch's mirror
+//! port, and keeping every answer record seen with when it was first and last seen. Later
+//! on that tells you what a name used to point at, or every name that has pointed at an
+//! address.
+//!
+//! Records are kept in a file of tab-separated lines: first and last seen as unix times,
+//! owner name, type and data in presentation form. The database is held in memory while
+//! capturing and the file rewritten every so often.
+
+use crate::history::{self, format_time};
+use crate::{DnsPacket, QueryType, Result, ResultCode};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+const DNS_PORT: u16 = 53;
+
+/// One record and when it was seen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Observation {
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub name: String,
+    pub qtype: QueryType,
+    pub rdata: String,
+}
+
+impl Observation {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.first_seen,
+            self.last_seen,
+            self.name.replace(char::is_control, "?"),
+            self.qtype.to_num(),
+            self.rdata.replace(char::is_control, "?")
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Observation> {
+        let mut fields = line.splitn(5, '\t');
+        Some(Observation {
+            first_seen: fields.next()?.parse().ok()?,
+            last_seen: fields.next()?.parse().ok()?,
+            name: fields.next()?.to_string(),
+            qtype: QueryType::from_num(fields.next()?.parse().ok()?),
+            rdata: fields.next()?.to_string(),
+        })
+    }
+}
+
+/// `2024-01-31 12:00:00 2024-02-03 08:15:00 www.example.com A 192.0.2.1`: first seen, last
+/// seen and the record, with the times in UTC.
+impl fmt::Display for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            format_time(self.first_seen),
+            format_time(self.last_seen),
+            self.name,
+            self.qtype,
+            self.rdata
+        )
+    }
+}
+
+/// Owner name, type and data: what makes a record the same record.
+type Key = (String, u16, String);
+
+/// The records seen so far, and the file they're kept in.
+#[derive(Debug)]
+pub struct PassiveDb {
+    path: PathBuf,
+    seen: Mutex<BTreeMap<Key, (u64, u64)>>,
+}
+
+impl PassiveDb {
+    /// Loads the database at `path`, or starts an empty one if there's no file yet.
+    pub fn open(path: &Path) -> Result<PassiveDb> {
+        let seen = read(path)?
+            .into_iter()
+            .map(|obs| {
+                (
+                    (obs.name, obs.qtype.to_num(), obs.rdata),
+                    (obs.first_seen, obs.last_seen),
+                )
+            })
+            .collect();
+
+        Ok(PassiveDb {
+            path: path.to_path_buf(),
+            seen: Mutex::new(seen),
+        })
+    }
+
+    /// Notes the answer records of `response`, seen at unix time `time`. Anything but a
+    /// successful response is ignored.
+    pub fn record(&self, response: &DnsPacket, time: u64) {
+        if !response.header.response || response.header.rescode != ResultCode::Success {
+            return;
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        for rec in &response.answers {
+            let rdata = match rec.rdata() {
+                Some(rdata) => rdata,
+                None => continue,
+            };
+            let key = (
+                rec.domain().trim_end_matches('.').to_lowercase(),
+                rec.query_type().to_num(),
+                rdata,
+            );
+            let times = seen.entry(key).or_insert((time, time));
+            times.0 = times.0.min(time);
+            times.1 = times.1.max(time);
+        }
+    }
+
+    /// Every record seen, in name order.
+    pub fn observations(&self) -> Vec<Observation> {
+        self.seen
+            .lock()
+            .unwrap()
+            .iter()
+            .map(
+                |((name, qtype, rdata), &(first_seen, last_seen))| Observation {
+                    first_seen,
+                    last_seen,
+                    name: name.clone(),
+                    qtype: QueryType::from_num(*qtype),
+                    rdata: rdata.clone(),
+                },
+            )
+            .collect()
+    }
+
+    /// Writes the database out to its file.
+    pub fn flush(&self) -> Result<()> {
+        let lines: String = self
+            .observations()
+            .iter()
+            .map(Observation::to_line)
+            .collect();
+
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, lines)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// The records in the database at `path` for `query`: a name, matching records owned by it
+/// or names under it, or an address, matching the A and AAAA records that point at it.
+pub fn search(path: &Path, query: &str) -> Result<Vec<Observation>> {
+    let matches: Box<dyn Fn(&Observation) -> bool> = match query.parse::<IpAddr>() {
+        Ok(addr) => Box::new(move |obs| obs.rdata.parse() == Ok(addr)),
+        Err(_) => {
+            let name = query.trim_matches('.').to_lowercase();
+            let suffix = format!(".{}", name);
+            Box::new(move |obs| obs.name == name || obs.name.ends_with(&suffix))
+        }
+    };
+
+    Ok(read(path)?.into_iter().filter(|obs| matches(obs)).collect())
+}
+
+/// Every record in the file. Lines that can't be read are skipped.
+fn read(path: &Path) -> Result<Vec<Observation>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut observations = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Some(obs) = Observation::from_line(&line?) {
+            observations.push(obs);
+        }
+    }
+
+    Ok(observations)
+}
+
+/// The DNS message in an Ethernet frame, if it holds a UDP datagram from port 53 over IPv4
+/// or IPv6, with or without a VLAN tag. Fragments and IPv6 extension headers aren't
+/// followed.
+pub fn dns_payload(frame: &[u8]) -> Option<&[u8]> {
+    let u16_at = |data: &[u8], pos: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+    };
+
+    let mut ethertype = u16_at(frame, 12)?;
+    let mut pos = 14;
+    if ethertype == ETHERTYPE_VLAN {
+        ethertype = u16_at(frame, 16)?;
+        pos = 18;
+    }
+    let ip = frame.get(pos..)?;
+
+    let udp = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = (*ip.first()? & 0x0F) as usize * 4;
+            // More fragments to come, or not the first fragment.
+            let fragmented = u16_at(ip, 6)? & 0x3FFF != 0;
+            if *ip.get(9)? != IPPROTO_UDP || fragmented {
+                return None;
+            }
+            ip.get(header_len..)?
+        }
+        ETHERTYPE_IPV6 => {
+            if *ip.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            ip.get(40..)?
+        }
+        _ => return None,
+    };
+
+    if u16_at(udp, 0)? != DNS_PORT {
+        return None;
+    }
+    let len = u16_at(udp, 4)? as usize;
+    udp.get(8..len.max(8))
+}
+
+/// Records the DNS responses seen on `interface` into `db` until asked to stop by SIGTERM
+/// or SIGINT, flushing it to its file every minute and on the way out. The interface is
+/// put in promiscuous mode, and capturing needs root or CAP_NET_RAW.
+#[cfg(target_os = "linux")]
+pub fn capture(interface: &str, db: &PassiveDb) -> Result<()> {
+    use crate::init;
+    use crate::log::{self, LogLevel};
+    use std::time::{Duration, Instant};
+
+    const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+    init::install_signal_handlers();
+    let socket = RawSocket::open(interface)?;
+
+    let mut last_flush = Instant::now();
+    let mut frame = vec![0; 65536];
+    while !init::shutdown_requested() {
+        if let Some(len) = socket.recv(&mut frame)? {
+            if let Some(packet) = dns_payload(&frame[..len]).and_then(|payload| {
+                // Responses too big for the codec are passed over.
+                DnsPacket::from_bytes(payload).ok()
+            }) {
+                db.record(&packet, history::unix_now());
+            }
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            if let Err(e) = db.flush() {
+                log::write(
+                    LogLevel::Error,
+                    &format!("Failed to write the passive DNS database: {}", e),
+                );
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    db.flush()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn capture(_interface: &str, _db: &PassiveDb) -> Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "capturing packets needs Linux packet sockets",
+    ))
+}
+
+/// A packet socket seeing every frame on one interface.
+#[cfg(target_os = "linux")]
+struct RawSocket {
+    fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl RawSocket {
+    fn open(interface: &str) -> Result<RawSocket> {
+        use std::ffi::CString;
+        use std::io::Error;
+        use std::mem;
+
+        let name = CString::new(interface)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "bad interface name"))?;
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("unknown interface {:?}", interface),
+            ));
+        }
+
+        let protocol = (libc::ETH_P_ALL as u16).to_be() as libc::c_int;
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // Closes the socket if the rest of the setup fails.
+        let socket = RawSocket { fd };
+
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol as u16;
+        addr.sll_ifindex = index as libc::c_int;
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut membership: libc::packet_mreq = unsafe { mem::zeroed() };
+        membership.mr_ifindex = index as libc::c_int;
+        membership.mr_type = libc::PACKET_MR_PROMISC as u16;
+        socket.set_option(libc::SOL_PACKET, libc::PACKET_ADD_MEMBERSHIP, &membership)?;
+
+        // Wakes up now and then to check for shutdown.
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 250_000,
+        };
+        socket.set_option(libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)?;
+
+        Ok(socket)
+    }
+
+    fn set_option<T>(&self, level: libc::c_int, name: libc::c_int, value: &T) -> Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                name,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    /// The next frame, or `None` if none came before the timeout or a signal.
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let len =
+            unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if len >= 0 {
+            return Ok(Some(len as usize));
+        }
+
+        let error = std::io::Error::last_os_error();
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => Ok(None),
+            _ => Err(error),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
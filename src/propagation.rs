@@ -0,0 +1,173 @@
+use crate::resolver::Resolver;
+use crate::{DnsPacket, DnsRecord, QueryType, Result, ResultCode};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, ToSocketAddrs};
+
+/// Public resolvers queried alongside the zone's own name servers, unless the caller
+/// supplies its own list.
+pub const DEFAULT_PUBLIC_RESOLVERS: [&str; 3] = ["8.8.8.8", "1.1.1.1", "9.9.9.9"];
+
+/// One server's view of the name being checked.
+#[derive(Debug)]
+pub struct ServerView {
+    /// The name server's host name, or `None` for a public resolver.
+    pub host: Option<String>,
+    pub addr: IpAddr,
+    pub response: Result<DnsPacket>,
+    /// The serial of the zone's SOA as a name server has it, which shows whether it has
+    /// picked up the latest version of the zone. `None` for public resolvers, and for name
+    /// servers that didn't give one.
+    pub serial: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Propagation {
+    pub zone: String,
+    pub views: Vec<ServerView>,
+    /// Whether the zone answers for made-up names, in which case an answer doesn't show
+    /// that the queried name itself exists.
+    pub wildcard: bool,
+}
+
+impl Propagation {
+    /// True when every server that answered gave the same set of records.
+    pub fn is_consistent(&self) -> bool {
+        let mut answers = self.views.iter().filter_map(|view| match view.response {
+            Ok(ref packet) => Some(sorted_answers(packet)),
+            Err(_) => None,
+        });
+
+        match answers.next() {
+            Some(first) => answers.all(|other| other == first),
+            None => true,
+        }
+    }
+
+    /// The serials the zone's name servers gave, lowest first, if they don't all agree.
+    /// Empty when they do.
+    pub fn serial_mismatch(&self) -> Vec<u32> {
+        let mut serials: Vec<u32> = self.views.iter().filter_map(|view| view.serial).collect();
+        serials.sort_unstable();
+        serials.dedup();
+        match serials.len() {
+            0 | 1 => Vec::new(),
+            _ => serials,
+        }
+    }
+}
+
+fn sorted_answers(packet: &DnsPacket) -> Vec<String> {
+    let mut rdata: Vec<String> = packet.answers.iter().filter_map(DnsRecord::rdata).collect();
+    rdata.sort();
+    rdata
+}
+
+/// The serial of `zone`'s SOA according to the name server at `addr`.
+fn soa_serial(resolver: &Resolver, zone: &str, addr: IpAddr) -> Option<u32> {
+    let response = resolver.lookup(zone, QueryType::SOA, (addr, 53)).ok()?;
+    response.answers.iter().find_map(|rec| match rec {
+        DnsRecord::SOA { domain, serial, .. } if domain == zone => Some(*serial),
+        _ => None,
+    })
+}
+
+/// Asks `server` about a random name under `zone`. A zone that answers for a label nobody
+/// could have created is a wildcard, which makes NXDOMAIN-based checks meaningless.
+pub fn has_wildcard<A: ToSocketAddrs>(resolver: &Resolver, server: A, zone: &str) -> bool {
+    let probe = format!("diydns-probe-{:016x}.{}", rand::random::<u64>(), zone);
+    match resolver.lookup(&probe, QueryType::A, server) {
+        Ok(response) => {
+            response.header.rescode == ResultCode::Success && !response.answers.is_empty()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Finds the zone containing `name` by asking `via` for NS records at each ancestor in
+/// turn, returning the zone apex and the addresses of its name servers.
+pub fn find_zone_servers(
+    resolver: &Resolver,
+    via: IpAddr,
+    name: &str,
+) -> Result<(String, Vec<(String, IpAddr)>)> {
+    let mut candidate = name;
+
+    loop {
+        let response = resolver.lookup(candidate, QueryType::NS, (via, 53))?;
+        let hosts: Vec<String> = response
+            .answers
+            .iter()
+            .filter_map(|rec| match rec {
+                DnsRecord::NS { domain, host, .. } if domain == candidate => Some(host.clone()),
+                _ => None,
+            })
+            .collect();
+
+        if !hosts.is_empty() {
+            let mut servers = Vec::new();
+            for host in hosts {
+                let response = resolver.lookup(&host, QueryType::A, (via, 53))?;
+                for rec in response.answers {
+                    if let DnsRecord::A { addr, .. } = rec {
+                        servers.push((host.clone(), IpAddr::V4(addr)));
+                    }
+                }
+            }
+
+            return Ok((candidate.to_string(), servers));
+        }
+
+        candidate = match candidate.find('.') {
+            Some(idx) => &candidate[idx + 1..],
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("No zone found for {}", name),
+                ))
+            }
+        };
+    }
+}
+
+/// Queries `name` against each of its zone's name servers and each of `public_resolvers`,
+/// and asks each name server for the serial of the zone.
+pub fn check_propagation(
+    resolver: &Resolver,
+    name: &str,
+    qtype: QueryType,
+    public_resolvers: &[IpAddr],
+) -> Result<Propagation> {
+    let via = public_resolvers.first().copied().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "At least one public resolver is required",
+        )
+    })?;
+
+    let (zone, servers) = find_zone_servers(resolver, via, name)?;
+    let wildcard = has_wildcard(resolver, (via, 53), &zone);
+
+    let mut views = Vec::new();
+    for (host, addr) in servers {
+        views.push(ServerView {
+            host: Some(host),
+            addr,
+            response: resolver.lookup(name, qtype, (addr, 53)),
+            serial: soa_serial(resolver, &zone, addr),
+        });
+    }
+    for addr in public_resolvers {
+        views.push(ServerView {
+            host: None,
+            addr: *addr,
+            response: resolver.lookup(name, qtype, (*addr, 53)),
+            serial: None,
+        });
+    }
+
+    Ok(Propagation {
+        zone,
+        views,
+        wildcard,
+    })
+}
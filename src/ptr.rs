@@ -0,0 +1,91 @@
+use crate::resolver::Resolver;
+use crate::{DnsRecord, QueryType, Result};
+use std::net::IpAddr;
+
+/// The name under `in-addr.arpa` or `ip6.arpa` that holds the PTR record for `addr`.
+pub fn reverse_name(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let octets = addr.octets();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa",
+                octets[3], octets[2], octets[1], octets[0]
+            )
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::new();
+            for octet in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", octet & 0x0F, octet >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// The forward addresses found for one of the names an address's PTR records point at.
+#[derive(Debug, Clone)]
+pub struct ForwardLookup {
+    pub host: String,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl ForwardLookup {
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        self.addrs.contains(addr)
+    }
+}
+
+/// The outcome of checking that an address's reverse and forward records agree.
+#[derive(Debug, Clone)]
+pub struct PtrCheck {
+    pub addr: IpAddr,
+    pub reverse_name: String,
+    pub forward: Vec<ForwardLookup>,
+}
+
+impl PtrCheck {
+    /// True when at least one PTR target resolves back to the original address.
+    pub fn is_consistent(&self) -> bool {
+        self.forward.iter().any(|lookup| lookup.matches(&self.addr))
+    }
+}
+
+/// Resolves the PTR records for `addr`, then each returned name's A or AAAA records, using
+/// `server` for every query.
+pub fn check_ptr(resolver: &Resolver, server: (&str, u16), addr: IpAddr) -> Result<PtrCheck> {
+    let reverse_name = reverse_name(&addr);
+    let response = resolver.lookup(&reverse_name, QueryType::PTR, server)?;
+
+    let qtype = match addr {
+        IpAddr::V4(_) => QueryType::A,
+        IpAddr::V6(_) => QueryType::AAAA,
+    };
+
+    let mut forward = Vec::new();
+    for rec in &response.answers {
+        if let DnsRecord::PTR { host, .. } = rec {
+            let response = resolver.lookup(host, qtype, server)?;
+            let addrs = response
+                .answers
+                .iter()
+                .filter_map(|rec| match rec {
+                    DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+                    DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+                    _ => None,
+                })
+                .collect();
+
+            forward.push(ForwardLookup {
+                host: host.clone(),
+                addrs,
+            });
+        }
+    }
+
+    Ok(PtrCheck {
+        addr,
+        reverse_name,
+        forward,
+    })
+}
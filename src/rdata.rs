@@ -0,0 +1,108 @@
+//! Support for record types the codec doesn't know, plugged in from outside the crate. A
+//! record of such a type is still a [`DnsRecord::Unknown`] holding its data as sent, but
+//! with a codec registered for the type, that data is checked as it's read, printed in the
+//! type's own presentation format rather than RFC 3597's generic one, and can be written
+//! in zone files under the type's mnemonic.
+//!
+//! ```
+//! use diydns::rdata::{self, RDataCodec};
+//! use diydns::{QueryType, Result};
+//! use std::io::{Error, ErrorKind};
+//!
+//! /// A private-use type holding a single counter.
+//! struct Counter;
+//!
+//! impl RDataCodec for Counter {
+//!     fn mnemonic(&self) -> &str {
+//!         "COUNTER"
+//!     }
+//!
+//!     fn present(&self, data: &[u8]) -> Result<String> {
+//!         match data {
+//!             [hi, lo] => Ok(u16::from_be_bytes([*hi, *lo]).to_string()),
+//!             _ => Err(Error::new(ErrorKind::InvalidData, "expected two bytes")),
+//!         }
+//!     }
+//!
+//!     fn parse(&self, text: &str) -> Result<Vec<u8>> {
+//!         let value: u16 = text
+//!             .parse()
+//!             .map_err(|_| Error::new(ErrorKind::InvalidInput, "expected a number"))?;
+//!         Ok(value.to_be_bytes().to_vec())
+//!     }
+//! }
+//!
+//! rdata::register_rdata_codec(65280, Box::new(Counter)).unwrap();
+//! assert_eq!(QueryType::from_num(65280).to_string(), "COUNTER");
+//! ```
+//!
+//! [`DnsRecord::Unknown`]: crate::DnsRecord::Unknown
+
+use crate::{QueryType, Result};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, RwLock};
+
+/// Parsing, writing and printing the data of one record type.
+pub trait RDataCodec: Send + Sync {
+    /// The type's name in presentation format, such as `TXT`.
+    fn mnemonic(&self) -> &str;
+
+    /// The record data in presentation format, or an error if `data` isn't valid wire
+    /// format for the type. Records read off the wire that fail this are rejected.
+    fn present(&self, data: &[u8]) -> Result<String>;
+
+    /// Wire format record data from presentation format, as written in a zone file.
+    fn parse(&self, text: &str) -> Result<Vec<u8>>;
+}
+
+static CODECS: RwLock<BTreeMap<u16, Arc<dyn RDataCodec>>> = RwLock::new(BTreeMap::new());
+
+/// Has `codec` handle records of type `qtype` from now on, everywhere in the process. Types
+/// the codec supports natively can't be taken over, and each type and mnemonic can only be
+/// registered once.
+pub fn register_rdata_codec(qtype: u16, codec: Box<dyn RDataCodec>) -> Result<()> {
+    let mnemonic = codec.mnemonic().to_ascii_uppercase();
+    if QueryType::from_num(qtype) != QueryType::Unknown(qtype) {
+        return Err(invalid(format!(
+            "type {} is {}, which has native support",
+            qtype,
+            QueryType::from_num(qtype)
+        )));
+    }
+    if let Some((other, _)) = by_mnemonic(&mnemonic) {
+        return Err(invalid(format!(
+            "{} already names type {}",
+            mnemonic, other
+        )));
+    }
+    if mnemonic.is_empty() || mnemonic.parse::<QueryType>().is_ok() {
+        return Err(invalid(format!("{:?} can't name a record type", mnemonic)));
+    }
+
+    let mut codecs = CODECS.write().unwrap();
+    if codecs.contains_key(&qtype) {
+        return Err(invalid(format!("type {} already has a codec", qtype)));
+    }
+    codecs.insert(qtype, Arc::from(codec));
+    Ok(())
+}
+
+/// The codec registered for `qtype`, if any.
+pub fn codec(qtype: u16) -> Option<Arc<dyn RDataCodec>> {
+    CODECS.read().unwrap().get(&qtype).cloned()
+}
+
+/// The type number and codec registered under `mnemonic`, in any case.
+pub fn by_mnemonic(mnemonic: &str) -> Option<(u16, Arc<dyn RDataCodec>)> {
+    CODECS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(_, codec)| codec.mnemonic().eq_ignore_ascii_case(mnemonic))
+        .map(|(&qtype, codec)| (qtype, codec.clone()))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
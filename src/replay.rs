@@ -0,0 +1,120 @@
+//! Replaying fuzzer output through the parser. Each input is parsed on its own with panics
+//! caught, and the panics are grouped by where in the source they happened, so a corpus of
+//! thousands of crashes comes down to the handful of bugs behind them.
+
+use crate::{DnsPacket, Result};
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// What parsing one input came to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Parsed,
+    /// The parser turned the input down, as it should for garbage.
+    Rejected(String),
+    Panicked(Panic),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Panic {
+    /// `file:line:column` of the panic.
+    pub site: String,
+    pub message: String,
+    pub backtrace: String,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+/// Parses `data`, catching a panic if there is one. Panics are recorded with a backtrace
+/// whatever `RUST_BACKTRACE` says. The panic hook is swapped out while the parser runs, so
+/// other threads' panics go unreported in the meantime.
+pub fn replay(data: &[u8]) -> Outcome {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|info| {
+        let site = match info.location() {
+            Some(location) => location.to_string(),
+            None => "unknown".to_string(),
+        };
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => String::new(),
+            },
+        };
+        let panic = Panic {
+            site,
+            message,
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(panic));
+    }));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| DnsPacket::from_bytes(data)));
+    panic::set_hook(hook);
+
+    match result {
+        Ok(Ok(_)) => Outcome::Parsed,
+        Ok(Err(e)) => Outcome::Rejected(e.to_string()),
+        Err(_) => Outcome::Panicked(
+            LAST_PANIC
+                .with(|last| last.borrow_mut().take())
+                .unwrap_or_else(|| Panic {
+                    site: "unknown".to_string(),
+                    message: String::new(),
+                    backtrace: String::new(),
+                }),
+        ),
+    }
+}
+
+/// The inputs that panicked at one place.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailureSite {
+    /// The first input to panic here, with its message and backtrace.
+    pub first: Panic,
+    pub inputs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub parsed: usize,
+    /// How many inputs were rejected with each error message.
+    pub rejected: BTreeMap<String, usize>,
+    /// Keyed by `Panic::site`.
+    pub panics: BTreeMap<String, FailureSite>,
+}
+
+/// Replays every file in `dir`, in name order.
+pub fn replay_dir(dir: &Path) -> Result<Summary> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    let mut summary = Summary::default();
+    for path in paths {
+        match replay(&fs::read(&path)?) {
+            Outcome::Parsed => summary.parsed += 1,
+            Outcome::Rejected(error) => *summary.rejected.entry(error).or_default() += 1,
+            Outcome::Panicked(panic) => summary
+                .panics
+                .entry(panic.site.clone())
+                .or_insert_with(|| FailureSite {
+                    first: panic,
+                    inputs: Vec::new(),
+                })
+                .inputs
+                .push(path),
+        }
+    }
+
+    Ok(summary)
+}
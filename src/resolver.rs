@@ -0,0 +1,1050 @@
+use crate::capability::Capabilities;
+use crate::log::{self, LogLevel};
+use crate::scheduler::Scheduler;
+use crate::stats::Stats;
+use crate::{
+    DnsPacket, DnsQuestion, DnsRecord, QueryType, ResponseKind, Result, ResultCode, Warning,
+    CLASS_IN, EDNS_DO, MAX_EDNS_PACKET_SIZE,
+};
+use rand::seq::SliceRandom;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u32 = 3;
+/// The fixed part at the start of every message, before the question.
+const HEADER_LEN: usize = 12;
+/// How many name server lookups may be nested inside one another before giving up.
+const MAX_NS_DEPTH: usize = 8;
+/// How many unglued name servers are resolved concurrently at each step.
+const MAX_PARALLEL_NS: usize = 3;
+/// How many threads resolving name servers may be running at once, across all the clones
+/// of a resolver. Nested lookups past this many are made on the thread that needs them.
+const MAX_NS_WORKERS: usize = 16;
+/// How often sockets are checked while waiting on more than one at a time.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+/// How long a cancellable lookup waits at a time before checking whether it's been
+/// cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The root name servers and their IPv4 addresses.
+pub const ROOT_HINTS: [(&str, Ipv4Addr); 13] = [
+    ("a.root-servers.net", Ipv4Addr::new(198, 41, 0, 4)),
+    ("b.root-servers.net", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+const ROOT_HINTS_TTL: u32 = 518_400;
+/// How many root servers go in a referral. All thirteen with glue won't fit in 512 bytes
+/// without name compression.
+const REFERRAL_HINTS: usize = 4;
+
+/// Fills in `packet` as a referral to a few randomly chosen root servers: the answer we
+/// give to queries we won't recurse for, having no cached or authoritative data of our own.
+pub fn root_referral(packet: &mut DnsPacket) {
+    let mut rng = rand::thread_rng();
+    for (host, addr) in ROOT_HINTS.choose_multiple(&mut rng, REFERRAL_HINTS) {
+        packet.authorities.push(DnsRecord::NS {
+            domain: String::new(),
+            host: host.to_string(),
+            ttl: ROOT_HINTS_TTL,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: host.to_string(),
+            addr: *addr,
+            ttl: ROOT_HINTS_TTL,
+        });
+    }
+
+    packet.header.authoritative_entries = packet.authorities.len() as u16;
+    packet.header.resource_entries = packet.resources.len() as u16;
+}
+
+/// Per-query settings beyond the name and type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryOptions {
+    pub qclass: u16,
+    pub recursion_desired: bool,
+    /// Ask the server not to validate DNSSEC, passed along from clients that set CD.
+    pub checking_disabled: bool,
+    /// Set the DO bit, asking for the RRSIGs and other DNSSEC records that go with the
+    /// answer, for clients that validate it themselves.
+    pub dnssec_ok: bool,
+    /// The number of the client query this is on behalf of. When set, each query sent and
+    /// its outcome are logged with it, so upstream traffic can be traced to its cause.
+    pub correlation: Option<u64>,
+    /// Send the name in a random mix of upper and lower case and accept only responses
+    /// that echo it back exactly, so a forger has to guess the case as well as the id
+    /// (the "0x20" defence).
+    pub randomize_case: bool,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            qclass: CLASS_IN,
+            recursion_desired: true,
+            checking_disabled: false,
+            dnssec_ok: false,
+            correlation: None,
+            randomize_case: false,
+        }
+    }
+}
+
+/// Cancels the lookups of the resolvers it's given to with
+/// [`Resolver::with_cancellation`], along with every query they're waiting on.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// The token this one was made from, whose cancellation cancels this one too.
+    parent: Option<Arc<CancellationToken>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// A token that's cancelled along with `parent`, or on its own.
+    fn child(parent: Option<&CancellationToken>) -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::default(),
+            parent: parent.map(|parent| Arc::new(parent.clone())),
+        }
+    }
+
+    /// Makes lookups in progress fail with `ErrorKind::Interrupted` within a few tens of
+    /// milliseconds, and later ones straight away.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+}
+
+/// Collects the warnings about the responses to the lookups of the resolvers it's given to
+/// with [`Resolver::with_warnings`], each with the server that sent it.
+#[derive(Clone, Debug, Default)]
+pub struct Warnings(Arc<Mutex<Vec<(SocketAddr, Warning)>>>);
+
+impl Warnings {
+    pub fn new() -> Warnings {
+        Warnings::default()
+    }
+
+    /// The warnings collected since the last call.
+    pub fn take(&self) -> Vec<(SocketAddr, Warning)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn add(&self, server: SocketAddr, warnings: Vec<Warning>) {
+        let mut collected = self.0.lock().unwrap();
+        collected.extend(warnings.into_iter().map(|warning| (server, warning)));
+    }
+}
+
+/// Sends queries and follows referrals. Clones are cheap and share everything the
+/// resolver keeps track of, the counters of suspicious responses, the queries in flight to
+/// each server and what each can take, so one resolver can be handed to any number of
+/// threads.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    stats: Arc<Stats>,
+    scheduler: Arc<Scheduler>,
+    capabilities: Arc<Capabilities>,
+    cancel: Option<CancellationToken>,
+    warnings: Option<Warnings>,
+    /// Where recursive lookups start, if not at a.root-servers.net on port 53.
+    root: Option<SocketAddr>,
+    /// The threads resolving name servers right now.
+    ns_workers: Arc<AtomicUsize>,
+}
+
+impl Resolver {
+    pub fn new(stats: Stats, scheduler: Scheduler) -> Resolver {
+        Resolver {
+            stats: Arc::new(stats),
+            scheduler: Arc::new(scheduler),
+            capabilities: Arc::default(),
+            cancel: None,
+            warnings: None,
+            root: None,
+            ns_workers: Arc::default(),
+        }
+    }
+
+    /// A clone that adds the warnings about every response it accepts to `warnings`,
+    /// including those to the queries nested inside a recursive lookup.
+    pub fn with_warnings(&self, warnings: &Warnings) -> Resolver {
+        Resolver {
+            warnings: Some(warnings.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// A clone whose lookups give up once `token` is cancelled, for callers with deadlines
+    /// of their own. That covers waiting on responses and between retries, and the name
+    /// server lookups nested inside a recursive one, so nothing is left running.
+    pub fn with_cancellation(&self, token: &CancellationToken) -> Resolver {
+        Resolver {
+            cancel: Some(token.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// A clone whose recursive lookups start at `root` instead of a.root-servers.net, and
+    /// ask every name server they're referred to on `root`'s port, for a private tree of
+    /// servers such as a test network.
+    pub fn with_root(&self, root: SocketAddr) -> Resolver {
+        Resolver {
+            root: Some(root),
+            ..self.clone()
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancel {
+            Some(ref token) if token.is_cancelled() => Err(cancelled()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleeps for `duration`, or until the lookup is cancelled.
+    fn sleep(&self, duration: Duration) -> Result<()> {
+        let until = Instant::now() + duration;
+        loop {
+            self.check_cancelled()?;
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok(());
+            }
+            thread::sleep(match self.cancel {
+                Some(_) => remaining.min(CANCEL_POLL_INTERVAL),
+                None => remaining,
+            });
+        }
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// What the resolver has learned about the servers it has queried.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn lookup<A: ToSocketAddrs>(
+        &self,
+        name: &str,
+        qtype: QueryType,
+        server: A,
+    ) -> Result<DnsPacket> {
+        self.lookup_with(name, qtype, &QueryOptions::default(), server)
+    }
+
+    /// Like [`Resolver::lookup`], with control over the class and header flags.
+    pub fn lookup_with<A: ToSocketAddrs>(
+        &self,
+        name: &str,
+        qtype: QueryType,
+        options: &QueryOptions,
+        server: A,
+    ) -> Result<DnsPacket> {
+        let server: SocketAddr = server
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid server address"))?;
+
+        self.race_with(name, qtype, options, &[server])
+    }
+
+    /// Sends the query to all of `servers` at once and returns the first answer, waiting
+    /// for the others only while answers come back as SERVFAIL or REFUSED. Answers that
+    /// arrive after that are ignored. It's the lowest latency the servers can offer, paid
+    /// for in queries.
+    pub fn race_with(
+        &self,
+        name: &str,
+        qtype: QueryType,
+        options: &QueryOptions,
+        servers: &[SocketAddr],
+    ) -> Result<DnsPacket> {
+        if servers.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No servers to query"));
+        }
+
+        let mut attempt = 1;
+        loop {
+            self.check_cancelled()?;
+            let result = {
+                let _permits: Vec<_> = servers
+                    .iter()
+                    .map(|server| self.scheduler.acquire(*server))
+                    .collect();
+                self.query(name, qtype, options, servers)
+            };
+
+            if let (Some(correlation), Err(ref e)) = (options.correlation, &result) {
+                log::write(
+                    LogLevel::Info,
+                    &format!(
+                        "Upstream query #{} for {} {} failed: {}",
+                        correlation, name, qtype, e
+                    ),
+                );
+            }
+
+            if matches!(result, Err(ref e) if is_timeout(e)) {
+                for server in servers {
+                    self.capabilities.timed_out(*server);
+                }
+            }
+
+            match result {
+                // Those that answered BADVERS now get queries without EDNS, straight away.
+                Ok(ref response)
+                    if response.header.rescode == ResultCode::BadVersion
+                        && attempt < MAX_ATTEMPTS =>
+                {
+                    attempt += 1;
+                }
+                Err(ref e) if is_timeout(e) && attempt < MAX_ATTEMPTS => {
+                    self.sleep(self.scheduler.retry_delay(attempt))?;
+                    attempt += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn query(
+        &self,
+        name: &str,
+        qtype: QueryType,
+        options: &QueryOptions,
+        servers: &[SocketAddr],
+    ) -> Result<DnsPacket> {
+        let mut packet: DnsPacket = Default::default();
+        packet.header.id = rand::random();
+        packet.header.questions = 1;
+        packet.header.recursion_desired = options.recursion_desired;
+        packet.header.checking_disabled = options.checking_disabled;
+        packet.questions.push(DnsQuestion {
+            name: match options.randomize_case {
+                true => randomize_case(name),
+                false => name.to_owned(),
+            },
+            qtype,
+            qclass: options.qclass,
+        });
+
+        let id = packet.header.id;
+
+        let mut request = Vec::new();
+        let mut sockets = Vec::with_capacity(servers.len());
+        for server in servers {
+            // Each server gets what it's shown it can take.
+            let capability = self.capabilities.get(*server);
+            packet.resources.clear();
+            if let Some(udp_payload_size) = capability.payload_size() {
+                packet.resources.push(DnsRecord::OPT {
+                    udp_payload_size,
+                    extended_rcode: 0,
+                    version: 0,
+                    flags: match options.dnssec_ok && capability.dnssec != Some(false) {
+                        true => EDNS_DO,
+                        false => 0,
+                    },
+                    options: Vec::new(),
+                });
+            }
+            request = packet.to_bytes()?;
+
+            let socket = connect(*server)?;
+            socket.send(&request)?;
+            sockets.push(socket);
+
+            if let Some(correlation) = options.correlation {
+                log::write(
+                    LogLevel::Info,
+                    &format!(
+                        "Upstream query #{} to {}: {} {} id {}",
+                        correlation, server, name, qtype, id
+                    ),
+                );
+            }
+        }
+        let sent = Instant::now();
+
+        // Anything that isn't a well-formed reply from a server we asked, carrying the id
+        // we sent, is counted and dropped; we keep listening until the deadline so a burst of
+        // forged datagrams can't crowd out the real answer.
+        let deadline = Instant::now() + LOOKUP_TIMEOUT;
+        let mut failed: Vec<SocketAddr> = Vec::new();
+        let mut failure = None;
+        loop {
+            let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+            let (index, received) = recv_any(&sockets, deadline, self.cancel.as_ref(), &mut buf)?;
+            let (len, src) = match received {
+                Ok(received) => received,
+                // Most likely the server's port unreachable, which won't change by waiting.
+                Err(e) => {
+                    if !failed.contains(&servers[index]) {
+                        failed.push(servers[index]);
+                    }
+                    if failed.len() < servers.len() {
+                        continue;
+                    }
+                    return failure.ok_or(e);
+                }
+            };
+
+            if !servers.contains(&src) {
+                self.stats.record_unexpected_source(src, servers[index]);
+                continue;
+            }
+
+            let (response, mut warnings) = match DnsPacket::from_bytes_with_warnings(&buf[..len]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    self.stats.record_malformed(src, &e);
+                    continue;
+                }
+            };
+
+            if response.header.id != id {
+                self.stats.record_wrong_id(src, id, response.header.id);
+                continue;
+            }
+
+            // Names are compared case-insensitively, since the parser lowercases them.
+            let answers_question = match response.questions.first() {
+                Some(q) => q.name.eq_ignore_ascii_case(name) && q.qtype == qtype,
+                None => true,
+            };
+            if !answers_question {
+                self.stats
+                    .record_wrong_question(src, name, &response.questions[0].name);
+                continue;
+            }
+
+            // The parser lowercases names, so the case is checked on the wire.
+            if options.randomize_case
+                && !response.questions.is_empty()
+                && question_bytes(&buf[..len]) != question_bytes(&request)
+            {
+                self.stats.record_wrong_question(
+                    src,
+                    &packet_name(&request),
+                    &packet_name(&buf[..len]),
+                );
+                continue;
+            }
+
+            // A server that doesn't speak our EDNS is asked again without it.
+            if response.header.rescode == ResultCode::BadVersion {
+                self.capabilities.bad_version(src);
+            }
+            let dnssec = response.resources.iter().find_map(|rec| match *rec {
+                DnsRecord::OPT { flags, .. } if options.dnssec_ok => Some(flags & EDNS_DO != 0),
+                _ => None,
+            });
+            self.capabilities.answered(src, len, dnssec);
+
+            // A server that couldn't answer doesn't win a race while others might.
+            let refused = matches!(
+                response.header.rescode,
+                ResultCode::ServerFail | ResultCode::Refused | ResultCode::BadVersion
+            );
+            if refused && !failed.contains(&src) {
+                failed.push(src);
+            }
+            if refused && failed.len() < servers.len() {
+                failure = Some(response);
+                continue;
+            }
+
+            if let Some(ref collected) = self.warnings {
+                if response.header.truncated_message {
+                    warnings.push(Warning::Truncated);
+                }
+                collected.add(src, warnings);
+            }
+
+            if let Some(correlation) = options.correlation {
+                log::write(
+                    LogLevel::Info,
+                    &format!(
+                        "Upstream response #{} from {}: {} with {} answers in {:.1}ms",
+                        correlation,
+                        src,
+                        response.header.rescode,
+                        response.answers.len(),
+                        sent.elapsed().as_secs_f64() * 1000.0
+                    ),
+                );
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Sends `request`, a query as a client sent it, to all of `servers` and returns the
+    /// first response byte for byte as it came, but for the id: the query goes upstream
+    /// under a fresh random one, and the client's is put back in the response. Nothing in
+    /// between is parsed or rebuilt beyond what's needed to match the response to the
+    /// query, so whatever the upstream says reaches the client unchanged. There's no retry;
+    /// the client will ask again if it wants to.
+    pub fn relay(
+        &self,
+        request: &[u8],
+        servers: &[SocketAddr],
+        correlation: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        if servers.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No servers to query"));
+        }
+        let question = question_bytes(request)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Malformed query"))?;
+        self.check_cancelled()?;
+
+        let id: u16 = rand::random();
+        let mut query = request.to_vec();
+        query[..2].copy_from_slice(&id.to_be_bytes());
+
+        let _permits: Vec<_> = servers
+            .iter()
+            .map(|server| self.scheduler.acquire(*server))
+            .collect();
+
+        let mut sockets = Vec::with_capacity(servers.len());
+        for server in servers {
+            let socket = connect(*server)?;
+            socket.send(&query)?;
+            sockets.push(socket);
+
+            if let Some(correlation) = correlation {
+                log::write(
+                    LogLevel::Info,
+                    &format!("Relayed query #{} to {} id {}", correlation, server, id),
+                );
+            }
+        }
+        let sent = Instant::now();
+
+        let deadline = Instant::now() + LOOKUP_TIMEOUT;
+        let mut failed: Vec<SocketAddr> = Vec::new();
+        loop {
+            let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+            let (index, received) = recv_any(&sockets, deadline, self.cancel.as_ref(), &mut buf)?;
+            let (len, src) = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    if !failed.contains(&servers[index]) {
+                        failed.push(servers[index]);
+                    }
+                    if failed.len() < servers.len() {
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+
+            if !servers.contains(&src) {
+                self.stats.record_unexpected_source(src, servers[index]);
+                continue;
+            }
+
+            let mut response = buf[..len].to_vec();
+            if len < HEADER_LEN || response[2] & 0x80 == 0 {
+                self.stats.record_malformed(src, &"not a response");
+                continue;
+            }
+
+            let found = u16::from_be_bytes([response[0], response[1]]);
+            if found != id {
+                self.stats.record_wrong_id(src, id, found);
+                continue;
+            }
+
+            // A response may leave the question out, but if it has one it must be ours.
+            let answers_question = response[4..6] == [0, 0]
+                || question_bytes(&response)
+                    .is_some_and(|theirs| theirs.eq_ignore_ascii_case(question));
+            if !answers_question {
+                self.stats
+                    .record_malformed(src, &"response to a different question");
+                continue;
+            }
+
+            response[..2].copy_from_slice(&request[..2]);
+
+            if let Some(correlation) = correlation {
+                log::write(
+                    LogLevel::Info,
+                    &format!(
+                        "Relayed response #{} from {}: {} bytes in {:.1}ms",
+                        correlation,
+                        src,
+                        len,
+                        sent.elapsed().as_secs_f64() * 1000.0
+                    ),
+                );
+            }
+            return Ok(response);
+        }
+    }
+
+    pub fn recursive_lookup(&self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.recursive_lookup_with(qname, qtype, &QueryOptions::default())
+    }
+
+    /// Like [`Resolver::recursive_lookup`], sending every query with `options`.
+    pub fn recursive_lookup_with(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        options: &QueryOptions,
+    ) -> Result<DnsPacket> {
+        self.resolve(qname, qtype, options, 0)
+    }
+
+    /// Walks the delegation chain for `qname` one query at a time, starting from the root.
+    /// Each item is one step: a referral to the next name server, or the final response.
+    /// Name servers without glue are resolved automatically along the way.
+    pub fn iterate(&self, qname: &str, qtype: QueryType) -> Iteration<'_> {
+        Iteration::new(self, qname, qtype, QueryOptions::default(), 0)
+    }
+
+    fn resolve(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        options: &QueryOptions,
+        depth: usize,
+    ) -> Result<DnsPacket> {
+        for step in Iteration::new(self, qname, qtype, *options, depth) {
+            match step? {
+                Step::Referral { server, next, .. } => {
+                    if let Some(correlation) = options.correlation {
+                        log::write(
+                            LogLevel::Info,
+                            &format!(
+                                "Upstream query #{} for {} {} referred from {} to {}",
+                                correlation, qname, qtype, server, next
+                            ),
+                        );
+                    }
+                }
+                Step::Final { response, .. } => return Ok(response),
+            }
+        }
+
+        unreachable!("iteration always ends with a final step or an error")
+    }
+
+    /// Resolves a few of `hosts` at once, each on its own thread so that long delegation
+    /// chains don't pile up on a single stack, and picks an address from the first that
+    /// resolves. The others are cancelled then, rather than waited on. Once
+    /// `MAX_NS_WORKERS` threads are busy, the hosts left over are resolved here one after
+    /// another, if none of the others has resolved.
+    fn resolve_ns_addr(
+        &self,
+        hosts: &[String],
+        options: &QueryOptions,
+        depth: usize,
+    ) -> Option<String> {
+        let options = QueryOptions {
+            correlation: options.correlation,
+            randomize_case: options.randomize_case,
+            ..QueryOptions::default()
+        };
+        let mut hosts = hosts.to_vec();
+        hosts.shuffle(&mut rand::thread_rng());
+        hosts.truncate(MAX_PARALLEL_NS);
+
+        let done = CancellationToken::child(self.cancel.as_ref());
+        let resolver = self.with_cancellation(&done);
+        let (found, results) = mpsc::channel();
+        let mut leftover = Vec::new();
+        for host in hosts {
+            let worker = match NsWorker::claim(&self.ns_workers) {
+                Some(worker) => worker,
+                None => {
+                    leftover.push(host);
+                    continue;
+                }
+            };
+            let (resolver, found) = (resolver.clone(), found.clone());
+            thread::spawn(move || {
+                let _worker = worker;
+                let _ = found.send(resolver.resolve_a(&host, &options, depth));
+            });
+        }
+        drop(found);
+
+        let addr = results.iter().flatten().next().or_else(|| {
+            leftover
+                .iter()
+                .find_map(|host| resolver.resolve_a(host, &options, depth))
+        });
+        done.cancel();
+        addr
+    }
+
+    /// An address for the name server `host`, looked up from the root.
+    fn resolve_a(&self, host: &str, options: &QueryOptions, depth: usize) -> Option<String> {
+        self.resolve(host, QueryType::A, options, depth + 1)
+            .ok()
+            .and_then(|response| response.get_random_a())
+    }
+
+    /// Looks up the addresses of any MX and SRV targets in `packet`'s answers and adds them
+    /// to its additional section, sparing the client a second round trip.
+    pub fn resolve_additional(&self, packet: &mut DnsPacket) {
+        self.resolve_additional_with(packet, &QueryOptions::default())
+    }
+
+    /// Like [`Resolver::resolve_additional`], sending every query with `options`.
+    pub fn resolve_additional_with(&self, packet: &mut DnsPacket, options: &QueryOptions) {
+        for target in packet.unresolved_targets() {
+            for qtype in &[QueryType::A, QueryType::AAAA] {
+                let response = match self.recursive_lookup_with(&target, *qtype, options) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        log::write(
+                            LogLevel::Error,
+                            &format!("Failed to resolve additional {} {}: {}", qtype, target, e),
+                        );
+                        continue;
+                    }
+                };
+
+                for rec in response.answers {
+                    let is_address = match rec {
+                        DnsRecord::A { ref domain, .. } | DnsRecord::AAAA { ref domain, .. } => {
+                            *domain == target
+                        }
+                        _ => false,
+                    };
+
+                    if is_address && !packet.resources.contains(&rec) {
+                        packet.resources.push(rec);
+                    }
+                }
+            }
+        }
+
+        packet.header.resource_entries = packet.resources.len() as u16;
+    }
+}
+
+/// One of the `MAX_NS_WORKERS` threads a resolver may resolve name servers on, given back
+/// when dropped.
+struct NsWorker(Arc<AtomicUsize>);
+
+impl NsWorker {
+    fn claim(workers: &Arc<AtomicUsize>) -> Option<NsWorker> {
+        workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |busy| {
+                (busy < MAX_NS_WORKERS).then_some(busy + 1)
+            })
+            .ok()
+            .map(|_| NsWorker(workers.clone()))
+    }
+}
+
+impl Drop for NsWorker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// One query made while iterating towards an answer.
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// `server` didn't have the answer, but pointed us at the name server `next`.
+    Referral {
+        server: String,
+        response: DnsPacket,
+        next: String,
+    },
+    /// `server` gave the response we're returning: an answer, NXDOMAIN, or the last word
+    /// of a delegation chain we couldn't follow any further.
+    Final { server: String, response: DnsPacket },
+}
+
+/// The steps of an iterative lookup, as returned by [`Resolver::iterate`].
+pub struct Iteration<'a> {
+    resolver: &'a Resolver,
+    qname: String,
+    qtype: QueryType,
+    options: QueryOptions,
+    depth: usize,
+    ns: Option<String>,
+}
+
+impl<'a> Iteration<'a> {
+    fn new(
+        resolver: &'a Resolver,
+        qname: &str,
+        qtype: QueryType,
+        options: QueryOptions,
+        depth: usize,
+    ) -> Iteration<'a> {
+        Iteration {
+            resolver,
+            qname: qname.to_string(),
+            qtype,
+            options,
+            depth,
+            // Unless told otherwise we're always starting with *a.root-servers.net*.
+            ns: Some(match resolver.root {
+                Some(root) => root.ip().to_string(),
+                None => ROOT_HINTS[0].1.to_string(),
+            }),
+        }
+    }
+
+    fn step(&self, ns: String) -> Result<Step> {
+        if self.depth > MAX_NS_DEPTH {
+            return Err(Error::other(format!(
+                "Gave up resolving {}: name server chain too deep",
+                self.qname
+            )));
+        }
+
+        let qname = self.qname.as_str();
+        let port = self.resolver.root.map_or(53, |root| root.port());
+        let response =
+            self.resolver
+                .lookup_with(qname, self.qtype, &self.options, (ns.as_str(), port))?;
+
+        // Anything but a referral is the last word: the answer, word that there's nothing
+        // there (NXDOMAIN, or NODATA for a name without records of the type), or an error.
+        match response.kind() {
+            ResponseKind::Referral => {}
+            ResponseKind::Answer
+            | ResponseKind::NoData
+            | ResponseKind::NxDomain
+            | ResponseKind::Error => {
+                return Ok(Step::Final {
+                    server: ns,
+                    response,
+                })
+            }
+        }
+
+        // For a referral, we'll try to find a new nameserver based on NS and a corresponding A
+        // record in the additional section. If this succeeds, we can switch name server
+        // and retry.
+        if let Some(next) = response.get_resolved_ns(qname) {
+            return Ok(Step::Referral {
+                server: ns,
+                response,
+                next,
+            });
+        }
+
+        // If not, we'll have to resolve the ip of a NS record. If no NS records exist,
+        // we'll go with what the last server told us.
+        let new_ns_names = response.get_all_unresolved_ns(qname);
+        if new_ns_names.is_empty() {
+            return Ok(Step::Final {
+                server: ns,
+                response,
+            });
+        }
+
+        // Here we go down the rabbit hole by starting _another_ lookup sequence in the
+        // midst of our current one. Hopefully, this will give us the IP of an appropriate
+        // name server. If no such record is available, we again return the last result
+        // we got.
+        match self
+            .resolver
+            .resolve_ns_addr(&new_ns_names, &self.options, self.depth)
+        {
+            Some(next) => Ok(Step::Referral {
+                server: ns,
+                response,
+                next,
+            }),
+            None => {
+                // The name servers may have failed for being cancelled, which is no answer.
+                self.resolver.check_cancelled()?;
+                Ok(Step::Final {
+                    server: ns,
+                    response,
+                })
+            }
+        }
+    }
+}
+
+impl Iterator for Iteration<'_> {
+    type Item = Result<Step>;
+
+    fn next(&mut self) -> Option<Result<Step>> {
+        let ns = self.ns.take()?;
+        let step = self.step(ns);
+        if let Ok(Step::Referral { ref next, .. }) = step {
+            self.ns = Some(next.clone());
+        }
+        Some(step)
+    }
+}
+
+/// A socket connected to `server`, so the kernel only hands us what that server sends, and
+/// reports the ICMP port unreachable that comes back if nothing is listening there rather
+/// than leaving us to wait out the timeout. For a link-local IPv6 server the scope id on
+/// the server's address picks the interface.
+fn connect(server: SocketAddr) -> Result<UdpSocket> {
+    let wildcard = match server {
+        SocketAddr::V4(_) => "0.0.0.0",
+        SocketAddr::V6(_) => "::",
+    };
+    let socket = UdpSocket::bind((wildcard, 0))?;
+    socket.connect(server)?;
+    Ok(socket)
+}
+
+/// `name` with each letter in upper or lower case at random.
+fn randomize_case(name: &str) -> String {
+    name.chars()
+        .map(|c| match rand::random() {
+            true => c.to_ascii_uppercase(),
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// The name in the first question of `message`, in the case it's written in there, for
+/// logging. Empty if there's no question or it's compressed.
+fn packet_name(message: &[u8]) -> String {
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LEN;
+    while let Some(&len) = message.get(pos) {
+        let label = match len {
+            1..=0x3F => message.get(pos + 1..pos + 1 + len as usize),
+            _ => None,
+        };
+        match label {
+            Some(label) => labels.push(String::from_utf8_lossy(label).into_owned()),
+            None => break,
+        }
+        pos += 1 + len as usize;
+    }
+    labels.join(".")
+}
+
+/// The first question of `message` as it is on the wire, from the name through the class,
+/// or None if the message has no question or it runs past the end. Compressed names end
+/// at their pointer, which is as far as two questions at the same offset can be compared.
+fn question_bytes(message: &[u8]) -> Option<&[u8]> {
+    if message.len() < HEADER_LEN || message[4..6] == [0, 0] {
+        return None;
+    }
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => {
+                pos += 1;
+                break;
+            }
+            _ if len & 0xC0 == 0xC0 => {
+                pos += 2;
+                break;
+            }
+            _ if len > 0x3F => return None,
+            _ => pos += len + 1,
+        }
+    }
+    message.get(HEADER_LEN..pos + 4)
+}
+
+/// Receives the next datagram on any of `sockets`, giving up at `deadline` or when `cancel`
+/// is cancelled. Returns the index of the socket along with what it got, which may be an
+/// error such as a refused connection.
+fn recv_any(
+    sockets: &[UdpSocket],
+    deadline: Instant,
+    cancel: Option<&CancellationToken>,
+    buf: &mut [u8],
+) -> Result<(usize, Result<(usize, SocketAddr)>)> {
+    let timed_out = || Error::new(ErrorKind::TimedOut, "Lookup timed out");
+    let is_cancelled = || cancel.is_some_and(CancellationToken::is_cancelled);
+
+    if let [socket] = sockets {
+        loop {
+            if is_cancelled() {
+                return Err(cancelled());
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(timed_out());
+            }
+            // Waking now and then to check on the token, if there is one.
+            let wait = match cancel {
+                Some(_) => remaining.min(CANCEL_POLL_INTERVAL),
+                None => remaining,
+            };
+            socket.set_read_timeout(Some(wait))?;
+            match socket.recv_from(buf) {
+                Err(ref e) if is_timeout(e) => {}
+                result => return Ok((0, result)),
+            }
+        }
+    }
+
+    // There's nothing in std to wait on several sockets at once, so take turns polling
+    // them.
+    for socket in sockets {
+        socket.set_nonblocking(true)?;
+    }
+    loop {
+        for (index, socket) in sockets.iter().enumerate() {
+            match socket.recv_from(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return Ok((index, result)),
+            }
+        }
+
+        if is_cancelled() {
+            return Err(cancelled());
+        }
+        if Instant::now() >= deadline {
+            return Err(timed_out());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn cancelled() -> Error {
+    Error::new(ErrorKind::Interrupted, "Lookup cancelled")
+}
+
+fn is_timeout(error: &Error) -> bool {
+    matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
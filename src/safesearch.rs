@@ -0,0 +1,123 @@
+//! Forcing safe search: the big search engines and YouTube each publish a host that
+//! serves their site with explicit results filtered out, and pointing their usual names
+//! at it with a CNAME turns filtering on for every browser on the network.
+
+use crate::{DnsRecord, Result};
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Clients see the rewrite for this long before asking again.
+const SAFE_SEARCH_TTL: u32 = 300;
+
+/// Names that are always rewritten, and what to. Google's search domains are matched
+/// separately since there's one per country.
+const REWRITES: [(&str, &str); 10] = [
+    ("www.youtube.com", "restrict.youtube.com"),
+    ("m.youtube.com", "restrict.youtube.com"),
+    ("youtubei.googleapis.com", "restrict.youtube.com"),
+    ("youtube.googleapis.com", "restrict.youtube.com"),
+    ("www.youtube-nocookie.com", "restrict.youtube.com"),
+    ("www.bing.com", "strict.bing.com"),
+    ("bing.com", "strict.bing.com"),
+    ("duckduckgo.com", "safe.duckduckgo.com"),
+    ("www.duckduckgo.com", "safe.duckduckgo.com"),
+    ("start.duckduckgo.com", "safe.duckduckgo.com"),
+];
+
+const GOOGLE_SAFE_SEARCH: &str = "forcesafesearch.google.com";
+
+/// The safe-search host to send `name` to, if it's one of the names we rewrite.
+pub fn target(name: &str) -> Option<&'static str> {
+    let name = name.trim_end_matches('.').to_lowercase();
+
+    if let Some(&(_, target)) = REWRITES.iter().find(|(from, _)| *from == name) {
+        return Some(target);
+    }
+
+    // google.com, www.google.de, www.google.co.uk and the like.
+    let host = name.strip_prefix("www.").unwrap_or(&name);
+    let tld = host.strip_prefix("google.")?;
+    let labels: Vec<&str> = tld.split('.').collect();
+    if labels.len() <= 2 && labels.iter().all(|label| (2..=3).contains(&label.len())) {
+        return Some(GOOGLE_SAFE_SEARCH);
+    }
+
+    None
+}
+
+/// The CNAME record that sends `name` to `target`.
+pub fn rewrite_record(name: &str, target: &str) -> DnsRecord {
+    DnsRecord::CNAME {
+        domain: name.to_string(),
+        host: target.to_string(),
+        ttl: SAFE_SEARCH_TTL,
+    }
+}
+
+/// An address range clients can be matched against, written as `10.0.0.0/24` or
+/// `fd00::/8`. A bare address is a network of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Network {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let full = prefix as usize / 8;
+    if net[..full] != addr[..full] {
+        return false;
+    }
+
+    let bits = prefix % 8;
+    bits == 0 || {
+        let mask = 0xffu8 << (8 - bits);
+        net[full] & mask == addr[full] & mask
+    }
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Network> {
+        let bad = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "expected an address or network like 10.0.0.0/24, found {:?}",
+                    s
+                ),
+            )
+        };
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| bad())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| bad())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(bad());
+        }
+
+        Ok(Network { addr, prefix })
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+const DEFAULT_MAX_OUTSTANDING: usize = 8;
+const RETRY_BASE_DELAY_MS: u64 = 100;
+const RETRY_JITTER_MS: u64 = 50;
+
+/// Keeps the resolver polite toward the servers it queries: at most `max_outstanding`
+/// queries may be in flight to any one server, and retries back off with a bit of
+/// random jitter so that bursts of failures don't turn into synchronized retry storms.
+#[derive(Debug)]
+pub struct Scheduler {
+    max_outstanding: usize,
+    outstanding: Mutex<HashMap<SocketAddr, usize>>,
+    released: Condvar,
+}
+
+/// A reserved slot for one outstanding query, released when dropped.
+pub struct Permit<'a> {
+    scheduler: &'a Scheduler,
+    server: SocketAddr,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new(DEFAULT_MAX_OUTSTANDING)
+    }
+}
+
+impl Scheduler {
+    pub fn new(max_outstanding: usize) -> Scheduler {
+        Scheduler {
+            max_outstanding: max_outstanding.max(1),
+            outstanding: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a query to `server` may be sent.
+    pub fn acquire(&self, server: SocketAddr) -> Permit<'_> {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        while outstanding.get(&server).copied().unwrap_or(0) >= self.max_outstanding {
+            outstanding = self.released.wait(outstanding).unwrap();
+        }
+        *outstanding.entry(server).or_insert(0) += 1;
+
+        Permit {
+            scheduler: self,
+            server,
+        }
+    }
+
+    /// How long to wait before retry number `attempt` (starting at 1): exponential
+    /// backoff plus up to `RETRY_JITTER_MS` of random jitter.
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let backoff = RETRY_BASE_DELAY_MS << attempt.saturating_sub(1).min(6);
+        let jitter = rand::random::<u64>() % (RETRY_JITTER_MS + 1);
+        Duration::from_millis(backoff + jitter)
+    }
+
+    fn release(&self, server: SocketAddr) {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if let Some(count) = outstanding.get_mut(&server) {
+            *count -= 1;
+            if *count == 0 {
+                outstanding.remove(&server);
+            }
+        }
+        self.released.notify_all();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release(self.server);
+    }
+}
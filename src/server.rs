@@ -0,0 +1,1091 @@
+use crate::analytics::{self, Analytics};
+use crate::blocklist::Blocklists;
+use crate::cache::Cache;
+use crate::config::{Config, LogFormat, LogLevel, UpstreamStrategy, DEFAULT_LISTENER};
+#[cfg(unix)]
+use crate::control::{self, Context};
+use crate::history::{self, History};
+use crate::local::LocalRecords;
+use crate::loopcheck::{self, LoopCheck};
+use crate::resolver::{self, QueryOptions, Resolver};
+use crate::stub::{self, ResolvConfTakeover};
+use crate::traffic::Traffic;
+use crate::tunnel::TunnelDetector;
+use crate::upstream::WeightedUpstreams;
+use crate::zone::Zones;
+use crate::{cluster, fallback, init, leases, log, safesearch, shaping, synth, zone};
+use crate::{
+    DnsPacket, DnsQuestion, DnsRecord, ParseLevel, QueryType, Result, ResultCode, CLASS_IN,
+    EDNS_DO, MAX_EDNS_PACKET_SIZE, MAX_PACKET_SIZE,
+};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a read on the server socket blocks before we check whether to shut down.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Numbers client queries for the log, so the upstream queries made for each can be tied
+/// back to it.
+static NEXT_QUERY: AtomicU64 = AtomicU64::new(1);
+
+/// How often a probe is sent upstream to check for a forwarding loop.
+const LOOP_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How often queries past their retention are dropped from the history file.
+const HISTORY_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// What the worker threads share besides the resolver and the configuration. Each is
+/// there only if it's turned on.
+#[derive(Clone, Copy)]
+struct Shared<'a> {
+    zones: Option<&'a Zones>,
+    local: Option<&'a LocalRecords>,
+    cache: Option<&'a Cache>,
+    history: Option<&'a History>,
+    analytics: Option<&'a Analytics>,
+    tunnels: Option<&'a TunnelDetector>,
+    blocklists: Option<&'a Blocklists>,
+    loop_check: Option<&'a LoopCheck>,
+    upstreams: Option<&'a WeightedUpstreams>,
+    traffic: Option<&'a Traffic>,
+}
+
+/// Answers queries on `config.bind` and `config.listeners` until a socket fails or we're
+/// asked to stop by SIGTERM or SIGINT.
+pub fn serve(resolver: &Resolver, config: &Config) -> Result<()> {
+    log::set_json(config.log_format == LogFormat::Json);
+    init::install_signal_handlers();
+
+    check_not_forwarding_to_self(config)?;
+    if config.strict_zones {
+        for zone in &config.zones {
+            if let Some(warning) = zone.warnings().first() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("zone {}: {}", zone.origin, warning),
+                ));
+            }
+        }
+    }
+    if config.transparent && config.upstream.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "transparent mode needs an upstream to relay to",
+        ));
+    }
+
+    let mut sockets = vec![(DEFAULT_LISTENER, UdpSocket::bind(config.bind)?)];
+    for listener in &config.listeners {
+        sockets.push((listener.label.as_str(), UdpSocket::bind(listener.addr)?));
+    }
+    for (_, socket) in &sockets {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    }
+
+    let cache = match config.cache {
+        true => Some(Cache::new(config.cache_min_ttl, config.cache_servfail_ttl)),
+        false => None,
+    };
+
+    let analytics = match config.analytics {
+        true => Some(Arc::new(Analytics::new(config.analytics_thresholds))),
+        false => None,
+    };
+
+    let tunnels = config
+        .tunnel_action
+        .map(|action| TunnelDetector::new(action, config.tunnel_limits));
+
+    let blocklists = match config.blocklists.is_empty() {
+        true => None,
+        false => Some(Arc::new(Blocklists::load(&config.blocklists)?)),
+    };
+
+    let loop_check = match config.upstream.is_empty() || !config.recursion {
+        true => None,
+        false => Some(LoopCheck::new()),
+    };
+
+    let history = match config.history {
+        Some(ref path) => Some(History::open(path, config.history_retention)?),
+        None => None,
+    };
+
+    let upstreams = match config.upstream_strategy {
+        UpstreamStrategy::Weighted => Some(weighted_upstreams(config)?),
+        _ => None,
+    };
+
+    let zones = (!config.zones.is_empty()).then(|| {
+        let zones = Arc::new(Zones::new(config.zones.clone()));
+        zone::watch(zones.clone());
+        zones
+    });
+
+    let local = config.dhcp_leases.as_ref().map(|path| {
+        let local = Arc::new(LocalRecords::new(vec![config.local_domain.clone()]));
+        leases::watch(
+            path.clone(),
+            config.dhcp_lease_format,
+            config.local_domain.clone(),
+            local.clone(),
+        );
+        local
+    });
+
+    // Restores the original when dropped, on shutdown or on error.
+    let _takeover = match config.rewrite_resolv_conf {
+        true => Some(ResolvConfTakeover::new(
+            Path::new(stub::SYSTEM_RESOLV_CONF),
+            config.bind.ip(),
+        )?),
+        false => None,
+    };
+
+    if let Some(addr) = config.health {
+        let listener = TcpListener::bind(addr)?;
+        let (analytics, blocklists) = (analytics.clone(), blocklists.clone());
+        thread::spawn(move || serve_health(listener, analytics, blocklists));
+        log::write(
+            LogLevel::Info,
+            &format!("Health checks on http://{}/healthz", addr),
+        );
+    }
+
+    // Only the control socket hands these out.
+    let traffic = config.control.as_ref().map(|_| Traffic::new());
+
+    #[cfg(unix)]
+    let control = match config.control {
+        Some(ref path) => Some((control::bind(path)?, path)),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if config.control.is_some() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "the control socket needs Unix domain sockets",
+        ));
+    }
+
+    log::write(
+        LogLevel::Info,
+        &format!("DNS running on {}...", config.bind),
+    );
+    for listener in &config.listeners {
+        log::write(
+            LogLevel::Info,
+            &format!("Listener {} on {}", listener.label, listener.addr),
+        );
+    }
+    for zone in &config.zones {
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Serving zone {} with {} records",
+                zone.origin,
+                zone.records().count()
+            ),
+        );
+        for warning in zone.warnings() {
+            log::write(
+                LogLevel::Error,
+                &format!("Warning for zone {}: {}", zone.origin, warning),
+            );
+        }
+    }
+    if let Some(ref blocklists) = blocklists {
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Blocking {} names from {} lists",
+                blocklists.len(),
+                config.blocklists.len()
+            ),
+        );
+    }
+
+    let cache = cache.as_ref();
+    let analytics = analytics.as_deref();
+    let shared = Shared {
+        zones: zones.as_deref(),
+        local: local.as_deref(),
+        cache,
+        history: history.as_ref(),
+        analytics,
+        tunnels: tunnels.as_ref(),
+        blocklists: blocklists.as_deref(),
+        loop_check: loop_check.as_ref(),
+        upstreams: upstreams.as_ref(),
+        traffic: traffic.as_ref(),
+    };
+
+    thread::scope(|scope| -> Result<()> {
+        for &(label, ref socket) in &sockets {
+            for _ in 0..config.threads.max(1) {
+                let socket = socket.try_clone()?;
+                scope.spawn(move || answer_queries(&socket, label, resolver, shared, config));
+            }
+        }
+
+        if let Some(loop_check) = shared.loop_check {
+            scope.spawn(move || check_for_loops(resolver, shared, config, loop_check));
+        }
+
+        #[cfg(unix)]
+        if let Some((listener, path)) = control {
+            let context = Context {
+                cache,
+                analytics,
+                traffic: shared.traffic,
+                capabilities: Some(resolver.capabilities()),
+            };
+            scope.spawn(move || control::serve(listener, path, context));
+        }
+
+        // Meanwhile this thread does the housekeeping.
+        let stats = resolver.stats();
+        let mut last_stats = stats.snapshot();
+        let mut last_prune = Instant::now();
+        let mut last_rotate = Instant::now();
+        while !init::shutdown_requested() {
+            init::reap_children();
+
+            if let Some(analytics) = analytics {
+                if last_rotate.elapsed() >= analytics::WINDOW {
+                    for report in analytics.rotate() {
+                        log::write(LogLevel::Info, &format!("Flagged client: {}", report));
+                    }
+                    last_rotate = Instant::now();
+                }
+            }
+
+            if let Some(history) = shared.history {
+                if last_prune.elapsed() >= HISTORY_PRUNE_INTERVAL {
+                    if let Err(e) = history.prune() {
+                        log::write(
+                            LogLevel::Error,
+                            &format!("Failed to prune the query history: {}", e),
+                        );
+                    }
+                    last_prune = Instant::now();
+                }
+            }
+
+            let current_stats = stats.snapshot();
+            if current_stats != last_stats {
+                log::write(
+                    LogLevel::Info,
+                    &format!("Suspicious traffic: {}", current_stats),
+                );
+                last_stats = current_stats;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    })?;
+
+    log::write(LogLevel::Info, "Shutting down");
+    Ok(())
+}
+
+/// Fails if an upstream or stub domain server is one of the addresses we answer on, which
+/// would have us forwarding queries to ourselves.
+fn check_not_forwarding_to_self(config: &Config) -> Result<()> {
+    let mut ours = vec![config.bind];
+    ours.extend(config.listeners.iter().map(|listener| listener.addr));
+
+    let servers = config
+        .upstream
+        .iter()
+        .chain(config.stub_domains.iter().flat_map(|stub| &stub.servers));
+    for server in servers {
+        if loopcheck::is_own_address(*server, &ours) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("upstream {} is this server, so queries would loop", server),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Probes the upstreams for a loop back to us at startup and every `LOOP_CHECK_INTERVAL`
+/// after, until shutdown.
+fn check_for_loops(resolver: &Resolver, shared: Shared, config: &Config, loop_check: &LoopCheck) {
+    let mut was_looping = false;
+    while !init::shutdown_requested() {
+        let looping = loop_check.probe(|name| {
+            let question = DnsQuestion {
+                name: name.to_string(),
+                qtype: QueryType::A,
+                qclass: CLASS_IN,
+            };
+            match shared.upstreams {
+                Some(upstreams) => {
+                    forward_weighted(resolver, upstreams, &question, &QueryOptions::default())
+                }
+                None => forward(
+                    resolver,
+                    config.upstream_strategy,
+                    &config.upstream,
+                    &question,
+                    &QueryOptions::default(),
+                ),
+            }
+        });
+
+        match (was_looping, looping) {
+            (false, true) => log::write(
+                LogLevel::Error,
+                "Forwarding loop: a query sent upstream came back to us, so forwarded queries \
+                 get SERVFAIL until it stops",
+            ),
+            (true, false) => log::write(LogLevel::Info, "Forwarding loop gone"),
+            _ => {}
+        }
+        was_looping = looping;
+
+        let started = Instant::now();
+        while started.elapsed() < LOOP_CHECK_INTERVAL && !init::shutdown_requested() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// One worker's loop: receives queries on `socket`, the listener labelled `label`, and
+/// answers them until shutdown.
+fn answer_queries(
+    socket: &UdpSocket,
+    label: &str,
+    resolver: &Resolver,
+    shared: Shared,
+    config: &Config,
+) {
+    while !init::shutdown_requested() {
+        let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(x) => x,
+            // Timeouts and signals just give us a chance to check for shutdown.
+            Err(ref e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(e) => {
+                log::write(
+                    LogLevel::Error,
+                    &format!("Failed to read from UDP socket: {:?}", e),
+                );
+                continue;
+            }
+        };
+
+        // Relayed queries only need their question read; the rest goes upstream as it is.
+        let level = match config.transparent {
+            true => ParseLevel::Questions,
+            false => ParseLevel::Full,
+        };
+        let request = match DnsPacket::from_bytes_at(&buf[..len], level) {
+            Ok(packet) => packet,
+            Err(error) => {
+                resolver.stats().record_malformed(src, &error);
+                continue;
+            }
+        };
+
+        let received = Instant::now();
+        let allowed = match (shared.tunnels, request.questions.first()) {
+            (Some(tunnels), Some(question)) => {
+                tunnels.screen(src.ip(), &question.name, question.qtype)
+            }
+            _ => true,
+        };
+        let blocked = match (shared.blocklists, request.questions.first()) {
+            (Some(blocklists), Some(question)) if allowed => blocklists.check(&question.name),
+            _ => None,
+        };
+        if let (Some(blocked), Some(question)) = (&blocked, request.questions.first()) {
+            log::write(
+                LogLevel::Info,
+                &format!(
+                    "Blocked query from {} on {}: {:?} by {}",
+                    src.ip(),
+                    label,
+                    question,
+                    blocked
+                ),
+            );
+        }
+
+        // In transparent mode the upstream's bytes go back as they came.
+        let relayed = match (allowed, &blocked) {
+            (true, None) if config.transparent => {
+                relay(resolver, shared, config, label, &buf[..len], &request)
+            }
+            _ => None,
+        };
+        let shaping = shaping::actions(&config.shapes, label, src.ip());
+        // The payload size, EDNS version and DO bit in the client's OPT record, if it sent
+        // one.
+        let edns = request.resources.iter().find_map(|rec| match *rec {
+            DnsRecord::OPT {
+                udp_payload_size,
+                version,
+                flags,
+                ..
+            } => Some((udp_payload_size, version, flags & EDNS_DO != 0)),
+            _ => None,
+        });
+        let unknown_version = edns.is_some_and(|(_, version, _)| version > 0);
+        let (rescode, data) = match relayed {
+            Some(data) => (
+                ResultCode::from_num(data[3] & 0x0F),
+                shaping::apply_bytes(data, &shaping),
+            ),
+            None => {
+                let mut packet = match (allowed, &blocked) {
+                    _ if unknown_version => bad_version(&request),
+                    (false, _) => refuse(&request),
+                    (true, Some(_)) => block(&request, config),
+                    (true, None) if config.transparent => fail(&request, config),
+                    (true, None) => answer(resolver, shared, config, label, src.ip(), &request),
+                };
+                shaping::apply(&mut packet, &shaping);
+
+                if config.log_level >= LogLevel::Debug {
+                    log::write(LogLevel::Debug, &format!("{:#?}", packet));
+                }
+
+                // Clients shaped to go without EDNS are answered as if they'd never used it,
+                // except with BADVERS, which means nothing without our OPT record.
+                let edns = edns
+                    .filter(|_| unknown_version || !shaping.contains(&shaping::Action::NoEdns))
+                    .map(|(size, _, dnssec_ok)| (size, dnssec_ok));
+                match encode(&mut packet, edns, config.edns_payload_size) {
+                    Ok(data) => (packet.header.rescode, data),
+                    Err(e) => {
+                        log::write(
+                            LogLevel::Error,
+                            &format!("Failed to encode UDP response packet: {:?}", e),
+                        );
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if let (Some(analytics), Some(question)) = (shared.analytics, request.questions.first()) {
+            analytics.record(src.ip(), &question.name, rescode);
+        }
+        if let (Some(traffic), Some(question)) = (shared.traffic, request.questions.first()) {
+            traffic.record(label, src.ip(), &question.name, rescode);
+        }
+
+        if let (Some(history), Some(question)) = (shared.history, request.questions.first()) {
+            let query = history::Query {
+                time: history::unix_now(),
+                client: src.ip(),
+                name: question.name.clone(),
+                qtype: question.qtype,
+                rescode,
+                latency: received.elapsed(),
+                blocked,
+            };
+            if let Err(e) = history.record(&query) {
+                log::write(
+                    LogLevel::Error,
+                    &format!("Failed to record query history: {}", e),
+                );
+            }
+        }
+
+        if let Err(e) = socket.send_to(&data, src) {
+            log::write(
+                LogLevel::Error,
+                &format!("Failed to send response buffer: {:?}", e),
+            );
+            continue;
+        };
+    }
+}
+
+/// A bare-bones HTTP responder for liveness probes: `GET /healthz` gets a 200, anything
+/// else a 404. Once the main loop is running there's nothing else to check. With
+/// analytics or blocklists on, `GET /metrics` serves their figures for Prometheus.
+fn serve_health(
+    listener: TcpListener,
+    analytics: Option<Arc<Analytics>>,
+    blocklists: Option<Arc<Blocklists>>,
+) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+
+        let mut request = [0; 1024];
+        let len = stream.read(&mut request).unwrap_or(0);
+        let request = String::from_utf8_lossy(&request[..len]);
+
+        let has_metrics = analytics.is_some() || blocklists.is_some();
+        let response = match () {
+            _ if request.starts_with("GET /healthz ") => {
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\nConnection: close\r\n\r\nok\n".to_string()
+            }
+            _ if has_metrics && request.starts_with("GET /metrics ") => {
+                let mut body = String::new();
+                if let Some(ref analytics) = analytics {
+                    body.push_str(&analytics.metrics());
+                }
+                if let Some(ref blocklists) = blocklists {
+                    body.push_str(&blocklists.metrics());
+                }
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        };
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
+
+/// Answers `request` from `client` on the listener labelled `label`, sending it to a
+/// safe-search host first if that's turned on for the client and listener.
+fn answer(
+    resolver: &Resolver,
+    shared: Shared,
+    config: &Config,
+    label: &str,
+    client: IpAddr,
+    request: &DnsPacket,
+) -> DnsPacket {
+    let enforced = config.safe_search
+        && (config.safe_search_clients.is_empty()
+            || config
+                .safe_search_clients
+                .iter()
+                .any(|net| net.contains(client)))
+        && (config.safe_search_listeners.is_empty()
+            || config.safe_search_listeners.iter().any(|l| l == label));
+
+    let rewrite = request
+        .questions
+        .first()
+        .filter(|_| enforced)
+        .and_then(|question| Some((question, safesearch::target(&question.name)?)));
+    let (question, target) = match rewrite {
+        Some(rewrite) => rewrite,
+        None => return handle_query(resolver, shared, config, label, request),
+    };
+
+    // Answer for the safe-search host, then present that as the answer for the name that
+    // was asked about, by way of a CNAME.
+    let mut rewritten = request.clone();
+    rewritten.questions[0].name = target.to_string();
+    let mut packet = handle_query(resolver, shared, config, label, &rewritten);
+
+    packet.questions[0] = question.clone();
+    packet
+        .answers
+        .insert(0, safesearch::rewrite_record(&question.name, target));
+    packet.header.answers = packet.answers.len() as u16;
+    packet
+}
+
+/// An NXDOMAIN response to `request`, for names on a blocklist.
+fn block(request: &DnsPacket, config: &Config) -> DnsPacket {
+    let mut packet = refuse(request);
+    packet.header.recursion_available = config.recursion;
+    packet.header.rescode = ResultCode::NonexistantDomain;
+    packet
+}
+
+/// A SERVFAIL response to `request`, for queries we couldn't get an answer to.
+fn fail(request: &DnsPacket, config: &Config) -> DnsPacket {
+    let mut packet = refuse(request);
+    packet.header.recursion_available = config.recursion;
+    packet.header.rescode = ResultCode::ServerFail;
+    packet
+}
+
+/// A BADVERS response to `request`, whose EDNS version is newer than the only one we
+/// speak, 0.
+fn bad_version(request: &DnsPacket) -> DnsPacket {
+    let mut packet = refuse(request);
+    packet.header.rescode = ResultCode::BadVersion;
+    packet
+}
+
+/// Encodes `packet` for a client that sent `edns`, the payload size it advertised and its
+/// DO bit, or that didn't use EDNS. The first kind gets our OPT record back, advertising
+/// `payload_size`, and up to the smaller of its payload size and that, the second up to 512
+/// bytes. A response that doesn't fit goes without its records and with the TC bit set.
+/// Only clients that set DO get DNSSEC records they didn't ask for by type.
+fn encode(packet: &mut DnsPacket, edns: Option<(u16, bool)>, payload_size: u16) -> Result<Vec<u8>> {
+    // Upstream responses bring the upstream's OPT record, which isn't ours to pass on.
+    packet
+        .resources
+        .retain(|rec| rec.query_type() != QueryType::OPT);
+
+    if !edns.is_some_and(|(_, dnssec_ok)| dnssec_ok) {
+        let asked = packet.questions.first().map(|question| question.qtype);
+        let wanted = |rec: &DnsRecord| {
+            let qtype = rec.query_type();
+            Some(qtype) == asked
+                || !matches!(qtype, QueryType::RRSIG | QueryType::NSEC | QueryType::NSEC3)
+        };
+        packet.answers.retain(wanted);
+        packet.authorities.retain(wanted);
+        packet.resources.retain(wanted);
+    }
+
+    // The setting is checked when it's parsed, but a Config built in code can hold anything.
+    let payload_size = payload_size.clamp(MAX_PACKET_SIZE as u16, MAX_EDNS_PACKET_SIZE as u16);
+    let limit = match edns {
+        Some((size, dnssec_ok)) => {
+            // DO goes back to the client as it came.
+            packet.resources.push(DnsRecord::OPT {
+                udp_payload_size: payload_size,
+                extended_rcode: 0,
+                version: 0,
+                flags: match dnssec_ok {
+                    true => EDNS_DO,
+                    false => 0,
+                },
+                options: Vec::new(),
+            });
+            // Anything under 512 is taken as 512.
+            usize::from(size.clamp(MAX_PACKET_SIZE as u16, payload_size))
+        }
+        None => MAX_PACKET_SIZE,
+    };
+
+    let data = packet.to_bytes()?;
+    if data.len() <= limit {
+        return Ok(data);
+    }
+    packet.header.truncated_message = true;
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet
+        .resources
+        .retain(|rec| rec.query_type() == QueryType::OPT);
+    packet.to_bytes()
+}
+
+/// A REFUSED response to `request`, for queries we won't answer at all.
+fn refuse(request: &DnsPacket) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.id = request.header.id;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::Refused;
+    packet.questions = request.questions.iter().take(1).cloned().collect();
+    packet.header.questions = packet.questions.len() as u16;
+    packet
+}
+
+/// Builds the response to `request`: from our zones, leases or synthetic domains if it's one
+/// of our own names, then from the cache if it's there, and only then by recursing or
+/// forwarding, or with a root referral if the client or the configuration rules that out.
+fn handle_query(
+    resolver: &Resolver,
+    shared: Shared,
+    config: &Config,
+    label: &str,
+    request: &DnsPacket,
+) -> DnsPacket {
+    let (local, cache) = (shared.local, shared.cache);
+    let mut packet: DnsPacket = Default::default();
+    packet.header.id = request.header.id;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.recursion_available = config.recursion;
+    packet.header.response = true;
+    // CD is echoed back to the client. AD stays clear: we don't validate DNSSEC, so we
+    // never vouch for the data we return.
+    packet.header.checking_disabled = request.header.checking_disabled;
+    packet.header.authed_data = false;
+
+    let question = match request.questions.first() {
+        Some(question) => question,
+        None => {
+            packet.header.rescode = ResultCode::FormError;
+            return packet;
+        }
+    };
+
+    let correlation = NEXT_QUERY.fetch_add(1, Ordering::Relaxed);
+    if config.log_level >= LogLevel::Info {
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Received query #{} on {}: {:?}",
+                correlation, label, question
+            ),
+        );
+    }
+
+    packet.questions.push(question.clone());
+    packet.header.questions = 1;
+
+    // Passing our own probe on would only send it round again.
+    if shared
+        .loop_check
+        .is_some_and(|loop_check| loop_check.is_probe(&question.name))
+    {
+        packet.header.rescode = ResultCode::ServerFail;
+        return packet;
+    }
+
+    // Our own zones are answered whether or not we recurse.
+    if let Some(zone) = shared.zones.and_then(|zones| zones.find(&question.name)) {
+        let answer = zone.answer(&question.name, question.qtype);
+        packet.header.authoritative_answer = answer.authoritative;
+        packet.header.rescode = answer.rescode;
+        packet.header.answers = answer.answers.len() as u16;
+        packet.header.authoritative_entries = answer.authorities.len() as u16;
+        packet.header.resource_entries = answer.additionals.len() as u16;
+        packet.answers = answer.answers;
+        packet.authorities = answer.authorities;
+        packet.resources = answer.additionals;
+        return packet;
+    }
+
+    // Lease names are ours too.
+    if let Some((rescode, answers)) =
+        local.and_then(|local| local.answer(&question.name, question.qtype))
+    {
+        packet.header.authoritative_answer = true;
+        packet.header.rescode = rescode;
+        packet.header.answers = answers.len() as u16;
+        packet.answers = answers;
+        return packet;
+    }
+
+    if let Some(domain) = synth::find(&config.synth, &question.name) {
+        packet.header.authoritative_answer = true;
+        match domain.answer(&question.name, question.qtype) {
+            Ok((rescode, answers)) => {
+                packet.header.rescode = rescode;
+                packet.header.answers = answers.len() as u16;
+                packet.answers = answers;
+            }
+            Err(e) => {
+                log::write(
+                    LogLevel::Error,
+                    &format!("Failed to synthesize {:?}: {}", question, e),
+                );
+                packet.header.rescode = ResultCode::ServerFail;
+            }
+        }
+        return packet;
+    }
+
+    if let Some(cached) = cache.and_then(|cache| cache.get(&question.name, question.qtype)) {
+        copy_response(&mut packet, cached);
+        return packet;
+    }
+
+    if !config.recursion || !request.header.recursion_desired {
+        // We have nothing but the root hints to answer from.
+        resolver::root_referral(&mut packet);
+        return packet;
+    }
+
+    let options = QueryOptions {
+        checking_disabled: request.header.checking_disabled,
+        correlation: match config.log_upstream {
+            true => Some(correlation),
+            false => None,
+        },
+        randomize_case: config.randomize_case,
+        dnssec_ok: config.dnssec_passthrough,
+        ..QueryOptions::default()
+    };
+
+    let stub = cluster::find_stub(&config.stub_domains, &question.name);
+    let servers = match stub {
+        Some(stub) => &stub.servers,
+        None => &config.upstream,
+    };
+
+    let looping = shared.loop_check.is_some_and(LoopCheck::looping);
+    if stub.is_none() && looping {
+        packet.header.rescode = ResultCode::ServerFail;
+        return packet;
+    }
+
+    let result = if servers.is_empty() {
+        resolver.recursive_lookup_with(&question.name, question.qtype, &options)
+    } else if let (None, Some(upstreams)) = (stub, shared.upstreams) {
+        forward_weighted(resolver, upstreams, question, &options)
+    } else {
+        forward(
+            resolver,
+            config.upstream_strategy,
+            servers,
+            question,
+            &options,
+        )
+    };
+
+    match result {
+        Ok(result) => {
+            copy_response(&mut packet, result);
+
+            if packet.header.rescode == ResultCode::NonexistantDomain {
+                link_local_fallback(config, question, &mut packet);
+            }
+
+            if config.resolve_targets {
+                resolver.resolve_additional_with(&mut packet, &options);
+            }
+        }
+        Err(e) => {
+            log::write(
+                LogLevel::Error,
+                &format!("Failed to resolve {:?}: {}", question, e),
+            );
+            packet.header.rescode = ResultCode::ServerFail;
+        }
+    }
+    if let Some(cache) = cache {
+        cache.insert(&question.name, question.qtype, &packet);
+    }
+
+    packet
+}
+
+/// Passes `bytes`, the query `request` as the client sent it, to the upstreams and returns
+/// their response as it came, for transparent mode. Zones, local records and the cache are
+/// all bypassed; the tunnel check and blocklists have already had their say. None if no
+/// upstream answered, which the client is to get SERVFAIL for.
+fn relay(
+    resolver: &Resolver,
+    shared: Shared,
+    config: &Config,
+    label: &str,
+    bytes: &[u8],
+    request: &DnsPacket,
+) -> Option<Vec<u8>> {
+    let question = request.questions.first();
+    let correlation = NEXT_QUERY.fetch_add(1, Ordering::Relaxed);
+    if config.log_level >= LogLevel::Info {
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Relaying query #{} on {}: {:?}",
+                correlation, label, question
+            ),
+        );
+    }
+
+    let name = question.map_or("", |question| question.name.as_str());
+    if shared
+        .loop_check
+        .is_some_and(|loop_check| loop_check.is_probe(name))
+    {
+        return None;
+    }
+
+    let stub = cluster::find_stub(&config.stub_domains, name);
+    if stub.is_none() && shared.loop_check.is_some_and(LoopCheck::looping) {
+        return None;
+    }
+
+    let correlation = match config.log_upstream {
+        true => Some(correlation),
+        false => None,
+    };
+    let result = match (stub, shared.upstreams) {
+        (Some(stub), _) => relay_ordered(resolver, &stub.servers, bytes, correlation),
+        (None, Some(upstreams)) => relay_weighted(resolver, upstreams, bytes, correlation),
+        (None, None) if config.upstream_strategy == UpstreamStrategy::Race => {
+            resolver.relay(bytes, &config.upstream, correlation)
+        }
+        (None, None) => relay_ordered(resolver, &config.upstream, bytes, correlation),
+    };
+
+    match result {
+        Ok(response) => Some(response),
+        Err(e) => {
+            log::write(
+                LogLevel::Error,
+                &format!("Failed to relay {:?}: {}", question, e),
+            );
+            None
+        }
+    }
+}
+
+/// Relays `bytes` to `servers` one at a time until one answers.
+fn relay_ordered(
+    resolver: &Resolver,
+    servers: &[SocketAddr],
+    bytes: &[u8],
+    correlation: Option<u64>,
+) -> Result<Vec<u8>> {
+    let mut last_error = None;
+
+    for server in servers {
+        match resolver.relay(bytes, &[*server], correlation) {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.expect("forward needs at least one server"))
+}
+
+/// Relays `bytes` to `upstreams` in the order they give, telling them how each server did.
+fn relay_weighted(
+    resolver: &Resolver,
+    upstreams: &WeightedUpstreams,
+    bytes: &[u8],
+    correlation: Option<u64>,
+) -> Result<Vec<u8>> {
+    let mut last_error = None;
+
+    for server in upstreams.order() {
+        match resolver.relay(bytes, &[server], correlation) {
+            Ok(response) => {
+                upstreams.succeeded(server);
+                return Ok(response);
+            }
+            Err(e) => {
+                upstreams.failed(server);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.expect("forward needs at least one server"))
+}
+
+/// Tries LLMNR and then NetBIOS for a single-label name DNS said doesn't exist, replacing
+/// the NXDOMAIN with whatever they find.
+fn link_local_fallback(config: &Config, question: &DnsQuestion, packet: &mut DnsPacket) {
+    if !fallback::is_single_label(&question.name) {
+        return;
+    }
+
+    let mut answers = Vec::new();
+    if config.llmnr && matches!(question.qtype, QueryType::A | QueryType::AAAA) {
+        answers = fallback::llmnr_lookup(&question.name, question.qtype).unwrap_or_default();
+    }
+    if answers.is_empty() && config.netbios && question.qtype == QueryType::A {
+        answers =
+            fallback::netbios_lookup(&question.name, config.netbios_broadcast).unwrap_or_default();
+    }
+    if answers.is_empty() {
+        return;
+    }
+
+    packet.header.rescode = ResultCode::Success;
+    packet.header.answers = answers.len() as u16;
+    packet.answers = answers;
+    packet.header.authoritative_entries = 0;
+    packet.authorities.clear();
+}
+
+fn copy_response(packet: &mut DnsPacket, result: DnsPacket) {
+    packet.header.rescode = result.header.rescode;
+
+    packet.answers = result.answers;
+    packet.authorities = result.authorities;
+    packet.resources = result.resources;
+
+    packet.header.answers = result.header.answers;
+    packet.header.authoritative_entries = result.header.authoritative_entries;
+    packet.header.resource_entries = result.header.resource_entries;
+}
+
+/// Asks `servers` as `strategy` says: each in turn, returning the first response or the
+/// last error, or all at once.
+fn forward(
+    resolver: &Resolver,
+    strategy: UpstreamStrategy,
+    servers: &[SocketAddr],
+    question: &DnsQuestion,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    if strategy == UpstreamStrategy::Race {
+        return resolver.race_with(&question.name, question.qtype, options, servers);
+    }
+
+    let mut last_error = None;
+
+    for server in servers {
+        match resolver.lookup_with(&question.name, question.qtype, options, *server) {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.expect("forward needs at least one server"))
+}
+
+/// Asks `upstreams` in the order they give for this query, telling them how each server
+/// did.
+fn forward_weighted(
+    resolver: &Resolver,
+    upstreams: &WeightedUpstreams,
+    question: &DnsQuestion,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    let mut last_error = None;
+
+    for server in upstreams.order() {
+        match resolver.lookup_with(&question.name, question.qtype, options, server) {
+            Ok(response) => {
+                upstreams.succeeded(server);
+                return Ok(response);
+            }
+            Err(e) => {
+                upstreams.failed(server);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.expect("forward needs at least one server"))
+}
+
+/// The upstreams paired with their weights, for the weighted strategy.
+fn weighted_upstreams(config: &Config) -> Result<WeightedUpstreams> {
+    if config.upstream_weights.len() > config.upstream.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} upstream weights given for {} upstreams",
+                config.upstream_weights.len(),
+                config.upstream.len()
+            ),
+        ));
+    }
+
+    let weights = config
+        .upstream_weights
+        .iter()
+        .copied()
+        .chain(std::iter::repeat(1));
+    let servers: Vec<(SocketAddr, u32)> = config.upstream.iter().copied().zip(weights).collect();
+    Ok(WeightedUpstreams::new(&servers))
+}
@@ -0,0 +1,223 @@
+//! Shaping responses for groups of clients that need something other than the usual: old
+//! clients that choke on EDNS, clients that mustn't take the AD bit on trust, and
+//! constrained devices that are better off with responses as small as they can be. It's
+//! the last thing done to a response before it's sent.
+
+use crate::safesearch::Network;
+use crate::{DnsPacket, DnsRecord, Result, ResultCode, Section, WirePacket};
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Where in the header the authority and additional counts are.
+const NSCOUNT: usize = 8;
+const ARCOUNT: usize = 10;
+
+/// The AD flag, in the fourth byte of the header.
+const AD_FLAG: u8 = 0x20;
+
+/// What a shape applies to: the queries arriving on a listener, or those from a range of
+/// clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Group {
+    Listener(String),
+    Clients(Network),
+}
+
+impl FromStr for Group {
+    type Err = Error;
+
+    /// An address or network, or failing that a listener's label.
+    fn from_str(s: &str) -> Result<Group> {
+        if s.contains('/') || s.parse::<IpAddr>().is_ok() {
+            Ok(Group::Clients(s.parse()?))
+        } else {
+            Ok(Group::Listener(s.to_string()))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Drop the OPT record, for clients that don't understand EDNS.
+    NoEdns,
+    /// Clear the AD flag, for clients that shouldn't trust an upstream's validation.
+    NoAd,
+    /// Leave out the authority and additional sections of answers, which the client can
+    /// do without. Referrals and negative answers, which need them, are left alone.
+    Minimal,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Action> {
+        match s {
+            "no-edns" => Ok(Action::NoEdns),
+            "no-ad" => Ok(Action::NoAd),
+            "minimal" => Ok(Action::Minimal),
+            _ => Err(invalid(format!(
+                "unknown shaping {:?}, expected no-edns, no-ad or minimal",
+                s
+            ))),
+        }
+    }
+}
+
+/// The shaping for one group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub group: Group,
+    pub actions: Vec<Action>,
+}
+
+impl FromStr for Shape {
+    type Err = Error;
+
+    /// Parses `<group> <action>...`, as in `iot minimal` or `10.0.5.0/24 no-edns no-ad`.
+    fn from_str(s: &str) -> Result<Shape> {
+        let mut fields = s.split_whitespace();
+        let group = fields
+            .next()
+            .ok_or_else(|| invalid("expected a group followed by shaping".to_string()))?
+            .parse()?;
+        let actions = fields.map(str::parse).collect::<Result<Vec<_>>>()?;
+        if actions.is_empty() {
+            return Err(invalid(format!(
+                "expected shaping after the group, found {:?}",
+                s
+            )));
+        }
+
+        Ok(Shape { group, actions })
+    }
+}
+
+impl Shape {
+    pub fn applies(&self, label: &str, client: IpAddr) -> bool {
+        match self.group {
+            Group::Listener(ref listener) => listener == label,
+            Group::Clients(ref net) => net.contains(client),
+        }
+    }
+}
+
+/// Everything `shapes` do to responses for `client` on the listener labelled `label`. A
+/// client in several groups gets the shaping of each.
+pub fn actions(shapes: &[Shape], label: &str, client: IpAddr) -> Vec<Action> {
+    let mut actions = Vec::new();
+    for shape in shapes.iter().filter(|shape| shape.applies(label, client)) {
+        for action in &shape.actions {
+            if !actions.contains(action) {
+                actions.push(*action);
+            }
+        }
+    }
+    actions
+}
+
+/// Shapes a response we built ourselves.
+pub fn apply(packet: &mut DnsPacket, actions: &[Action]) {
+    if actions.contains(&Action::NoAd) {
+        packet.header.authed_data = false;
+    }
+    if actions.contains(&Action::NoEdns) {
+        packet.resources.retain(|rec| !is_opt(rec));
+    }
+    if actions.contains(&Action::Minimal) && is_answer(packet) {
+        packet.authorities.clear();
+        packet.resources.retain(is_opt);
+    }
+
+    packet.header.authoritative_entries = packet.authorities.len() as u16;
+    packet.header.resource_entries = packet.resources.len() as u16;
+}
+
+/// Shapes a response relayed as the upstream sent it, cutting records out of its bytes so
+/// that the rest stays as it was. Only if an OPT record to drop sits in front of other
+/// additional records, whose compression pointers could point past it, is the response
+/// re-encoded instead. A response that doesn't parse only has its AD flag cleared.
+pub fn apply_bytes(mut response: Vec<u8>, actions: &[Action]) -> Vec<u8> {
+    if actions.contains(&Action::NoAd) && response.len() > 3 {
+        response[3] &= !AD_FLAG;
+    }
+    let (no_edns, minimal) = (
+        actions.contains(&Action::NoEdns),
+        actions.contains(&Action::Minimal),
+    );
+    if !no_edns && !minimal {
+        return response;
+    }
+
+    let wire = match WirePacket::from_bytes(&response) {
+        Ok(wire) => wire,
+        Err(_) => return response,
+    };
+    let packet = &wire.packet;
+    let additional = |index: usize| wire.record_bytes(Section::Additional, index).unwrap();
+    let opts: Vec<usize> = (0..packet.resources.len())
+        .filter(|&index| is_opt(&packet.resources[index]))
+        .collect();
+
+    if minimal && is_answer(packet) {
+        let end = wire
+            .span(Section::Answer, packet.answers.len() - 1)
+            .unwrap()
+            .record
+            .end;
+        let mut shaped = response[..end].to_vec();
+        let kept = match no_edns {
+            true => Vec::new(),
+            false => opts,
+        };
+        for &index in &kept {
+            shaped.extend_from_slice(additional(index));
+        }
+        set_count(&mut shaped, NSCOUNT, 0);
+        set_count(&mut shaped, ARCOUNT, kept.len());
+        return shaped;
+    }
+
+    if !no_edns || opts.is_empty() {
+        return response;
+    }
+
+    // The OPT record normally comes last, and can simply be cut off the end.
+    let first = packet.resources.len() - opts.len();
+    if opts.iter().copied().eq(first..packet.resources.len()) {
+        let start = wire.span(Section::Additional, first).unwrap().record.start;
+        response.truncate(start);
+        set_count(&mut response, ARCOUNT, first);
+        return response;
+    }
+
+    let mut packet = wire.packet.clone();
+    apply(&mut packet, &[Action::NoEdns]);
+    packet.to_bytes().unwrap_or(response)
+}
+
+/// Whether `packet` answers its question outright, with records of the type asked for.
+/// Anything short of that, like a CNAME to a name with no such records, needs its
+/// authority section for the SOA that says how long to remember there's nothing.
+fn is_answer(packet: &DnsPacket) -> bool {
+    packet.header.rescode == ResultCode::Success
+        && match packet.questions.first() {
+            Some(question) => packet
+                .answers
+                .iter()
+                .any(|rec| rec.query_type() == question.qtype),
+            None => !packet.answers.is_empty(),
+        }
+}
+
+fn is_opt(rec: &DnsRecord) -> bool {
+    matches!(rec, DnsRecord::OPT { .. })
+}
+
+fn set_count(message: &mut [u8], at: usize, count: usize) {
+    message[at..at + 2].copy_from_slice(&(count as u16).to_be_bytes());
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,105 @@
+use crate::log::{self, LogLevel};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for datagrams that don't look like legitimate DNS traffic. Each kind is
+/// tracked separately, since a steady stream of wrong-ID or wrong-source responses
+/// usually means someone is trying to poison the resolver.
+///
+/// There's no counter for UDP checksums. The kernel drops datagrams whose checksum is
+/// wrong before a socket sees them, and takes a zero checksum over IPv4 to mean none was
+/// computed, so from a UDP socket the two look like silence and like any other datagram.
+#[derive(Debug, Default)]
+pub struct Stats {
+    malformed: AtomicU64,
+    wrong_id: AtomicU64,
+    wrong_question: AtomicU64,
+    unexpected_source: AtomicU64,
+    log: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub malformed: u64,
+    pub wrong_id: u64,
+    pub wrong_question: u64,
+    pub unexpected_source: u64,
+}
+
+impl Stats {
+    pub fn new(log: bool) -> Stats {
+        Stats {
+            log,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_malformed(&self, src: SocketAddr, reason: &dyn fmt::Display) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+        if self.log {
+            log::write(
+                LogLevel::Info,
+                &format!("Malformed datagram from {}: {}", src, reason),
+            );
+        }
+    }
+
+    pub fn record_wrong_id(&self, src: SocketAddr, expected: u16, found: u16) {
+        self.wrong_id.fetch_add(1, Ordering::Relaxed);
+        if self.log {
+            log::write(
+                LogLevel::Info,
+                &format!(
+                    "Response from {} with wrong id: expected {}, found {}",
+                    src, expected, found
+                ),
+            );
+        }
+    }
+
+    pub fn record_wrong_question(&self, src: SocketAddr, expected: &str, found: &str) {
+        self.wrong_question.fetch_add(1, Ordering::Relaxed);
+        if self.log {
+            log::write(
+                LogLevel::Info,
+                &format!(
+                    "Response from {} for the wrong question: expected {}, found {}",
+                    src, expected, found
+                ),
+            );
+        }
+    }
+
+    pub fn record_unexpected_source(&self, src: SocketAddr, expected: SocketAddr) {
+        self.unexpected_source.fetch_add(1, Ordering::Relaxed);
+        if self.log {
+            log::write(
+                LogLevel::Info,
+                &format!(
+                    "Response from unexpected source {} (waiting on {})",
+                    src, expected
+                ),
+            );
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            malformed: self.malformed.load(Ordering::Relaxed),
+            wrong_id: self.wrong_id.load(Ordering::Relaxed),
+            wrong_question: self.wrong_question.load(Ordering::Relaxed),
+            unexpected_source: self.unexpected_source.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "malformed={} wrong_id={} wrong_question={} unexpected_source={}",
+            self.malformed, self.wrong_id, self.wrong_question, self.unexpected_source
+        )
+    }
+}
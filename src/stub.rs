@@ -0,0 +1,139 @@
+//! Running as the machine's own caching stub resolver: `serve --stub`.
+
+use crate::config::{Config, LogLevel};
+use crate::Result;
+use crate::{cluster, log};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+pub const STUB_ADDR: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 53);
+
+pub const SYSTEM_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Where systemd-resolved keeps the real upstreams when `/etc/resolv.conf` points at its own
+/// stub on 127.0.0.53.
+const SYSTEMD_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Answers that would expire sooner are kept for this long in stub mode.
+const STUB_MIN_TTL: u32 = 60;
+
+impl Config {
+    /// The `--stub` preset: listen on 127.0.0.53, forward to the system's resolvers and
+    /// cache aggressively. Other settings still layer on top.
+    pub fn stub() -> Result<Config> {
+        let mut upstream = system_resolvers(Path::new(SYSTEM_RESOLV_CONF))?;
+        if upstream.is_empty() {
+            upstream = system_resolvers(Path::new(SYSTEMD_RESOLV_CONF)).unwrap_or_default();
+        }
+        if upstream.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no upstream resolvers found in {}", SYSTEM_RESOLV_CONF),
+            ));
+        }
+
+        Ok(Config {
+            bind: SocketAddr::new(IpAddr::V4(STUB_ADDR), 53),
+            upstream,
+            cache: true,
+            cache_min_ttl: STUB_MIN_TTL,
+            ..Config::default()
+        })
+    }
+}
+
+/// The name servers in a resolv.conf, minus any loopback stub (ours or systemd-resolved's)
+/// that would have us forwarding to ourselves.
+fn system_resolvers(path: &Path) -> Result<Vec<SocketAddr>> {
+    Ok(cluster::load_resolv_conf(path)?
+        .into_iter()
+        .filter(|addr| !addr.ip().is_loopback())
+        .collect())
+}
+
+/// Points the system resolv.conf at us for as long as it's alive, putting the original
+/// back when dropped.
+#[derive(Debug)]
+pub struct ResolvConfTakeover {
+    path: PathBuf,
+    backup: PathBuf,
+    /// The original was a symlink to this, as when systemd-resolved manages it.
+    link_target: Option<PathBuf>,
+}
+
+impl ResolvConfTakeover {
+    pub fn new(path: &Path, server: IpAddr) -> Result<ResolvConfTakeover> {
+        let backup = path.with_extension("conf.diydns-backup");
+        if backup.exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} exists; restore it over {} before taking over again",
+                    backup.display(),
+                    path.display()
+                ),
+            ));
+        }
+
+        let original = fs::read_to_string(path)?;
+        let link_target = match fs::symlink_metadata(path)?.file_type().is_symlink() {
+            true => Some(fs::read_link(path)?),
+            false => None,
+        };
+
+        // Keep the search domains and options, swapping in ourselves as the only server.
+        let mut contents = format!("# Managed by diydns; original in {}\n", backup.display());
+        contents.push_str(&format!("nameserver {}\n", server));
+        for line in original.lines() {
+            if line.starts_with("search")
+                || line.starts_with("domain")
+                || line.starts_with("options")
+            {
+                contents.push_str(line);
+                contents.push('\n');
+            }
+        }
+
+        fs::write(&backup, &original)?;
+        if link_target.is_some() {
+            fs::remove_file(path)?;
+        }
+        fs::write(path, contents)?;
+
+        Ok(ResolvConfTakeover {
+            path: path.to_path_buf(),
+            backup,
+            link_target,
+        })
+    }
+
+    fn restore(&self) -> Result<()> {
+        match self.link_target {
+            #[cfg(unix)]
+            Some(ref target) => {
+                fs::remove_file(&self.path)?;
+                std::os::unix::fs::symlink(target, &self.path)?;
+                fs::remove_file(&self.backup)
+            }
+            _ => fs::rename(&self.backup, &self.path),
+        }
+    }
+}
+
+impl Drop for ResolvConfTakeover {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::write(
+                LogLevel::Error,
+                &format!(
+                    "Failed to restore {} from {}: {}",
+                    self.path.display(),
+                    self.backup.display(),
+                    e
+                ),
+            );
+        }
+    }
+}
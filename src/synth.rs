@@ -0,0 +1,279 @@
+//! Made-up top-level domains for development setups, like `.lab` or `.home`, whose names
+//! are answered from a fixed address or by running a script instead of being resolved.
+
+use crate::{DnsRecord, QueryType, Result, ResultCode};
+use std::io::{Error, ErrorKind, Read};
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Synthesized answers are cheap to make again, so they aren't kept for long.
+const SYNTH_TTL: u32 = 60;
+
+/// A script that hasn't answered by now is killed and the query gets SERVFAIL.
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Synthesizer {
+    /// Every name gets this address.
+    Fixed(IpAddr),
+    /// nip.io style: the address is read from the name itself, as with `10.0.0.7.lab`,
+    /// `app.10-0-0-7.lab`, `app-0a000007.lab` or `fd00--7.lab` for `fd00::7`.
+    EmbeddedIp,
+    /// Run as `<script> <name> <type>`. Each line it prints is a record for the name, such
+    /// as `A 10.0.0.7` or `TXT hello`. Exiting with a non-zero status means the name
+    /// doesn't exist.
+    Script(PathBuf),
+}
+
+/// A domain whose names, and the domain itself, are synthesized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntheticDomain {
+    pub suffix: String,
+    pub synthesizer: Synthesizer,
+}
+
+impl FromStr for SyntheticDomain {
+    type Err = Error;
+
+    /// Parses `<suffix> <address>`, `<suffix> ip` or `<suffix> exec:<script>`.
+    fn from_str(s: &str) -> Result<SyntheticDomain> {
+        let mut fields = s.split_whitespace();
+        let (suffix, answer) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(suffix), Some(answer), None) => (suffix, answer),
+            _ => {
+                return Err(invalid(format!(
+                    "expected <domain> <answer>, found {:?}",
+                    s
+                )))
+            }
+        };
+
+        let synthesizer = match answer.strip_prefix("exec:") {
+            Some(script) => Synthesizer::Script(PathBuf::from(script)),
+            None if answer == "ip" => Synthesizer::EmbeddedIp,
+            None => Synthesizer::Fixed(answer.parse().map_err(|_| {
+                invalid(format!(
+                    "expected an address, ip or exec:<script>, found {:?}",
+                    answer
+                ))
+            })?),
+        };
+
+        Ok(SyntheticDomain {
+            suffix: suffix.trim_matches('.').to_lowercase(),
+            synthesizer,
+        })
+    }
+}
+
+/// The synthetic domain that `name` falls under, preferring the longest suffix.
+pub fn find<'a>(domains: &'a [SyntheticDomain], name: &str) -> Option<&'a SyntheticDomain> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    domains
+        .iter()
+        .filter(|domain| name == domain.suffix || name.ends_with(&format!(".{}", domain.suffix)))
+        .max_by_key(|domain| domain.suffix.len())
+}
+
+impl SyntheticDomain {
+    pub fn answer(&self, name: &str, qtype: QueryType) -> Result<(ResultCode, Vec<DnsRecord>)> {
+        match self.synthesizer {
+            Synthesizer::Fixed(addr) => Ok((
+                ResultCode::Success,
+                iter::once(address_record(name, addr))
+                    .filter(|rec| rec.query_type() == qtype)
+                    .collect(),
+            )),
+            Synthesizer::EmbeddedIp => {
+                let name = name.trim_end_matches('.');
+                let prefix = name
+                    .len()
+                    .checked_sub(self.suffix.len() + 1)
+                    .map(|end| &name[..end]);
+
+                match prefix.and_then(embedded_ip) {
+                    Some(addr) => Ok((
+                        ResultCode::Success,
+                        iter::once(address_record(name, addr))
+                            .filter(|rec| rec.query_type() == qtype)
+                            .collect(),
+                    )),
+                    None if prefix.is_none() => Ok((ResultCode::Success, Vec::new())),
+                    None => Ok((ResultCode::NonexistantDomain, Vec::new())),
+                }
+            }
+            Synthesizer::Script(ref script) => run_script(script, name, qtype),
+        }
+    }
+}
+
+/// Finds the address in the part of a name before the synthetic suffix. The last label is
+/// tried first, on its own or after a dash-separated prefix (`app-10-0-0-7`), then the last
+/// four labels as a dotted quad.
+pub fn embedded_ip(prefix: &str) -> Option<IpAddr> {
+    let labels: Vec<&str> = prefix.split('.').collect();
+    let last = labels.last()?;
+
+    if let Some(addr) = dashed_ip(last) {
+        return Some(addr);
+    }
+
+    if labels.len() >= 4 {
+        let quad = labels[labels.len() - 4..].join(".");
+        if let Ok(addr) = quad.parse::<Ipv4Addr>() {
+            return Some(IpAddr::V4(addr));
+        }
+    }
+
+    None
+}
+
+fn dashed_ip(label: &str) -> Option<IpAddr> {
+    // Dashed IPv4, possibly after other dash-separated words.
+    let parts: Vec<&str> = label.split('-').collect();
+    if parts.len() >= 4 {
+        let quad = parts[parts.len() - 4..].join(".");
+        if let Ok(addr) = quad.parse::<Ipv4Addr>() {
+            return Some(IpAddr::V4(addr));
+        }
+    }
+
+    // Eight hex digits, possibly after a dash.
+    let hex = label.rsplit('-').next()?;
+    if hex.len() == 8 {
+        if let Ok(bits) = u32::from_str_radix(hex, 16) {
+            return Some(IpAddr::V4(Ipv4Addr::from(bits)));
+        }
+    }
+
+    // IPv6 with dashes for colons, and `--` for `::`.
+    if label.contains('-') {
+        if let Ok(addr) = label.replace('-', ":").parse::<Ipv6Addr>() {
+            return Some(IpAddr::V6(addr));
+        }
+    }
+
+    None
+}
+
+pub(crate) fn address_record(name: &str, addr: IpAddr) -> DnsRecord {
+    match addr {
+        IpAddr::V4(addr) => DnsRecord::A {
+            domain: name.to_string(),
+            addr,
+            ttl: SYNTH_TTL,
+        },
+        IpAddr::V6(addr) => DnsRecord::AAAA {
+            domain: name.to_string(),
+            addr,
+            ttl: SYNTH_TTL,
+        },
+    }
+}
+
+fn run_script(script: &Path, name: &str, qtype: QueryType) -> Result<(ResultCode, Vec<DnsRecord>)> {
+    let mut child = Command::new(script)
+        .arg(name)
+        .arg(qtype.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let deadline = Instant::now() + SCRIPT_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("{} took too long to answer", script.display()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    if !status.success() {
+        return Ok((ResultCode::NonexistantDomain, Vec::new()));
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+
+    let records = output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_record(name, line))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((
+        ResultCode::Success,
+        records
+            .into_iter()
+            .filter(|rec| rec.query_type() == qtype)
+            .collect(),
+    ))
+}
+
+/// Parses one line of script output: a type followed by the record data.
+fn parse_record(name: &str, line: &str) -> Result<DnsRecord> {
+    let (rtype, data) = line
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((line, ""));
+    let data = data.trim();
+    let domain = name.to_string();
+    let ttl = SYNTH_TTL;
+    let bad = || invalid(format!("can't parse script output {:?}", line));
+
+    Ok(match rtype.to_ascii_uppercase().as_str() {
+        "A" => DnsRecord::A {
+            domain,
+            addr: data.parse().map_err(|_| bad())?,
+            ttl,
+        },
+        "AAAA" => DnsRecord::AAAA {
+            domain,
+            addr: data.parse().map_err(|_| bad())?,
+            ttl,
+        },
+        "CNAME" => DnsRecord::CNAME {
+            domain,
+            host: data.to_string(),
+            ttl,
+        },
+        "PTR" => DnsRecord::PTR {
+            domain,
+            host: data.to_string(),
+            ttl,
+        },
+        "MX" => {
+            let (priority, host) = data.split_once(char::is_whitespace).ok_or_else(bad)?;
+            DnsRecord::MX {
+                domain,
+                priority: priority.parse().map_err(|_| bad())?,
+                host: host.trim().to_string(),
+                ttl,
+            }
+        }
+        "TXT" => DnsRecord::TXT {
+            domain,
+            data: vec![data.to_string()],
+            ttl,
+        },
+        _ => return Err(bad()),
+    })
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,227 @@
+//! Running totals of what the server has answered since it started: how many queries, on
+//! which listeners, with which response codes, for which names and from which clients. The
+//! control socket's `stats` command hands them out, and `diydns top` turns them into a live
+//! display.
+
+use crate::{Result, ResultCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Names or clients beyond this many aren't counted, so a flood of random names or spoofed
+/// sources can't use up memory.
+const MAX_TRACKED: usize = 10_000;
+
+/// Every response code, in the order they're listed.
+const RESCODES: [ResultCode; 7] = [
+    ResultCode::Success,
+    ResultCode::FormError,
+    ResultCode::ServerFail,
+    ResultCode::NonexistantDomain,
+    ResultCode::NotImplemented,
+    ResultCode::Refused,
+    ResultCode::BadVersion,
+];
+
+#[derive(Debug, Default)]
+struct Totals {
+    queries: u64,
+    rescodes: [u64; 7],
+    /// There are only as many listeners as the config names, so these needn't be capped.
+    listeners: HashMap<String, u64>,
+    names: HashMap<String, u64>,
+    clients: HashMap<IpAddr, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Traffic {
+    totals: Mutex<Totals>,
+}
+
+impl Traffic {
+    pub fn new() -> Traffic {
+        Traffic::default()
+    }
+
+    /// Counts one query on the listener labelled `listener` from `client` for `name`,
+    /// answered with `rescode`.
+    pub fn record(&self, listener: &str, client: IpAddr, name: &str, rescode: ResultCode) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.queries += 1;
+        totals.rescodes[rescode as usize] += 1;
+        *totals.listeners.entry(listener.to_string()).or_default() += 1;
+
+        let name = name.trim_end_matches('.').to_lowercase();
+        if totals.names.len() < MAX_TRACKED || totals.names.contains_key(&name) {
+            *totals.names.entry(name).or_default() += 1;
+        }
+        if totals.clients.len() < MAX_TRACKED || totals.clients.contains_key(&client) {
+            *totals.clients.entry(client).or_default() += 1;
+        }
+    }
+
+    /// The totals so far, with every listener's and the `count` most asked-for names and
+    /// busiest clients.
+    pub fn snapshot(&self, count: usize) -> Snapshot {
+        let totals = self.totals.lock().unwrap();
+
+        Snapshot {
+            queries: totals.queries,
+            rescodes: RESCODES
+                .iter()
+                .map(|&rescode| (rescode, totals.rescodes[rescode as usize]))
+                .filter(|&(_, count)| count > 0)
+                .collect(),
+            listeners: busiest(
+                totals
+                    .listeners
+                    .iter()
+                    .map(|(label, n)| (label.clone(), *n)),
+                totals.listeners.len(),
+            ),
+            names: busiest(
+                totals.names.iter().map(|(name, n)| (name.clone(), *n)),
+                count,
+            ),
+            clients: busiest(
+                totals.clients.iter().map(|(client, n)| (*client, *n)),
+                count,
+            ),
+        }
+    }
+}
+
+/// The `count` entries with the highest counts, highest first and then in order.
+fn busiest<T: Ord>(entries: impl Iterator<Item = (T, u64)>, count: usize) -> Vec<(T, u64)> {
+    let mut entries: Vec<(T, u64)> = entries.collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(count);
+    entries
+}
+
+/// The server's totals at one moment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub queries: u64,
+    /// Queries answered with each response code that has come up.
+    pub rescodes: Vec<(ResultCode, u64)>,
+    /// Queries on each listener, by label.
+    pub listeners: Vec<(String, u64)>,
+    pub names: Vec<(String, u64)>,
+    pub clients: Vec<(IpAddr, u64)>,
+}
+
+/// One total per line, as the control socket sends them:
+///
+/// ```text
+/// queries 1520
+/// rcode NOERROR 1490
+/// listener default 1320
+/// listener guest 200
+/// name example.com 212
+/// client 10.0.0.5 871
+/// ```
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "queries {}", self.queries)?;
+        for (rescode, count) in &self.rescodes {
+            writeln!(f, "rcode {} {}", rescode, count)?;
+        }
+        for (label, count) in &self.listeners {
+            writeln!(f, "listener {} {}", label, count)?;
+        }
+        for (name, count) in &self.names {
+            writeln!(f, "name {} {}", name, count)?;
+        }
+        for (client, count) in &self.clients {
+            writeln!(f, "client {} {}", client, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::default();
+
+        for line in s.lines() {
+            let bad = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected line {:?}", line),
+                )
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["queries", count] => snapshot.queries = count.parse().map_err(|_| bad())?,
+                ["rcode", rescode, count] => {
+                    let rescode = RESCODES
+                        .iter()
+                        .find(|code| code.to_string() == *rescode)
+                        .ok_or_else(bad)?;
+                    snapshot
+                        .rescodes
+                        .push((*rescode, count.parse().map_err(|_| bad())?));
+                }
+                ["listener", label, count] => snapshot
+                    .listeners
+                    .push((label.to_string(), count.parse().map_err(|_| bad())?)),
+                ["name", name, count] => snapshot
+                    .names
+                    .push((name.to_string(), count.parse().map_err(|_| bad())?)),
+                ["client", client, count] => snapshot.clients.push((
+                    client.parse().map_err(|_| bad())?,
+                    count.parse().map_err(|_| bad())?,
+                )),
+                _ => return Err(bad()),
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+/// The screen `diydns top` shows for `snapshot`, given the query rate since the last one
+/// if there was one.
+pub fn render(snapshot: &Snapshot, rate: Option<f64>) -> String {
+    let mut out = String::new();
+    let rate = match rate {
+        Some(rate) => format!("{:.1}/s", rate),
+        None => "-/s".to_string(),
+    };
+    out.push_str(&format!("Queries: {} total, {}\n", snapshot.queries, rate));
+
+    out.push_str("\nResponse codes\n");
+    for (rescode, count) in &snapshot.rescodes {
+        let share = *count as f64 * 100.0 / snapshot.queries.max(1) as f64;
+        out.push_str(&format!(
+            "  {:<40} {:>10} {:>6.1}%\n",
+            rescode, count, share
+        ));
+    }
+
+    // Not worth a section when everything arrives on `bind`.
+    if snapshot.listeners.len() > 1 {
+        out.push_str("\nListeners\n");
+        for (label, count) in &snapshot.listeners {
+            out.push_str(&format!("  {:<40} {:>10}\n", label, count));
+        }
+    }
+
+    out.push_str("\nTop names\n");
+    for (name, count) in &snapshot.names {
+        out.push_str(&format!("  {:<40} {:>10}\n", name, count));
+    }
+
+    out.push_str("\nTop clients\n");
+    for (client, count) in &snapshot.clients {
+        out.push_str(&format!("  {:<40} {:>10}\n", client, count));
+    }
+
+    out
+}
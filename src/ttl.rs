@@ -0,0 +1,68 @@
+//! Durations as people write them: `90`, `30m`, `1h30m` or `2d`. A bare number is seconds,
+//! and otherwise each number takes a unit of `s`, `m`, `h` or `d`.
+
+use crate::Result;
+use std::io::{Error, ErrorKind};
+
+const UNITS: [(char, u32); 4] = [('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+
+/// Parses a duration such as `90`, `45s`, `1h30m` or `2d` into seconds.
+pub fn parse(value: &str) -> Result<u32> {
+    let value = value.trim();
+    let bad = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "expected a duration like 90s, 30m, 1h30m or 7d, found {:?}",
+                value
+            ),
+        )
+    };
+
+    if value.is_empty() {
+        return Err(bad());
+    }
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().map_err(|_| bad());
+    }
+
+    let mut total: u32 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (num, tail) = rest.split_at(digits);
+        let unit = tail.chars().next().ok_or_else(bad)?;
+        let scale = match UNITS.iter().find(|(name, _)| *name == unit) {
+            Some(&(_, scale)) => scale,
+            None => return Err(bad()),
+        };
+
+        let num: u32 = num.parse().map_err(|_| bad())?;
+        total = num
+            .checked_mul(scale)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(bad)?;
+        rest = &tail[1..];
+    }
+
+    Ok(total)
+}
+
+/// `secs` the way `parse` reads them, largest units first: `1h30m`, `2d`, `45s`.
+pub fn humanize(secs: u32) -> String {
+    if secs == 0 {
+        return "0s".to_string();
+    }
+
+    let mut out = String::new();
+    let mut rest = secs;
+    for &(unit, scale) in UNITS.iter() {
+        if rest >= scale {
+            out.push_str(&format!("{}{}", rest / scale, unit));
+            rest %= scale;
+        }
+    }
+    out
+}
@@ -0,0 +1,254 @@
+//! Spotting queries that smuggle data through DNS. Tunnels encode their payload in the
+//! names they ask for, which makes for very long labels and random-looking names, and
+//! fetch the replies in bulky TXT or NULL records, so one domain gets far more of those
+//! than a normal zone would.
+
+use crate::log::{self, LogLevel};
+use crate::{QueryType, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Query volumes are counted per domain over windows this long.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Domains beyond this many aren't counted until old windows are swept out.
+const MAX_DOMAINS: usize = 10_000;
+
+/// Names whose subdomain part is at least this long and comes within `ENTROPY_RATIO` of
+/// the most Shannon entropy a string of its length could have look like encoded data.
+/// Shorter strings can't show much entropy whatever they hold.
+const MIN_ENTROPY_LEN: usize = 24;
+const ENTROPY_RATIO: f64 = 0.82;
+
+/// Letters, digits and the hyphen: all a host name can be made of.
+const HOSTNAME_ALPHABET: usize = 37;
+
+/// The NULL record type, a favourite of tunnels since it can carry anything.
+const QTYPE_NULL: u16 = 10;
+
+/// What happens to a query that looks like tunnel traffic. It's logged whatever the
+/// action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TunnelAction {
+    /// Answer it as usual.
+    Log,
+    /// Answer the first `Limits::rate_limit` suspicious queries per domain each minute and
+    /// refuse the rest.
+    RateLimit,
+    /// Refuse it.
+    Block,
+}
+
+impl FromStr for TunnelAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TunnelAction> {
+        match s {
+            "log" => Ok(TunnelAction::Log),
+            "rate_limit" => Ok(TunnelAction::RateLimit),
+            "block" => Ok(TunnelAction::Block),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown tunnel action {:?}, expected log, rate_limit or block",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Labels longer than this are suspicious. Ordinary host names rarely come close to
+    /// the 63 the protocol allows.
+    pub label_length: usize,
+    /// TXT and NULL queries per domain per minute beyond this are suspicious.
+    pub txt_per_minute: u64,
+    /// Suspicious queries answered per domain per minute under `TunnelAction::RateLimit`.
+    pub rate_limit: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            label_length: 50,
+            txt_per_minute: 60,
+            rate_limit: 10,
+        }
+    }
+}
+
+/// Why a query looked like tunnel traffic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Suspicion {
+    LongLabel(usize),
+    HighEntropy(f64),
+    TxtVolume(u64),
+}
+
+impl fmt::Display for Suspicion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Suspicion::LongLabel(len) => write!(f, "label of {} characters", len),
+            Suspicion::HighEntropy(bits) => write!(f, "{:.2} bits of entropy per character", bits),
+            Suspicion::TxtVolume(count) => write!(f, "{} TXT/NULL queries this minute", count),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DomainWindow {
+    started: Instant,
+    txt: u64,
+    suspicious: u64,
+}
+
+impl DomainWindow {
+    fn new() -> DomainWindow {
+        DomainWindow {
+            started: Instant::now(),
+            txt: 0,
+            suspicious: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TunnelDetector {
+    action: TunnelAction,
+    limits: Limits,
+    domains: Mutex<HashMap<String, DomainWindow>>,
+}
+
+impl TunnelDetector {
+    pub fn new(action: TunnelAction, limits: Limits) -> TunnelDetector {
+        TunnelDetector {
+            action,
+            limits,
+            domains: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks a query from `client`, logging it if it looks like tunnel traffic, and
+    /// returns whether to answer it.
+    pub fn screen(&self, client: IpAddr, name: &str, qtype: QueryType) -> bool {
+        let suspicion = match self.inspect(name, qtype) {
+            Some(suspicion) => suspicion,
+            None => return true,
+        };
+
+        let allowed = match self.action {
+            TunnelAction::Log => true,
+            TunnelAction::Block => false,
+            TunnelAction::RateLimit => self.with_window(name, |window| {
+                window.suspicious += 1;
+                window.suspicious <= self.limits.rate_limit
+            }),
+        };
+
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Possible DNS tunnel from {}: {} {} ({}){}",
+                client,
+                name,
+                qtype,
+                suspicion,
+                if allowed { "" } else { ", refused" }
+            ),
+        );
+
+        allowed
+    }
+
+    /// What, if anything, makes the query look like tunnel traffic. TXT and NULL queries
+    /// are counted towards their domain's volume as they're inspected.
+    pub fn inspect(&self, name: &str, qtype: QueryType) -> Option<Suspicion> {
+        let name = name.trim_end_matches('.');
+
+        if let Some(len) = name
+            .split('.')
+            .map(str::len)
+            .max()
+            .filter(|len| *len > self.limits.label_length)
+        {
+            return Some(Suspicion::LongLabel(len));
+        }
+
+        // The part of the name left of the domain the tunnel runs under.
+        let labels: Vec<&str> = name.split('.').collect();
+        if labels.len() > 2 {
+            let data: String = labels[..labels.len() - 2].concat();
+            if data.len() >= MIN_ENTROPY_LEN {
+                let bits = entropy(&data.to_lowercase());
+                let most = (data.len().min(HOSTNAME_ALPHABET) as f64).log2();
+                if bits > most * ENTROPY_RATIO {
+                    return Some(Suspicion::HighEntropy(bits));
+                }
+            }
+        }
+
+        if qtype == QueryType::TXT || qtype == QueryType::Unknown(QTYPE_NULL) {
+            let count = self.with_window(name, |window| {
+                window.txt += 1;
+                window.txt
+            });
+            if count > self.limits.txt_per_minute {
+                return Some(Suspicion::TxtVolume(count));
+            }
+        }
+
+        None
+    }
+
+    /// Runs `f` on the current window of the domain `name` is under, taken to be its last
+    /// two labels.
+    fn with_window<T>(&self, name: &str, f: impl FnOnce(&mut DomainWindow) -> T) -> T {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let domain = match name.rmatch_indices('.').nth(1) {
+            Some((dot, _)) => &name[dot + 1..],
+            None => name.as_str(),
+        };
+
+        let mut domains = self.domains.lock().unwrap();
+        if domains.len() >= MAX_DOMAINS && !domains.contains_key(domain) {
+            domains.retain(|_, window| window.started.elapsed() < WINDOW);
+            if domains.len() >= MAX_DOMAINS {
+                // No room to keep count, so this query counts on its own.
+                return f(&mut DomainWindow::new());
+            }
+        }
+
+        let window = domains
+            .entry(domain.to_string())
+            .or_insert_with(DomainWindow::new);
+        if window.started.elapsed() >= WINDOW {
+            *window = DomainWindow::new();
+        }
+
+        f(window)
+    }
+}
+
+/// Shannon entropy in bits per character.
+fn entropy(s: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+
+    let len = s.chars().count() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
@@ -0,0 +1,172 @@
+//! Sharing queries out among upstreams by weight. A server with weight 3 gets three
+//! queries for every one sent to a server with weight 1. A server that fails is left out
+//! until its retry time comes round, when the next query tries it first. If that works
+//! it's back in the rotation at its full share straight away; if not, it waits twice as
+//! long before the next try.
+
+use crate::log::{self, LogLevel};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a failed server is left out before it's tried again, at first.
+const FIRST_RETRY: Duration = Duration::from_secs(5);
+
+/// The longest it's ever left out between tries.
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddr,
+    weight: u32,
+    /// Smooth weighted round robin's running score: the server with the highest goes next.
+    current: i64,
+    /// Set while the server is out of the rotation.
+    down: Option<Down>,
+}
+
+#[derive(Debug)]
+struct Down {
+    retry_in: Duration,
+    retry_at: Instant,
+}
+
+/// One server's share and whether it's in the rotation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpstreamStatus {
+    pub addr: SocketAddr,
+    pub weight: u32,
+    pub healthy: bool,
+}
+
+#[derive(Debug)]
+pub struct WeightedUpstreams {
+    servers: Mutex<Vec<Upstream>>,
+}
+
+impl WeightedUpstreams {
+    /// Shares queries among `servers`, each given with its weight.
+    pub fn new(servers: &[(SocketAddr, u32)]) -> WeightedUpstreams {
+        WeightedUpstreams {
+            servers: Mutex::new(
+                servers
+                    .iter()
+                    .map(|&(addr, weight)| Upstream {
+                        addr,
+                        weight: weight.max(1),
+                        current: 0,
+                        down: None,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The servers to try for the next query, in order. A failed server whose retry time
+    /// has come goes first; then the healthy server whose turn it is, then the other
+    /// healthy ones, heaviest first; and last the failed ones, in case nothing else works.
+    pub fn order(&self) -> Vec<SocketAddr> {
+        let mut servers = self.servers.lock().unwrap();
+        let now = Instant::now();
+        let mut order = Vec::with_capacity(servers.len());
+
+        // Only one query at a time tries a server that's due another go.
+        if let Some(server) = servers.iter_mut().find(|server| {
+            server
+                .down
+                .as_ref()
+                .is_some_and(|down| down.retry_at <= now)
+        }) {
+            let down = server.down.as_mut().unwrap();
+            down.retry_at = now + down.retry_in;
+            order.push(server.addr);
+        }
+
+        let total: i64 = servers
+            .iter()
+            .filter(|server| server.down.is_none())
+            .map(|server| server.weight as i64)
+            .sum();
+        let mut healthy: Vec<&mut Upstream> = servers
+            .iter_mut()
+            .filter(|server| server.down.is_none())
+            .collect();
+        for server in healthy.iter_mut() {
+            server.current += server.weight as i64;
+        }
+        if let Some(next) = healthy.iter_mut().max_by(|a, b| a.current.cmp(&b.current)) {
+            next.current -= total;
+            order.push(next.addr);
+        }
+
+        let mut rest: Vec<&Upstream> = servers
+            .iter()
+            .filter(|server| !order.contains(&server.addr))
+            .collect();
+        rest.sort_by_key(|server| (server.down.is_some(), std::cmp::Reverse(server.weight)));
+        order.extend(rest.iter().map(|server| server.addr));
+
+        order
+    }
+
+    /// Notes that `addr` answered, putting it back in the rotation if it had failed.
+    pub fn succeeded(&self, addr: SocketAddr) {
+        let mut servers = self.servers.lock().unwrap();
+        let recovered = match servers.iter_mut().find(|server| server.addr == addr) {
+            Some(server) => server.down.take().is_some(),
+            None => false,
+        };
+
+        if recovered {
+            // Everyone starts level so the shares come out right from the next query on.
+            for server in servers.iter_mut() {
+                server.current = 0;
+            }
+            log::write(
+                LogLevel::Info,
+                &format!("Upstream {} is answering again", addr),
+            );
+        }
+    }
+
+    /// Notes that `addr` failed, taking it out of the rotation until its retry time.
+    pub fn failed(&self, addr: SocketAddr) {
+        let mut servers = self.servers.lock().unwrap();
+        let server = match servers.iter_mut().find(|server| server.addr == addr) {
+            Some(server) => server,
+            None => return,
+        };
+
+        let retry_in = match server.down {
+            Some(ref down) => (down.retry_in * 2).min(MAX_RETRY),
+            None => FIRST_RETRY,
+        };
+        server.down = Some(Down {
+            retry_in,
+            retry_at: Instant::now() + retry_in,
+        });
+        server.current = 0;
+
+        log::write(
+            LogLevel::Info,
+            &format!(
+                "Upstream {} failed, trying again in {}s",
+                addr,
+                retry_in.as_secs()
+            ),
+        );
+    }
+
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| UpstreamStatus {
+                addr: server.addr,
+                weight: server.weight,
+                healthy: server.down.is_none(),
+            })
+            .collect()
+    }
+}
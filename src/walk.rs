@@ -0,0 +1,220 @@
+//! Finding out how much of a zone anyone can list. A name server that allows zone
+//! transfers hands the whole zone to whoever asks; one that doesn't may still give it away
+//! a name at a time if the zone is signed with NSEC, since each NSEC record names the
+//! next name in the zone. NSEC3 hashes the names, so zones signed with it can't be walked
+//! this way.
+
+use crate::propagation::find_zone_servers;
+use crate::resolver::Resolver;
+use crate::MAX_EDNS_PACKET_SIZE;
+use crate::{DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode, CLASS_IN};
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The zone transfer query type.
+const QTYPE_AXFR: u16 = 252;
+
+/// A walk stops after this many names, in case the chain never comes back to the apex.
+const MAX_WALK: usize = 10_000;
+
+/// What a zone transfer gave away.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transfer {
+    /// The owner names of the records we could read.
+    pub names: BTreeSet<String>,
+    pub records: usize,
+    /// Messages too big for the codec to read, whose records aren't counted.
+    pub undecoded: usize,
+}
+
+/// Transfers `zone` from `server` over TCP. Fails if the server refuses or closes the
+/// connection without sending anything.
+pub fn transfer(zone: &str, server: SocketAddr) -> Result<Transfer> {
+    let mut packet = DnsPacket::default();
+    packet.header.id = rand::random();
+    packet.questions.push(DnsQuestion {
+        name: zone.to_string(),
+        qtype: QueryType::Unknown(QTYPE_AXFR),
+        qclass: CLASS_IN,
+    });
+    let request = packet.to_bytes()?;
+
+    let mut stream = TcpStream::connect_timeout(&server, TRANSFER_TIMEOUT)?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(&request)?;
+
+    let mut transfer = Transfer::default();
+    let mut soas = 0;
+    // The zone starts and ends with its SOA record.
+    while soas < 2 {
+        let message = match read_message(&mut stream) {
+            Ok(message) => message,
+            // Without being able to read every message we can't see the closing SOA, so
+            // the server hanging up or going quiet has to do.
+            Err(_) if transfer.records > 0 || transfer.undecoded > 0 => break,
+            Err(e) => return Err(e),
+        };
+
+        if message.len() > MAX_EDNS_PACKET_SIZE {
+            transfer.undecoded += 1;
+            continue;
+        }
+        let response = DnsPacket::from_bytes(&message)?;
+        if response.header.rescode != ResultCode::Success || response.answers.is_empty() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("transfer refused with {}", response.header.rescode),
+            ));
+        }
+
+        for rec in &response.answers {
+            if rec.query_type() == QueryType::SOA {
+                soas += 1;
+            }
+            transfer.names.insert(rec.domain().to_lowercase());
+            transfer.records += 1;
+        }
+    }
+
+    Ok(transfer)
+}
+
+fn read_message(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// The names found by following a zone's NSEC chain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NsecWalk {
+    /// In the order the chain gave them, starting at the apex.
+    pub names: Vec<String>,
+    /// Whether the chain led back to the apex, rather than breaking off or running past
+    /// the limit.
+    pub complete: bool,
+}
+
+/// Follows the NSEC chain of `zone` on `server`, asking for the NSEC record at each name
+/// for the next. Fails if there's no NSEC record at the apex, as when the zone isn't
+/// signed or uses NSEC3.
+pub fn nsec_walk(resolver: &Resolver, zone: &str, server: SocketAddr) -> Result<NsecWalk> {
+    let apex = zone.trim_end_matches('.').to_lowercase();
+    let mut walk = NsecWalk::default();
+    let mut name = apex.clone();
+
+    while walk.names.len() < MAX_WALK {
+        let next = match next_name(resolver, &name, server) {
+            Ok(Some(next)) => next,
+            // Keep what we have if the chain breaks off part way.
+            _ if !walk.names.is_empty() => break,
+            Ok(None) => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("no NSEC record at {}", apex),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        walk.names.push(name);
+
+        if next == apex {
+            walk.complete = true;
+            break;
+        }
+        // A chain that loops back on itself anywhere but the apex is broken.
+        if walk.names.contains(&next) {
+            break;
+        }
+        name = next;
+    }
+
+    Ok(walk)
+}
+
+/// The next name in the chain after `name`, from its NSEC record.
+fn next_name(resolver: &Resolver, name: &str, server: SocketAddr) -> Result<Option<String>> {
+    let response = resolver.lookup(name, QueryType::NSEC, server)?;
+
+    Ok(response.answers.iter().find_map(|rec| match rec {
+        DnsRecord::NSEC {
+            domain,
+            next_domain,
+            ..
+        } if domain.eq_ignore_ascii_case(name) => Some(next_domain.to_lowercase()),
+        _ => None,
+    }))
+}
+
+/// How a zone's names were listed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Listing {
+    /// A name server handed over the whole zone.
+    Transfer { host: String, addr: IpAddr },
+    /// Its NSEC chain was followed on a name server.
+    NsecWalk { host: String, addr: IpAddr },
+}
+
+#[derive(Clone, Debug)]
+pub struct Exposure {
+    pub zone: String,
+    /// Each of the zone's name servers that allowed a transfer.
+    pub transfers: Vec<(String, IpAddr)>,
+    /// How the names were found, if they could be.
+    pub listing: Option<Listing>,
+    pub names: BTreeSet<String>,
+    /// Whether `names` is the whole zone, as far as we can tell.
+    pub complete: bool,
+}
+
+/// Tries a zone transfer of the zone holding `domain` from each of its name servers, and
+/// failing that walks its NSEC chain, to see how much of it anyone can list. `via` is a
+/// recursive resolver used to find the name servers.
+pub fn exposure(resolver: &Resolver, via: IpAddr, domain: &str) -> Result<Exposure> {
+    let (zone, servers) = find_zone_servers(resolver, via, domain)?;
+    let mut exposure = Exposure {
+        zone: zone.clone(),
+        transfers: Vec::new(),
+        listing: None,
+        names: BTreeSet::new(),
+        complete: false,
+    };
+
+    for (host, addr) in &servers {
+        if let Ok(transfer) = self::transfer(&zone, SocketAddr::new(*addr, 53)) {
+            exposure.transfers.push((host.clone(), *addr));
+            if exposure.listing.is_none() {
+                exposure.listing = Some(Listing::Transfer {
+                    host: host.clone(),
+                    addr: *addr,
+                });
+                exposure.complete = transfer.undecoded == 0;
+                exposure.names = transfer.names;
+            }
+        }
+    }
+    if exposure.listing.is_some() {
+        return Ok(exposure);
+    }
+
+    for (host, addr) in &servers {
+        if let Ok(walk) = nsec_walk(resolver, &zone, SocketAddr::new(*addr, 53)) {
+            exposure.listing = Some(Listing::NsecWalk {
+                host: host.clone(),
+                addr: *addr,
+            });
+            exposure.complete = walk.complete;
+            exposure.names = walk.names.into_iter().collect();
+            break;
+        }
+    }
+
+    Ok(exposure)
+}
@@ -0,0 +1,1155 @@
+//! Zones we're the authority for, read from zone files in the usual master file format.
+//! Any number can be served at once, and a query goes to the zone with the longest origin
+//! it falls under, so a `sub.example.com` zone answers for the names beneath it even when
+//! `example.com` is served too. A zone whose files change is read again in the background
+//! and swapped in whole, once the new version has been checked.
+
+use crate::config::{expand_braced, LogLevel};
+use crate::{days_from_civil, log, rdata, ttl, DnsRecord, QueryType, Result, ResultCode};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Records without a TTL of their own and no `$TTL` in force get this.
+const DEFAULT_TTL: u32 = 3600;
+
+/// How many CNAMEs in the zone are followed before giving up on a chain.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// How deep `$INCLUDE`s can nest, which stops a file that includes itself.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// The most records one `$GENERATE` line can make.
+const MAX_GENERATED: u64 = 65_536;
+
+/// How often zone files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Zone {
+    /// The name at the top of the zone, without a trailing dot.
+    pub origin: String,
+    records: HashMap<String, Vec<DnsRecord>>,
+    /// Every name with records, and the names above them in the zone that have none.
+    names: HashSet<String>,
+    /// The file the zone was loaded from and the variables it saw, to load it again from.
+    source: Option<(PathBuf, HashMap<String, String>)>,
+    /// Every file read for the zone, its own and those it includes.
+    files: Vec<PathBuf>,
+}
+
+/// What a zone has to say about a question.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Answer {
+    pub rescode: ResultCode,
+    /// False for a referral to a delegated child zone, which isn't ours to answer.
+    pub authoritative: bool,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>,
+}
+
+impl Zone {
+    /// Reads the zone file at `path` for the zone `origin`.
+    pub fn load(origin: &str, path: &Path) -> Result<Zone> {
+        Zone::load_with(origin, path, &HashMap::new())
+    }
+
+    /// Like [`Zone::load`], with the variables for [`Zone::parse_with`].
+    pub fn load_with(origin: &str, path: &Path, vars: &HashMap<String, String>) -> Result<Zone> {
+        let text = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut zone = Zone::read(origin, &text, vars, dir)
+            .map_err(|e| Error::new(e.kind(), format!("{}:{}", path.display(), e)))?;
+        zone.source = Some((path.to_path_buf(), vars.clone()));
+        zone.files.insert(0, path.to_path_buf());
+        Ok(zone)
+    }
+
+    /// Loads the zone again from its file, for a reload. The new version needs SOA and NS
+    /// records at its apex and, if this one has a serial, a later one (RFC 1982), so that a
+    /// file caught halfway through being written, or edited without the serial being
+    /// bumped, leaves this version in place.
+    pub fn reload(&self) -> Result<Zone> {
+        let (path, vars) = self
+            .source
+            .as_ref()
+            .ok_or_else(|| invalid(format!("zone {} has no file", self.origin)))?;
+        let zone = Zone::load_with(&self.origin, path, vars)?;
+
+        for qtype in [QueryType::SOA, QueryType::NS] {
+            if zone.lookup(&zone.origin, qtype).is_empty() {
+                return Err(invalid(format!("the apex has no {} record", qtype)));
+            }
+        }
+        match (self.serial(), zone.serial()) {
+            (Some(old), Some(new)) if (new.wrapping_sub(old) as i32) <= 0 => Err(invalid(format!(
+                "the serial {} isn't later than {}",
+                new, old
+            ))),
+            _ => Ok(zone),
+        }
+    }
+
+    /// The serial of the zone's SOA record.
+    pub fn serial(&self) -> Option<u32> {
+        self.lookup(&self.origin, QueryType::SOA)
+            .iter()
+            .find_map(|rec| match *rec {
+                DnsRecord::SOA { serial, .. } => Some(serial),
+                _ => None,
+            })
+    }
+
+    /// When the zone's files were last changed, going by the latest of them.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.files
+            .iter()
+            .filter_map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+            .max()
+    }
+
+    /// Parses a zone file for `origin`. Errors start with the line they're on.
+    pub fn parse(origin: &str, text: &str) -> Result<Zone> {
+        Zone::parse_with(origin, text, &HashMap::new())
+    }
+
+    /// Like [`Zone::parse`], replacing each `${NAME}` with the variable of that name in
+    /// `vars` or, failing that, the environment variable. `$INCLUDE`d files are found from
+    /// the current directory.
+    pub fn parse_with(origin: &str, text: &str, vars: &HashMap<String, String>) -> Result<Zone> {
+        Zone::read(origin, text, vars, Path::new(""))
+    }
+
+    /// Parses a zone file whose `$INCLUDE`s are relative to `dir`.
+    fn read(origin: &str, text: &str, vars: &HashMap<String, String>, dir: &Path) -> Result<Zone> {
+        let origin = normalize(origin);
+        let mut zone = Zone {
+            origin: origin.clone(),
+            records: HashMap::new(),
+            names: HashSet::new(),
+            source: None,
+            files: Vec::new(),
+        };
+
+        let mut parser = Parser {
+            origin,
+            default_ttl: None,
+            last_owner: None,
+            last_ttl: None,
+        };
+        zone.add_entries(&mut parser, text, vars, dir, 0)?;
+
+        Ok(zone)
+    }
+
+    /// Adds the records of a zone file, or of one it includes `depth` deep.
+    fn add_entries(
+        &mut self,
+        parser: &mut Parser,
+        text: &str,
+        vars: &HashMap<String, String>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<()> {
+        for mut entry in entries(text)? {
+            let line = entry.line;
+            let at_line = |e: Error| invalid(format!("{}: {}", line, e));
+            let directive = match entry.indented {
+                true => String::new(),
+                false => entry.tokens[0].to_ascii_uppercase(),
+            };
+
+            // $GENERATE's own ${...} modifiers would be taken for variables.
+            if directive != "$GENERATE" {
+                for token in &mut entry.tokens {
+                    *token = expand_braced(token, vars).map_err(at_line)?;
+                }
+            }
+
+            match directive.as_str() {
+                "$INCLUDE" => self
+                    .include(parser, &entry.tokens[1..], vars, dir, depth)
+                    .map_err(at_line)?,
+                "$GENERATE" => {
+                    for rec in parser.generate(&entry.tokens[1..]).map_err(at_line)? {
+                        self.insert(rec).map_err(at_line)?;
+                    }
+                }
+                _ => {
+                    if let Some(rec) = parser.record(entry).map_err(at_line)? {
+                        self.insert(rec).map_err(at_line)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the records of the file an `$INCLUDE` names in `args`, with the origin it gives
+    /// if it gives one. A relative path is taken from `dir`, the including file's directory.
+    /// The origin goes back to what it was once the file is done with.
+    fn include(
+        &mut self,
+        parser: &mut Parser,
+        args: &[String],
+        vars: &HashMap<String, String>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<()> {
+        let (file, origin) = match args {
+            [file] => (file, None),
+            [file, origin] => (file, Some(origin)),
+            _ => {
+                return Err(invalid(
+                    "expected a file to include and optionally its origin".to_string(),
+                ))
+            }
+        };
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(invalid(format!(
+                "$INCLUDE nested more than {} deep",
+                MAX_INCLUDE_DEPTH
+            )));
+        }
+
+        let path = dir.join(file);
+        let text =
+            fs::read_to_string(&path).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        self.files.push(path.clone());
+        let outer_origin = parser.origin.clone();
+        if let Some(origin) = origin {
+            parser.origin = parser.name(origin)?;
+        }
+        let inner_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let result = self
+            .add_entries(parser, &text, vars, inner_dir, depth + 1)
+            .map_err(|e| invalid(format!("{}:{}", path.display(), e)));
+        parser.origin = outer_origin;
+        result
+    }
+
+    fn insert(&mut self, rec: DnsRecord) -> Result<()> {
+        let name = rec.domain().to_string();
+        if !in_zone(&name, &self.origin) {
+            return Err(invalid(format!("{} is outside {}", name, self.origin)));
+        }
+
+        // A CNAME leaves no room for other records, bar the signature and NSEC record
+        // that go with it in a signed zone (RFC 4035).
+        let existing = self.records.entry(name.clone()).or_default();
+        let beside_cname = |qtype| matches!(qtype, QueryType::RRSIG | QueryType::NSEC);
+        let qtype = rec.query_type();
+        if qtype == QueryType::CNAME && name == self.origin {
+            return Err(invalid(format!(
+                "{} is the zone's apex, and can't have a CNAME",
+                name
+            )));
+        }
+        if qtype == QueryType::SOA && name != self.origin {
+            return Err(invalid(format!(
+                "{} has an SOA record, which only the apex can have",
+                name
+            )));
+        }
+        for other in existing.iter().map(DnsRecord::query_type) {
+            if qtype == QueryType::SOA && other == QueryType::SOA {
+                return Err(invalid(format!("{} has more than one SOA record", name)));
+            }
+            if qtype == QueryType::CNAME && other == QueryType::CNAME {
+                return Err(invalid(format!("{} has more than one CNAME", name)));
+            }
+            if (qtype == QueryType::CNAME && !beside_cname(other))
+                || (other == QueryType::CNAME && !beside_cname(qtype))
+            {
+                return Err(invalid(format!(
+                    "{} has a CNAME, and can't have other records",
+                    name
+                )));
+            }
+        }
+        existing.push(rec);
+
+        let mut name = name.as_str();
+        loop {
+            self.names.insert(name.to_string());
+            if name == self.origin {
+                break;
+            }
+            name = name.split_once('.').map_or("", |(_, parent)| parent);
+        }
+
+        Ok(())
+    }
+
+    /// Every record in the zone, by name.
+    pub fn records(&self) -> impl Iterator<Item = &DnsRecord> {
+        self.records.values().flatten()
+    }
+
+    /// The records of one type at a name.
+    pub fn lookup(&self, name: &str, qtype: QueryType) -> Vec<DnsRecord> {
+        self.records
+            .get(&normalize(name))
+            .map(|recs| {
+                recs.iter()
+                    .filter(|rec| rec.query_type() == qtype)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Answers a question about a name in the zone: with its records, a referral if it's
+    /// at or under a delegation, or NODATA or NXDOMAIN along with the zone's SOA.
+    pub fn answer(&self, name: &str, qtype: QueryType) -> Answer {
+        let name = normalize(name);
+
+        // The DS records at a delegation are the parent's, and answered here (RFC 4035).
+        let delegation = self
+            .delegation(&name)
+            .filter(|&cut| qtype != QueryType::DS || cut != name);
+        if let Some(cut) = delegation {
+            let authorities = self.lookup(cut, QueryType::NS);
+            return Answer {
+                rescode: ResultCode::Success,
+                authoritative: false,
+                additionals: self.glue(&authorities),
+                authorities,
+                ..Answer::default()
+            };
+        }
+
+        let mut answer = Answer {
+            rescode: ResultCode::Success,
+            authoritative: true,
+            ..Answer::default()
+        };
+
+        // A chain that comes back to a name it has been through stops there, with each
+        // CNAME in it once.
+        let mut visited = HashSet::new();
+        visited.insert(name.clone());
+        let mut current = name.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let found = self.lookup(&current, qtype);
+            if !found.is_empty() || qtype == QueryType::CNAME {
+                answer.answers.extend(found);
+                break;
+            }
+
+            // Follow an alias as far as it stays in the zone.
+            let target = match self.lookup(&current, QueryType::CNAME).pop() {
+                Some(cname) => {
+                    let target = cname.rdata().unwrap_or_default();
+                    answer.answers.push(cname);
+                    target
+                }
+                None => break,
+            };
+            let target = normalize(&target);
+            if !in_zone(&target, &self.origin)
+                || self.delegation(&target).is_some()
+                || !visited.insert(target.clone())
+            {
+                break;
+            }
+            current = target;
+        }
+
+        if answer.answers.is_empty() {
+            if !self.names.contains(&name) {
+                answer.rescode = ResultCode::NonexistantDomain;
+            }
+            answer.authorities = self.lookup(&self.origin, QueryType::SOA);
+        }
+        answer.additionals = self.glue(&answer.answers);
+
+        answer
+    }
+
+    /// What's wrong with the zone that doesn't stop it being answered from, each described
+    /// for a warning: no SOA or NS records at the apex, which other servers need to find
+    /// and check the zone, and name servers missing glue. The zone is loaded with these,
+    /// unless `strict_zones` is set.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for qtype in [QueryType::SOA, QueryType::NS] {
+            if self.lookup(&self.origin, qtype).is_empty() {
+                warnings.push(format!("the apex has no {} record", qtype));
+            }
+        }
+        warnings.extend(self.missing_glue());
+        warnings
+    }
+
+    /// The addresses the zone has for the name servers among `recs` that are named inside
+    /// it, for the additional section. A resolver can't look up a name server under the
+    /// zone it serves without them.
+    fn glue(&self, recs: &[DnsRecord]) -> Vec<DnsRecord> {
+        let mut glue = Vec::new();
+        for host in name_servers(recs) {
+            if !in_zone(&host, &self.origin) {
+                continue;
+            }
+            for addr in self
+                .lookup(&host, QueryType::A)
+                .into_iter()
+                .chain(self.lookup(&host, QueryType::AAAA))
+            {
+                if !glue.contains(&addr) {
+                    glue.push(addr);
+                }
+            }
+        }
+        glue
+    }
+
+    /// Name servers named inside the zone that it has no address for, each described for
+    /// a warning. Nothing could reach them: a name server at or under the zone it serves
+    /// can only be found through the addresses given with the delegation. One under some
+    /// other delegation is left out, as that delegation's servers can answer for it.
+    pub fn missing_glue(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        for (name, recs) in &self.records {
+            let cut = self.delegation(name);
+            for host in name_servers(recs) {
+                if !in_zone(&host, &self.origin) || self.delegation(&host) != cut {
+                    continue;
+                }
+                if self.lookup(&host, QueryType::A).is_empty()
+                    && self.lookup(&host, QueryType::AAAA).is_empty()
+                {
+                    missing.push(format!(
+                        "{}, a name server for {}, has no A or AAAA record",
+                        host, name
+                    ));
+                }
+            }
+        }
+        missing.sort();
+        missing
+    }
+
+    /// The highest name at or above `name`, below the origin, with NS records: where the
+    /// zone hands `name` over to another.
+    fn delegation<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let mut cut = None;
+        let mut current = name;
+        while current != self.origin && in_zone(current, &self.origin) {
+            if !self.lookup(current, QueryType::NS).is_empty() {
+                cut = Some(current);
+            }
+            current = current.split_once('.').map_or("", |(_, parent)| parent);
+        }
+        cut
+    }
+}
+
+/// The hosts of the NS records among `recs`.
+fn name_servers(recs: &[DnsRecord]) -> impl Iterator<Item = String> + '_ {
+    recs.iter().filter_map(|rec| match rec {
+        DnsRecord::NS { host, .. } => Some(normalize(host)),
+        _ => None,
+    })
+}
+
+/// The zones being served, each of which [`watch`] swaps for a new version when its files
+/// change. A query sees one version of a zone or the other, never a mix.
+#[derive(Debug, Default)]
+pub struct Zones {
+    zones: RwLock<Vec<Arc<Zone>>>,
+}
+
+impl Zones {
+    pub fn new(zones: Vec<Zone>) -> Zones {
+        Zones {
+            zones: RwLock::new(zones.into_iter().map(Arc::new).collect()),
+        }
+    }
+
+    /// The zone `name` falls under, as [`find`] picks it.
+    pub fn find(&self, name: &str) -> Option<Arc<Zone>> {
+        let name = normalize(name);
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|zone| in_zone(&name, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+            .cloned()
+    }
+
+    /// Every zone as it is now.
+    pub fn snapshot(&self) -> Vec<Arc<Zone>> {
+        self.zones.read().unwrap().clone()
+    }
+
+    /// Puts `zone` in place of the zone with its origin.
+    pub fn replace(&self, zone: Zone) {
+        let mut zones = self.zones.write().unwrap();
+        if let Some(current) = zones.iter_mut().find(|z| z.origin == zone.origin) {
+            *current = Arc::new(zone);
+        }
+    }
+}
+
+/// Reloads each of `zones` from a background thread when its files change, keeping the
+/// version being served if the new one doesn't load or doesn't pass [`Zone::reload`]'s
+/// checks. A file that fails is tried again when it next changes.
+pub fn watch(zones: Arc<Zones>) {
+    thread::spawn(move || {
+        let mut seen: HashMap<String, Option<SystemTime>> = zones
+            .snapshot()
+            .iter()
+            .map(|zone| (zone.origin.clone(), zone.modified()))
+            .collect();
+
+        loop {
+            thread::sleep(RELOAD_INTERVAL);
+            for zone in zones.snapshot() {
+                let modified = zone.modified();
+                if modified.is_none() || seen.get(&zone.origin) == Some(&modified) {
+                    continue;
+                }
+                seen.insert(zone.origin.clone(), modified);
+
+                match zone.reload() {
+                    Ok(new) => {
+                        log::write(
+                            LogLevel::Info,
+                            &format!(
+                                "Reloaded zone {} with {} records, serial {}",
+                                new.origin,
+                                new.records().count(),
+                                new.serial().unwrap_or_default()
+                            ),
+                        );
+                        zones.replace(new);
+                    }
+                    Err(e) => log::write(
+                        LogLevel::Error,
+                        &format!("Keeping zone {} as it was: {}", zone.origin, e),
+                    ),
+                }
+            }
+        }
+    });
+}
+
+/// The zone among `zones` that `name` falls under, preferring the longest origin.
+pub fn find<'a>(zones: &'a [Zone], name: &str) -> Option<&'a Zone> {
+    let name = normalize(name);
+    zones
+        .iter()
+        .filter(|zone| in_zone(&name, &zone.origin))
+        .max_by_key(|zone| zone.origin.len())
+}
+
+fn in_zone(name: &str, origin: &str) -> bool {
+    origin.is_empty() || name == origin || name.ends_with(&format!(".{}", origin))
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// `template` with each `$` replaced by `n`, or by `n` as `${offset,width,base}` has it:
+/// shifted by `offset`, padded with zeros to `width` and written in base `d`, `o`, `x` or
+/// `X`. `\$` is a plain `$`.
+fn generated(template: &str, n: u64) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        if rest[..dollar].ends_with('\\') {
+            out.push_str(&rest[..dollar - 1]);
+            out.push('$');
+            rest = &rest[dollar + 1..];
+            continue;
+        }
+        out.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        let mut modifiers = "";
+        if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| invalid(format!("unclosed ${{ in {:?}", template)))?;
+            modifiers = &braced[..end];
+            rest = &braced[end + 1..];
+        }
+
+        let bad = || invalid(format!("bad $GENERATE modifier {:?}", modifiers));
+        let mut fields = modifiers.split(',');
+        let offset = match fields.next() {
+            Some("") | None => 0,
+            Some(offset) => offset.parse::<i64>().map_err(|_| bad())?,
+        };
+        let width = match fields.next() {
+            Some(width) => width.parse::<usize>().map_err(|_| bad())?,
+            None => 0,
+        };
+        let value = n.checked_add_signed(offset).ok_or_else(bad)?;
+        match fields.next().unwrap_or("d") {
+            "d" => out.push_str(&format!("{:0width$}", value, width = width)),
+            "o" => out.push_str(&format!("{:0width$o}", value, width = width)),
+            "x" => out.push_str(&format!("{:0width$x}", value, width = width)),
+            "X" => out.push_str(&format!("{:0width$X}", value, width = width)),
+            _ => return Err(bad()),
+        }
+        if fields.next().is_some() {
+            return Err(bad());
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The bytes spelled out by `text`, two hex digits to a byte.
+fn hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+/// The numbers of the types named in an NSEC or NSEC3 record, in order.
+fn type_numbers(names: &[&str]) -> Option<Vec<u16>> {
+    let mut types = names
+        .iter()
+        .map(|name| Some(name.parse::<QueryType>().ok()?.to_num()))
+        .collect::<Option<Vec<_>>>()?;
+    types.sort_unstable();
+    types.dedup();
+    Some(types)
+}
+
+/// An NSEC3 or NSEC3PARAM record's salt, in hex or `-` for none.
+fn nsec3_salt(text: &str) -> Option<Vec<u8>> {
+    match text {
+        "-" => Some(Vec::new()),
+        text => hex(text).filter(|salt| salt.len() <= u8::MAX as usize),
+    }
+}
+
+/// The bytes spelled out by `text` in unpadded base32 with the extended hex alphabet
+/// (RFC 4648), in either case, as NSEC3 records give hashed names.
+fn base32hex(text: &str) -> Option<Vec<u8>> {
+    // Whole bytes, with nothing but zeros left over.
+    if matches!(text.len() % 8, 1 | 3 | 6) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'A'..=b'V' => c - b'A' + 10,
+            _ => return None,
+        };
+        bits = (bits << 5 | value as u32) & 0xFFFF;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    (bytes.len() <= u8::MAX as usize).then_some(bytes)
+}
+
+/// An RRSIG record's expiration or inception, given as `YYYYMMDDHHmmSS` in UTC or as
+/// seconds since 1970.
+fn signature_time(text: &str) -> Option<u32> {
+    if text.len() != 14 {
+        return text.parse().ok();
+    }
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let time = days * 86400 + hour * 3600 + minute * 60 + second;
+    (0..=u32::MAX as i64).contains(&time).then_some(time as u32)
+}
+
+/// The bytes spelled out by `text` in padded base64 (RFC 4648).
+fn base64(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let data = text.trim_end_matches('=');
+    if text.len() - data.len() > 2 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in data.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6 | value as u32) & 0xFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// One record or directive, which parentheses can spread over several lines.
+#[derive(Debug)]
+struct Entry {
+    /// The line it starts on, counting from 1.
+    line: usize,
+    /// Whether it starts with blank space, meaning it's for the previous owner.
+    indented: bool,
+    /// Its words. Quoted strings keep their spaces and have their escapes resolved.
+    tokens: Vec<String>,
+}
+
+fn entries(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut depth = 0;
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    let mut at_line_start = true;
+
+    while let Some(c) = chars.next() {
+        if at_line_start && entry.is_none() {
+            entry = Some(Entry {
+                line,
+                indented: c == ' ' || c == '\t',
+                tokens: Vec::new(),
+            });
+        }
+        at_line_start = false;
+        let current = entry.as_mut().expect("an entry is always open here");
+
+        match c {
+            '\n' => {
+                line += 1;
+                at_line_start = true;
+                if depth == 0 {
+                    entries.extend(entry.take().filter(|e| !e.tokens.is_empty()));
+                }
+            }
+            ';' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(invalid(format!("{}: unbalanced )", line))),
+            ')' => depth -= 1,
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut bytes = Vec::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => bytes.extend(escape(&mut chars)),
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        None => return Err(invalid(format!("{}: unclosed quote", line))),
+                    }
+                }
+                current
+                    .tokens
+                    .push(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            c => {
+                let mut text = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "();\"".contains(next) {
+                        break;
+                    }
+                    text.push(next);
+                    chars.next();
+                }
+                current.tokens.push(text);
+            }
+        }
+    }
+
+    if depth != 0 {
+        let start = entry.as_ref().map_or(line, |entry| entry.line);
+        return Err(invalid(format!("{}: unclosed (", start)));
+    }
+    entries.extend(entry.filter(|e| !e.tokens.is_empty()));
+    Ok(entries)
+}
+
+/// The byte for a backslash escape in a quoted string: `\DDD` in decimal, or the next
+/// character as it is.
+fn escape(chars: &mut std::iter::Peekable<std::str::Chars>) -> Vec<u8> {
+    let digits: String = chars.clone().take(3).collect();
+    if digits.len() == 3 && digits.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(byte) = digits.parse::<u8>() {
+            chars.nth(2);
+            return vec![byte];
+        }
+    }
+
+    match chars.next() {
+        Some(c) => {
+            let mut buf = [0; 4];
+            c.encode_utf8(&mut buf).as_bytes().to_vec()
+        }
+        None => Vec::new(),
+    }
+}
+
+/// What carries over from one entry to the next.
+struct Parser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_owner: Option<String>,
+    last_ttl: Option<u32>,
+}
+
+impl Parser {
+    /// The record for an entry, or `None` for a directive.
+    fn record(&mut self, entry: Entry) -> Result<Option<DnsRecord>> {
+        let mut tokens = entry.tokens.into_iter().peekable();
+
+        let owner = if entry.indented {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| invalid("no owner name to carry over".to_string()))?
+        } else {
+            let first = tokens.next().expect("entries have at least one token");
+            match first.to_ascii_uppercase().as_str() {
+                "$ORIGIN" => {
+                    let origin = tokens.next().unwrap_or_default();
+                    self.origin = self.name(&origin)?;
+                    return Ok(None);
+                }
+                "$TTL" => {
+                    let value = tokens.next().unwrap_or_default();
+                    self.default_ttl = Some(ttl::parse(&value)?);
+                    return Ok(None);
+                }
+                directive if directive.starts_with('$') => {
+                    return Err(invalid(format!("unsupported directive {}", first)))
+                }
+                _ => self.name(&first)?,
+            }
+        };
+        self.last_owner = Some(owner.clone());
+
+        // A TTL and the class may come in either order before the type.
+        let mut record_ttl = None;
+        let rtype = loop {
+            let token = tokens
+                .next()
+                .ok_or_else(|| invalid("expected a record type".to_string()))?;
+            let upper = token.to_ascii_uppercase();
+            if upper == "IN" {
+                continue;
+            }
+            if token.starts_with(|c: char| c.is_ascii_digit()) {
+                record_ttl = Some(ttl::parse(&token)?);
+                continue;
+            }
+            break upper;
+        };
+        let ttl = record_ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .unwrap_or(DEFAULT_TTL);
+        self.last_ttl = Some(ttl);
+
+        let rdata: Vec<String> = tokens.collect();
+        let words: Vec<&str> = rdata.iter().map(String::as_str).collect();
+        let domain = owner;
+        let bad = || invalid(format!("bad {} record data {:?}", rtype, words.join(" ")));
+
+        let rec = match (rtype.as_str(), words.as_slice()) {
+            ("A", [addr]) => DnsRecord::A {
+                domain,
+                addr: addr.parse().map_err(|_| bad())?,
+                ttl,
+            },
+            ("AAAA", [addr]) => DnsRecord::AAAA {
+                domain,
+                addr: addr.parse().map_err(|_| bad())?,
+                ttl,
+            },
+            ("NS", [host]) => DnsRecord::NS {
+                domain,
+                host: self.name(host)?,
+                ttl,
+            },
+            ("CNAME", [host]) => DnsRecord::CNAME {
+                domain,
+                host: self.name(host)?,
+                ttl,
+            },
+            ("PTR", [host]) => DnsRecord::PTR {
+                domain,
+                host: self.name(host)?,
+                ttl,
+            },
+            ("MX", [priority, host]) => DnsRecord::MX {
+                domain,
+                priority: priority.parse().map_err(|_| bad())?,
+                host: self.name(host)?,
+                ttl,
+            },
+            ("SRV", [priority, weight, port, host]) => DnsRecord::SRV {
+                domain,
+                priority: priority.parse().map_err(|_| bad())?,
+                weight: weight.parse().map_err(|_| bad())?,
+                port: port.parse().map_err(|_| bad())?,
+                host: self.name(host)?,
+                ttl,
+            },
+            ("NAPTR", [order, preference, flags, services, regexp, replacement]) => {
+                DnsRecord::NAPTR {
+                    domain,
+                    order: order.parse().map_err(|_| bad())?,
+                    preference: preference.parse().map_err(|_| bad())?,
+                    flags: flags.to_string(),
+                    services: services.to_string(),
+                    regexp: regexp.to_string(),
+                    replacement: self.name(replacement)?,
+                    ttl,
+                }
+            }
+            ("NSEC3", [hash_algorithm, flags, iterations, salt, next, types @ ..]) => {
+                DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm: hash_algorithm.parse().map_err(|_| bad())?,
+                    flags: flags.parse().map_err(|_| bad())?,
+                    iterations: iterations.parse().map_err(|_| bad())?,
+                    salt: nsec3_salt(salt).ok_or_else(bad)?,
+                    next_hashed_owner: base32hex(next).ok_or_else(bad)?,
+                    types: type_numbers(types).ok_or_else(bad)?,
+                    ttl,
+                }
+            }
+            ("NSEC3PARAM", [hash_algorithm, flags, iterations, salt]) => DnsRecord::NSEC3PARAM {
+                domain,
+                hash_algorithm: hash_algorithm.parse().map_err(|_| bad())?,
+                flags: flags.parse().map_err(|_| bad())?,
+                iterations: iterations.parse().map_err(|_| bad())?,
+                salt: nsec3_salt(salt).ok_or_else(bad)?,
+                ttl,
+            },
+            ("NSEC", [next_domain, types @ ..]) => DnsRecord::NSEC {
+                domain,
+                next_domain: self.name(next_domain)?,
+                types: type_numbers(types).ok_or_else(bad)?,
+                ttl,
+            },
+            // Signatures and keys are base64, which may be split up with spaces.
+            (
+                "RRSIG",
+                [type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature @ ..],
+            ) if !signature.is_empty() => DnsRecord::RRSIG {
+                domain,
+                type_covered: type_covered
+                    .parse::<QueryType>()
+                    .map_err(|_| bad())?
+                    .to_num(),
+                algorithm: algorithm.parse().map_err(|_| bad())?,
+                labels: labels.parse().map_err(|_| bad())?,
+                original_ttl: original_ttl.parse().map_err(|_| bad())?,
+                expiration: signature_time(expiration).ok_or_else(bad)?,
+                inception: signature_time(inception).ok_or_else(bad)?,
+                key_tag: key_tag.parse().map_err(|_| bad())?,
+                signer: self.name(signer)?,
+                signature: base64(&signature.concat()).ok_or_else(bad)?,
+                ttl,
+            },
+            ("DNSKEY", [flags, protocol, algorithm, key @ ..]) if !key.is_empty() => {
+                DnsRecord::DNSKEY {
+                    domain,
+                    flags: flags.parse().map_err(|_| bad())?,
+                    protocol: protocol.parse().map_err(|_| bad())?,
+                    algorithm: algorithm.parse().map_err(|_| bad())?,
+                    public_key: base64(&key.concat()).ok_or_else(bad)?,
+                    ttl,
+                }
+            }
+            // Digests, fingerprints and certificate data are hex, which may be split up with
+            // spaces.
+            ("DS", [key_tag, algorithm, digest_type, digest @ ..]) if !digest.is_empty() => {
+                DnsRecord::DS {
+                    domain,
+                    key_tag: key_tag.parse().map_err(|_| bad())?,
+                    algorithm: algorithm.parse().map_err(|_| bad())?,
+                    digest_type: digest_type.parse().map_err(|_| bad())?,
+                    digest: hex(&digest.concat()).ok_or_else(bad)?,
+                    ttl,
+                }
+            }
+            ("SSHFP", [algorithm, fingerprint_type, fingerprint @ ..])
+                if !fingerprint.is_empty() =>
+            {
+                DnsRecord::SSHFP {
+                    domain,
+                    algorithm: algorithm.parse().map_err(|_| bad())?,
+                    fingerprint_type: fingerprint_type.parse().map_err(|_| bad())?,
+                    fingerprint: hex(&fingerprint.concat()).ok_or_else(bad)?,
+                    ttl,
+                }
+            }
+            ("TLSA", [usage, selector, matching_type, data @ ..]) if !data.is_empty() => {
+                DnsRecord::TLSA {
+                    domain,
+                    usage: usage.parse().map_err(|_| bad())?,
+                    selector: selector.parse().map_err(|_| bad())?,
+                    matching_type: matching_type.parse().map_err(|_| bad())?,
+                    data: hex(&data.concat()).ok_or_else(bad)?,
+                    ttl,
+                }
+            }
+            ("CAA", [flags, tag, value]) if !tag.is_empty() => DnsRecord::CAA {
+                domain,
+                flags: flags.parse().map_err(|_| bad())?,
+                tag: tag.to_string(),
+                value: value.to_string(),
+                ttl,
+            },
+            ("TXT", [_, ..]) => DnsRecord::TXT {
+                domain,
+                data: rdata.clone(),
+                ttl,
+            },
+            ("SOA", [mname, rname, serial, refresh, retry, expire, minimum]) => DnsRecord::SOA {
+                domain,
+                mname: self.name(mname)?,
+                rname: self.name(rname)?,
+                serial: serial.parse().map_err(|_| bad())?,
+                refresh: ttl::parse(refresh)?,
+                retry: ttl::parse(retry)?,
+                expire: ttl::parse(expire)?,
+                minimum: ttl::parse(minimum)?,
+                ttl,
+            },
+            (
+                "A" | "AAAA" | "NS" | "CNAME" | "PTR" | "MX" | "SRV" | "NAPTR" | "DS" | "SSHFP"
+                | "RRSIG" | "NSEC" | "DNSKEY" | "NSEC3" | "NSEC3PARAM" | "TLSA" | "CAA" | "TXT"
+                | "SOA",
+                _,
+            ) => return Err(bad()),
+            _ => match rdata::by_mnemonic(&rtype) {
+                Some((qtype, codec)) => {
+                    let data = codec
+                        .parse(&words.join(" "))
+                        .map_err(|e| invalid(format!("bad {} record data: {}", rtype, e)))?;
+                    DnsRecord::Unknown {
+                        domain,
+                        qtype,
+                        data_len: data.len() as u16,
+                        data,
+                        ttl,
+                    }
+                }
+                None => return Err(invalid(format!("unsupported record type {}", rtype))),
+            },
+        };
+
+        Ok(Some(rec))
+    }
+
+    /// A name from the file made absolute: `@` is the origin, names ending in a dot are
+    /// already absolute and the rest are relative to the origin.
+    /// The records of a `$GENERATE` line, BIND's shorthand for a run of records numbered
+    /// through a range: `$GENERATE 1-100 host-$ A 192.0.2.$` is 100 A records. `args` are
+    /// the range, `start-stop` or `start-stop/step`, then the owner and the data with a `$`
+    /// for each number, and any TTL and class between them and the type.
+    fn generate(&mut self, args: &[String]) -> Result<Vec<DnsRecord>> {
+        let (range, owner, middle, data) = match args {
+            [range, owner, middle @ .., data] if !middle.is_empty() => (range, owner, middle, data),
+            _ => {
+                return Err(invalid(
+                    "expected a range, an owner, a type and the record data".to_string(),
+                ))
+            }
+        };
+        let bad_range = || invalid(format!("bad $GENERATE range {:?}", range));
+        let (span, step) = match range.split_once('/') {
+            Some((span, step)) => (span, step.parse::<u64>().map_err(|_| bad_range())?),
+            None => (range.as_str(), 1),
+        };
+        let (start, stop) = span.split_once('-').ok_or_else(bad_range)?;
+        let start: u64 = start.parse().map_err(|_| bad_range())?;
+        let stop: u64 = stop.parse().map_err(|_| bad_range())?;
+        if step == 0 || start > stop || stop > u64::from(u32::MAX) {
+            return Err(bad_range());
+        }
+        if (stop - start) / step >= MAX_GENERATED {
+            return Err(invalid(format!(
+                "$GENERATE makes more than {} records",
+                MAX_GENERATED
+            )));
+        }
+
+        let mut records = Vec::new();
+        for n in (start..=stop).step_by(step as usize) {
+            let mut tokens = vec![generated(owner, n)?];
+            tokens.extend(middle.iter().cloned());
+            tokens.push(generated(data, n)?);
+            let entry = Entry {
+                line: 0,
+                indented: false,
+                tokens,
+            };
+            records.extend(self.record(entry)?);
+        }
+        Ok(records)
+    }
+
+    fn name(&self, name: &str) -> Result<String> {
+        let absolute = match name {
+            "@" => self.origin.clone(),
+            name if name.ends_with('.') => normalize(name),
+            name if self.origin.is_empty() => normalize(name),
+            name => format!("{}.{}", normalize(name), self.origin),
+        };
+        if absolute.split('.').any(|label| label.len() > 63) || absolute.len() > 253 {
+            return Err(invalid(format!("bad name {:?}", name)));
+        }
+        Ok(absolute)
+    }
+}
@@ -0,0 +1,118 @@
+//! Per-client traffic figures and flagging.
+
+#![cfg(feature = "server")]
+
+use diydns::analytics::{Analytics, Flag, Thresholds};
+use diydns::control::{self, Context};
+use diydns::ResultCode;
+use std::net::IpAddr;
+
+fn addr(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn flags_suspicious_clients() {
+    let analytics = Analytics::new(Thresholds {
+        qps: 1000,
+        nxdomain_percent: 50,
+        unique_subdomains: 20,
+    });
+
+    // A well-behaved client.
+    for _ in 0..30 {
+        analytics.record(addr("10.0.0.1"), "www.example.com", ResultCode::Success);
+    }
+    // Looking up generated domains, most of which don't exist.
+    for i in 0..40 {
+        let rescode = match i % 4 {
+            0 => ResultCode::Success,
+            _ => ResultCode::NonexistantDomain,
+        };
+        analytics.record(addr("10.0.0.2"), &format!("x{}q.com", i), rescode);
+    }
+    // Smuggling data out in the names under one domain.
+    for i in 0..25 {
+        analytics.record(
+            addr("10.0.0.3"),
+            &format!("{:08x}.t.evil.example.", i * 7919),
+            ResultCode::Success,
+        );
+    }
+
+    let flagged = analytics.rotate();
+    assert_eq!(
+        flagged
+            .iter()
+            .map(|report| (report.client, report.flags.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (addr("10.0.0.2"), vec![Flag::NxDomain]),
+            (addr("10.0.0.3"), vec![Flag::Subdomains]),
+        ]
+    );
+    assert_eq!(
+        flagged[1].busiest_domain,
+        Some(("t.evil.example".to_string(), 21))
+    );
+
+    let top = analytics.top(1);
+    assert_eq!((top[0].client, top[0].queries), (addr("10.0.0.2"), 40));
+    assert_eq!(analytics.flagged(), flagged);
+
+    // The next window starts from nothing.
+    assert!(analytics.rotate().is_empty());
+    assert!(analytics.top(10).is_empty());
+}
+
+#[test]
+fn flags_busy_clients() {
+    let analytics = Analytics::new(Thresholds {
+        qps: 5,
+        ..Thresholds::default()
+    });
+    for _ in 0..10 {
+        analytics.record(addr("10.0.0.1"), "example.com", ResultCode::Success);
+    }
+
+    // The window lasted well under a second, which counts as one.
+    let flagged = analytics.rotate();
+    assert_eq!(flagged[0].flags, vec![Flag::Qps]);
+    assert_eq!(flagged[0].qps, 10.0);
+}
+
+#[test]
+fn reported_over_metrics_and_control() {
+    let analytics = Analytics::new(Thresholds {
+        qps: 5,
+        ..Thresholds::default()
+    });
+    for _ in 0..10 {
+        analytics.record(addr("10.0.0.1"), "example.com", ResultCode::Success);
+    }
+    analytics.record(addr("10.0.0.2"), "example.com", ResultCode::Success);
+    analytics.rotate();
+
+    let metrics = analytics.metrics();
+    assert!(metrics.contains("diydns_client_queries{client=\"10.0.0.1\"} 10\n"));
+    assert!(metrics.contains("diydns_client_queries{client=\"10.0.0.2\"} 1\n"));
+    assert!(metrics.contains("diydns_client_flagged{client=\"10.0.0.1\",reason=\"qps\"} 1\n"));
+    assert!(!metrics.contains("diydns_client_flagged{client=\"10.0.0.2\""));
+
+    let context = Context {
+        analytics: Some(&analytics),
+        ..Context::default()
+    };
+    assert_eq!(
+        control::execute(context, "clients flagged"),
+        "10.0.0.1 queries=10 qps=10.0 nxdomain=0.00 flagged=qps\n"
+    );
+    assert_eq!(
+        control::execute(context, "clients top 5").lines().count(),
+        2
+    );
+    assert_eq!(
+        control::execute(Context::default(), "clients top"),
+        "error: analytics are off\n"
+    );
+}
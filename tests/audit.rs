@@ -0,0 +1,21 @@
+//! Forged responses against the resolver, each of which it has to drop and count.
+
+#![cfg(feature = "resolver")]
+
+use diydns::audit::{self, Attack};
+
+#[test]
+fn every_forgery_is_dropped_and_counted() {
+    let results = audit::run_audit();
+
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.attack)
+            .collect::<Vec<_>>(),
+        Attack::ALL
+    );
+    for result in &results {
+        assert!(result.passed(), "{:?}", result);
+    }
+}
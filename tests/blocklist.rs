@@ -0,0 +1,126 @@
+//! Blocking names from lists, and putting each block down to its list and rule.
+
+#![cfg(feature = "server")]
+
+use diydns::blocklist::{Blocklists, ListFile, Match};
+use diydns::config::Config;
+use diydns::history::{self, Filter, History, Query};
+use diydns::{QueryType, ResultCode};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn list_file(label: &str, contents: &str) -> ListFile {
+    let path = env::temp_dir().join(format!("diydns-blocklist-{}-{}", label, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    ListFile {
+        label: label.to_string(),
+        path,
+    }
+}
+
+#[test]
+fn matches_names_and_counts_hits() {
+    let ads = list_file(
+        "ads",
+        "# Ads\ndoubleclick.net\n\n0.0.0.0 ads.example.com tracker.example.com # hosts style\n127.0.0.1 localhost\nads.example.com\n",
+    );
+    let malware = list_file("malware", "example.com\nbad.example.org.\nnot a rule\n");
+    let blocklists = Blocklists::load(&[ads.clone(), malware.clone()]).unwrap();
+    assert_eq!(blocklists.len(), 5);
+
+    let cases = [
+        ("doubleclick.net", Some(("ads", "doubleclick.net", 2))),
+        (
+            "Stats.G.DoubleClick.net.",
+            Some(("ads", "doubleclick.net", 2)),
+        ),
+        ("x.ads.example.com", Some(("ads", "ads.example.com", 4))),
+        (
+            "tracker.example.com",
+            Some(("ads", "tracker.example.com", 4)),
+        ),
+        ("www.example.com", Some(("malware", "example.com", 1))),
+        ("bad.example.org", Some(("malware", "bad.example.org", 2))),
+        ("example.org", None),
+        ("notdoubleclick.net", None),
+        ("localhost", None),
+    ];
+    for (name, expected) in cases.iter() {
+        let expected = expected.map(|(list, rule, line)| Match {
+            list: list.to_string(),
+            rule: rule.to_string(),
+            line,
+        });
+        assert_eq!(blocklists.check(name), expected, "{}", name);
+    }
+
+    assert_eq!(
+        blocklists.hits(),
+        [("ads".to_string(), 4), ("malware".to_string(), 2)]
+    );
+    let metrics = blocklists.metrics();
+    assert!(metrics.contains("diydns_blocklist_hits_total{list=\"ads\",action=\"nxdomain\"} 4\n"));
+    assert!(metrics.contains("diydns_blocklist_rules{list=\"malware\"} 2\n"));
+
+    let _ = fs::remove_file(&ads.path);
+    let _ = fs::remove_file(&malware.path);
+}
+
+#[test]
+fn configured_by_label() {
+    let mut config = Config::default();
+    config.set("blocklist", "ads /etc/diydns/ads.txt").unwrap();
+    config
+        .set("blocklist", "malware /etc/diydns/malware.txt")
+        .unwrap();
+    assert_eq!(config.blocklists.len(), 2);
+    assert_eq!(
+        config.blocklists[1].path,
+        PathBuf::from("/etc/diydns/malware.txt")
+    );
+
+    assert!(config.set("blocklist", "ads /tmp/other.txt").is_err());
+    assert!(config.set("blocklist", "/etc/diydns/ads.txt").is_err());
+
+    config.set("blocklist", "").unwrap();
+    assert!(config.blocklists.is_empty());
+
+    let missing = ListFile {
+        label: "gone".to_string(),
+        path: PathBuf::from("/nonexistent/diydns-blocklist"),
+    };
+    let err = Blocklists::load(&[missing]).unwrap_err();
+    assert!(err.to_string().starts_with("blocklist gone: "), "{}", err);
+}
+
+#[test]
+fn attribution_in_history() {
+    let path = env::temp_dir().join(format!("diydns-blocked-{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let blocked = Query {
+        time: history::unix_now(),
+        client: "10.0.0.5".parse().unwrap(),
+        name: "ads.example.com".to_string(),
+        qtype: QueryType::A,
+        rescode: ResultCode::NonexistantDomain,
+        latency: Duration::from_micros(50),
+        blocked: Some(Match {
+            list: "ads".to_string(),
+            rule: "example.com".to_string(),
+            line: 12,
+        }),
+    };
+    let history = History::open(&path, 3600).unwrap();
+    history.record(&blocked).unwrap();
+
+    let found = history::search(&path, &Filter::default()).unwrap();
+    assert_eq!(found, [blocked]);
+    assert!(found[0]
+        .to_string()
+        .ends_with(" NXDOMAIN 0.1ms blocked by ads rule example.com (line 12)"));
+
+    let _ = fs::remove_file(&path);
+}
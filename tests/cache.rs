@@ -0,0 +1,140 @@
+//! The response cache, including from several threads at once.
+
+#![cfg(feature = "server")]
+
+use diydns::cache::Cache;
+use diydns::clock::ManualClock;
+use diydns::{DnsPacket, DnsRecord, QueryType, ResultCode};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn response(name: &str, ttl: u32) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.answers = 1;
+    packet.answers.push(DnsRecord::A {
+        domain: name.to_string(),
+        addr: "192.0.2.1".parse().unwrap(),
+        ttl,
+    });
+    packet
+}
+
+#[test]
+fn keeps_answers_for_their_ttl() {
+    let cache = Cache::new(0, 0);
+    cache.insert("Example.com", QueryType::A, &response("example.com", 300));
+
+    let cached = cache.get("example.com", QueryType::A).unwrap();
+    assert_eq!(cached.answers[0].ttl(), 300);
+    assert!(cache.get("example.com", QueryType::AAAA).is_none());
+
+    // Nothing to say how long to keep it.
+    cache.insert("zero.example", QueryType::A, &response("zero.example", 0));
+    assert!(cache.get("zero.example", QueryType::A).is_none());
+
+    let mut failure = response("fail.example", 300);
+    failure.header.rescode = ResultCode::ServerFail;
+    cache.insert("fail.example", QueryType::A, &failure);
+    assert!(cache.get("fail.example", QueryType::A).is_none());
+}
+
+#[test]
+fn min_ttl_raises_short_ttls() {
+    let cache = Cache::new(60, 0);
+    cache.insert("short.example", QueryType::A, &response("short.example", 5));
+    assert_eq!(
+        cache.get("short.example", QueryType::A).unwrap().answers[0].ttl(),
+        60
+    );
+}
+
+#[test]
+fn keeps_servfail_briefly() {
+    let cache = Cache::new(60, 5);
+    let mut failure = DnsPacket::default();
+    failure.header.rescode = ResultCode::ServerFail;
+    cache.insert("fail.example", QueryType::A, &failure);
+
+    let cached = cache.get("FAIL.example", QueryType::A).unwrap();
+    assert_eq!(cached.header.rescode, ResultCode::ServerFail);
+
+    let mut refused = DnsPacket::default();
+    refused.header.rescode = ResultCode::Refused;
+    cache.insert("refused.example", QueryType::A, &refused);
+    assert!(cache.get("refused.example", QueryType::A).is_none());
+
+    let cache = Cache::new(0, 0);
+    cache.insert("fail.example", QueryType::A, &failure);
+    assert!(cache.get("fail.example", QueryType::A).is_none());
+}
+
+#[test]
+fn ttls_count_down_to_expiry() {
+    let clock = ManualClock::new();
+    let cache = Cache::with_clock(0, 5, Arc::new(clock.clone()));
+    cache.insert("example.com", QueryType::A, &response("example.com", 300));
+    let mut failure = DnsPacket::default();
+    failure.header.rescode = ResultCode::ServerFail;
+    cache.insert("fail.example", QueryType::A, &failure);
+
+    clock.advance(Duration::from_millis(4999));
+    assert!(cache.get("fail.example", QueryType::A).is_some());
+    clock.advance(Duration::from_millis(1));
+    assert!(cache.get("fail.example", QueryType::A).is_none());
+
+    clock.advance(Duration::from_secs(294));
+    let cached = cache.get("example.com", QueryType::A).unwrap();
+    assert_eq!(cached.answers[0].ttl(), 1);
+    assert_eq!(cache.dump("").len(), 1);
+
+    clock.advance(Duration::from_secs(1));
+    assert!(cache.dump("").is_empty());
+    assert!(cache.get("example.com", QueryType::A).is_none());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn negative_answers_last_the_soa_minimum() {
+    let clock = ManualClock::new();
+    let cache = Cache::with_clock(0, 0, Arc::new(clock.clone()));
+    let mut nxdomain = DnsPacket::default();
+    nxdomain.header.rescode = ResultCode::NonexistantDomain;
+    nxdomain.authorities.push(DnsRecord::SOA {
+        domain: "example.com".to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 60,
+        ttl: 3600,
+    });
+    cache.insert("nope.example.com", QueryType::A, &nxdomain);
+
+    clock.advance(Duration::from_secs(59));
+    assert!(cache.get("nope.example.com", QueryType::A).is_some());
+    clock.advance(Duration::from_secs(1));
+    assert!(cache.get("nope.example.com", QueryType::A).is_none());
+}
+
+#[test]
+fn shared_between_threads() {
+    let cache = Cache::new(0, 0);
+
+    thread::scope(|scope| {
+        for t in 0..8 {
+            let cache = &cache;
+            scope.spawn(move || {
+                for i in 0..100 {
+                    let name = format!("host{}.thread{}.example", i, t);
+                    cache.insert(&name, QueryType::A, &response(&name, 300));
+                    assert!(cache.get(&name, QueryType::A).is_some());
+                }
+            });
+        }
+    });
+
+    assert_eq!(cache.len(), 800);
+}
@@ -0,0 +1,109 @@
+//! Encoding packets the same way whatever order their records came in.
+
+use diydns::{DnsPacket, DnsRecord};
+
+fn a(domain: &str, last: u8) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr: [192, 0, 2, last].into(),
+        ttl: 300,
+    }
+}
+
+fn ns(domain: &str, host: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: domain.to_string(),
+        host: host.to_string(),
+        ttl: 3600,
+    }
+}
+
+fn packet(answers: Vec<DnsRecord>, authorities: Vec<DnsRecord>) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.answers = answers.len() as u16;
+    packet.header.authoritative_entries = authorities.len() as u16;
+    packet.answers = answers;
+    packet.authorities = authorities;
+    packet
+}
+
+#[test]
+fn record_order_doesnt_change_the_bytes() {
+    let one = packet(
+        vec![
+            a("www.example.com", 2),
+            a("example.com", 1),
+            a("www.example.com", 1),
+        ],
+        vec![
+            ns("example.com", "b.ns.example"),
+            ns("example.com", "a.ns.example"),
+        ],
+    );
+    let other = packet(
+        vec![
+            a("www.example.com", 1),
+            a("example.com", 1),
+            a("www.example.com", 2),
+        ],
+        vec![
+            ns("example.com", "a.ns.example"),
+            ns("example.com", "b.ns.example"),
+        ],
+    );
+
+    assert_ne!(one.to_bytes().unwrap(), other.to_bytes().unwrap());
+    assert_eq!(
+        one.to_canonical_bytes().unwrap(),
+        other.to_canonical_bytes().unwrap()
+    );
+}
+
+#[test]
+fn names_sort_from_the_right() {
+    let mut packet = packet(
+        vec![
+            a("b.example.org", 1),
+            a("z.example.com", 1),
+            a("example.com", 1),
+            a("a.example.org", 1),
+        ],
+        Vec::new(),
+    );
+    packet.canonicalize();
+
+    let names: Vec<&str> = packet.answers.iter().map(DnsRecord::domain).collect();
+    assert_eq!(
+        names,
+        vec![
+            "example.com",
+            "z.example.com",
+            "a.example.org",
+            "b.example.org"
+        ]
+    );
+}
+
+#[test]
+fn records_of_a_set_sort_by_their_data() {
+    let mut first = a("www.example.com", 2);
+    first.set_ttl(60);
+    let mut packet = packet(
+        vec![first, a("www.example.com", 1)],
+        vec![
+            ns("example.com", "B.ns.example"),
+            ns("example.com", "a.ns.example"),
+        ],
+    );
+    packet.canonicalize();
+
+    // Not by TTL, and not by the case of the names in the data.
+    let addrs: Vec<String> = packet.answers.iter().filter_map(DnsRecord::rdata).collect();
+    assert_eq!(addrs, vec!["192.0.2.1", "192.0.2.2"]);
+    let hosts: Vec<String> = packet
+        .authorities
+        .iter()
+        .filter_map(DnsRecord::rdata)
+        .collect();
+    assert_eq!(hosts, vec!["a.ns.example", "B.ns.example"]);
+}
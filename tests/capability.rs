@@ -0,0 +1,95 @@
+//! What the resolver learns about the servers it queries, and how it shapes later queries.
+
+#![cfg(feature = "resolver")]
+
+use diydns::capability::Capabilities;
+use diydns::resolver::{QueryOptions, Resolver};
+use diydns::{DnsPacket, DnsRecord, QueryType, EDNS_DO};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+
+#[test]
+fn timeouts_lower_the_payload_size_until_big_responses_have_got_through() {
+    let capabilities = Capabilities::new();
+    let (quiet, big): (SocketAddr, SocketAddr) = (
+        "192.0.2.1:53".parse().unwrap(),
+        "192.0.2.2:53".parse().unwrap(),
+    );
+
+    assert_eq!(capabilities.get(quiet).payload_size(), Some(1232));
+    capabilities.answered(quiet, 100, None);
+    capabilities.timed_out(quiet);
+    assert_eq!(capabilities.get(quiet).payload_size(), Some(512));
+
+    capabilities.answered(big, 700, None);
+    capabilities.timed_out(big);
+    assert_eq!(capabilities.get(big).payload_size(), Some(1232));
+    assert_eq!(capabilities.get(big).largest_response, 700);
+
+    capabilities.bad_version(big);
+    assert_eq!(capabilities.get(big).payload_size(), None);
+
+    let listed: Vec<String> = capabilities
+        .snapshot()
+        .iter()
+        .map(|(server, capability)| format!("{} {}", server, capability))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            "192.0.2.1:53 edns 512, largest response 100 bytes, dnssec unknown",
+            "192.0.2.2:53 no edns, largest response 700 bytes, dnssec unknown",
+        ]
+    );
+}
+
+#[test]
+fn servers_that_ignore_do_are_not_asked_with_it_again() {
+    // Answers with an OPT record of its own that never has DO set.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    let (sent_do, asked_with_do) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let flags = request.resources.iter().find_map(|rec| match *rec {
+            DnsRecord::OPT { flags, .. } => Some(flags),
+            _ => None,
+        });
+        sent_do
+            .send(flags.map(|flags| flags & EDNS_DO != 0))
+            .unwrap();
+
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        response.resources.push(DnsRecord::OPT {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let resolver = Resolver::default();
+    let options = QueryOptions {
+        dnssec_ok: true,
+        ..QueryOptions::default()
+    };
+    for _ in 0..2 {
+        resolver
+            .lookup_with("example.com", QueryType::A, &options, server)
+            .unwrap();
+    }
+
+    assert_eq!(
+        asked_with_do.try_iter().collect::<Vec<_>>(),
+        [Some(true), Some(false)]
+    );
+    assert_eq!(resolver.capabilities().get(server).dnssec, Some(false));
+}
@@ -0,0 +1,71 @@
+//! Parsing of the kube-dns ConfigMap values and resolv.conf.
+
+#![cfg(feature = "server")]
+
+use diydns::cluster::{self, StubDomain};
+use std::net::SocketAddr;
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn stub_domains() {
+    let stubs = cluster::parse_stub_domains(
+        r#"{"acme.local": ["1.2.3.4", "1.2.3.5:5353"], "Corp.Example.": ["[2001:db8::1]:53"]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        stubs,
+        vec![
+            StubDomain {
+                domain: "acme.local".to_string(),
+                servers: vec![addr("1.2.3.4:53"), addr("1.2.3.5:5353")],
+            },
+            StubDomain {
+                domain: "corp.example".to_string(),
+                servers: vec![addr("[2001:db8::1]:53")],
+            },
+        ]
+    );
+
+    assert_eq!(
+        cluster::find_stub(&stubs, "db.acme.local").map(|s| s.domain.as_str()),
+        Some("acme.local")
+    );
+    assert_eq!(cluster::find_stub(&stubs, "notacme.local"), None);
+    assert!(cluster::parse_stub_domains(r#"{"acme.local": "1.2.3.4"}"#).is_err());
+}
+
+#[test]
+fn upstream_nameservers() {
+    assert_eq!(
+        cluster::parse_upstream_nameservers(r#"["8.8.8.8", "8.8.4.4"]"#).unwrap(),
+        vec![addr("8.8.8.8:53"), addr("8.8.4.4:53")]
+    );
+    assert!(cluster::parse_upstream_nameservers(r#"["8.8.8.8""#).is_err());
+}
+
+#[test]
+fn resolv_conf() {
+    let contents = "# generated\nsearch default.svc.cluster.local\nnameserver 10.96.0.10\n\
+                    nameserver fd00::a\noptions ndots:5\n";
+    assert_eq!(
+        cluster::parse_resolv_conf(contents),
+        vec![addr("10.96.0.10:53"), addr("[fd00::a]:53")]
+    );
+}
+
+#[test]
+fn link_local_servers() {
+    let contents = "nameserver fe80::1%1\nnameserver router.lan\n";
+    assert_eq!(
+        cluster::parse_resolv_conf(contents),
+        vec![addr("[fe80::1%1]:53")]
+    );
+    assert_eq!(
+        cluster::parse_upstream_nameservers(r#"["[fe80::1%1]:5353"]"#).unwrap(),
+        vec![addr("[fe80::1%1]:5353")]
+    );
+}
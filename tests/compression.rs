@@ -0,0 +1,96 @@
+//! Compressing names when encoding, whatever case they're written in.
+
+use diydns::{DnsPacket, DnsQuestion, DnsRecord, QueryType, WritePolicy, CLASS_IN};
+
+/// Where the question's name starts, right after the header.
+const QUESTION: u16 = 12;
+
+fn packet(question: &str, answers: Vec<DnsRecord>) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.questions.push(DnsQuestion {
+        name: question.to_string(),
+        qtype: QueryType::A,
+        qclass: CLASS_IN,
+    });
+    packet.header.questions = 1;
+    packet.header.answers = answers.len() as u16;
+    packet.answers = answers;
+    packet
+}
+
+fn a(domain: &str) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr: [192, 0, 2, 1].into(),
+        ttl: 300,
+    }
+}
+
+fn pointer(target: u16) -> [u8; 2] {
+    (0xC000 | target).to_be_bytes()
+}
+
+#[test]
+fn points_back_across_case() {
+    // As a resolver randomizing the case of its queries would send it.
+    let packet = packet(
+        "wWw.ExAmple.COM",
+        vec![a("www.example.com"), a("WWW.EXAMPLE.COM")],
+    );
+    let bytes = packet.to_bytes().unwrap();
+
+    // The question keeps the case it was written in.
+    let name = b"\x03wWw\x07ExAmple\x03COM\x00";
+    let question = QUESTION as usize;
+    assert_eq!(&bytes[question..question + name.len()], name);
+
+    let first = question + name.len() + 4;
+    let second = first + 2 + 14;
+    assert_eq!(bytes[first..first + 2], pointer(QUESTION));
+    assert_eq!(bytes[second..second + 2], pointer(QUESTION));
+    assert_eq!(bytes.len(), second + 2 + 14);
+
+    let decoded = DnsPacket::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.answers[1].domain(), "www.example.com");
+}
+
+#[test]
+fn points_back_to_endings() {
+    let cname = DnsRecord::CNAME {
+        domain: "www.example.com".to_string(),
+        host: "web.Example.com".to_string(),
+        ttl: 300,
+    };
+    let packet = packet(
+        "www.example.com",
+        vec![cname.clone(), a("mail.EXAMPLE.com")],
+    );
+    let bytes = packet.to_bytes().unwrap();
+
+    // The CNAME's target is its own first label and a pointer to the question's ending.
+    let rdata = QUESTION as usize + 17 + 4 + 2 + 10;
+    assert_eq!(&bytes[rdata - 2..rdata], &[0, 6]);
+    assert_eq!(bytes[rdata..rdata + 4], *b"\x03web");
+    assert_eq!(bytes[rdata + 4..rdata + 6], pointer(QUESTION + 4));
+    // And the next owner a pointer to the same ending.
+    assert_eq!(bytes[rdata + 6..rdata + 11], *b"\x04mail");
+    assert_eq!(bytes[rdata + 11..rdata + 13], pointer(QUESTION + 4));
+
+    let decoded = DnsPacket::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.answers[0].rdata().unwrap(), "web.example.com");
+    assert_eq!(decoded.answers[1].domain(), "mail.example.com");
+}
+
+#[test]
+fn verbatim_writes_names_out() {
+    let packet = packet("www.example.com", vec![a("www.example.com")]);
+
+    let compressed = packet.to_bytes().unwrap();
+    let verbatim = packet.to_bytes_with(WritePolicy::Verbatim).unwrap();
+    assert_eq!(verbatim.len(), compressed.len() + 15);
+    assert_eq!(
+        DnsPacket::from_bytes(&verbatim).unwrap().answers,
+        DnsPacket::from_bytes(&compressed).unwrap().answers
+    );
+}
@@ -0,0 +1,224 @@
+//! Settings and variables in the configuration file, and checking it.
+
+#![cfg(feature = "server")]
+
+use diydns::config::Config;
+use diydns::zone::Zone;
+use diydns::DnsRecord;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("diydns-config-{}-{}", name, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn load(name: &str, contents: &str) -> std::io::Result<Config> {
+    let path = write(name, contents);
+    let mut config = Config::default();
+    let result = config.load_file(path.to_str().unwrap());
+    let _ = fs::remove_file(&path);
+    result.map(|_| config)
+}
+
+#[test]
+fn expands_variables() {
+    env::set_var("DIYDNS_TEST_UPSTREAM", "9.9.9.9");
+    let config = load(
+        "vars",
+        "$SERVER_IP = 10.0.0.5\n\
+         $NET = 10.0.0\n\
+         $LISTEN = \"${SERVER_IP}:53\"\n\
+         bind = $LISTEN\n\
+         listen = guest ${NET}.6\n\
+         upstream = $DIYDNS_TEST_UPSTREAM\n\
+         local_domain = cost$$centre\n",
+    )
+    .unwrap();
+
+    assert_eq!(config.bind, "10.0.0.5:53".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.listeners[0].addr, "10.0.0.6:2053".parse().unwrap());
+    assert_eq!(config.upstream, ["9.9.9.9:53".parse().unwrap()]);
+    assert_eq!(config.local_domain, "cost$centre");
+}
+
+#[test]
+fn rejects_bad_references() {
+    let err = load("undefined", "bind = $NOT_DEFINED_ANYWHERE_42\n").unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with(":1: undefined variable $NOT_DEFINED_ANYWHERE_42"));
+
+    // Only earlier definitions count.
+    assert!(load("order", "bind = $LATER_IP_42\n$LATER_IP_42 = 10.0.0.1\n").is_err());
+    assert!(load("unclosed", "bind = ${SERVER_IP\n").is_err());
+    assert!(load("name", "$1ST = 10.0.0.1\n").is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn per_host_variables() {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    let config = load(
+        "hosts",
+        &format!(
+            "$IP = 10.0.0.5\n\
+             $IP@{} = 10.0.0.6\n\
+             $IP@not-this-host-42 = 10.0.0.7\n\
+             $PORT@not-this-host-42 = $UNDEFINED_ELSEWHERE_42\n\
+             bind = $IP:53\n",
+            hostname.trim().to_uppercase()
+        ),
+    )
+    .unwrap();
+    assert_eq!(config.bind, "10.0.0.6:53".parse::<SocketAddr>().unwrap());
+
+    assert!(load("empty-host", "$IP@ = 10.0.0.5\n").is_err());
+}
+
+#[test]
+fn variables_in_zone_files() {
+    let zone_path = env::temp_dir().join(format!("diydns-config-{}.zone", std::process::id()));
+    fs::write(
+        &zone_path,
+        "$TTL 1h\n\
+         @   IN SOA ns1 hostmaster 1 2h 15m 14d 5m\n\
+         ns1 IN A ${SERVER_IP}\n\
+         txt IN TXT \"costs $5\" \"at ${SITE}\"\n",
+    )
+    .unwrap();
+
+    let config = load(
+        "zone",
+        &format!(
+            "$SERVER_IP = 10.0.0.5\n\
+             $SITE = lab\n\
+             zone = example.com {}\n",
+            zone_path.display()
+        ),
+    );
+    let _ = fs::remove_file(&zone_path);
+
+    let zone = &config.unwrap().zones[0];
+    let records: Vec<String> = zone.records().filter_map(DnsRecord::rdata).collect();
+    assert!(records.contains(&"10.0.0.5".to_string()));
+    assert!(records.contains(&"\"costs $5\" \"at lab\"".to_string()));
+}
+
+#[test]
+fn limits_the_servfail_ttl() {
+    let mut config = Config::default();
+    config.set("cache_servfail_ttl", "5m").unwrap();
+    assert_eq!(config.cache_servfail_ttl, 300);
+
+    let err = config.set("cache_servfail_ttl", "301").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "SERVFAIL can be cached for at most 5m, found \"301\""
+    );
+}
+
+#[test]
+fn limits_the_edns_payload_size() {
+    let mut config = Config::default();
+    assert_eq!(config.edns_payload_size, 1232);
+    config.set("edns_payload_size", "4096").unwrap();
+    assert_eq!(config.edns_payload_size, 4096);
+
+    for value in &["511", "4097", "big"] {
+        let err = config.set("edns_payload_size", value).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "expected a payload size of 512 to 4096 bytes, found {:?}",
+                value
+            )
+        );
+    }
+}
+
+#[test]
+fn check_reports_every_bad_line() {
+    let path = write(
+        "check",
+        "# comment\n\
+         bind = 127.0.0.1:5353\n  \
+         upstrem = 9.9.9.9\n\
+         $1ST = 10.0.0.1\n\
+         cache_min_ttl =  $NOT_DEFINED_ANYWHERE_42\n\
+         threads = many\n\
+         no equals sign\n",
+    );
+    let mut config = Config::default();
+    let diagnostics = config.check_file(path.to_str().unwrap()).unwrap();
+    let _ = fs::remove_file(&path);
+
+    let diagnostics: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        diagnostics,
+        [
+            "3:3: unknown option \"upstrem\", did you mean \"upstream\"?",
+            "4:1: bad variable name \"$1ST\"",
+            "5:18: undefined variable $NOT_DEFINED_ANYWHERE_42",
+            "6:11: expected a thread count, found \"many\"",
+            "7:1: expected key = value",
+        ]
+    );
+    // The good lines still apply.
+    assert_eq!(config.bind, "127.0.0.1:5353".parse().unwrap());
+
+    assert!(config.set("nonsense", "1").is_err());
+}
+
+#[test]
+fn check_finds_conflicts() {
+    let mut config = Config::default();
+    assert!(config.check().is_empty());
+
+    config.set("bind", "0.0.0.0:53").unwrap();
+    config.set("listen", "guest 192.168.2.1:53").unwrap();
+    config.set("listen", "lab [::1]:53").unwrap();
+    config.set("upstream", "9.9.9.9").unwrap();
+    config.set("stub_domain", "corp.example 10.0.0.1").unwrap();
+    config.set("stub_domain", "lab 10.0.0.2").unwrap();
+    config.set("synth", "lab 10.0.0.7").unwrap();
+    config.set("recursion", "false").unwrap();
+    config.set("dhcp_leases", "/nonexistent-42/leases").unwrap();
+    config.set("history", "/nonexistent-42/history").unwrap();
+
+    let problems: Vec<String> = config.check().iter().map(ToString::to_string).collect();
+    assert_eq!(
+        problems,
+        [
+            "listeners default (0.0.0.0:53) and guest (192.168.2.1:53) overlap",
+            "upstream is never forwarded to with recursion off",
+            "stub_domain corp.example is never forwarded to with recursion off",
+            "stub_domain lab is never forwarded to with recursion off",
+            "stub_domain lab is never forwarded to, synth lab answers for it",
+            "dhcp_leases /nonexistent-42/leases: No such file or directory (os error 2)",
+            "history /nonexistent-42/history: no directory /nonexistent-42",
+        ]
+    );
+}
+
+#[test]
+fn strict_zones_make_warnings_problems() {
+    let mut config = Config::default();
+    config
+        .zones
+        .push(Zone::parse("example.com", "www A 192.0.2.1\n").unwrap());
+    assert!(config.check().is_empty());
+
+    config.set("strict_zones", "true").unwrap();
+    let problems: Vec<String> = config.check().iter().map(ToString::to_string).collect();
+    assert_eq!(
+        problems,
+        [
+            "zone example.com: the apex has no SOA record",
+            "zone example.com: the apex has no NS record",
+        ]
+    );
+}
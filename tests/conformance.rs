@@ -0,0 +1,113 @@
+//! Queries well-known public resolvers and checks that what comes back parses into
+//! something sane. These need network access, so they only run with
+//! `cargo test --features network-tests`.
+#![cfg(feature = "network-tests")]
+
+use diydns::resolver::Resolver;
+use diydns::{DnsPacket, QueryType, ResultCode};
+
+const RESOLVERS: [&str; 3] = ["8.8.8.8", "1.1.1.1", "9.9.9.9"];
+
+const QUERIES: [(&str, QueryType); 6] = [
+    ("example.com", QueryType::A),
+    ("example.com", QueryType::AAAA),
+    ("example.com", QueryType::NS),
+    ("example.com", QueryType::TXT),
+    ("gmail.com", QueryType::MX),
+    ("www.github.com", QueryType::A),
+];
+
+/// Checks the invariants every response should satisfy, whatever the question.
+fn check_invariants(server: &str, name: &str, qtype: QueryType, packet: &DnsPacket) {
+    let context = format!("{:?} {} via {}", qtype, name, server);
+
+    assert!(packet.header.response, "{}: QR bit not set", context);
+    assert_eq!(
+        packet.questions.len(),
+        1,
+        "{}: question not echoed",
+        context
+    );
+    assert_eq!(
+        packet.questions[0].name, name,
+        "{}: wrong question",
+        context
+    );
+    assert_eq!(packet.questions[0].qtype, qtype, "{}: wrong qtype", context);
+
+    assert_eq!(
+        packet.header.answers as usize,
+        packet.answers.len(),
+        "{}",
+        context
+    );
+    assert_eq!(
+        packet.header.authoritative_entries as usize,
+        packet.authorities.len(),
+        "{}",
+        context
+    );
+    assert_eq!(
+        packet.header.resource_entries as usize,
+        packet.resources.len(),
+        "{}",
+        context
+    );
+
+    // Re-encoding what we parsed and parsing it again must give the same records.
+    let reparsed = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+    assert_eq!(
+        reparsed.questions, packet.questions,
+        "{}: round trip",
+        context
+    );
+    assert_eq!(reparsed.answers, packet.answers, "{}: round trip", context);
+    assert_eq!(reparsed.authorities, packet.authorities, "{}", context);
+    assert_eq!(reparsed.resources, packet.resources, "{}", context);
+}
+
+#[test]
+fn answers_have_the_requested_type() {
+    let resolver = Resolver::default();
+
+    for server in RESOLVERS.iter() {
+        for (name, qtype) in QUERIES.iter() {
+            let packet = resolver.lookup(name, *qtype, (*server, 53)).unwrap();
+            check_invariants(server, name, *qtype, &packet);
+
+            assert_eq!(packet.header.rescode, ResultCode::Success);
+            assert!(
+                !packet.answers.is_empty(),
+                "{:?} {} via {}",
+                qtype,
+                name,
+                server
+            );
+            for rec in &packet.answers {
+                let found = rec.query_type();
+                assert!(
+                    found == *qtype || found == QueryType::CNAME,
+                    "{:?} {} via {}: unexpected {:?} answer",
+                    qtype,
+                    name,
+                    server,
+                    found
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn nonexistent_names_are_nxdomain() {
+    let resolver = Resolver::default();
+    let name = format!("diydns-{:016x}.example.com", rand::random::<u64>());
+
+    for server in RESOLVERS.iter() {
+        let packet = resolver.lookup(&name, QueryType::A, (*server, 53)).unwrap();
+        check_invariants(server, &name, QueryType::A, &packet);
+
+        assert_eq!(packet.header.rescode, ResultCode::NonexistantDomain);
+        assert!(packet.answers.is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+//! Commands on the control socket.
+
+#![cfg(feature = "server")]
+
+use diydns::cache::Cache;
+use diydns::capability::Capabilities;
+use diydns::control::{self, Context};
+use diydns::{DnsPacket, DnsRecord, QueryType};
+
+fn response(records: Vec<DnsRecord>) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.answers = records.len() as u16;
+    packet.answers = records;
+    packet
+}
+
+fn cache() -> Cache {
+    let cache = Cache::new(0, 0);
+    cache.insert(
+        "www.example.com",
+        QueryType::A,
+        &response(vec![DnsRecord::A {
+            domain: "www.example.com".to_string(),
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        }]),
+    );
+    cache.insert(
+        "example.com",
+        QueryType::MX,
+        &response(vec![DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        }]),
+    );
+    cache.insert(
+        "example.org",
+        QueryType::A,
+        &response(vec![DnsRecord::A {
+            domain: "example.org".to_string(),
+            addr: "192.0.2.2".parse().unwrap(),
+            ttl: 300,
+        }]),
+    );
+    cache
+}
+
+fn with_cache(cache: &Cache) -> Context<'_> {
+    Context {
+        cache: Some(cache),
+        ..Context::default()
+    }
+}
+
+#[test]
+fn cache_dump() {
+    let cache = cache();
+
+    assert_eq!(
+        control::execute(with_cache(&cache), "cache dump example.com"),
+        "; example.com MX Success\n\
+         example.com. 300 IN MX 10 mail.example.com.\n\
+         ; www.example.com A Success\n\
+         www.example.com. 300 IN A 192.0.2.1\n"
+    );
+    assert_eq!(
+        control::execute(with_cache(&cache), "cache dump")
+            .lines()
+            .count(),
+        6
+    );
+}
+
+#[test]
+fn cache_lookup() {
+    let cache = cache();
+
+    assert_eq!(
+        control::execute(with_cache(&cache), "cache lookup WWW.example.com."),
+        "; www.example.com A Success\nwww.example.com. 300 IN A 192.0.2.1\n"
+    );
+    assert_eq!(
+        control::execute(with_cache(&cache), "cache lookup www.example.com aaaa"),
+        "; www.example.com AAAA not cached\n"
+    );
+    assert!(
+        control::execute(with_cache(&cache), "cache lookup www.example.com BOGUS")
+            .starts_with("error:")
+    );
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        control::execute(Context::default(), "cache dump"),
+        "error: the cache is off\n"
+    );
+    assert!(control::execute(with_cache(&cache()), "reload").starts_with("error:"));
+    assert!(control::execute(with_cache(&cache()), "cache").starts_with("error:"));
+}
+
+#[cfg(unix)]
+#[test]
+fn over_the_socket() {
+    use std::{env, thread};
+
+    let path = env::temp_dir().join(format!("diydns-control-{}.sock", std::process::id()));
+    let listener = control::bind(&path).unwrap();
+
+    let cache: &'static Cache = Box::leak(Box::new(cache()));
+    let served = path.clone();
+    thread::spawn(move || control::serve(listener, &served, with_cache(cache)));
+
+    let reply = control::request(&path, "cache lookup example.org").unwrap();
+    assert_eq!(
+        reply,
+        "; example.org A Success\nexample.org. 300 IN A 192.0.2.2\n"
+    );
+
+    // A socket left over from an earlier run doesn't stop the next one binding.
+    drop(control::bind(&path).unwrap());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn query_type_names() {
+    for qtype in [QueryType::A, QueryType::MX, QueryType::Unknown(99)].iter() {
+        assert_eq!(qtype.to_string().parse::<QueryType>().unwrap(), *qtype);
+    }
+    assert_eq!("type28".parse::<QueryType>().unwrap(), QueryType::AAAA);
+
+    let rec = DnsRecord::TXT {
+        domain: "example.com".to_string(),
+        data: vec!["v=spf1 -all".to_string()],
+        ttl: 60,
+    };
+    assert_eq!(rec.to_string(), "example.com. 60 IN TXT \"v=spf1 -all\"");
+}
+
+#[test]
+fn servers() {
+    let capabilities = Capabilities::new();
+    capabilities.answered("192.0.2.53:53".parse().unwrap(), 300, Some(true));
+    let context = Context {
+        capabilities: Some(&capabilities),
+        ..Context::default()
+    };
+
+    assert_eq!(
+        control::execute(context, "servers"),
+        "192.0.2.53:53 edns 1232, largest response 300 bytes, dnssec yes\n"
+    );
+    assert_eq!(
+        control::execute(Context::default(), "servers"),
+        "error: no resolver to ask\n"
+    );
+}
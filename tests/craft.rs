@@ -0,0 +1,62 @@
+//! Building broken queries on purpose.
+
+use diydns::craft::{Craft, Defect};
+use diydns::{DnsError, DnsErrorKind, DnsPacket, Location, QueryType, Section};
+
+fn craft(defects: &[Defect]) -> Vec<u8> {
+    let mut craft = Craft::new("example.com", QueryType::A);
+    craft.id = 0x1234;
+    craft.defects = defects.to_vec();
+    craft.build().unwrap()
+}
+
+fn parse_error(data: &[u8]) -> DnsError {
+    DnsError::find(&DnsPacket::from_bytes(data).unwrap_err())
+        .unwrap()
+        .clone()
+}
+
+#[test]
+fn without_defects_its_a_plain_query() {
+    let packet = DnsPacket::from_bytes(&craft(&[])).unwrap();
+
+    assert_eq!(packet.header.id, 0x1234);
+    assert_eq!(packet.questions[0].name, "example.com");
+    assert_eq!(packet.questions[0].qtype, QueryType::A);
+}
+
+#[test]
+fn overlong_labels_and_names() {
+    let error = parse_error(&craft(&[Defect::LongLabel]));
+    assert_eq!(error.kind(), DnsErrorKind::BadLabelType(64));
+
+    // Every label is legal, but the name as a whole isn't.
+    let data = craft(&[Defect::LongName]);
+    let name = DnsPacket::from_bytes(&data).unwrap().questions[0]
+        .name
+        .clone();
+    assert!(name.len() + 2 > 255, "{}", name.len());
+}
+
+#[test]
+fn pointers() {
+    let error = parse_error(&craft(&[Defect::BadPointer]));
+    assert_eq!(error.kind(), DnsErrorKind::BadPointer { target: 0x3FFF });
+
+    let data = craft(&[Defect::PointerLoop]);
+    assert_eq!(&data[12..14], &[0xC0, 12]);
+    assert_eq!(
+        parse_error(&data).kind(),
+        DnsErrorKind::BadPointer { target: 12 }
+    );
+}
+
+#[test]
+fn counts_and_truncation() {
+    let data = craft(&[Defect::AnswerCount(2), Defect::TruncateAt(20)]);
+    assert_eq!(data.len(), 20);
+    assert_eq!(&data[6..8], &[0, 2]);
+
+    let error = parse_error(&craft(&[Defect::AnswerCount(2)]));
+    assert_eq!(error.location(), Some(Location::Record(Section::Answer, 0)));
+}
@@ -0,0 +1,55 @@
+//! Decodes each wire-format packet in `tests/corpus` and compares the result against the
+//! `.txt` snapshot next to it. Run with `UPDATE_SNAPSHOTS=1` to rewrite the snapshots after
+//! an intended change to decoding, then review the diff.
+
+use diydns::DnsPacket;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut packets: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+        .collect();
+    packets.sort();
+    packets
+}
+
+#[test]
+fn corpus_matches_snapshots() {
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut mismatches = Vec::new();
+
+    for path in corpus() {
+        let decoded = match DnsPacket::from_bytes(&fs::read(&path).unwrap()) {
+            Ok(packet) => format!("{:#?}\n", packet),
+            Err(e) => format!("Error: {}\n", e),
+        };
+
+        let snapshot = path.with_extension("txt");
+        if update {
+            fs::write(&snapshot, &decoded).unwrap();
+            continue;
+        }
+
+        match fs::read_to_string(&snapshot) {
+            Ok(expected) if expected == decoded => {}
+            Ok(expected) => mismatches.push(format!(
+                "{}:\n--- expected\n{}\n--- decoded\n{}",
+                path.display(),
+                expected,
+                decoded
+            )),
+            Err(_) => mismatches.push(format!("{}: no snapshot", path.display())),
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "decode output differs from snapshots (UPDATE_SNAPSHOTS=1 to accept):\n{}",
+        mismatches.join("\n")
+    );
+}
@@ -0,0 +1,61 @@
+//! Writing lookup answers out as zone file lines, CSV and JSON.
+
+#![cfg(feature = "resolver")]
+
+use diydns::export::{export, Format};
+use diydns::DnsRecord;
+
+fn records() -> Vec<DnsRecord> {
+    vec![
+        DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        },
+        DnsRecord::TXT {
+            domain: "example.com.".to_string(),
+            data: vec!["v=spf1 a, mx -all".to_string(), "say \"hi\"".to_string()],
+            ttl: 3600,
+        },
+    ]
+}
+
+#[test]
+fn as_zone_lines() {
+    assert_eq!(
+        export(Format::Zone, &records()),
+        "example.com. 300 IN MX 10 mail.example.com.\n\
+         example.com. 3600 IN TXT \"v=spf1 a, mx -all\" \"say \\\"hi\\\"\"\n"
+    );
+}
+
+#[test]
+fn as_csv() {
+    assert_eq!(
+        export(Format::Csv, &records()),
+        "name,type,ttl,data\n\
+         example.com,MX,300,10 mail.example.com.\n\
+         example.com,TXT,3600,\"\"\"v=spf1 a, mx -all\"\" \"\"say \\\"\"hi\\\"\"\"\"\"\n"
+    );
+    assert_eq!(export(Format::Csv, &[]), "name,type,ttl,data\n");
+}
+
+#[test]
+fn as_json() {
+    assert_eq!(
+        export(Format::Json, &records()),
+        "[\n  \
+         {\"name\":\"example.com\",\"type\":\"MX\",\"ttl\":300,\"data\":\"10 mail.example.com.\"},\n  \
+         {\"name\":\"example.com\",\"type\":\"TXT\",\"ttl\":3600,\
+         \"data\":\"\\\"v=spf1 a, mx -all\\\" \\\"say \\\\\\\"hi\\\\\\\"\\\"\"}\n\
+         ]\n"
+    );
+    assert_eq!(export(Format::Json, &[]), "[]\n");
+}
+
+#[test]
+fn formats_by_name() {
+    assert_eq!("csv".parse::<Format>().unwrap(), Format::Csv);
+    assert!("yaml".parse::<Format>().is_err());
+}
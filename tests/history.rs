@@ -0,0 +1,86 @@
+//! Recording queries to the history file and searching it.
+
+#![cfg(feature = "server")]
+
+use diydns::history::{self, Filter, History, Query};
+use diydns::{QueryType, ResultCode};
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+fn query(time: u64, client: &str, name: &str) -> Query {
+    Query {
+        time,
+        client: client.parse().unwrap(),
+        name: name.to_string(),
+        qtype: QueryType::A,
+        rescode: ResultCode::Success,
+        latency: Duration::from_micros(1250),
+        blocked: None,
+    }
+}
+
+#[test]
+fn record_and_search() {
+    let path = env::temp_dir().join(format!("diydns-history-{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let now = history::unix_now();
+
+    let history = History::open(&path, 3600).unwrap();
+    history
+        .record(&query(now - 7200, "10.0.0.5", "old.example.com"))
+        .unwrap();
+    history
+        .record(&query(now - 60, "10.0.0.5", "www.example.com"))
+        .unwrap();
+    history
+        .record(&query(now - 30, "10.0.0.6", "example.org"))
+        .unwrap();
+    history
+        .record(&query(now, "10.0.0.5", "tab\tname"))
+        .unwrap();
+
+    let filter = Filter {
+        client: Some("10.0.0.5".parse().unwrap()),
+        since: Some(now - 3600),
+        ..Filter::default()
+    };
+    let found = history::search(&path, &filter).unwrap();
+    assert_eq!(
+        found.iter().map(|q| q.name.as_str()).collect::<Vec<_>>(),
+        vec!["www.example.com", "tab?name"]
+    );
+    assert_eq!(found[0], query(now - 60, "10.0.0.5", "www.example.com"));
+
+    let filter = Filter {
+        name: Some("Example.com.".to_string()),
+        ..Filter::default()
+    };
+    assert_eq!(history::search(&path, &filter).unwrap().len(), 2);
+
+    // Only the hour-old query has passed the retention period.
+    history.prune().unwrap();
+    assert_eq!(history::search(&path, &Filter::default()).unwrap().len(), 3);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn display() {
+    let mut q = query(1_700_000_000, "10.0.0.5", "example.com");
+    q.rescode = ResultCode::NonexistantDomain;
+    assert_eq!(
+        q.to_string(),
+        "2023-11-14 22:13:20 10.0.0.5 example.com A NXDOMAIN 1.2ms"
+    );
+}
+
+#[test]
+fn durations() {
+    assert_eq!(history::parse_duration("90").unwrap(), 90);
+    assert_eq!(history::parse_duration("30m").unwrap(), 1800);
+    assert_eq!(history::parse_duration("1h").unwrap(), 3600);
+    assert_eq!(history::parse_duration("7d").unwrap(), 604_800);
+    assert!(history::parse_duration("1w").is_err());
+    assert!(history::parse_duration("h").is_err());
+}
@@ -0,0 +1,128 @@
+//! Reading DHCP lease files and the records published for them.
+
+#![cfg(feature = "server")]
+
+use diydns::leases::{self, Lease, LeaseFormat};
+use diydns::local::LocalRecords;
+use diydns::{DnsRecord, QueryType, ResultCode};
+
+const NOW: u64 = 1_700_000_000;
+
+fn lease(addr: &str, hostname: &str, expires: Option<u64>) -> Lease {
+    Lease {
+        addr: addr.parse().unwrap(),
+        hostname: hostname.to_string(),
+        expires,
+    }
+}
+
+#[test]
+fn dnsmasq() {
+    let contents = "1700003600 aa:bb:cc:dd:ee:01 192.168.1.10 laptop 01:aa:bb:cc:dd:ee:01\n\
+                    0 aa:bb:cc:dd:ee:02 192.168.1.11 printer *\n\
+                    1700003600 aa:bb:cc:dd:ee:03 192.168.1.12 * *\n\
+                    duid 00:01:00:01:2c:1f:aa:bb\n\
+                    1700003600 1234 fd00::10 phone 00:01:00:01\n";
+
+    assert_eq!(
+        leases::parse_leases(LeaseFormat::Dnsmasq, contents),
+        vec![
+            lease("192.168.1.10", "laptop", Some(1_700_003_600)),
+            lease("192.168.1.11", "printer", None),
+            lease("fd00::10", "phone", Some(1_700_003_600)),
+        ]
+    );
+}
+
+#[test]
+fn isc() {
+    let contents = r#"
+# The format of this file is documented in the dhcpd.leases(5) manual page.
+lease 192.168.1.10 {
+  starts 2 2023/11/14 22:13:20;
+  ends 3 2023/11/15 22:13:20;
+  binding state active;
+  client-hostname "laptop";
+}
+lease 192.168.1.11 {
+  ends never;
+  binding state active;
+  client-hostname "printer";
+}
+lease 192.168.1.10 {
+  ends 3 2023/11/15 22:13:20;
+  binding state free;
+  client-hostname "laptop";
+}
+lease 192.168.1.12 {
+  ends epoch 1700003600; # Tue Nov 14 23:13:20 2023
+  binding state active;
+  client-hostname "tv";
+}
+"#;
+
+    assert_eq!(
+        leases::parse_leases(LeaseFormat::Isc, contents),
+        vec![
+            lease("192.168.1.11", "printer", None),
+            lease("192.168.1.12", "tv", Some(1_700_003_600)),
+        ]
+    );
+}
+
+#[test]
+fn isc_dates_are_utc() {
+    let contents = "lease 10.0.0.1 {\n  ends 2 2023/11/14 22:13:20;\n  client-hostname \"a\";\n}\n";
+    assert_eq!(
+        leases::parse_leases(LeaseFormat::Isc, contents)[0].expires,
+        Some(NOW)
+    );
+}
+
+#[test]
+fn kea() {
+    let contents = "address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context\n\
+                    192.168.1.10,aa:bb:cc:dd:ee:01,,3600,1700003600,1,0,0,laptop.example.com.,0,\n\
+                    192.168.1.11,aa:bb:cc:dd:ee:02,,3600,1700003600,1,0,0,printer,1,\n\
+                    192.168.1.12,aa:bb:cc:dd:ee:03,,3600,1700003600,1,0,0,,0,\n";
+
+    assert_eq!(
+        leases::parse_leases(LeaseFormat::Kea, contents),
+        vec![lease("192.168.1.10", "laptop", Some(1_700_003_600))]
+    );
+}
+
+#[test]
+fn published_records() {
+    let leases = vec![
+        lease("192.168.1.10", "Laptop", Some(NOW + 60)),
+        lease("192.168.1.11", "gone", Some(NOW - 60)),
+    ];
+
+    let local = LocalRecords::new(vec!["lan".to_string()]);
+    local.replace(leases::lease_records(&leases, "lan", NOW));
+
+    let (rescode, answers) = local.answer("laptop.lan", QueryType::A).unwrap();
+    assert_eq!(rescode, ResultCode::Success);
+    assert_eq!(
+        answers,
+        vec![DnsRecord::A {
+            domain: "laptop.lan".to_string(),
+            addr: "192.168.1.10".parse().unwrap(),
+            ttl: 60,
+        }]
+    );
+
+    let (_, answers) = local
+        .answer("10.1.168.192.in-addr.arpa", QueryType::PTR)
+        .unwrap();
+    assert_eq!(answers[0].rdata().as_deref(), Some("laptop.lan"));
+
+    let (rescode, answers) = local.answer("laptop.lan", QueryType::AAAA).unwrap();
+    assert_eq!((rescode, answers.len()), (ResultCode::Success, 0));
+
+    let (rescode, _) = local.answer("gone.lan", QueryType::A).unwrap();
+    assert_eq!(rescode, ResultCode::NonexistantDomain);
+
+    assert_eq!(local.answer("example.com", QueryType::A), None);
+}
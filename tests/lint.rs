@@ -0,0 +1,73 @@
+//! Checking SOA timers against the recommended ranges.
+
+#![cfg(feature = "resolver")]
+
+use diydns::lint::{self, Severity};
+use diydns::DnsRecord;
+
+fn soa(refresh: u32, retry: u32, expire: u32, minimum: u32) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: "example.com".to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 2024010101,
+        refresh,
+        retry,
+        expire,
+        minimum,
+        ttl: 3600,
+    }
+}
+
+fn messages(soa: &DnsRecord) -> Vec<String> {
+    lint::soa_findings(soa)
+        .into_iter()
+        .inspect(|finding| assert_eq!(finding.severity, Severity::Warning))
+        .map(|finding| finding.message)
+        .collect()
+}
+
+#[test]
+fn recommended_timers_pass() {
+    assert!(messages(&soa(7200, 3600, 1_209_600, 3600)).is_empty());
+    assert!(messages(&soa(43_200, 7200, 2_419_200, 86_400)).is_empty());
+}
+
+#[test]
+fn timers_out_of_range() {
+    assert_eq!(
+        messages(&soa(600, 60, 86_400, 604_800)),
+        [
+            "SOA refresh of 10m is outside the recommended 20m to 12h",
+            "SOA retry of 1m is outside the recommended 2m to 2h",
+            "SOA expire of 1d is outside the recommended 14d to 28d",
+            "SOA minimum of 7d is outside the recommended 5m to 1d",
+        ]
+    );
+}
+
+#[test]
+fn timers_that_contradict_each_other() {
+    assert_eq!(
+        messages(&soa(3600, 3600, 1_209_600, 3600)),
+        ["SOA retry of 1h isn't shorter than the refresh of 1h"]
+    );
+    assert_eq!(
+        messages(&soa(3600, 7200, 7200, 3600)),
+        [
+            "SOA expire of 2h is outside the recommended 14d to 28d",
+            "SOA retry of 2h isn't shorter than the refresh of 1h",
+            "SOA expire of 2h leaves secondaries no time to retry a failed refresh",
+        ]
+    );
+}
+
+#[test]
+fn other_records_have_no_timers() {
+    let a = DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: [192, 0, 2, 1].into(),
+        ttl: 3600,
+    };
+    assert!(lint::soa_findings(&a).is_empty());
+}
@@ -0,0 +1,60 @@
+//! Catching upstreams that loop back to us.
+
+#![cfg(feature = "server")]
+
+use diydns::loopcheck::{self, LoopCheck};
+use diydns::{DnsPacket, ResultCode};
+use std::io::{Error, ErrorKind};
+
+#[test]
+fn own_addresses() {
+    let ours = [
+        "127.0.0.1:2053".parse().unwrap(),
+        "0.0.0.0:53".parse().unwrap(),
+    ];
+    let cases = [
+        ("127.0.0.1:2053", true),
+        ("127.0.0.1:53", true),
+        ("127.0.0.2:53", true),
+        ("0.0.0.0:53", true),
+        ("127.0.0.1:5353", false),
+        ("9.9.9.9:53", false),
+        ("[::1]:53", false),
+    ];
+
+    for (server, expected) in cases.iter() {
+        assert_eq!(
+            loopcheck::is_own_address(server.parse().unwrap(), &ours),
+            *expected,
+            "{}",
+            server
+        );
+    }
+}
+
+#[test]
+fn probes_that_come_back() {
+    let check = LoopCheck::new();
+    assert!(!check.looping());
+
+    // An upstream that passes the probe straight back to us.
+    let looped = check.probe(|name| {
+        assert!(check.is_probe(&name.to_uppercase()));
+        let mut response = DnsPacket::default();
+        response.header.rescode = ResultCode::ServerFail;
+        Ok(response)
+    });
+    assert!(looped);
+    assert!(check.looping());
+    // Only while it's in flight.
+    assert!(!check.is_probe("example.com"));
+
+    let mut probe = String::new();
+    let looped = check.probe(|name| {
+        probe = name.to_string();
+        Err(Error::new(ErrorKind::TimedOut, "timed out"))
+    });
+    assert!(!looped);
+    assert!(!check.looping());
+    assert!(!check.is_probe(&probe));
+}
@@ -0,0 +1,95 @@
+//! Splitting a large answer across several messages.
+
+use diydns::{
+    DnsHeader, DnsPacket, DnsQuestion, DnsRecord, MessageWriter, QueryType, CLASS_IN,
+    MAX_PACKET_SIZE,
+};
+
+fn records(count: usize) -> impl Iterator<Item = DnsRecord> {
+    (0..count).map(|i| DnsRecord::A {
+        domain: format!("host{}.example.com", i),
+        addr: [10, 0, (i / 256) as u8, (i % 256) as u8].into(),
+        ttl: 3600,
+    })
+}
+
+fn writer<I: Iterator<Item = DnsRecord>>(records: I) -> MessageWriter<I> {
+    let header = DnsHeader {
+        id: 4321,
+        response: true,
+        authoritative_answer: true,
+        ..DnsHeader::default()
+    };
+    let question = DnsQuestion {
+        name: "example.com".to_string(),
+        qtype: QueryType::Unknown(252),
+        qclass: CLASS_IN,
+    };
+
+    MessageWriter::new(header, vec![question], records)
+}
+
+#[test]
+fn splits_at_the_size_limit() {
+    let messages = writer(records(200))
+        .map(|message| message.unwrap())
+        .collect::<Vec<_>>();
+    assert!(messages.len() > 1);
+
+    let mut answers = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        assert!(message.len() <= MAX_PACKET_SIZE);
+
+        let packet = DnsPacket::from_bytes(message).unwrap();
+        assert_eq!(packet.header.id, 4321);
+        assert!(packet.header.authoritative_answer);
+        assert_eq!(packet.questions.len(), if i == 0 { 1 } else { 0 });
+        assert!(!packet.answers.is_empty());
+        answers.extend(packet.answers);
+    }
+
+    assert_eq!(answers, records(200).collect::<Vec<_>>());
+}
+
+#[test]
+fn empty_answer_is_one_message() {
+    let messages = writer(records(0)).collect::<Vec<_>>();
+    assert_eq!(messages.len(), 1);
+
+    let packet = DnsPacket::from_bytes(messages[0].as_ref().unwrap()).unwrap();
+    assert_eq!((packet.questions.len(), packet.answers.len()), (1, 0));
+}
+
+#[test]
+fn framed_for_tcp() {
+    let mut out = Vec::new();
+    let count = writer(records(200)).write_framed(&mut out).unwrap();
+
+    let mut pos = 0;
+    let mut answers = 0;
+    for _ in 0..count {
+        let len = u16::from_be_bytes([out[pos], out[pos + 1]]) as usize;
+        answers += DnsPacket::from_bytes(&out[pos + 2..pos + 2 + len])
+            .unwrap()
+            .answers
+            .len();
+        pos += 2 + len;
+    }
+
+    assert_eq!((pos, answers), (out.len(), 200));
+}
+
+#[test]
+fn record_too_big_for_any_message() {
+    let huge = DnsRecord::TXT {
+        domain: "big.example.com".to_string(),
+        data: vec!["x".repeat(255), "y".repeat(255)],
+        ttl: 60,
+    };
+
+    let mut messages = writer(records(1).chain(Some(huge)).chain(records(1)));
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().is_none());
+}
@@ -0,0 +1,101 @@
+//! Parse errors that say what went wrong and where.
+
+use diydns::{DnsError, DnsErrorKind, DnsPacket, Location, Section, WirePacket};
+use std::fs;
+use std::path::Path;
+
+fn compressed() -> Vec<u8> {
+    fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/compressed.bin")).unwrap()
+}
+
+fn parse_error(data: &[u8]) -> DnsError {
+    let error = DnsPacket::from_bytes(data).unwrap_err();
+    DnsError::find(&error).unwrap().clone()
+}
+
+#[test]
+fn truncated_records() {
+    let data = compressed();
+    let span = WirePacket::from_bytes(&data)
+        .unwrap()
+        .span(Section::Answer, 1)
+        .unwrap()
+        .clone();
+
+    let error = parse_error(&data[..span.rdata.start + 1]);
+    assert_eq!(error.section(), Some(Section::Answer));
+    assert_eq!(error.record_index(), Some(1));
+    assert_eq!(error.offset(), span.rdata.start + 1);
+    assert_eq!(error.found(), Some(0));
+}
+
+#[test]
+fn pointers_that_could_loop() {
+    let mut data = compressed();
+    // The first answer's owner is a pointer to the question; point it at itself.
+    let start = WirePacket::from_bytes(&data)
+        .unwrap()
+        .span(Section::Answer, 0)
+        .unwrap()
+        .record
+        .start;
+    assert_eq!(data[start] & 0xC0, 0xC0);
+    data[start] = 0xC0 | (start >> 8) as u8;
+    data[start + 1] = start as u8;
+
+    let error = parse_error(&data);
+    assert_eq!(error.kind(), DnsErrorKind::BadPointer { target: start });
+    assert_eq!(error.location(), Some(Location::Record(Section::Answer, 0)));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "bad compression pointer at offset 0x{:X} in answer record 1: points to 0x{:X}",
+            start, start
+        )
+    );
+}
+
+#[test]
+fn reserved_label_types() {
+    let mut data = compressed();
+    data[12] = 0x80;
+
+    let error = parse_error(&data);
+    assert_eq!(error.kind(), DnsErrorKind::BadLabelType(0x80));
+    assert_eq!(error.location(), Some(Location::Question(0)));
+    assert_eq!(error.offset(), 12);
+}
+
+#[test]
+fn record_data_that_isnt_its_length() {
+    let mut data = compressed();
+    let wire = WirePacket::from_bytes(&data).unwrap();
+    let index = wire
+        .packet
+        .resources
+        .iter()
+        .position(|rec| matches!(rec, diydns::DnsRecord::A { .. }))
+        .unwrap();
+    let rdata = wire.span(Section::Additional, index).unwrap().rdata.clone();
+    data[rdata.start - 1] = 3;
+
+    let error = parse_error(&data);
+    assert_eq!(
+        error.kind(),
+        DnsErrorKind::DataLength {
+            expected: 3,
+            found: 4
+        }
+    );
+    assert_eq!(error.record_index(), Some(index));
+    assert_eq!(error.expected(), Some(3));
+}
+
+#[test]
+fn short_headers() {
+    let error = parse_error(&compressed()[..7]);
+    assert_eq!(error.location(), Some(Location::Header));
+    assert!(error
+        .to_string()
+        .starts_with("unexpected end of message at offset 0x7 in the header"));
+}
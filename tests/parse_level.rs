@@ -0,0 +1,47 @@
+//! Reading packets only as far as the caller needs.
+
+use diydns::{DnsPacket, ParseLevel, QueryType};
+use std::fs;
+use std::path::Path;
+
+fn compressed() -> Vec<u8> {
+    fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/compressed.bin")).unwrap()
+}
+
+#[test]
+fn levels_read_as_far_as_they_say() {
+    let data = compressed();
+    let full = DnsPacket::from_bytes(&data).unwrap();
+
+    let header = DnsPacket::from_bytes_at(&data, ParseLevel::Header).unwrap();
+    assert_eq!(header.header.id, full.header.id);
+    assert_eq!(header.header.answers, full.header.answers);
+    assert!(header.questions.is_empty() && header.answers.is_empty());
+
+    let questions = DnsPacket::from_bytes_at(&data, ParseLevel::Questions).unwrap();
+    assert_eq!(questions.questions, full.questions);
+    assert_eq!(questions.header.answers as usize, full.answers.len());
+    assert!(questions.answers.is_empty() && questions.resources.is_empty());
+
+    let everything = DnsPacket::from_bytes_at(&data, ParseLevel::Full).unwrap();
+    assert_eq!(everything.answers, full.answers);
+}
+
+#[test]
+fn what_isnt_read_isnt_checked() {
+    let data = compressed();
+    let question_end = 12 + data[12..].iter().position(|&b| b == 0).unwrap() + 5;
+
+    // Cut off in the middle of the first answer.
+    let cut = &data[..question_end + 3];
+    assert!(DnsPacket::from_bytes(cut).is_err());
+    let packet = DnsPacket::from_bytes_at(cut, ParseLevel::Questions).unwrap();
+    assert_eq!(
+        packet.questions[0].qtype,
+        QueryType::from_num(data[question_end - 3] as u16)
+    );
+
+    assert!(DnsPacket::from_bytes_at(&data[..question_end - 1], ParseLevel::Questions).is_err());
+    assert!(DnsPacket::from_bytes_at(&data[..12], ParseLevel::Header).is_ok());
+    assert!(DnsPacket::from_bytes_at(&data[..11], ParseLevel::Header).is_err());
+}
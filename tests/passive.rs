@@ -0,0 +1,127 @@
+//! Picking DNS responses out of captured frames, and keeping and searching what they
+//! answered.
+
+#![cfg(feature = "server")]
+
+use diydns::passive::{self, PassiveDb};
+use diydns::{DnsPacket, DnsRecord, QueryType, ResultCode};
+use std::env;
+use std::fs;
+
+fn response(name: &str, addr: &str) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.answers.push(DnsRecord::A {
+        domain: name.to_string(),
+        addr: addr.parse().unwrap(),
+        ttl: 300,
+    });
+    packet
+}
+
+fn udp(source_port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut udp = Vec::new();
+    udp.extend_from_slice(&source_port.to_be_bytes());
+    udp.extend_from_slice(&40000u16.to_be_bytes());
+    udp.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    udp
+}
+
+fn ipv4_frame(vlan: bool, flags: u16, udp: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; 12];
+    if vlan {
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x05]);
+    }
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&flags.to_be_bytes());
+    frame.extend_from_slice(&[64, 17, 0, 0, 192, 0, 2, 53, 192, 0, 2, 10]);
+    frame.extend_from_slice(udp);
+    // Ethernet pads short frames.
+    frame.extend_from_slice(&[0; 4]);
+    frame
+}
+
+fn ipv6_frame(udp: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; 12];
+    frame.extend_from_slice(&[0x86, 0xDD]);
+    let mut header = vec![0; 40];
+    header[0] = 0x60;
+    header[6] = 17;
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(udp);
+    frame
+}
+
+#[test]
+fn finds_responses_in_frames() {
+    let payload = response("www.example.com", "192.0.2.1").to_bytes().unwrap();
+
+    assert_eq!(
+        passive::dns_payload(&ipv4_frame(false, 0, &udp(53, &payload))),
+        Some(&payload[..])
+    );
+    assert_eq!(
+        passive::dns_payload(&ipv4_frame(true, 0x4000, &udp(53, &payload))),
+        Some(&payload[..])
+    );
+    assert_eq!(
+        passive::dns_payload(&ipv6_frame(&udp(53, &payload))),
+        Some(&payload[..])
+    );
+
+    // Queries, fragments and cut-off frames.
+    assert_eq!(
+        passive::dns_payload(&ipv4_frame(false, 0, &udp(40000, &payload))),
+        None
+    );
+    assert_eq!(
+        passive::dns_payload(&ipv4_frame(false, 0x2000, &udp(53, &payload))),
+        None
+    );
+    assert_eq!(passive::dns_payload(&[0; 20]), None);
+}
+
+#[test]
+fn records_and_searches() {
+    let path = env::temp_dir().join(format!("diydns-passive-{}.db", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let db = PassiveDb::open(&path).unwrap();
+    db.record(&response("WWW.example.com.", "192.0.2.1"), 1000);
+    db.record(&response("www.example.com", "192.0.2.1"), 3000);
+    db.record(&response("www.example.com", "192.0.2.2"), 2000);
+    db.record(&response("mail.example.org", "192.0.2.1"), 2500);
+
+    let mut failed = response("bad.example.com", "192.0.2.9");
+    failed.header.rescode = ResultCode::ServerFail;
+    db.record(&failed, 2000);
+    let mut query = response("query.example.com", "192.0.2.9");
+    query.header.response = false;
+    db.record(&query, 2000);
+    db.flush().unwrap();
+
+    // First and last seen survive reopening.
+    let db = PassiveDb::open(&path).unwrap();
+    db.record(&response("www.example.com", "192.0.2.1"), 500);
+    db.flush().unwrap();
+
+    let by_name = passive::search(&path, "example.com").unwrap();
+    assert_eq!(by_name.len(), 2);
+    assert_eq!((by_name[0].first_seen, by_name[0].last_seen), (500, 3000));
+    assert_eq!(by_name[0].qtype, QueryType::A);
+    assert_eq!(by_name[1].rdata, "192.0.2.2");
+
+    let by_addr = passive::search(&path, "192.0.2.1").unwrap();
+    let names: Vec<_> = by_addr.iter().map(|obs| obs.name.as_str()).collect();
+    assert_eq!(names, ["mail.example.org", "www.example.com"]);
+    assert_eq!(
+        by_addr[1].to_string(),
+        "1970-01-01 00:08:20 1970-01-01 00:50:00 www.example.com A 192.0.2.1"
+    );
+
+    assert!(passive::search(&path, "192.0.2.9").unwrap().is_empty());
+    let _ = fs::remove_file(&path);
+}
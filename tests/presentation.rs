@@ -0,0 +1,368 @@
+//! Records and packets in presentation format, as `decode`, `resolve` and `trace` print
+//! them.
+
+use diydns::{DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, CLASS_IN};
+
+fn mx(priority: u16, host: &str) -> DnsRecord {
+    DnsRecord::MX {
+        domain: "example.com".to_string(),
+        priority,
+        host: host.to_string(),
+        ttl: 300,
+    }
+}
+
+#[test]
+fn txt_strings_are_escaped() {
+    let rec = DnsRecord::TXT {
+        domain: "example.com".to_string(),
+        data: vec![
+            "say \"hi\" \\ bye".to_string(),
+            "tab\there".to_string(),
+            "café".to_string(),
+        ],
+        ttl: 60,
+    };
+
+    assert_eq!(
+        rec.to_string(),
+        "example.com. 60 IN TXT \"say \\\"hi\\\" \\\\ bye\" \"tab\\009here\" \"caf\\195\\169\""
+    );
+}
+
+#[test]
+fn aaaa_addresses_are_compressed() {
+    let rec = DnsRecord::AAAA {
+        domain: "example.com".to_string(),
+        addr: "2001:db8:0:0:0:0:0:1".parse().unwrap(),
+        ttl: 60,
+    };
+
+    assert_eq!(rec.to_string(), "example.com. 60 IN AAAA 2001:db8::1");
+}
+
+#[test]
+fn soa_fields_follow_zone_file_order() {
+    let rec = DnsRecord::SOA {
+        domain: "example.com".to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 2024010101,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 300,
+        ttl: 3600,
+    };
+
+    assert_eq!(
+        rec.to_string(),
+        "example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300"
+    );
+    assert_eq!(
+        rec.rdata().unwrap(),
+        "ns1.example.com hostmaster.example.com 2024010101 7200 900 1209600 300"
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.authorities.push(rec.clone());
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(DnsPacket::from_bytes(&bytes).unwrap().authorities, [rec]);
+    assert_eq!("soa".parse::<QueryType>().unwrap(), QueryType::SOA);
+}
+
+#[test]
+fn srv_targets_are_written_in_full() {
+    let srv = |host: &str| DnsRecord::SRV {
+        domain: "_sip._tcp.example.com".to_string(),
+        priority: 10,
+        weight: 60,
+        port: 5060,
+        host: host.to_string(),
+        ttl: 300,
+    };
+    let rec = srv("sip.example.com");
+    assert_eq!(
+        rec.to_string(),
+        "_sip._tcp.example.com. 300 IN SRV 10 60 5060 sip.example.com."
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec.clone());
+    packet.answers.push(srv(""));
+    let bytes = packet.to_bytes().unwrap();
+    let target = b"\x03sip\x07example\x03com\x00";
+    assert!(bytes.windows(target.len()).any(|w| w == target));
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+    assert!(packet.answers[1].to_string().ends_with("SRV 10 60 5060 ."));
+
+    // No service at all isn't a host to look up.
+    assert_eq!(packet.unresolved_targets(), ["sip.example.com"]);
+}
+
+#[test]
+fn naptr_rules_round_trip() {
+    let rec = DnsRecord::NAPTR {
+        domain: "4.3.2.1.5.5.5.e164.arpa".to_string(),
+        order: 100,
+        preference: 10,
+        flags: "S".to_string(),
+        services: "SIP+D2U".to_string(),
+        regexp: String::new(),
+        replacement: "_sip._udp.example.com".to_string(),
+        ttl: 300,
+    };
+    assert_eq!(
+        rec.to_string(),
+        "4.3.2.1.5.5.5.e164.arpa. 300 IN NAPTR 100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp.example.com."
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+    assert_eq!("naptr".parse::<QueryType>().unwrap(), QueryType::NAPTR);
+}
+
+#[test]
+fn tlsa_data_is_hex() {
+    let rec = DnsRecord::TLSA {
+        domain: "_443._tcp.example.com".to_string(),
+        usage: 3,
+        selector: 1,
+        matching_type: 1,
+        data: vec![0x0d, 0x6f, 0xce, 0x33, 0x68, 0xa3],
+        ttl: 300,
+    };
+    assert_eq!(
+        rec.to_string(),
+        "_443._tcp.example.com. 300 IN TLSA 3 1 1 0D6FCE3368A3"
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+    assert_eq!("tlsa".parse::<QueryType>().unwrap(), QueryType::TLSA);
+}
+
+#[test]
+fn sshfp_fingerprints_round_trip() {
+    let rec = DnsRecord::SSHFP {
+        domain: "host.example.com".to_string(),
+        algorithm: 4,
+        fingerprint_type: 2,
+        fingerprint: vec![0x9d, 0x4b, 0x5c, 0x2a],
+        ttl: 300,
+    };
+    assert_eq!(
+        rec.to_string(),
+        "host.example.com. 300 IN SSHFP 4 2 9D4B5C2A"
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+    assert_eq!("sshfp".parse::<QueryType>().unwrap(), QueryType::SSHFP);
+}
+
+#[test]
+fn nsec_type_bitmaps_round_trip() {
+    // RFC 4034's example.
+    let rec = DnsRecord::NSEC {
+        domain: "alfa.example.com".to_string(),
+        next_domain: "host.example.com".to_string(),
+        types: vec![1, 15, 46, 47, 1234],
+        ttl: 86400,
+    };
+    assert_eq!(
+        rec.to_string(),
+        "alfa.example.com. 86400 IN NSEC host.example.com. A MX RRSIG NSEC TYPE1234"
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    let mut bitmap = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1b];
+    bitmap.extend_from_slice(&[0; 26]);
+    bitmap.push(0x20);
+    assert!(bytes.ends_with(&bitmap));
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+
+    // Blocks out of order, after the next name's 18 bytes.
+    let rdata = bytes.len() - bitmap.len() - 18;
+    let mut backwards = bytes[..rdata + 18].to_vec();
+    backwards[rdata - 2..rdata].copy_from_slice(&24u16.to_be_bytes());
+    backwards.extend_from_slice(&[0x04, 0x01, 0x20, 0x00, 0x01, 0x40]);
+    let err = DnsPacket::from_bytes(&backwards).unwrap_err();
+    assert!(err.to_string().starts_with("bad NSEC data"), "{}", err);
+}
+
+#[test]
+fn nsec3_hashes_round_trip() {
+    let nsec3 = DnsRecord::NSEC3 {
+        domain: "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example".to_string(),
+        hash_algorithm: 1,
+        flags: 1,
+        iterations: 12,
+        salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+        // RFC 4648's test vector, hashes being opaque.
+        next_hashed_owner: b"foobar".to_vec(),
+        types: vec![1, 13, 46, 47],
+        ttl: 3600,
+    };
+    assert_eq!(
+        nsec3.rdata().unwrap(),
+        "1 1 12 AABBCCDD CPNMUOJ1E8 A TYPE13 RRSIG NSEC"
+    );
+
+    let param = DnsRecord::NSEC3PARAM {
+        domain: "example".to_string(),
+        hash_algorithm: 1,
+        flags: 0,
+        iterations: 0,
+        salt: Vec::new(),
+        ttl: 3600,
+    };
+    assert_eq!(param.to_string(), "example. 3600 IN NSEC3PARAM 1 0 0 -");
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(nsec3);
+    packet.authorities.push(param);
+    let bytes = packet.to_bytes().unwrap();
+    let decoded = DnsPacket::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.answers, packet.answers);
+    assert_eq!(decoded.authorities, packet.authorities);
+}
+
+#[test]
+fn caa_values_are_quoted() {
+    let rec = DnsRecord::CAA {
+        domain: "example.com".to_string(),
+        flags: 0,
+        tag: "iodef".to_string(),
+        value: "mailto:security@example.com".to_string(),
+        ttl: 300,
+    };
+    assert_eq!(
+        rec.to_string(),
+        "example.com. 300 IN CAA 0 iodef \"mailto:security@example.com\""
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+
+    // A record with no tag can't be read or written.
+    let data_start = bytes.len() - "mailto:security@example.com".len() - "iodef".len() - 2;
+    let mut no_tag = bytes[..data_start].to_vec();
+    no_tag[data_start - 1] = 2;
+    no_tag.extend_from_slice(&[0, 0]);
+    assert!(DnsPacket::from_bytes(&no_tag).is_err());
+    if let DnsRecord::CAA { ref mut tag, .. } = packet.answers[0] {
+        tag.clear();
+    }
+    assert!(packet.to_bytes().is_err());
+}
+
+#[test]
+fn edns_fields_round_trip() {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::BadVersion;
+    packet.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: "192.0.2.1".parse().unwrap(),
+        ttl: 300,
+    });
+    packet.resources.push(DnsRecord::OPT {
+        udp_payload_size: 1232,
+        extended_rcode: 1,
+        version: 0,
+        flags: 0x8000,
+        options: vec![(10, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])],
+    });
+    packet.header.answers = 1;
+    packet.header.resource_entries = 1;
+
+    let bytes = packet.to_bytes().unwrap();
+    // Root name, type 41, the payload size as its class and the rest in its TTL.
+    let opt = &bytes[bytes.len() - 23..];
+    assert_eq!(opt[..11], [0, 0, 41, 0x04, 0xD0, 1, 0, 0x80, 0, 0, 12]);
+    // The header has the low four bits of BADVERS, 16, which are all zero.
+    assert_eq!(bytes[3] & 0x0F, 0);
+    let parsed = DnsPacket::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed.header.rescode, ResultCode::BadVersion);
+    assert_eq!(parsed.resources, packet.resources);
+
+    assert_eq!(
+        packet.to_string(),
+        ";; opcode: 0, status: BADVERS, id: 0\n\
+         ;; flags: qr; QUERY: 0, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1\n\
+         \n\
+         ;; OPT PSEUDOSECTION:\n\
+         ; EDNS: version: 0, flags: do; udp: 1232; option 10: 0102030405060708\n\
+         \n\
+         ;; ANSWER SECTION:\n\
+         example.com. 300 IN A 192.0.2.1 ; 5m\n"
+    );
+
+    // Without an OPT record there's nowhere for the upper bits to go.
+    packet.resources.clear();
+    assert!(packet.to_bytes().is_err());
+}
+
+#[test]
+fn packets_print_like_dig_with_mx_by_priority() {
+    let mut packet = DnsPacket::default();
+    packet.header.id = 4660;
+    packet.header.response = true;
+    packet.header.recursion_desired = true;
+    packet.header.rescode = ResultCode::Success;
+    packet.questions.push(DnsQuestion {
+        name: "example.com".to_string(),
+        qtype: QueryType::MX,
+        qclass: CLASS_IN,
+    });
+    packet.answers = vec![
+        mx(30, "backup.example.com"),
+        mx(10, "mail.example.com"),
+        mx(20, "mail2.example.com"),
+    ];
+    packet.header.questions = 1;
+    packet.header.answers = 3;
+
+    assert_eq!(
+        packet.to_string(),
+        ";; opcode: 0, status: NOERROR, id: 4660\n\
+         ;; flags: qr rd; QUERY: 1, ANSWER: 3, AUTHORITY: 0, ADDITIONAL: 0\n\
+         \n\
+         ;; QUESTION SECTION:\n\
+         ;example.com. IN MX\n\
+         \n\
+         ;; ANSWER SECTION:\n\
+         example.com. 300 IN MX 10 mail.example.com. ; 5m\n\
+         example.com. 300 IN MX 20 mail2.example.com. ; 5m\n\
+         example.com. 300 IN MX 30 backup.example.com. ; 5m\n"
+    );
+}
@@ -0,0 +1,48 @@
+//! Comparing what the name servers of a zone say.
+
+#![cfg(feature = "resolver")]
+
+use diydns::propagation::{Propagation, ServerView};
+use diydns::DnsPacket;
+use std::io::{Error, ErrorKind};
+
+fn view(last: u8, serial: Option<u32>) -> ServerView {
+    ServerView {
+        host: serial.map(|_| format!("ns{}.example.com", last)),
+        addr: [192, 0, 2, last].into(),
+        response: Ok(DnsPacket::default()),
+        serial,
+    }
+}
+
+fn propagation(views: Vec<ServerView>) -> Propagation {
+    Propagation {
+        zone: "example.com".to_string(),
+        views,
+        wildcard: false,
+    }
+}
+
+#[test]
+fn matching_serials() {
+    let propagation = propagation(vec![
+        view(1, Some(2024010102)),
+        view(2, Some(2024010102)),
+        view(3, None),
+    ]);
+    assert!(propagation.serial_mismatch().is_empty());
+}
+
+#[test]
+fn a_secondary_behind_the_primary() {
+    let mut propagation = propagation(vec![
+        view(1, Some(2024010102)),
+        view(2, Some(2024010101)),
+        view(3, Some(2024010102)),
+    ]);
+    propagation.views.push(ServerView {
+        response: Err(Error::new(ErrorKind::TimedOut, "timed out")),
+        ..view(4, None)
+    });
+    assert_eq!(propagation.serial_mismatch(), [2024010101, 2024010102]);
+}
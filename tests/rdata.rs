@@ -0,0 +1,102 @@
+//! Record types plugged in from outside the codec.
+
+use diydns::rdata::{self, RDataCodec};
+use diydns::{DnsError, DnsErrorKind, DnsPacket, DnsRecord, QueryType, Result};
+use std::io::{Error, ErrorKind};
+
+/// A weight and a label, as `<weight> <label>`.
+struct Weighted(&'static str);
+
+impl RDataCodec for Weighted {
+    fn mnemonic(&self) -> &str {
+        self.0
+    }
+
+    fn present(&self, data: &[u8]) -> Result<String> {
+        match data {
+            [weight, label @ ..] if !label.is_empty() && label.is_ascii() => {
+                Ok(format!("{} {}", weight, String::from_utf8_lossy(label)))
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "expected a weight and a label",
+            )),
+        }
+    }
+
+    fn parse(&self, text: &str) -> Result<Vec<u8>> {
+        let bad = || Error::new(ErrorKind::InvalidInput, "expected a weight and a label");
+        let (weight, label) = text.split_once(' ').ok_or_else(bad)?;
+        let mut data = vec![weight.parse().map_err(|_| bad())?];
+        data.extend_from_slice(label.as_bytes());
+        Ok(data)
+    }
+}
+
+fn response(qtype: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.answers.push(DnsRecord::Unknown {
+        domain: "example.com".to_string(),
+        qtype,
+        data_len: data.len() as u16,
+        data: data.to_vec(),
+        ttl: 300,
+    });
+    packet.to_bytes().unwrap()
+}
+
+#[test]
+fn parses_and_presents() {
+    rdata::register_rdata_codec(65280, Box::new(Weighted("WEIGHTED"))).unwrap();
+
+    assert_eq!(QueryType::from_num(65280).to_string(), "WEIGHTED");
+    assert_eq!(
+        "weighted".parse::<QueryType>().unwrap(),
+        QueryType::Unknown(65280)
+    );
+
+    let packet = DnsPacket::from_bytes(&response(65280, b"\x05blue")).unwrap();
+    assert_eq!(packet.answers[0].rdata().unwrap(), "5 blue");
+    assert_eq!(
+        packet.answers[0].to_string(),
+        "example.com. 300 IN WEIGHTED 5 blue"
+    );
+
+    let codec = rdata::codec(65280).unwrap();
+    assert_eq!(codec.parse("5 blue").unwrap(), b"\x05blue");
+}
+
+#[test]
+fn rejects_bad_data() {
+    rdata::register_rdata_codec(65281, Box::new(Weighted("WEIGHTED2"))).unwrap();
+
+    let error = DnsPacket::from_bytes(&response(65281, b"\x05")).unwrap_err();
+    let dns_error = DnsError::find(&error).unwrap();
+    assert_eq!(dns_error.kind(), DnsErrorKind::BadData { qtype: 65281 });
+    assert_eq!(
+        error.to_string(),
+        "bad WEIGHTED2 data at offset 0x23 in answer record 1"
+    );
+
+    // Types without a codec are taken as they come.
+    let packet = DnsPacket::from_bytes(&response(65282, b"\x05")).unwrap();
+    assert_eq!(packet.answers[0].rdata().unwrap(), "\\# 1 05");
+}
+
+#[test]
+fn registers_each_type_once() {
+    rdata::register_rdata_codec(65283, Box::new(Weighted("ONCE"))).unwrap();
+
+    let errors = [
+        (65283, "OTHER", "type 65283 already has a codec"),
+        (65284, "once", "ONCE already names type 65283"),
+        (1, "ADDRESS", "type 1 is A, which has native support"),
+        (65284, "mx", "\"MX\" can't name a record type"),
+        (65284, "TYPE99", "\"TYPE99\" can't name a record type"),
+    ];
+    for (qtype, mnemonic, expected) in errors.iter() {
+        let err = rdata::register_rdata_codec(*qtype, Box::new(Weighted(mnemonic))).unwrap_err();
+        assert_eq!(err.to_string(), *expected);
+    }
+}
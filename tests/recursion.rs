@@ -0,0 +1,107 @@
+//! Recursive lookups through a tree of stand-in servers on the loopback interface.
+
+#![cfg(feature = "resolver")]
+
+use diydns::resolver::Resolver;
+use diydns::{DnsPacket, DnsRecord, QueryType, ResultCode};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the slow name servers' addresses take to come back from the root.
+const SLOW: Duration = Duration::from_secs(3);
+
+/// Sockets on 127.0.0.1 and 127.0.0.2 with the same port, since every server in a tree is
+/// asked on the root's port.
+fn bind_pair() -> (UdpSocket, UdpSocket) {
+    loop {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = first.local_addr().unwrap().port();
+        if let Ok(second) = UdpSocket::bind(("127.0.0.2", port)) {
+            return (first, second);
+        }
+    }
+}
+
+/// Answers each query on `socket` on a thread of its own, with what `answer` makes of it.
+fn serve(socket: UdpSocket, answer: fn(&DnsPacket, &mut DnsPacket)) {
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let socket = socket.try_clone().unwrap();
+        thread::spawn(move || {
+            let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+            let mut response = DnsPacket::default();
+            response.header.id = request.header.id;
+            response.header.response = true;
+            response.header.rescode = ResultCode::Success;
+            response.questions = request.questions.clone();
+            response.header.questions = 1;
+            answer(&request, &mut response);
+            response.header.answers = response.answers.len() as u16;
+            response.header.authoritative_entries = response.authorities.len() as u16;
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src);
+        });
+    });
+}
+
+fn a(domain: &str, addr: [u8; 4]) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr: Ipv4Addr::from(addr),
+        ttl: 300,
+    }
+}
+
+/// The root delegates example.com to three name servers without glue, and knows their
+/// addresses itself, two of them only after a while.
+fn root(request: &DnsPacket, response: &mut DnsPacket) {
+    let name = request.questions[0].name.as_str();
+    match name {
+        "www.example.com" => {
+            for host in &["ns1.slow.test", "ns2.slow.test", "ns.fast.test"] {
+                response.authorities.push(DnsRecord::NS {
+                    domain: "example.com".to_string(),
+                    host: host.to_string(),
+                    ttl: 300,
+                });
+            }
+        }
+        "ns.fast.test" => {
+            response.header.authoritative_answer = true;
+            response.answers.push(a(name, [127, 0, 0, 2]));
+        }
+        _ => {
+            thread::sleep(SLOW);
+            response.header.authoritative_answer = true;
+            response.answers.push(a(name, [127, 0, 0, 3]));
+        }
+    }
+}
+
+fn example_com(request: &DnsPacket, response: &mut DnsPacket) {
+    response.header.authoritative_answer = true;
+    response
+        .answers
+        .push(a(&request.questions[0].name, [192, 0, 2, 80]));
+}
+
+#[test]
+fn unglued_name_servers_are_used_as_soon_as_one_resolves() {
+    let (root_socket, example_socket) = bind_pair();
+    let root_addr: SocketAddr = root_socket.local_addr().unwrap();
+    serve(root_socket, root);
+    serve(example_socket, example_com);
+
+    let resolver = Resolver::default().with_root(root_addr);
+    let started = Instant::now();
+    let response = resolver
+        .recursive_lookup("www.example.com", QueryType::A)
+        .unwrap();
+
+    assert_eq!(
+        response.answers,
+        vec![a("www.example.com", [192, 0, 2, 80])]
+    );
+    assert!(started.elapsed() < SLOW, "took {:?}", started.elapsed());
+}
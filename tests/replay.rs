@@ -0,0 +1,37 @@
+//! Replaying fuzzer inputs through the parser.
+
+use diydns::replay::{self, Outcome};
+use std::fs;
+use std::path::Path;
+
+fn corpus() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus"))
+}
+
+#[test]
+fn good_and_bad_inputs() {
+    let response = fs::read(corpus().join("response.bin")).unwrap();
+    assert_eq!(replay::replay(&response), Outcome::Parsed);
+
+    match replay::replay(&response[..20]) {
+        Outcome::Rejected(error) => assert!(error.contains("end of message"), "{}", error),
+        outcome => panic!("expected a rejection, got {:?}", outcome),
+    }
+}
+
+#[test]
+fn replaying_a_directory() {
+    let dir = std::env::temp_dir().join(format!("diydns-replay-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::copy(corpus().join("query.bin"), dir.join("a")).unwrap();
+    fs::copy(corpus().join("response.bin"), dir.join("b")).unwrap();
+    fs::write(dir.join("c"), [0u8; 3]).unwrap();
+    fs::write(dir.join("d"), [0u8; 5]).unwrap();
+
+    let summary = replay::replay_dir(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(summary.parsed, 2);
+    assert_eq!(summary.rejected.values().sum::<usize>(), 2);
+    assert!(summary.panics.is_empty());
+}
@@ -0,0 +1,87 @@
+//! Telling answers, negative answers, referrals and errors apart, however oddly the
+//! response is put together.
+
+use diydns::{DnsPacket, DnsRecord, ResponseKind, ResultCode};
+
+fn response(rescode: ResultCode) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.header.rescode = rescode;
+    packet
+}
+
+fn ns(domain: &str, host: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: domain.to_string(),
+        host: host.to_string(),
+        ttl: 3600,
+    }
+}
+
+fn soa(domain: &str) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: domain.to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 300,
+        ttl: 3600,
+    }
+}
+
+#[test]
+fn answers_and_errors() {
+    let mut answer = response(ResultCode::Success);
+    answer.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: [192, 0, 2, 1].into(),
+        ttl: 60,
+    });
+    assert_eq!(answer.kind(), ResponseKind::Answer);
+
+    assert_eq!(
+        response(ResultCode::NonexistantDomain).kind(),
+        ResponseKind::NxDomain
+    );
+    assert_eq!(response(ResultCode::Refused).kind(), ResponseKind::Error);
+    assert_eq!(response(ResultCode::FormError).kind(), ResponseKind::Error);
+}
+
+#[test]
+fn referrals_and_nodata() {
+    let mut referral = response(ResultCode::Success);
+    referral
+        .authorities
+        .push(ns("example.com", "a.iana-servers.net"));
+    assert_eq!(referral.kind(), ResponseKind::Referral);
+
+    let mut nodata = response(ResultCode::Success);
+    nodata.authorities.push(soa("example.com"));
+    assert_eq!(nodata.kind(), ResponseKind::NoData);
+
+    // An authoritative server listing its own name servers alongside the SOA isn't
+    // referring us anywhere.
+    nodata
+        .authorities
+        .push(ns("example.com", "a.iana-servers.net"));
+    assert_eq!(nodata.kind(), ResponseKind::NoData);
+
+    assert_eq!(response(ResultCode::Success).kind(), ResponseKind::NoData);
+}
+
+#[test]
+fn odd_packets_parse() {
+    // FORMERR without echoing the question.
+    let formerr = [0x12, 0x34, 0x80, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+    let packet = DnsPacket::from_bytes(&formerr).unwrap();
+    assert!(packet.questions.is_empty());
+    assert_eq!(packet.kind(), ResponseKind::Error);
+
+    // NOTAUTH, a response code from dynamic updates.
+    let notauth = [0x12, 0x34, 0x80, 0x09, 0, 0, 0, 0, 0, 0, 0, 0];
+    let packet = DnsPacket::from_bytes(&notauth).unwrap();
+    assert_eq!(packet.kind(), ResponseKind::Error);
+}
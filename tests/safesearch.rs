@@ -0,0 +1,49 @@
+//! Which names get sent to safe-search hosts, and matching clients by network.
+
+#![cfg(feature = "server")]
+
+use diydns::safesearch::{self, Network};
+
+#[test]
+fn targets() {
+    let cases = [
+        ("www.google.com", Some("forcesafesearch.google.com")),
+        ("google.de.", Some("forcesafesearch.google.com")),
+        ("WWW.Google.co.uk", Some("forcesafesearch.google.com")),
+        ("www.google.com.au", Some("forcesafesearch.google.com")),
+        ("www.youtube.com", Some("restrict.youtube.com")),
+        ("www.bing.com", Some("strict.bing.com")),
+        ("duckduckgo.com", Some("safe.duckduckgo.com")),
+        ("mail.google.com", None),
+        ("google.example.com", None),
+        ("forcesafesearch.google.com", None),
+        ("example.com", None),
+    ];
+
+    for (name, expected) in cases.iter() {
+        assert_eq!(safesearch::target(name), *expected, "{}", name);
+    }
+}
+
+#[test]
+fn networks() {
+    let net: Network = "10.0.0.0/24".parse().unwrap();
+    assert!(net.contains("10.0.0.200".parse().unwrap()));
+    assert!(!net.contains("10.0.1.1".parse().unwrap()));
+    assert!(!net.contains("::1".parse().unwrap()));
+
+    let net: Network = "192.168.1.128/25".parse().unwrap();
+    assert!(net.contains("192.168.1.130".parse().unwrap()));
+    assert!(!net.contains("192.168.1.127".parse().unwrap()));
+
+    let net: Network = "fd00::/8".parse().unwrap();
+    assert!(net.contains("fd12::1".parse().unwrap()));
+
+    let host: Network = "10.0.0.5".parse().unwrap();
+    assert_eq!(host.prefix, 32);
+    assert!(host.contains("10.0.0.5".parse().unwrap()));
+    assert!(!host.contains("10.0.0.6".parse().unwrap()));
+
+    assert!("10.0.0.0/33".parse::<Network>().is_err());
+    assert!("kids".parse::<Network>().is_err());
+}
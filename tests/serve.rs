@@ -0,0 +1,462 @@
+//! Queries to a running server on the loopback interface, with and without recursion.
+
+#![cfg(feature = "server")]
+
+use diydns::config::Config;
+use diydns::resolver::Resolver;
+use diydns::server;
+use diydns::zone::Zone;
+use diydns::{
+    DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, EDNS_DO, MAX_EDNS_PACKET_SIZE,
+    MAX_PACKET_SIZE,
+};
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `serve` with `config` on a free loopback port, for as long as the test runs.
+fn start(mut config: Config) -> SocketAddr {
+    let addr = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    config.bind = addr;
+    thread::spawn(move || server::serve(&Resolver::default(), &config));
+    addr
+}
+
+/// An upstream that answers every A query with 192.0.2.1, counting the queries it gets.
+fn fake_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counted = queries.clone();
+
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        counted.fetch_add(1, Ordering::SeqCst);
+
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.header.recursion_available = true;
+        response.questions = request.questions.clone();
+        response.header.questions = 1;
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: [192, 0, 2, 1].into(),
+            ttl: 300,
+        });
+        response.header.answers = 1;
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    (addr, queries)
+}
+
+/// An upstream that answers every A query with 192.0.2.1 and, if the query has the DO bit
+/// set, the record's signature.
+fn signing_upstream() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let name = request.questions[0].name.clone();
+
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.header.recursion_available = true;
+        response.questions = request.questions.clone();
+        response.answers.push(DnsRecord::A {
+            domain: name.clone(),
+            addr: [192, 0, 2, 1].into(),
+            ttl: 300,
+        });
+        if opt_flags(&request).is_some_and(|flags| flags & EDNS_DO != 0) {
+            response.answers.push(DnsRecord::RRSIG {
+                domain: name,
+                type_covered: QueryType::A.to_num(),
+                algorithm: 13,
+                labels: 3,
+                original_ttl: 300,
+                expiration: 1_900_000_000,
+                inception: 1_700_000_000,
+                key_tag: 12345,
+                signer: "example.com".to_string(),
+                signature: vec![1; 64],
+                ttl: 300,
+            });
+        }
+        response.resources = request.resources;
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    addr
+}
+
+/// Asks `server` for `name`, retrying until `done` is happy with the response or a few
+/// seconds are up, since the server and what it publishes take a moment to start.
+fn query(server: SocketAddr, name: &str, rd: bool, done: impl Fn(&DnsPacket) -> bool) -> DnsPacket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+
+    let mut request = DnsPacket::default();
+    request.header.id = 77;
+    request.header.recursion_desired = rd;
+    request.header.questions = 1;
+    request.questions.push(DnsQuestion {
+        name: name.to_string(),
+        qtype: QueryType::A,
+        qclass: 1,
+    });
+    let request = request.to_bytes().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut last = None;
+    while Instant::now() < deadline {
+        socket.send_to(&request, server).unwrap();
+        let mut buf = [0; 512];
+        if let Ok(len) = socket.recv(&mut buf) {
+            let response = DnsPacket::from_bytes(&buf[..len]).unwrap();
+            if done(&response) {
+                return response;
+            }
+            last = Some(response);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    last.expect("no response from the server")
+}
+
+/// Sends `request` to `server` until it answers, and returns the answer as it came.
+fn exchange(server: SocketAddr, request: &DnsPacket) -> Vec<u8> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let request = request.to_bytes().unwrap();
+
+    for _ in 0..25 {
+        socket.send_to(&request, server).unwrap();
+        let mut buf = [0; MAX_EDNS_PACKET_SIZE];
+        if let Ok(len) = socket.recv(&mut buf) {
+            return buf[..len].to_vec();
+        }
+    }
+    panic!("no response from the server");
+}
+
+/// A recursive query for `name`'s A records, with an OPT record for `edns`, its payload size and
+/// version, if there is one.
+fn edns_query(name: &str, edns: Option<(u16, u8)>) -> DnsPacket {
+    edns_query_with(name, edns, 0)
+}
+
+/// Like [`edns_query`], with `flags` in the OPT record.
+fn edns_query_with(name: &str, edns: Option<(u16, u8)>, flags: u16) -> DnsPacket {
+    let mut request = DnsPacket::default();
+    request.header.id = 78;
+    request.header.recursion_desired = true;
+    request.questions.push(DnsQuestion {
+        name: name.to_string(),
+        qtype: QueryType::A,
+        qclass: 1,
+    });
+    if let Some((udp_payload_size, version)) = edns {
+        request.resources.push(DnsRecord::OPT {
+            udp_payload_size,
+            extended_rcode: 0,
+            version,
+            flags,
+            options: Vec::new(),
+        });
+    }
+    request
+}
+
+fn opt_flags(packet: &DnsPacket) -> Option<u16> {
+    packet.resources.iter().find_map(|rec| match *rec {
+        DnsRecord::OPT { flags, .. } => Some(flags),
+        _ => None,
+    })
+}
+
+fn opt_payload_size(response: &DnsPacket) -> Option<u16> {
+    response.resources.iter().find_map(|rec| match *rec {
+        DnsRecord::OPT {
+            udp_payload_size, ..
+        } => Some(udp_payload_size),
+        _ => None,
+    })
+}
+
+fn addrs(response: &DnsPacket) -> Vec<String> {
+    response
+        .answers
+        .iter()
+        .filter_map(|rec| match rec {
+            DnsRecord::A { addr, .. } => Some(addr.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn leases_are_answered_without_recursion() {
+    let path = std::env::temp_dir().join(format!("diydns-serve-{}.leases", std::process::id()));
+    fs::write(&path, "0 aa:bb:cc:dd:ee:01 192.168.1.10 laptop *\n").unwrap();
+
+    let server = start(Config {
+        recursion: false,
+        dhcp_leases: Some(path.clone()),
+        ..Config::default()
+    });
+
+    for &rd in &[false, true] {
+        let response = query(server, "laptop.lan", rd, |r| !r.answers.is_empty());
+        assert_eq!(response.header.rescode, ResultCode::Success);
+        assert!(response.header.authoritative_answer);
+        assert_eq!(addrs(&response), ["192.168.1.10"]);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn changed_zone_files_are_reloaded() {
+    let path = std::env::temp_dir().join(format!("diydns-serve-{}.zone", std::process::id()));
+    let version = |serial: u32, addr: &str| {
+        format!(
+            "@ SOA ns1 host {} 2h 15m 14d 5m\n@ NS ns1\nns1 A 192.0.2.1\nwww A {}\n",
+            serial, addr
+        )
+    };
+    fs::write(&path, version(1, "192.0.2.10")).unwrap();
+    let server = start(Config {
+        recursion: false,
+        zones: vec![Zone::load("example.com", &path).unwrap()],
+        ..Config::default()
+    });
+    let response = query(server, "www.example.com", false, |r| !r.answers.is_empty());
+    assert_eq!(addrs(&response), ["192.0.2.10"]);
+
+    // Without a later serial the change is left alone; with one it's served.
+    fs::write(&path, version(1, "192.0.2.11")).unwrap();
+    thread::sleep(Duration::from_secs(3));
+    let response = query(server, "www.example.com", false, |r| !r.answers.is_empty());
+    assert_eq!(addrs(&response), ["192.0.2.10"]);
+
+    fs::write(&path, version(2, "192.0.2.12")).unwrap();
+    let response = query(server, "www.example.com", false, |r| {
+        addrs(r) == ["192.0.2.12"]
+    });
+    assert_eq!(addrs(&response), ["192.0.2.12"]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn synthetic_domains_are_answered_without_recursion() {
+    let server = start(Config {
+        recursion: false,
+        synth: vec![
+            "lab 10.0.0.7".parse().unwrap(),
+            "nip.io ip".parse().unwrap(),
+        ],
+        ..Config::default()
+    });
+
+    for &rd in &[false, true] {
+        let response = query(server, "web.lab", rd, |r| !r.answers.is_empty());
+        assert!(response.header.authoritative_answer);
+        assert_eq!(addrs(&response), ["10.0.0.7"]);
+
+        let response = query(server, "app.10-0-0-8.nip.io", rd, |r| !r.answers.is_empty());
+        assert_eq!(addrs(&response), ["10.0.0.8"]);
+    }
+}
+
+#[test]
+fn cached_answers_are_given_without_rd() {
+    let (upstream, queries) = fake_upstream();
+    let server = start(Config {
+        upstream: vec![upstream],
+        cache: true,
+        ..Config::default()
+    });
+
+    // Nothing cached yet, so there's only the referral.
+    let response = query(server, "www.example.com", false, |_| true);
+    assert!(response.answers.is_empty());
+
+    let response = query(server, "www.example.com", true, |r| !r.answers.is_empty());
+    assert_eq!(addrs(&response), ["192.0.2.1"]);
+    let forwarded = queries.load(Ordering::SeqCst);
+
+    let response = query(server, "www.example.com", false, |r| !r.answers.is_empty());
+    assert_eq!(addrs(&response), ["192.0.2.1"]);
+    assert_eq!(queries.load(Ordering::SeqCst), forwarded);
+}
+
+#[test]
+fn other_names_are_referred_to_the_root() {
+    let (upstream, queries) = fake_upstream();
+    let server = start(Config {
+        upstream: vec![upstream],
+        recursion: false,
+        ..Config::default()
+    });
+
+    for &rd in &[false, true] {
+        let response = query(server, "www.example.com", rd, |_| true);
+        assert_eq!(response.header.rescode, ResultCode::Success);
+        assert!(!response.header.recursion_available);
+        assert!(response.answers.is_empty());
+        assert!(!response.authorities.is_empty());
+        assert!(response
+            .authorities
+            .iter()
+            .all(|rec| matches!(rec, DnsRecord::NS { domain, .. } if domain.is_empty())));
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 0);
+}
+
+/// example.com with 40 addresses for big, which come to about 700 bytes, and 100 for huge,
+/// about 1700.
+fn big_answers() -> Zone {
+    let mut zone = String::new();
+    for i in 1..=100 {
+        if i <= 40 {
+            zone.push_str(&format!("big A 192.0.2.{}\n", i));
+        }
+        zone.push_str(&format!("huge A 192.0.2.{}\n", i));
+    }
+    Zone::parse("example.com", &zone).unwrap()
+}
+
+#[test]
+fn responses_fit_the_payload_size_the_client_advertised() {
+    let server = start(Config {
+        zones: vec![big_answers()],
+        ..Config::default()
+    });
+
+    // Without EDNS there's room for 512 bytes, and the client has to ask again over TCP.
+    let data = exchange(server, &edns_query("big.example.com", None));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() <= MAX_PACKET_SIZE);
+    assert!(response.header.truncated_message);
+    assert!(response.answers.is_empty());
+    assert_eq!(opt_payload_size(&response), None);
+
+    // With it there's room for as much as both sides advertise.
+    let data = exchange(server, &edns_query("big.example.com", Some((4096, 0))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() > MAX_PACKET_SIZE);
+    assert!(!response.header.truncated_message);
+    assert_eq!(response.answers.len(), 40);
+    assert_eq!(opt_payload_size(&response), Some(1232));
+
+    let data = exchange(server, &edns_query("huge.example.com", Some((4096, 0))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() <= 1232);
+    assert!(response.header.truncated_message);
+    assert_eq!(opt_payload_size(&response), Some(1232));
+}
+
+#[test]
+fn the_advertised_payload_size_can_be_set() {
+    let server = start(Config {
+        zones: vec![big_answers()],
+        edns_payload_size: 4096,
+        ..Config::default()
+    });
+    let data = exchange(server, &edns_query("huge.example.com", Some((4096, 0))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() > 1232);
+    assert!(!response.header.truncated_message);
+    assert_eq!(response.answers.len(), 100);
+    assert_eq!(opt_payload_size(&response), Some(4096));
+
+    let server = start(Config {
+        zones: vec![big_answers()],
+        edns_payload_size: 600,
+        ..Config::default()
+    });
+    let data = exchange(server, &edns_query("big.example.com", Some((4096, 0))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() <= 600);
+    assert!(response.header.truncated_message);
+    assert_eq!(opt_payload_size(&response), Some(600));
+
+    // Config::set won't take less than 512, but a Config built in code can hold anything.
+    let server = start(Config {
+        zones: vec![big_answers()],
+        edns_payload_size: 100,
+        ..Config::default()
+    });
+    let data = exchange(server, &edns_query("big.example.com", Some((4096, 0))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert!(data.len() <= MAX_PACKET_SIZE);
+    assert!(response.header.truncated_message);
+    assert_eq!(opt_payload_size(&response), Some(512));
+}
+
+#[test]
+fn newer_edns_versions_get_badvers() {
+    let server = start(Config::default());
+
+    let data = exchange(server, &edns_query("www.example.com", Some((1232, 1))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::BadVersion);
+    assert_eq!(opt_payload_size(&response), Some(1232));
+
+    // BADVERS needs our OPT record, so it goes even to clients shaped to go without EDNS.
+    let server = start(Config {
+        shapes: vec!["127.0.0.0/8 no-edns".parse().unwrap()],
+        ..Config::default()
+    });
+    let data = exchange(server, &edns_query("www.example.com", Some((1232, 1))));
+    let response = DnsPacket::from_bytes(&data).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::BadVersion);
+    assert_eq!(opt_payload_size(&response), Some(1232));
+}
+
+#[test]
+fn dnssec_records_go_only_to_clients_that_set_do() {
+    let server = start(Config {
+        upstream: vec![signing_upstream()],
+        cache: true,
+        dnssec_passthrough: true,
+        ..Config::default()
+    });
+    let types = |response: &DnsPacket| {
+        response
+            .answers
+            .iter()
+            .map(|rec| rec.query_type())
+            .collect::<Vec<_>>()
+    };
+
+    let signed = edns_query_with("www.example.com", Some((1232, 0)), EDNS_DO);
+    let response = DnsPacket::from_bytes(&exchange(server, &signed)).unwrap();
+    assert_eq!(types(&response), [QueryType::A, QueryType::RRSIG]);
+    assert_eq!(opt_flags(&response), Some(EDNS_DO));
+
+    // The same answer again, from the cache, without the signature.
+    let unsigned = edns_query("www.example.com", Some((1232, 0)));
+    let response = DnsPacket::from_bytes(&exchange(server, &unsigned)).unwrap();
+    assert_eq!(types(&response), [QueryType::A]);
+    assert_eq!(opt_flags(&response), Some(0));
+}
@@ -0,0 +1,128 @@
+//! Shaping responses for groups of clients.
+
+#![cfg(feature = "server")]
+
+use diydns::shaping::{self, Action, Group, Shape};
+use diydns::{DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, Section, WirePacket};
+
+fn opt() -> DnsRecord {
+    DnsRecord::OPT {
+        udp_payload_size: 1232,
+        extended_rcode: 0,
+        version: 0,
+        flags: 0,
+        options: Vec::new(),
+    }
+}
+
+fn a(name: &str, addr: &str) -> DnsRecord {
+    DnsRecord::A {
+        domain: name.to_string(),
+        addr: addr.parse().unwrap(),
+        ttl: 300,
+    }
+}
+
+/// An answer for www.example.com with the name servers and their addresses thrown in, and
+/// an OPT record where `opt_at` says among the additional records.
+fn response(opt_at: usize) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.header.authed_data = true;
+    packet.questions.push(DnsQuestion {
+        name: "www.example.com".to_string(),
+        qtype: QueryType::A,
+        qclass: 1,
+    });
+    packet.answers.push(a("www.example.com", "192.0.2.1"));
+    packet.authorities.push(DnsRecord::NS {
+        domain: "example.com".to_string(),
+        host: "ns1.example.com".to_string(),
+        ttl: 300,
+    });
+    packet.resources.push(a("ns1.example.com", "192.0.2.53"));
+    packet.resources.push(a("ns1.example.com", "192.0.2.54"));
+    packet.resources.insert(opt_at, opt());
+    packet
+}
+
+#[test]
+fn parses_shapes() {
+    let shape: Shape = "10.0.5.0/24 no-edns no-ad".parse().unwrap();
+    assert!(matches!(shape.group, Group::Clients(_)));
+    assert_eq!(shape.actions, [Action::NoEdns, Action::NoAd]);
+
+    let shape: Shape = "iot minimal".parse().unwrap();
+    assert_eq!(shape.group, Group::Listener("iot".to_string()));
+
+    assert!("iot".parse::<Shape>().is_err());
+    assert!("iot tiny".parse::<Shape>().is_err());
+    assert!("10.0.5.0/33 minimal".parse::<Shape>().is_err());
+}
+
+#[test]
+fn groups_add_up() {
+    let shapes: Vec<Shape> = ["iot minimal", "10.0.5.0/24 no-ad minimal"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+
+    let client = "10.0.5.9".parse().unwrap();
+    assert_eq!(
+        shaping::actions(&shapes, "iot", client),
+        [Action::Minimal, Action::NoAd]
+    );
+    assert_eq!(
+        shaping::actions(&shapes, "default", "10.0.6.1".parse().unwrap()),
+        []
+    );
+}
+
+#[test]
+fn minimal_answers_keep_only_the_answer() {
+    let mut packet = response(2);
+    shaping::apply(&mut packet, &[Action::Minimal, Action::NoAd]);
+    assert!(!packet.header.authed_data);
+    assert!(packet.authorities.is_empty());
+    assert_eq!(packet.resources, [opt()]);
+
+    // Referrals and negative answers need what's in the other sections.
+    let mut nxdomain = response(2);
+    nxdomain.header.rescode = ResultCode::NonexistantDomain;
+    nxdomain.answers.clear();
+    shaping::apply(&mut nxdomain, &[Action::Minimal, Action::NoEdns]);
+    assert_eq!(nxdomain.authorities.len(), 1);
+    assert_eq!(nxdomain.resources.len(), 2);
+}
+
+#[test]
+fn relayed_bytes_are_cut_rather_than_rebuilt() {
+    let bytes = response(2).to_bytes().unwrap();
+    let wire = WirePacket::from_bytes(&bytes).unwrap();
+    let answer_end = wire.span(Section::Answer, 0).unwrap().record.end;
+    let opt_bytes = wire.record_bytes(Section::Additional, 2).unwrap();
+
+    let minimal = shaping::apply_bytes(bytes.clone(), &[Action::Minimal]);
+    assert_eq!(minimal[12..answer_end], bytes[12..answer_end]);
+    assert_eq!(&minimal[answer_end..], opt_bytes);
+    let parsed = DnsPacket::from_bytes(&minimal).unwrap();
+    assert!(parsed.header.authed_data);
+    assert!(parsed.authorities.is_empty());
+    assert_eq!(parsed.resources, [opt()]);
+
+    let no_edns = shaping::apply_bytes(bytes.clone(), &[Action::NoEdns, Action::NoAd]);
+    assert_eq!(no_edns[12..], bytes[12..no_edns.len()]);
+    let parsed = DnsPacket::from_bytes(&no_edns).unwrap();
+    assert!(!parsed.header.authed_data);
+    assert_eq!(parsed.resources.len(), 2);
+
+    // An OPT record in front of others means re-encoding, with the same records.
+    let bytes = response(0).to_bytes().unwrap();
+    let parsed = DnsPacket::from_bytes(&shaping::apply_bytes(bytes, &[Action::NoEdns])).unwrap();
+    assert_eq!(parsed.resources, response(0).resources[1..]);
+
+    assert_eq!(
+        shaping::apply_bytes(vec![1, 2, 3], &[Action::Minimal]),
+        [1, 2, 3]
+    );
+}
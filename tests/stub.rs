@@ -0,0 +1,66 @@
+//! Taking over resolv.conf for `serve --stub` and handing it back.
+
+#![cfg(feature = "server")]
+
+use diydns::stub::ResolvConfTakeover;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("diydns-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+const ORIGINAL: &str = "search example.com\nnameserver 192.0.2.1\noptions ndots:2\n";
+
+#[test]
+fn takeover_rewrites_and_restores() {
+    let dir = scratch_dir("takeover");
+    let path = dir.join("resolv.conf");
+    fs::write(&path, ORIGINAL).unwrap();
+
+    let server: IpAddr = "127.0.0.53".parse().unwrap();
+    let takeover = ResolvConfTakeover::new(&path, server).unwrap();
+
+    let rewritten = fs::read_to_string(&path).unwrap();
+    assert!(rewritten.contains("nameserver 127.0.0.53\n"));
+    assert!(!rewritten.contains("192.0.2.1"));
+    assert!(rewritten.contains("search example.com\n"));
+    assert!(rewritten.contains("options ndots:2\n"));
+
+    // A second takeover would lose the original.
+    assert!(ResolvConfTakeover::new(&path, server).is_err());
+
+    drop(takeover);
+    assert_eq!(fs::read_to_string(&path).unwrap(), ORIGINAL);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn takeover_restores_symlink() {
+    let dir = scratch_dir("takeover-link");
+    let target = dir.join("managed.conf");
+    let path = dir.join("resolv.conf");
+    fs::write(&target, ORIGINAL).unwrap();
+    std::os::unix::fs::symlink(&target, &path).unwrap();
+
+    let takeover = ResolvConfTakeover::new(&path, "127.0.0.53".parse().unwrap()).unwrap();
+    assert!(!fs::symlink_metadata(&path)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), ORIGINAL);
+
+    drop(takeover);
+    assert_eq!(fs::read_link(&path).unwrap(), target);
+    assert_eq!(fs::read_to_string(&path).unwrap(), ORIGINAL);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
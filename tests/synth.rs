@@ -0,0 +1,103 @@
+//! Answers for synthetic domains.
+
+#![cfg(feature = "server")]
+
+use diydns::synth::{self, Synthesizer, SyntheticDomain};
+use diydns::{DnsRecord, QueryType, ResultCode};
+
+#[test]
+fn fixed_address() {
+    let domains: Vec<SyntheticDomain> = vec!["lab 10.0.0.7".parse().unwrap()];
+    assert_eq!(
+        domains[0].synthesizer,
+        Synthesizer::Fixed("10.0.0.7".parse().unwrap())
+    );
+
+    let domain = synth::find(&domains, "Web.Lab.").unwrap();
+    let (rescode, answers) = domain.answer("web.lab", QueryType::A).unwrap();
+    assert_eq!(rescode, ResultCode::Success);
+    assert_eq!(answers[0].rdata().as_deref(), Some("10.0.0.7"));
+
+    let (rescode, answers) = domain.answer("web.lab", QueryType::AAAA).unwrap();
+    assert_eq!((rescode, answers.len()), (ResultCode::Success, 0));
+
+    assert!(synth::find(&domains, "lab.example.com").is_none());
+    assert!("lab".parse::<SyntheticDomain>().is_err());
+    assert!("lab not-an-address".parse::<SyntheticDomain>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn script() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("diydns-synth-{}.sh", std::process::id()));
+    fs::write(
+        &path,
+        "#!/bin/sh\n\
+         case \"$1\" in\n\
+           missing.dev) exit 1 ;;\n\
+           *) echo \"A 10.1.2.3\"; echo \"TXT asked for $2\" ;;\n\
+         esac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let domain: SyntheticDomain = format!("dev exec:{}", path.display()).parse().unwrap();
+
+    let (rescode, answers) = domain.answer("api.dev", QueryType::TXT).unwrap();
+    assert_eq!(rescode, ResultCode::Success);
+    assert_eq!(
+        answers,
+        vec![DnsRecord::TXT {
+            domain: "api.dev".to_string(),
+            data: vec!["asked for TXT".to_string()],
+            ttl: 60,
+        }]
+    );
+
+    let (rescode, _) = domain.answer("missing.dev", QueryType::A).unwrap();
+    assert_eq!(rescode, ResultCode::NonexistantDomain);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn embedded_ip() {
+    let cases = [
+        ("10.0.0.7", Some("10.0.0.7")),
+        ("app.10.0.0.7", Some("10.0.0.7")),
+        ("10-0-0-7", Some("10.0.0.7")),
+        ("app.10-0-0-7", Some("10.0.0.7")),
+        ("app-10-0-0-7", Some("10.0.0.7")),
+        ("app-0a000007", Some("10.0.0.7")),
+        ("fd00--7", Some("fd00::7")),
+        ("www.2001-db8--1", Some("2001:db8::1")),
+        ("app", None),
+        ("1.2.3", None),
+        ("10-0-0-256", None),
+    ];
+
+    for (prefix, expected) in cases.iter() {
+        assert_eq!(
+            synth::embedded_ip(prefix),
+            expected.map(|addr| addr.parse().unwrap()),
+            "{}",
+            prefix
+        );
+    }
+
+    let domain: SyntheticDomain = "nip.io ip".parse().unwrap();
+    let (_, answers) = domain.answer("app.10-0-0-7.nip.io", QueryType::A).unwrap();
+    assert_eq!(answers[0].rdata().as_deref(), Some("10.0.0.7"));
+
+    let (_, answers) = domain.answer("fd00--7.nip.io", QueryType::AAAA).unwrap();
+    assert_eq!(answers[0].rdata().as_deref(), Some("fd00::7"));
+
+    let (rescode, answers) = domain.answer("nip.io", QueryType::A).unwrap();
+    assert_eq!((rescode, answers.len()), (ResultCode::Success, 0));
+
+    let (rescode, _) = domain.answer("app.nip.io", QueryType::A).unwrap();
+    assert_eq!(rescode, ResultCode::NonexistantDomain);
+}
@@ -0,0 +1,97 @@
+//! Running totals of the server's traffic, as `stats` hands them out and `top` shows them.
+
+#![cfg(feature = "server")]
+
+use diydns::control::{self, Context};
+use diydns::traffic::{self, Snapshot, Traffic};
+use diydns::ResultCode;
+use std::net::IpAddr;
+
+fn client(last: u8) -> IpAddr {
+    IpAddr::from([10, 0, 0, last])
+}
+
+fn traffic() -> Traffic {
+    let traffic = Traffic::new();
+    for _ in 0..3 {
+        traffic.record(
+            "default",
+            client(1),
+            "www.example.com.",
+            ResultCode::Success,
+        );
+    }
+    traffic.record("default", client(2), "WWW.example.com", ResultCode::Success);
+    traffic.record(
+        "guest",
+        client(2),
+        "nope.example.com",
+        ResultCode::NonexistantDomain,
+    );
+    traffic.record("guest", client(3), "example.org", ResultCode::Success);
+    traffic
+}
+
+#[test]
+fn totals_and_the_busiest() {
+    let snapshot = traffic().snapshot(2);
+
+    assert_eq!(snapshot.queries, 6);
+    assert_eq!(
+        snapshot.rescodes,
+        vec![(ResultCode::Success, 5), (ResultCode::NonexistantDomain, 1)]
+    );
+    assert_eq!(
+        snapshot.names,
+        vec![
+            ("www.example.com".to_string(), 4),
+            ("example.org".to_string(), 1)
+        ]
+    );
+    assert_eq!(snapshot.clients, vec![(client(1), 3), (client(2), 2)]);
+}
+
+#[test]
+fn every_listener_is_counted() {
+    let snapshot = traffic().snapshot(1);
+
+    assert_eq!(
+        snapshot.listeners,
+        vec![("default".to_string(), 4), ("guest".to_string(), 2)]
+    );
+}
+
+#[test]
+fn snapshots_survive_the_trip_through_the_socket() {
+    let snapshot = traffic().snapshot(10);
+    let text = control::execute(
+        Context {
+            traffic: Some(&traffic()),
+            ..Context::default()
+        },
+        "stats",
+    );
+
+    assert_eq!(text.parse::<Snapshot>().unwrap(), snapshot);
+    assert!(text.starts_with("queries 6\nrcode NOERROR 5\nrcode NXDOMAIN 1\n"));
+}
+
+#[test]
+fn stats_need_the_control_socket() {
+    assert_eq!(
+        control::execute(Context::default(), "stats"),
+        "error: stats are off\n"
+    );
+    assert!("queries 6\nbogus 1\n".parse::<Snapshot>().is_err());
+}
+
+#[test]
+fn render_shows_shares_and_rate() {
+    let screen = traffic::render(&traffic().snapshot(1), Some(2.5));
+
+    assert!(screen.starts_with("Queries: 6 total, 2.5/s\n"));
+    assert!(screen.contains(&format!("  {:<40} {:>10} {:>6.1}%\n", "NOERROR", 5, 83.3)));
+    assert!(screen.contains(&format!("  {:<40} {:>10}\n", "www.example.com", 4)));
+    assert!(screen.contains(&format!("  {:<40} {:>10}\n", "10.0.0.1", 3)));
+    assert!(screen.contains(&format!("Listeners\n  {:<40} {:>10}\n", "default", 4)));
+}
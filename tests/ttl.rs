@@ -0,0 +1,32 @@
+//! Reading and writing durations like `1h30m`.
+
+use diydns::ttl;
+
+#[test]
+fn parses_single_and_compound_durations() {
+    assert_eq!(ttl::parse("90").unwrap(), 90);
+    assert_eq!(ttl::parse("45s").unwrap(), 45);
+    assert_eq!(ttl::parse("1h30m").unwrap(), 5400);
+    assert_eq!(ttl::parse(" 2d ").unwrap(), 172_800);
+    assert_eq!(ttl::parse("1d2h3m4s").unwrap(), 93_784);
+}
+
+#[test]
+fn rejects_anything_else() {
+    for value in ["", "h", "1w", "1h30", "1.5h", "-5", "99999999999"] {
+        assert!(ttl::parse(value).is_err(), "{:?} parsed", value);
+    }
+}
+
+#[test]
+fn humanizes_largest_units_first() {
+    assert_eq!(ttl::humanize(0), "0s");
+    assert_eq!(ttl::humanize(45), "45s");
+    assert_eq!(ttl::humanize(5400), "1h30m");
+    assert_eq!(ttl::humanize(172_800), "2d");
+    assert_eq!(ttl::humanize(93_784), "1d2h3m4s");
+
+    for secs in [1, 59, 61, 3599, 86_401, 604_800] {
+        assert_eq!(ttl::parse(&ttl::humanize(secs)).unwrap(), secs);
+    }
+}
@@ -0,0 +1,99 @@
+//! Spotting DNS tunnel traffic.
+
+#![cfg(feature = "server")]
+
+use diydns::tunnel::{Limits, Suspicion, TunnelAction, TunnelDetector};
+use diydns::QueryType;
+
+fn client() -> std::net::IpAddr {
+    "10.0.0.5".parse().unwrap()
+}
+
+#[test]
+fn ordinary_names_pass() {
+    let detector = TunnelDetector::new(TunnelAction::Block, Limits::default());
+    for name in [
+        "www.example.com",
+        "mail.google.com.",
+        "r3---sn-ab5l6nzr.googlevideo.com",
+        "10.1.168.192.in-addr.arpa",
+        "my-load-balancer-primary.us-west-2.elb.amazonaws.com",
+    ]
+    .iter()
+    {
+        assert_eq!(detector.inspect(name, QueryType::A), None, "{}", name);
+    }
+}
+
+#[test]
+fn long_and_random_labels() {
+    let detector = TunnelDetector::new(TunnelAction::Block, Limits::default());
+
+    let long = format!("{}.t.example.com", "a".repeat(60));
+    assert_eq!(
+        detector.inspect(&long, QueryType::A),
+        Some(Suspicion::LongLabel(60))
+    );
+
+    let random = "t45ay57b2jfvlef.kcpep42znihqh6.t.example.com";
+    assert!(matches!(
+        detector.inspect(random, QueryType::A),
+        Some(Suspicion::HighEntropy(_))
+    ));
+    assert!(!detector.screen(client(), random, QueryType::A));
+}
+
+#[test]
+fn txt_volume() {
+    let limits = Limits {
+        txt_per_minute: 5,
+        ..Limits::default()
+    };
+    let detector = TunnelDetector::new(TunnelAction::Log, limits);
+
+    for i in 0..5 {
+        let name = format!("q{}.t.example.com", i);
+        assert_eq!(detector.inspect(&name, QueryType::TXT), None);
+    }
+    assert_eq!(
+        detector.inspect("q5.t.example.com", QueryType::Unknown(10)),
+        Some(Suspicion::TxtVolume(6))
+    );
+    // Other domains and types aren't affected.
+    assert_eq!(detector.inspect("example.org", QueryType::TXT), None);
+    assert_eq!(detector.inspect("q6.t.example.com", QueryType::A), None);
+
+    // Logging only, so it's still answered.
+    assert!(detector.screen(client(), "q7.t.example.com", QueryType::TXT));
+}
+
+#[test]
+fn rate_limit() {
+    let limits = Limits {
+        rate_limit: 3,
+        ..Limits::default()
+    };
+    let detector = TunnelDetector::new(TunnelAction::RateLimit, limits);
+    let long = |i: usize| format!("{}{}.t.example.com", "a".repeat(60), i);
+
+    let allowed: Vec<bool> = (0..5)
+        .map(|i| detector.screen(client(), &long(i), QueryType::A))
+        .collect();
+    assert_eq!(allowed, vec![true, true, true, false, false]);
+
+    assert!(detector.screen(
+        client(),
+        &format!("{}.example.org", "b".repeat(60)),
+        QueryType::A
+    ));
+    assert!(detector.screen(client(), "www.example.com", QueryType::A));
+}
+
+#[test]
+fn actions() {
+    assert_eq!(
+        "rate_limit".parse::<TunnelAction>().unwrap(),
+        TunnelAction::RateLimit
+    );
+    assert!("drop".parse::<TunnelAction>().is_err());
+}
@@ -0,0 +1,491 @@
+//! Sending queries to several upstreams, against stand-in servers on the loopback
+//! interface.
+
+#![cfg(feature = "resolver")]
+
+use diydns::resolver::{CancellationToken, QueryOptions, Resolver, Warnings};
+use diydns::{DnsPacket, DnsRecord, QueryType, ResultCode, Warning};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A server that answers every query with `rescode` and one A record after `delay`, or
+/// never answers if there's no `rescode`.
+fn fake_server(rescode: Option<ResultCode>, delay: Duration, addr: [u8; 4]) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let local = socket.local_addr().unwrap();
+
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let rescode = match rescode {
+            Some(rescode) => rescode,
+            None => continue,
+        };
+
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.header.rescode = rescode;
+        response.questions = request.questions.clone();
+        response.header.questions = 1;
+        if rescode == ResultCode::Success {
+            response.answers.push(DnsRecord::A {
+                domain: request.questions[0].name.clone(),
+                addr: addr.into(),
+                ttl: 60,
+            });
+            response.header.answers = 1;
+        }
+
+        thread::sleep(delay);
+        let _ = socket.send_to(&response.to_bytes().unwrap(), src);
+    });
+
+    local
+}
+
+#[test]
+fn race_takes_the_first_good_answer() {
+    let silent = fake_server(None, Duration::from_secs(0), [0, 0, 0, 0]);
+    let failing = fake_server(
+        Some(ResultCode::ServerFail),
+        Duration::from_secs(0),
+        [0, 0, 0, 0],
+    );
+    let slow = fake_server(
+        Some(ResultCode::Success),
+        Duration::from_millis(500),
+        [10, 0, 0, 2],
+    );
+    let fast = fake_server(
+        Some(ResultCode::Success),
+        Duration::from_millis(50),
+        [10, 0, 0, 1],
+    );
+
+    let resolver = Resolver::default();
+    let started = Instant::now();
+    let response = resolver
+        .race_with(
+            "example.com",
+            QueryType::A,
+            &QueryOptions::default(),
+            &[silent, failing, slow, fast],
+        )
+        .unwrap();
+
+    assert_eq!(response.header.rescode, ResultCode::Success);
+    assert_eq!(response.answers[0].rdata().as_deref(), Some("10.0.0.1"));
+    assert!(started.elapsed() < Duration::from_millis(400));
+}
+
+#[test]
+fn race_falls_back_to_a_failure() {
+    let failing = fake_server(
+        Some(ResultCode::ServerFail),
+        Duration::from_secs(0),
+        [0, 0, 0, 0],
+    );
+    let refusing = fake_server(
+        Some(ResultCode::Refused),
+        Duration::from_millis(20),
+        [0, 0, 0, 0],
+    );
+
+    let response = Resolver::default()
+        .race_with(
+            "example.com",
+            QueryType::A,
+            &QueryOptions::default(),
+            &[failing, refusing],
+        )
+        .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::Refused);
+}
+
+/// An address on the loopback interface with nothing listening, so queries to it get an
+/// ICMP port unreachable back.
+fn closed_port() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+#[test]
+fn an_unreachable_server_fails_without_waiting_out_the_timeout() {
+    let started = Instant::now();
+    let error = Resolver::default()
+        .lookup("example.com", QueryType::A, closed_port())
+        .unwrap_err();
+
+    assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn race_waits_on_the_others_when_one_is_unreachable() {
+    let good = fake_server(
+        Some(ResultCode::Success),
+        Duration::from_millis(100),
+        [10, 0, 0, 1],
+    );
+
+    let response = Resolver::default()
+        .race_with(
+            "example.com",
+            QueryType::A,
+            &QueryOptions::default(),
+            &[closed_port(), good],
+        )
+        .unwrap();
+    assert_eq!(response.answers[0].rdata().as_deref(), Some("10.0.0.1"));
+}
+
+#[test]
+fn responses_without_the_question_are_accepted() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        let mut response = DnsPacket::default();
+        response.header.id = u16::from_be_bytes([buf[0], buf[1]]);
+        response.header.response = true;
+        response.header.rescode = ResultCode::FormError;
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let response = Resolver::default()
+        .lookup("example.com", QueryType::A, server)
+        .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::FormError);
+    assert!(response.questions.is_empty());
+}
+
+#[test]
+fn queries_advertise_edns_and_take_large_responses() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let advertised = request.resources.iter().find_map(|rec| match *rec {
+            DnsRecord::OPT {
+                udp_payload_size, ..
+            } => Some(udp_payload_size),
+            _ => None,
+        });
+        assert_eq!(advertised, Some(1232));
+
+        // Some 700 bytes of answers.
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        for i in 1..=40 {
+            response.answers.push(DnsRecord::A {
+                domain: request.questions[0].name.clone(),
+                addr: [192, 0, 2, i].into(),
+                ttl: 60,
+            });
+        }
+        response.resources = request.resources;
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let response = Resolver::default()
+        .lookup("example.com", QueryType::A, server)
+        .unwrap();
+    assert_eq!(response.answers.len(), 40);
+}
+
+#[test]
+fn servers_that_time_out_are_offered_smaller_payloads() {
+    // A path that loses fragments: queries offering more than 512 bytes go unanswered.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    let (offered, sizes) = std::sync::mpsc::channel();
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let size = request.resources.iter().find_map(|rec| match *rec {
+            DnsRecord::OPT {
+                udp_payload_size, ..
+            } => Some(udp_payload_size),
+            _ => None,
+        });
+        offered.send(size).unwrap();
+        if size > Some(512) {
+            continue;
+        }
+
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: [192, 0, 2, 1].into(),
+            ttl: 60,
+        });
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let resolver = Resolver::default();
+    resolver
+        .lookup("example.com", QueryType::A, server)
+        .unwrap();
+    // The server is remembered, so the next lookup goes straight to 512 bytes.
+    let started = Instant::now();
+    resolver
+        .lookup("example.com", QueryType::A, server)
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    assert_eq!(
+        sizes.try_iter().collect::<Vec<_>>(),
+        [Some(1232), Some(512), Some(512)]
+    );
+}
+
+#[test]
+fn servers_that_answer_badvers_are_asked_without_edns() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    let (sent_opt, with_opt) = std::sync::mpsc::channel();
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let edns = !request.resources.is_empty();
+        sent_opt.send(edns).unwrap();
+
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        if edns {
+            response.header.rescode = ResultCode::BadVersion;
+            response.resources = request.resources;
+        } else {
+            response.answers.push(DnsRecord::A {
+                domain: request.questions[0].name.clone(),
+                addr: [192, 0, 2, 1].into(),
+                ttl: 60,
+            });
+        }
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let resolver = Resolver::default();
+    for _ in 0..2 {
+        let response = resolver
+            .lookup("example.com", QueryType::A, server)
+            .unwrap();
+        assert_eq!(response.header.rescode, ResultCode::Success);
+        assert_eq!(response.answers.len(), 1);
+    }
+    // Only the very first query carried EDNS.
+    assert_eq!(
+        with_opt.try_iter().collect::<Vec<_>>(),
+        [true, false, false]
+    );
+}
+
+#[test]
+fn correlated_queries_are_answered_as_usual() {
+    let server = fake_server(
+        Some(ResultCode::Success),
+        Duration::from_secs(0),
+        [10, 0, 0, 9],
+    );
+    let options = QueryOptions {
+        correlation: Some(42),
+        ..QueryOptions::default()
+    };
+
+    let response = Resolver::default()
+        .lookup_with("example.com", QueryType::A, &options, server)
+        .unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    let error = Resolver::default()
+        .lookup_with("example.com", QueryType::A, &options, closed_port())
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn clones_share_their_state() {
+    // Every query first gets a reply with the wrong id, then the real answer.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id.wrapping_add(1);
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+        response.header.id = request.header.id;
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let resolver = Resolver::default();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let resolver = resolver.clone();
+            thread::spawn(move || resolver.lookup("example.com", QueryType::A, server))
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    assert_eq!(resolver.stats().snapshot().wrong_id, 4);
+}
+
+#[test]
+fn cancelled_lookups_stop_waiting() {
+    let silent = fake_server(None, Duration::from_secs(0), [0, 0, 0, 0]);
+    let also_silent = fake_server(None, Duration::from_secs(0), [0, 0, 0, 0]);
+
+    let token = CancellationToken::new();
+    let resolver = Resolver::default().with_cancellation(&token);
+    let canceller = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+
+    let started = Instant::now();
+    let one = resolver.clone();
+    let single = thread::spawn(move || one.lookup("example.com", QueryType::A, silent));
+    let error = resolver
+        .race_with(
+            "example.com",
+            QueryType::A,
+            &QueryOptions::default(),
+            &[silent, also_silent],
+        )
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Interrupted);
+    assert_eq!(
+        single.join().unwrap().unwrap_err().kind(),
+        ErrorKind::Interrupted
+    );
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // Once cancelled, lookups fail without sending anything, but other resolvers carry on.
+    let error = resolver
+        .lookup("example.com", QueryType::A, silent)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Interrupted);
+    let server = fake_server(
+        Some(ResultCode::Success),
+        Duration::from_secs(0),
+        [10, 0, 0, 1],
+    );
+    assert!(Resolver::default()
+        .lookup("example.com", QueryType::A, server)
+        .is_ok());
+}
+
+#[test]
+fn warnings_are_collected() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.header.truncated_message = true;
+        response.questions = request.questions.clone();
+        for ttl in [60, 300].iter() {
+            response.answers.push(DnsRecord::A {
+                domain: "example.com".to_string(),
+                addr: [10, 0, 0, 1].into(),
+                ttl: *ttl,
+            });
+        }
+        socket.send_to(&response.to_bytes().unwrap(), src).unwrap();
+    });
+
+    let warnings = Warnings::new();
+    let resolver = Resolver::default().with_warnings(&warnings);
+    resolver
+        .lookup("example.com", QueryType::A, server)
+        .unwrap();
+
+    let collected = warnings.take();
+    assert_eq!(collected.len(), 2);
+    assert!(collected.iter().all(|(from, _)| *from == server));
+    assert!(matches!(collected[0].1, Warning::TtlMismatch { .. }));
+    assert_eq!(collected[1].1, Warning::Truncated);
+    assert!(warnings.take().is_empty());
+}
+
+#[test]
+fn relayed_responses_keep_their_bytes() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.local_addr().unwrap();
+
+    // Upper case the parser would fold, a flag we don't model and a record of a type we
+    // don't know, all of which a re-encoded response would lose.
+    let mut query = DnsPacket::default();
+    query.header.id = 4321;
+    query.header.recursion_desired = true;
+    query.header.questions = 1;
+    query.questions.push(diydns::DnsQuestion {
+        name: "Example.COM".to_string(),
+        qtype: QueryType::A,
+        qclass: 1,
+    });
+    let request = query.to_bytes().unwrap();
+
+    let mut answer = request.clone();
+    answer[2] |= 0x80;
+    answer[3] |= 0x40;
+    answer[7] = 1;
+    answer.extend_from_slice(&[0xC0, 12, 0xFF, 0x00, 0, 1, 0, 0, 0, 60, 0, 2, 0xAB, 0xCD]);
+
+    let reply = answer.clone();
+    let (sent, received) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (len, src) = socket.recv_from(&mut buf).unwrap();
+        let mut response = reply;
+        // A stray reply with the wrong id first, which is to be dropped.
+        response[..2].copy_from_slice(&[buf[0], buf[1] ^ 1]);
+        socket.send_to(&response, src).unwrap();
+        response[..2].copy_from_slice(&buf[..2]);
+        socket.send_to(&response, src).unwrap();
+        sent.send(buf[..len].to_vec()).unwrap();
+    });
+
+    let resolver = Resolver::default();
+    let relayed = resolver.relay(&request, &[server], None).unwrap();
+    assert_eq!(relayed, answer);
+    // Everything but the id went upstream as it was.
+    assert_eq!(received.recv().unwrap()[2..], request[2..]);
+    assert_eq!(resolver.stats().snapshot().wrong_id, 1);
+
+    assert_eq!(
+        resolver.relay(&[0; 4], &[server], None).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+}
@@ -0,0 +1,124 @@
+//! Listing zones by transfer and by NSEC walking, against stand-in servers on the
+//! loopback interface.
+
+#![cfg(feature = "resolver")]
+
+use diydns::resolver::Resolver;
+use diydns::walk;
+use diydns::{DnsHeader, DnsPacket, DnsRecord, MessageWriter, QueryType};
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+
+const ZONE: [&str; 4] = [
+    "example.com",
+    "a.example.com",
+    "mail.example.com",
+    "www.example.com",
+];
+
+/// The NSEC record at `name`, pointing to the next name in `ZONE` and round to the apex.
+fn nsec(name: &str) -> Option<DnsRecord> {
+    let index = ZONE.iter().position(|n| *n == name)?;
+
+    Some(DnsRecord::NSEC {
+        domain: name.to_string(),
+        next_domain: ZONE[(index + 1) % ZONE.len()].to_string(),
+        types: vec![QueryType::A.to_num()],
+        ttl: 3600,
+    })
+}
+
+fn nsec_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let local = socket.local_addr().unwrap();
+
+    thread::spawn(move || loop {
+        let mut buf = [0; 512];
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        let request = DnsPacket::from_bytes(&buf[..len]).unwrap();
+        let mut response = DnsPacket::default();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        response.answers.extend(nsec(&request.questions[0].name));
+        let _ = socket.send_to(&response.to_bytes().unwrap(), src);
+    });
+
+    local
+}
+
+fn soa() -> DnsRecord {
+    DnsRecord::SOA {
+        domain: "example.com".to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 300,
+        ttl: 3600,
+    }
+}
+
+/// Hands the zone, with plenty of records per name, to anyone who asks.
+fn axfr_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let local = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut request = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let request = DnsPacket::from_bytes(&request).unwrap();
+
+            let header = DnsHeader {
+                id: request.header.id,
+                response: true,
+                ..DnsHeader::default()
+            };
+            let records = ZONE.iter().flat_map(|name| {
+                (0..20).map(move |i| DnsRecord::A {
+                    domain: name.to_string(),
+                    addr: [192, 0, 2, i].into(),
+                    ttl: 3600,
+                })
+            });
+            let records = std::iter::once(soa())
+                .chain(records)
+                .chain(std::iter::once(soa()));
+            MessageWriter::new(header, request.questions, records)
+                .write_framed(&mut stream)
+                .unwrap();
+        }
+    });
+
+    local
+}
+
+#[test]
+fn walking_the_nsec_chain() {
+    let walk = walk::nsec_walk(&Resolver::default(), "example.com", nsec_server()).unwrap();
+
+    assert_eq!(walk.names, ZONE);
+    assert!(walk.complete);
+
+    assert!(walk::nsec_walk(&Resolver::default(), "example.org", nsec_server()).is_err());
+}
+
+#[test]
+fn transferring_the_zone() {
+    let transfer = walk::transfer("example.com", axfr_server()).unwrap();
+
+    assert_eq!(transfer.records, ZONE.len() * 20 + 2);
+    assert_eq!(transfer.names, ZONE.iter().map(|n| n.to_string()).collect());
+    assert_eq!(transfer.undecoded, 0);
+}
@@ -0,0 +1,77 @@
+//! Noticing what's legal but careless about a message while parsing it.
+
+use diydns::{DnsPacket, DnsQuestion, DnsRecord, QueryType, Section, Warning, CLASS_IN};
+
+fn a(domain: &str, ttl: u32) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr: [192, 0, 2, 1].into(),
+        ttl,
+    }
+}
+
+fn parse(packet: &DnsPacket) -> (DnsPacket, Vec<Warning>) {
+    DnsPacket::from_bytes_with_warnings(&packet.to_bytes().unwrap()).unwrap()
+}
+
+#[test]
+fn ttls_differing_within_an_rrset() {
+    let packet = DnsPacket {
+        answers: vec![
+            a("www.example.com", 300),
+            a("www.example.com", 300),
+            a("www.example.com", 60),
+            a("www.example.com", 30),
+            a("mail.example.com", 60),
+        ],
+        resources: vec![a("www.example.com", 3600)],
+        ..DnsPacket::default()
+    };
+
+    let (_, warnings) = parse(&packet);
+    assert_eq!(
+        warnings,
+        [Warning::TtlMismatch {
+            section: Section::Answer,
+            name: "www.example.com".to_string(),
+            qtype: QueryType::A,
+        }]
+    );
+    assert_eq!(
+        warnings[0].to_string(),
+        "TTLs differ within the www.example.com A RRset in the answer section"
+    );
+}
+
+#[test]
+fn names_in_upper_case() {
+    let mut packet = DnsPacket::default();
+    packet.questions.push(DnsQuestion {
+        name: "WWW.Example.com".to_string(),
+        qtype: QueryType::A,
+        qclass: CLASS_IN,
+    });
+    packet.answers = vec![a("WWW.Example.com", 300), a("www.example.com", 300)];
+
+    // Names are still read in lower case, and the pointers back to the question's name
+    // don't repeat the warning.
+    let (parsed, warnings) = parse(&packet);
+    assert_eq!(parsed.answers[0].domain(), "www.example.com");
+    assert_eq!(
+        warnings,
+        [Warning::NotCanonical {
+            name: "WWW.Example.com".to_string()
+        }]
+    );
+}
+
+#[test]
+fn nothing_to_say_about_clean_packets() {
+    let packet = DnsPacket {
+        answers: vec![a("www.example.com", 300), a("www.example.com", 300)],
+        ..DnsPacket::default()
+    };
+
+    assert!(parse(&packet).1.is_empty());
+    assert!(packet.warnings().is_empty());
+}
@@ -0,0 +1,53 @@
+//! Sharing queries out among upstreams by weight, and taking failed ones out of the
+//! rotation.
+
+#![cfg(feature = "server")]
+
+use diydns::upstream::WeightedUpstreams;
+use std::net::SocketAddr;
+
+fn addr(last: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, last], 53))
+}
+
+/// How many of `rounds` queries go to each of `servers` first.
+fn firsts(upstreams: &WeightedUpstreams, servers: &[SocketAddr], rounds: usize) -> Vec<usize> {
+    let mut counts = vec![0; servers.len()];
+    for _ in 0..rounds {
+        let first = upstreams.order()[0];
+        counts[servers.iter().position(|s| *s == first).unwrap()] += 1;
+    }
+    counts
+}
+
+#[test]
+fn queries_are_shared_by_weight() {
+    let servers = [addr(1), addr(2), addr(3)];
+    let upstreams = WeightedUpstreams::new(&[(servers[0], 3), (servers[1], 1), (servers[2], 1)]);
+
+    assert_eq!(firsts(&upstreams, &servers, 50), vec![30, 10, 10]);
+}
+
+#[test]
+fn every_server_is_in_the_order() {
+    let upstreams = WeightedUpstreams::new(&[(addr(1), 1), (addr(2), 5), (addr(3), 2)]);
+
+    let mut order = upstreams.order();
+    order.sort();
+    assert_eq!(order, vec![addr(1), addr(2), addr(3)]);
+}
+
+#[test]
+fn a_failed_server_goes_last_until_it_answers() {
+    let servers = [addr(1), addr(2)];
+    let upstreams = WeightedUpstreams::new(&[(servers[0], 1), (servers[1], 1)]);
+
+    upstreams.failed(servers[0]);
+    assert_eq!(firsts(&upstreams, &servers, 10), vec![0, 10]);
+    assert_eq!(upstreams.order(), vec![servers[1], servers[0]]);
+    assert!(!upstreams.status()[0].healthy);
+
+    upstreams.succeeded(servers[0]);
+    assert!(upstreams.status()[0].healthy);
+    assert_eq!(firsts(&upstreams, &servers, 10), vec![5, 5]);
+}
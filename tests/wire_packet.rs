@@ -0,0 +1,62 @@
+//! Getting at each record's bytes exactly as they were sent.
+
+use diydns::{DnsRecord, Section, WirePacket};
+use std::fs;
+use std::path::Path;
+
+fn compressed() -> WirePacket {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/compressed.bin");
+    WirePacket::from_bytes(&fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn rdata_is_the_original_bytes() {
+    let wire = compressed();
+
+    for (index, rec) in wire.packet.resources.iter().enumerate() {
+        let rdata = wire.rdata(Section::Additional, index).unwrap();
+        match rec {
+            DnsRecord::A { addr, .. } => assert_eq!(rdata, &addr.octets()[..]),
+            DnsRecord::AAAA { addr, .. } => assert_eq!(rdata, &addr.octets()[..]),
+            _ => {}
+        }
+    }
+
+    // The MX data starts with the priority, and its host is left compressed.
+    let mx = wire
+        .packet
+        .answers
+        .iter()
+        .position(|rec| matches!(rec, DnsRecord::MX { .. }));
+    let rdata = wire.rdata(Section::Answer, mx.unwrap()).unwrap();
+    assert_eq!(&rdata[..2], &[0, 10]);
+    assert!(rdata.len() < "mx1.mx.example.org".len());
+}
+
+#[test]
+fn spans_cover_the_message_in_order() {
+    let wire = compressed();
+    let counts = [
+        (Section::Answer, wire.packet.answers.len()),
+        (Section::Authority, wire.packet.authorities.len()),
+        (Section::Additional, wire.packet.resources.len()),
+    ];
+
+    let mut end = None;
+    for (section, count) in counts {
+        for index in 0..count {
+            let span = wire.span(section, index).unwrap();
+            if let Some(end) = end {
+                assert_eq!(span.record.start, end);
+            }
+            assert_eq!(span.record.end, span.rdata.end);
+            assert_eq!(
+                wire.record_bytes(section, index).unwrap().len(),
+                span.record.len()
+            );
+            end = Some(span.record.end);
+        }
+        assert!(wire.span(section, count).is_none());
+    }
+    assert_eq!(end, Some(wire.bytes().len()));
+}
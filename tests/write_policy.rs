@@ -0,0 +1,156 @@
+//! Encoding values the protocol doesn't allow: refused, fixed, or written as they are.
+
+use diydns::{
+    DnsError, DnsErrorKind, DnsPacket, DnsQuestion, DnsRecord, QueryType, WritePolicy, CLASS_IN,
+};
+
+fn packet(name: &str, ttl: u32) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.response = true;
+    packet.questions.push(DnsQuestion {
+        name: name.to_string(),
+        qtype: QueryType::A,
+        qclass: CLASS_IN,
+    });
+    packet.answers.push(DnsRecord::A {
+        domain: name.to_string(),
+        addr: [192, 0, 2, 1].into(),
+        ttl,
+    });
+    packet.header.questions = 1;
+    packet.header.answers = 1;
+    packet
+}
+
+#[test]
+fn ttls_past_the_top_bit() {
+    let packet = packet("example.com", u32::MAX);
+
+    assert!(packet.to_bytes_with(WritePolicy::Strict).is_err());
+
+    let lenient = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+    assert_eq!(lenient.answers[0].ttl(), i32::MAX as u32);
+
+    let verbatim = packet.to_bytes_with(WritePolicy::Verbatim).unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&verbatim).unwrap().answers[0].ttl(),
+        u32::MAX
+    );
+}
+
+#[test]
+fn counts_that_dont_match_the_sections() {
+    let mut packet = packet("example.com", 300);
+    packet.header.answers = 3;
+
+    assert!(packet.to_bytes_with(WritePolicy::Strict).is_err());
+
+    let lenient = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+    assert_eq!(lenient.header.answers, 1);
+
+    // The message claims records it doesn't have.
+    let verbatim = packet.to_bytes_with(WritePolicy::Verbatim).unwrap();
+    let error = DnsPacket::from_bytes(&verbatim).unwrap_err();
+    assert!(matches!(
+        DnsError::find(&error).unwrap().kind(),
+        DnsErrorKind::Truncated { .. }
+    ));
+}
+
+#[test]
+fn labels_past_63_characters() {
+    let longest = format!("{}.example.com", "a".repeat(63));
+    assert!(packet(&longest, 300)
+        .to_bytes_with(WritePolicy::Strict)
+        .is_ok());
+
+    let packet = packet(&format!("{}.example.com", "a".repeat(64)), 300);
+    assert!(packet.to_bytes_with(WritePolicy::Strict).is_err());
+    assert!(packet.to_bytes().is_err());
+
+    let verbatim = packet.to_bytes_with(WritePolicy::Verbatim).unwrap();
+    let error = DnsPacket::from_bytes(&verbatim).unwrap_err();
+    assert_eq!(
+        DnsError::find(&error).unwrap().kind(),
+        DnsErrorKind::BadLabelType(64)
+    );
+}
+
+#[test]
+fn names_past_255_bytes() {
+    let label = "a".repeat(60);
+    let name = [label.as_str(); 5].join(".");
+    let mut packet = packet(&name, 300);
+    // Two of them wouldn't fit in a message.
+    packet.answers.clear();
+
+    assert!(packet.to_bytes().is_err());
+    assert!(packet.to_bytes_with(WritePolicy::Verbatim).is_ok());
+}
+
+#[test]
+fn unknown_data_lengths() {
+    let mut packet = packet("example.com", 300);
+    packet.answers[0] = DnsRecord::Unknown {
+        domain: "example.com".to_string(),
+        qtype: 99,
+        data_len: 2,
+        data: vec![1, 2, 3, 4],
+        ttl: 300,
+    };
+
+    assert!(packet.to_bytes_with(WritePolicy::Strict).is_err());
+    let lenient = DnsPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+    assert!(matches!(
+        lenient.answers[0],
+        DnsRecord::Unknown { data_len: 4, .. }
+    ));
+}
+
+/// Asserts that `record` is refused however it's written, rather than overflowing its length.
+fn too_long(record: DnsRecord) {
+    let mut packet = packet("example.com", 300);
+    packet.answers[0] = record;
+    for &policy in &[
+        WritePolicy::Strict,
+        WritePolicy::Lenient,
+        WritePolicy::Verbatim,
+    ] {
+        assert!(packet.to_bytes_with(policy).is_err());
+    }
+}
+
+#[test]
+fn data_past_the_length_field() {
+    too_long(DnsRecord::DNSKEY {
+        domain: "example.com".to_string(),
+        flags: 257,
+        protocol: 3,
+        algorithm: 13,
+        public_key: vec![0; 65532],
+        ttl: 300,
+    });
+    too_long(DnsRecord::TLSA {
+        domain: "_443._tcp.example.com".to_string(),
+        usage: 3,
+        selector: 1,
+        matching_type: 0,
+        data: vec![0; 65533],
+        ttl: 300,
+    });
+    too_long(DnsRecord::DS {
+        domain: "example.com".to_string(),
+        key_tag: 12345,
+        algorithm: 13,
+        digest_type: 2,
+        digest: vec![0; 65532],
+        ttl: 300,
+    });
+    too_long(DnsRecord::SSHFP {
+        domain: "host.example.com".to_string(),
+        algorithm: 4,
+        fingerprint_type: 2,
+        fingerprint: vec![0; 65534],
+        ttl: 300,
+    });
+}
@@ -0,0 +1,696 @@
+//! Serving zones from zone files, and picking the zone for a name.
+
+#![cfg(feature = "server")]
+
+use diydns::config::Config;
+use diydns::rdata::{self, RDataCodec};
+use diydns::zone::{self, Zone, Zones};
+use diydns::{DnsPacket, DnsRecord, QueryType, Result, ResultCode};
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+const EXAMPLE: &str = r#"
+$TTL 1h
+@       IN SOA  ns1 hostmaster (
+                2024013101 ; serial
+                2h 15m 14d 5m )
+        IN NS   ns1
+        IN MX   10 mail
+ns1     IN A    192.0.2.1
+mail    300 IN A 192.0.2.2
+        IN AAAA 2001:db8::2
+www     CNAME   web.example.com.
+web     A       192.0.2.3
+txt     TXT     "v=spf1 -all" "two words" "q\"uote\059"
+a.b.deep A      192.0.2.4
+caa     CAA     128 issue "ca.example.net; account=1"
+web     SSHFP   4 2 9d4b5c2a21c1e0b4ed63e5b8a2f7b5b6e93c2d8f1c4d6e0a9b1f3e5d7c9a0b2c
+_443._tcp.www TLSA 3 1 1 ( 0d6fce3368a3d6b8e4a7e2a2b0d5
+                        e0b6cf49 )
+web     NSEC    txt.example.com. SSHFP A RRSIG NSEC
+sip     NAPTR   100 10 "U" "E2U+sip" "!^.*$!sip:info@example.com!" .
+$ORIGIN dept.example.com.
+@       NS      ns
+        DS      60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118
+ns      A       192.0.2.5
+"#;
+
+fn example() -> Zone {
+    Zone::parse("example.com", EXAMPLE).unwrap()
+}
+
+#[test]
+fn answers_from_the_zone() {
+    let zone = example();
+
+    let answer = zone.answer("MAIL.example.com.", QueryType::A);
+    assert!(answer.authoritative);
+    assert_eq!(
+        answer.answers,
+        [DnsRecord::A {
+            domain: "mail.example.com".to_string(),
+            addr: "192.0.2.2".parse().unwrap(),
+            ttl: 300,
+        }]
+    );
+    // Records without a TTL take the $TTL, not the one before them.
+    assert_eq!(
+        zone.answer("mail.example.com", QueryType::AAAA).answers[0].ttl(),
+        3600
+    );
+
+    let answer = zone.answer("example.com", QueryType::MX);
+    assert_eq!(
+        answer.answers[0].to_string(),
+        "example.com. 3600 IN MX 10 mail.example.com."
+    );
+
+    let answer = zone.answer("www.example.com", QueryType::A);
+    let types: Vec<_> = answer.answers.iter().map(DnsRecord::query_type).collect();
+    assert_eq!(types, [QueryType::CNAME, QueryType::A]);
+
+    let answer = zone.answer("txt.example.com", QueryType::TXT);
+    match answer.answers[0] {
+        DnsRecord::TXT { ref data, .. } => {
+            assert_eq!(data, &["v=spf1 -all", "two words", "q\"uote;"])
+        }
+        ref other => panic!("expected TXT, got {:?}", other),
+    }
+
+    let answer = zone.answer("caa.example.com", QueryType::CAA);
+    assert_eq!(
+        answer.answers[0].to_string(),
+        "caa.example.com. 3600 IN CAA 128 issue \"ca.example.net; account=1\""
+    );
+
+    let answer = zone.answer("web.example.com", QueryType::SSHFP);
+    assert_eq!(
+        answer.answers[0].rdata().unwrap(),
+        "4 2 9D4B5C2A21C1E0B4ED63E5B8A2F7B5B6E93C2D8F1C4D6E0A9B1F3E5D7C9A0B2C"
+    );
+
+    let answer = zone.answer("_443._tcp.www.example.com", QueryType::TLSA);
+    assert_eq!(
+        answer.answers[0].rdata().unwrap(),
+        "3 1 1 0D6FCE3368A3D6B8E4A7E2A2B0D5E0B6CF49"
+    );
+
+    let answer = zone.answer("web.example.com", QueryType::NSEC);
+    assert_eq!(
+        answer.answers[0].rdata().unwrap(),
+        "txt.example.com. A SSHFP RRSIG NSEC"
+    );
+
+    let answer = zone.answer("sip.example.com", QueryType::NAPTR);
+    assert_eq!(
+        answer.answers[0].rdata().unwrap(),
+        "100 10 \"U\" \"E2U+sip\" \"!^.*$!sip:info@example.com!\" ."
+    );
+}
+
+#[test]
+fn cname_loops_stop_at_the_first_repeat() {
+    let zone = Zone::parse("example.com", "a CNAME b\nb CNAME a\nself CNAME self\n").unwrap();
+
+    let chain = |name| -> Vec<String> {
+        let answer = zone.answer(name, QueryType::A);
+        assert_eq!(answer.rescode, ResultCode::Success);
+        answer.answers.iter().map(ToString::to_string).collect()
+    };
+    assert_eq!(
+        chain("a.example.com"),
+        [
+            "a.example.com. 3600 IN CNAME b.example.com.",
+            "b.example.com. 3600 IN CNAME a.example.com.",
+        ]
+    );
+    assert_eq!(
+        chain("self.example.com"),
+        ["self.example.com. 3600 IN CNAME self.example.com."]
+    );
+}
+
+#[test]
+fn negative_answers_and_referrals() {
+    let zone = example();
+
+    // NODATA, for a name with records and for one that only has names beneath it.
+    for name in ["web.example.com", "b.deep.example.com"].iter() {
+        let answer = zone.answer(name, QueryType::AAAA);
+        assert_eq!(answer.rescode, ResultCode::Success, "{}", name);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authorities.len(), 1);
+        assert_eq!(answer.authorities[0].query_type(), QueryType::SOA);
+    }
+
+    let answer = zone.answer("nope.example.com", QueryType::A);
+    assert_eq!(answer.rescode, ResultCode::NonexistantDomain);
+    assert!(answer.authoritative);
+
+    // Under the delegation to dept.example.com, even where we hold the glue.
+    for name in [
+        "dept.example.com",
+        "host.dept.example.com",
+        "ns.dept.example.com",
+    ]
+    .iter()
+    {
+        let answer = zone.answer(name, QueryType::A);
+        assert!(!answer.authoritative, "{}", name);
+        assert!(answer.answers.is_empty());
+        assert_eq!(
+            answer.authorities,
+            [DnsRecord::NS {
+                domain: "dept.example.com".to_string(),
+                host: "ns.dept.example.com".to_string(),
+                ttl: 3600,
+            }]
+        );
+        assert_eq!(answer.additionals.len(), 1);
+    }
+
+    // Except for the DS records at the delegation, which are ours to answer.
+    let answer = zone.answer("dept.example.com", QueryType::DS);
+    assert!(answer.authoritative);
+    assert_eq!(answer.answers[0].query_type(), QueryType::DS);
+}
+
+#[test]
+fn adds_glue() {
+    let zone = example();
+
+    let answer = zone.answer("example.com", QueryType::NS);
+    assert_eq!(
+        answer.additionals,
+        [DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 3600,
+        }]
+    );
+
+    let answer = zone.answer("www.dept.example.com", QueryType::AAAA);
+    assert_eq!(answer.additionals[0].domain(), "ns.dept.example.com");
+
+    // Only for name servers inside the zone, and only with the addresses it has.
+    let zone = Zone::parse(
+        "example.com",
+        "@ NS ns1\n@ NS ns.example.net.\nns1 A 192.0.2.1\nns1 AAAA 2001:db8::1\nns1 MX 10 mail\n",
+    )
+    .unwrap();
+    let types: Vec<_> = zone
+        .answer("example.com", QueryType::NS)
+        .additionals
+        .iter()
+        .map(DnsRecord::query_type)
+        .collect();
+    assert_eq!(types, [QueryType::A, QueryType::AAAA]);
+    assert!(zone.missing_glue().is_empty());
+}
+
+#[test]
+fn finds_missing_glue() {
+    let zone = Zone::parse(
+        "example.com",
+        "@ NS ns1\n@ NS ns.example.net.\n\
+         child NS ns.child\nchild NS ns.other\n\
+         other NS ns.other\nns.other A 192.0.2.9\n",
+    )
+    .unwrap();
+
+    // ns.other.example.com is under a delegation of its own, whose servers can answer
+    // for it, so only the other two count.
+    assert_eq!(
+        zone.missing_glue(),
+        [
+            "ns.child.example.com, a name server for child.example.com, has no A or AAAA record",
+            "ns1.example.com, a name server for example.com, has no A or AAAA record",
+        ]
+    );
+}
+
+#[test]
+fn warns_of_a_bare_apex() {
+    let zone = Zone::parse("example.com", "www A 192.0.2.1\n").unwrap();
+    assert_eq!(
+        zone.warnings(),
+        ["the apex has no SOA record", "the apex has no NS record"]
+    );
+
+    let zone = Zone::parse("example.com", "@ NS ns1\n").unwrap();
+    assert_eq!(
+        zone.warnings(),
+        [
+            "the apex has no SOA record",
+            "ns1.example.com, a name server for example.com, has no A or AAAA record",
+        ]
+    );
+
+    assert!(example().warnings().is_empty());
+}
+
+#[test]
+fn records_of_registered_types() {
+    struct Counter;
+
+    impl RDataCodec for Counter {
+        fn mnemonic(&self) -> &str {
+            "COUNTER"
+        }
+
+        fn present(&self, data: &[u8]) -> Result<String> {
+            match *data {
+                [a, b, c, d] => Ok(u32::from_be_bytes([a, b, c, d]).to_string()),
+                _ => Err(Error::new(ErrorKind::InvalidData, "expected four bytes")),
+            }
+        }
+
+        fn parse(&self, text: &str) -> Result<Vec<u8>> {
+            let value: u32 = text
+                .parse()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "not a number"))?;
+            Ok(value.to_be_bytes().to_vec())
+        }
+    }
+    rdata::register_rdata_codec(65280, Box::new(Counter)).unwrap();
+
+    let zone = Zone::parse("example.com", "visits 60 COUNTER 1234\n").unwrap();
+    let answer = zone.answer("visits.example.com", QueryType::Unknown(65280));
+    assert_eq!(
+        answer.answers[0].to_string(),
+        "visits.example.com. 60 IN COUNTER 1234"
+    );
+
+    let err = Zone::parse("example.com", "visits COUNTER many\n").unwrap_err();
+    assert_eq!(err.to_string(), "1: bad COUNTER record data: not a number");
+}
+
+#[test]
+fn longest_origin_wins() {
+    let zones = [
+        example(),
+        Zone::parse("sub.example.com", "@ A 192.0.2.10\nhost A 192.0.2.11\n").unwrap(),
+        Zone::parse("example.org", "@ A 192.0.2.20\n").unwrap(),
+    ];
+
+    let origin = |name| zone::find(&zones, name).map(|zone| zone.origin.as_str());
+    assert_eq!(origin("www.example.com"), Some("example.com"));
+    assert_eq!(origin("host.sub.example.com."), Some("sub.example.com"));
+    assert_eq!(origin("sub.example.com"), Some("sub.example.com"));
+    assert_eq!(origin("notsub.example.com"), Some("example.com"));
+    assert_eq!(origin("example.org"), Some("example.org"));
+    assert_eq!(origin("example.net"), None);
+}
+
+#[test]
+fn rejects_bad_zone_files() {
+    let cases = [
+        (
+            "www A 192.0.2.300\n",
+            "1: bad A record data \"192.0.2.300\"",
+        ),
+        ("\n\nwww HINFO a b\n", "3: unsupported record type HINFO"),
+        ("www CAA 0 \"\" letsencrypt.org\n", "1: bad CAA record data"),
+        (
+            "www TLSA 3 1 1 abc\n",
+            "1: bad TLSA record data \"3 1 1 abc\"",
+        ),
+        ("@ DNSKEY 257 3 13 AQ=A\n", "1: bad DNSKEY record data"),
+        (
+            "www.example.org. A 192.0.2.1\n",
+            "1: www.example.org is outside example.com",
+        ),
+        (
+            "www CNAME a\nwww A 192.0.2.1\n",
+            "2: www.example.com has a CNAME",
+        ),
+        (
+            "www A 192.0.2.1\nwww CNAME a\n",
+            "2: www.example.com has a CNAME",
+        ),
+        (
+            "www CNAME a\nwww CNAME b\n",
+            "2: www.example.com has more than one CNAME",
+        ),
+        (
+            "@ CNAME example.net.\n",
+            "1: example.com is the zone's apex, and can't have a CNAME",
+        ),
+        (
+            "@ SOA ns1 host 1 2 3 4 5\n@ SOA ns1 host 2 2 3 4 5\n",
+            "2: example.com has more than one SOA record",
+        ),
+        (
+            "www SOA ns1 host 1 2 3 4 5\n",
+            "1: www.example.com has an SOA record, which only the apex can have",
+        ),
+        ("  A 192.0.2.1\n", "1: no owner name to carry over"),
+        ("@ SOA ns1 host ( 1 2 3 4\n", "1: unclosed ("),
+        ("$INCLUDE\n", "1: expected a file to include"),
+        (
+            "$INCLUDE /nonexistent-42.zone\n",
+            "1: /nonexistent-42.zone: No such file or directory",
+        ),
+        (
+            "$GENERATE 5-1 host-$ A 192.0.2.$\n",
+            "1: bad $GENERATE range \"5-1\"",
+        ),
+        (
+            "$GENERATE 1-2 host-$ A\n",
+            "1: expected a range, an owner, a type and the record data",
+        ),
+        (
+            "$GENERATE 1-2 host-${0,3,n} A 192.0.2.$\n",
+            "1: bad $GENERATE modifier \"0,3,n\"",
+        ),
+        (
+            "$INCLUDE/x other.zone\n",
+            "1: unsupported directive $INCLUDE/x",
+        ),
+    ];
+
+    for (text, expected) in cases.iter() {
+        let err = Zone::parse("example.com", text).unwrap_err();
+        assert!(err.to_string().starts_with(expected), "{}: {}", text, err);
+    }
+}
+
+#[test]
+fn signed_aliases_keep_their_dnssec_records() {
+    let text = "www CNAME web\n\
+                www RRSIG CNAME 13 3 3600 20240301000000 20240201000000 12345 example.com. c2ln\n\
+                www NSEC web CNAME RRSIG NSEC\n\
+                web A 192.0.2.1\n";
+    let zone = Zone::parse("example.com", text).unwrap();
+
+    let types = |qtype| -> Vec<QueryType> {
+        zone.answer("www.example.com", qtype)
+            .answers
+            .iter()
+            .map(DnsRecord::query_type)
+            .collect()
+    };
+    assert_eq!(types(QueryType::A), [QueryType::CNAME, QueryType::A]);
+    assert_eq!(types(QueryType::RRSIG), [QueryType::RRSIG]);
+    assert_eq!(types(QueryType::NSEC), [QueryType::NSEC]);
+}
+
+#[test]
+fn keys_match_their_ds_by_key_tag() {
+    // The key of RFC 4034's DS example.
+    let public_key = "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZ\
+                      DRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9Xzc\
+                      nOf+EPbtG9DMBmADjFDc2w/rljwvFw==";
+    let text = format!(
+        "dskey 86400 DNSKEY 256 3 5 ( {}\n {} )\n\
+         dskey 86400 DS 60485 5 1 ( 2BB183AF5F22588179A53B0A\n 98631FAD1A292118 )\n",
+        &public_key[..50],
+        &public_key[50..]
+    );
+    let zone = Zone::parse("example.com", &text).unwrap();
+    let rec = zone.answer("dskey.example.com", QueryType::DNSKEY).answers[0].clone();
+
+    assert_eq!(rec.key_tag(), Some(60485));
+    let ds = zone.answer("dskey.example.com", QueryType::DS).answers[0].clone();
+    assert_eq!(
+        ds.to_string(),
+        "dskey.example.com. 86400 IN DS 60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118"
+    );
+    assert_eq!(
+        rec.to_string(),
+        format!("dskey.example.com. 86400 IN DNSKEY 256 3 5 {}", public_key)
+    );
+
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    packet.answers.push(ds);
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+}
+
+#[test]
+fn signatures_parse_with_their_times() {
+    let text = "www 300 RRSIG A 13 3 300 20240301000000 1706745600 12345 example.com. (\n\
+                \t\tc2lnbmF0dXJl )\n";
+    let zone = Zone::parse("example.com", text).unwrap();
+    let rec = zone.answer("www.example.com", QueryType::RRSIG).answers[0].clone();
+
+    match rec {
+        DnsRecord::RRSIG {
+            type_covered,
+            expiration,
+            inception,
+            ref signer,
+            ref signature,
+            ..
+        } => {
+            assert_eq!(type_covered, QueryType::A.to_num());
+            assert_eq!((expiration, inception), (1709251200, 1706745600));
+            assert_eq!(signer, "example.com");
+            assert_eq!(signature, b"signature");
+        }
+        ref other => panic!("expected RRSIG, got {:?}", other),
+    }
+    assert_eq!(
+        rec.to_string(),
+        "www.example.com. 300 IN RRSIG A 13 3 300 20240301000000 20240201000000 12345 \
+         example.com. c2lnbmF0dXJl"
+    );
+
+    // The signer is written out in full rather than pointing back to the owner's name.
+    let mut packet = DnsPacket::default();
+    packet.answers.push(rec);
+    let bytes = packet.to_bytes().unwrap();
+    let signer = b"\x07example\x03com\x00";
+    assert_eq!(
+        bytes.windows(signer.len()).filter(|w| w == signer).count(),
+        2
+    );
+    assert_eq!(
+        DnsPacket::from_bytes(&bytes).unwrap().answers,
+        packet.answers
+    );
+
+    let err = Zone::parse(
+        "example.com",
+        "www RRSIG A 13 3 300 20241301000000 20240201000000 12345 example.com. c2ln\n",
+    )
+    .unwrap_err();
+    assert!(err.to_string().starts_with("1: bad RRSIG record data"));
+}
+
+#[test]
+fn nsec3_records_parse() {
+    let text = "@ NSEC3PARAM 1 0 10 aabb\n\
+                0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 10 aabb cpnmuoj1 A RRSIG\n\
+                2t7b4g4vsa5smi47k61mv5bv1a22bojr NSEC3 1 0 0 - CO\n";
+    let zone = Zone::parse("example.com", text).unwrap();
+
+    let hashed = |name: &str| match zone.answer(name, QueryType::NSEC3).answers[0] {
+        DnsRecord::NSEC3 {
+            ref salt,
+            ref next_hashed_owner,
+            ..
+        } => (salt.clone(), next_hashed_owner.clone()),
+        ref other => panic!("expected NSEC3, got {:?}", other),
+    };
+    assert_eq!(
+        hashed("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example.com"),
+        (vec![0xaa, 0xbb], b"fooba".to_vec())
+    );
+    assert_eq!(
+        hashed("2t7b4g4vsa5smi47k61mv5bv1a22bojr.example.com"),
+        (Vec::new(), b"f".to_vec())
+    );
+    assert_eq!(
+        zone.answer("example.com", QueryType::NSEC3PARAM).answers[0]
+            .rdata()
+            .unwrap(),
+        "1 0 10 AABB"
+    );
+
+    for bad in ["abc", "cpnmuoj1e8!"].iter() {
+        let text = format!("x NSEC3 1 0 0 - {} A\n", bad);
+        let err = Zone::parse("example.com", &text).unwrap_err();
+        assert!(
+            err.to_string().starts_with("1: bad NSEC3 record data"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn includes_files_from_the_including_files_directory() {
+    let dir = env::temp_dir().join(format!("diydns-include-{}", std::process::id()));
+    fs::create_dir_all(dir.join("hosts")).unwrap();
+    fs::write(
+        dir.join("example.com.zone"),
+        "$INCLUDE hosts/lab.zone lab\nwww A 192.0.2.1\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("hosts/lab.zone"),
+        "printer A 192.0.2.20\n$INCLUDE more.zone\n",
+    )
+    .unwrap();
+    fs::write(dir.join("hosts/more.zone"), "scanner A 192.0.2.21\n").unwrap();
+
+    let zone = Zone::load("example.com", &dir.join("example.com.zone")).unwrap();
+    // The included file's origin goes for the files it includes, and no further.
+    for name in &[
+        "printer.lab.example.com",
+        "scanner.lab.example.com",
+        "www.example.com",
+    ] {
+        assert_eq!(zone.lookup(name, QueryType::A).len(), 1, "{}", name);
+    }
+
+    // Errors say which file and line they're on.
+    fs::write(dir.join("hosts/more.zone"), "scanner A nowhere\n").unwrap();
+    let err = Zone::load("example.com", &dir.join("example.com.zone")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "{}:1: {}:2: {}:1: bad A record data \"nowhere\"",
+            dir.join("example.com.zone").display(),
+            dir.join("hosts/lab.zone").display(),
+            dir.join("hosts/more.zone").display()
+        )
+    );
+
+    // A file that includes itself goes only so deep.
+    fs::write(dir.join("hosts/more.zone"), "$INCLUDE more.zone\n").unwrap();
+    let err = Zone::load("example.com", &dir.join("example.com.zone")).unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("$INCLUDE nested more than 8 deep"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn generates_numbered_records() {
+    let text = "$GENERATE 1-3 host-$ A 192.0.2.$\n\
+                $GENERATE 10-14/2 ${-9,2} 300 IN CNAME pool-${0,2,x}\n\
+                $GENERATE 7-7 price TXT \\$$\n";
+    let zone = Zone::parse("example.com", text).unwrap();
+
+    let mut records: Vec<String> = zone.records().map(ToString::to_string).collect();
+    records.sort();
+    assert_eq!(
+        records,
+        [
+            "01.example.com. 300 IN CNAME pool-0a.example.com.",
+            "03.example.com. 300 IN CNAME pool-0c.example.com.",
+            "05.example.com. 300 IN CNAME pool-0e.example.com.",
+            "host-1.example.com. 3600 IN A 192.0.2.1",
+            "host-2.example.com. 3600 IN A 192.0.2.2",
+            "host-3.example.com. 3600 IN A 192.0.2.3",
+            "price.example.com. 300 IN TXT \"$7\"",
+        ]
+    );
+}
+
+#[test]
+fn reloads_only_checked_versions() {
+    let path = env::temp_dir().join(format!("diydns-reload-{}.zone", std::process::id()));
+    let version = |serial: u32, addr: &str| {
+        format!(
+            "@ SOA ns1 host {} 2h 15m 14d 5m\n@ NS ns1\nns1 A 192.0.2.1\nwww A {}\n",
+            serial, addr
+        )
+    };
+    fs::write(&path, version(1, "192.0.2.10")).unwrap();
+    let zone = Zone::load("example.com", &path).unwrap();
+    assert_eq!(zone.serial(), Some(1));
+    assert!(zone.modified().is_some());
+
+    let reload_error = |text: &str| {
+        fs::write(&path, text).unwrap();
+        zone.reload().unwrap_err().to_string()
+    };
+    assert_eq!(
+        reload_error(&version(1, "192.0.2.11")),
+        "the serial 1 isn't later than 1"
+    );
+    assert_eq!(
+        reload_error("@ SOA ns1 host 2 2h 15m 14d 5m\nwww A 192.0.2.11\n"),
+        "the apex has no NS record"
+    );
+    assert!(
+        reload_error(&format!("{}www A nowhere\n", version(2, "192.0.2.11")))
+            .ends_with("bad A record data \"nowhere\"")
+    );
+
+    // Serials wrap around (RFC 1982).
+    let mut zone = zone;
+    for &serial in &[2_000_000_000, 4_000_000_000] {
+        fs::write(&path, version(serial, "192.0.2.11")).unwrap();
+        zone = zone.reload().unwrap();
+    }
+    fs::write(&path, version(5, "192.0.2.12")).unwrap();
+    let zone = zone.reload().unwrap();
+    assert_eq!(zone.serial(), Some(5));
+    assert_eq!(
+        zone.answer("www.example.com", QueryType::A).answers[0]
+            .rdata()
+            .unwrap(),
+        "192.0.2.12"
+    );
+
+    // A zone from text has no file to reload from.
+    assert!(Zone::parse("example.com", "www A 192.0.2.1\n")
+        .unwrap()
+        .reload()
+        .is_err());
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn swapped_zones_are_found_in_place_of_the_old() {
+    let zones = Zones::new(vec![
+        Zone::parse("example.com", "www A 192.0.2.1\n").unwrap(),
+        Zone::parse("sub.example.com", "www A 192.0.2.2\n").unwrap(),
+    ]);
+    zones.replace(Zone::parse("example.com", "www A 192.0.2.3\n").unwrap());
+
+    let answer = |name| zones.find(name).unwrap().answer(name, QueryType::A).answers;
+    assert_eq!(answer("www.example.com")[0].rdata().unwrap(), "192.0.2.3");
+    assert_eq!(
+        answer("www.sub.example.com")[0].rdata().unwrap(),
+        "192.0.2.2"
+    );
+    assert!(zones.find("www.example.net").is_none());
+}
+
+#[test]
+fn configured_from_files() {
+    let path = env::temp_dir().join(format!("diydns-zone-{}", std::process::id()));
+    fs::write(&path, EXAMPLE).unwrap();
+
+    let mut config = Config::default();
+    config
+        .set("zone", &format!("example.com. {}", path.display()))
+        .unwrap();
+    assert_eq!(config.zones[0].origin, "example.com");
+    assert!(config
+        .set("zone", &format!("Example.com {}", path.display()))
+        .is_err());
+
+    fs::write(&path, "www A nowhere\n").unwrap();
+    let err = config
+        .set("zone", &format!("example.net {}", path.display()))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("{}:1: bad A record data \"nowhere\"", path.display())
+    );
+
+    let _ = fs::remove_file(&path);
+}